@@ -0,0 +1,259 @@
+//! Access logs: a line for every request answered, in the Common or Combined Log
+//! Format that log analysers read, or as a JSON object.
+
+use std::{
+    fmt::Write as _,
+    io::{self, Write},
+    net::IpAddr,
+    path::Path,
+    str::FromStr,
+    sync::Mutex,
+    time::{Duration, SystemTime},
+};
+
+use crate::{
+    json::Object,
+    log_file::{LogFile, Output, Rotation},
+    request::Request,
+    response::Status,
+    timestamp,
+};
+
+/// A request that has been answered, as the connection saw it.
+#[derive(Debug)]
+pub struct Exchange {
+    /// The request line and headers, or `None` if the request could not be read as
+    /// far as them.
+    pub request: Option<Request>,
+    /// The id the request was given, or `None` if nothing of it arrived.
+    pub request_id: Option<String>,
+    pub status: Status,
+    /// The bytes of the response body written, which are fewer than its length if
+    /// the connection failed while it was written.
+    pub body_bytes: u64,
+    /// When the request started to arrive.
+    pub started: SystemTime,
+    /// How long it took from then until the response was written.
+    pub duration: Duration,
+}
+
+/// Where a connection gives each exchange once it is over.
+pub type Sink<'a> = &'a (dyn Fn(Exchange) + Sync);
+
+/// How each line of an access log is laid out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// `host ident user [time] "request" status bytes`.
+    Common,
+    /// The Common Log Format followed by `"referer" "user-agent"`, then the
+    /// milliseconds the request took and its id in quotes.
+    Combined,
+    /// An object with `ts`, `level`, `request_id`, `method`, `path`, `status`,
+    /// `bytes`, `duration_ms`, `peer`, `referer` and `user_agent`, any of which are
+    /// `null` if they are not known.
+    Json,
+}
+
+impl FromStr for Format {
+    type Err = ();
+
+    fn from_str(format: &str) -> Result<Format, ()> {
+        match format.to_ascii_lowercase().as_str() {
+            "common" => Ok(Format::Common),
+            "combined" => Ok(Format::Combined),
+            "json" => Ok(Format::Json),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Lay out the line `format` gives `exchange`, with a client at `peer`, or `-` for
+/// clients without an IP address.
+/// ```
+/// use std::time::{Duration, UNIX_EPOCH};
+/// use ch20_web_server::{
+///     access_log::{self, Exchange, Format},
+///     response::Status,
+/// };
+///
+/// let exchange = Exchange {
+///     request: None,
+///     request_id: Some(String::from("4bf92f3577b34da6")),
+///     status: Status::BadRequest,
+///     body_bytes: 15,
+///     started: UNIX_EPOCH + Duration::from_secs(971_182_536),
+///     duration: Duration::from_millis(2),
+/// };
+/// assert_eq!(
+///     access_log::line(Format::Common, Some("127.0.0.1".parse().unwrap()), &exchange),
+///     "127.0.0.1 - - [10/Oct/2000:12:55:36 +0000] \"-\" 400 15",
+/// );
+/// ```
+pub fn line(format: Format, peer: Option<IpAddr>, exchange: &Exchange) -> String {
+    if format == Format::Json {
+        return json(peer, exchange);
+    }
+    let mut line = match peer {
+        Some(peer) => peer.to_string(),
+        None => String::from("-"),
+    };
+    let _ = write!(line, " - - [{}] ", timestamp::common_log(exchange.started));
+    match &exchange.request {
+        Some(request) => quote(
+            &mut line,
+            &format!(
+                "{} {} {}",
+                request.method(),
+                request.target(),
+                request.version()
+            ),
+        ),
+        None => line.push_str("\"-\""),
+    }
+    let _ = write!(line, " {}", exchange.status.code());
+    match exchange.body_bytes {
+        0 => line.push_str(" -"),
+        bytes => {
+            let _ = write!(line, " {bytes}");
+        }
+    }
+
+    if format == Format::Combined {
+        let header = |name| {
+            exchange
+                .request
+                .as_ref()
+                .and_then(|request| request.header(name))
+        };
+        for value in [header("Referer"), header("User-Agent")] {
+            line.push(' ');
+            quote(&mut line, value.unwrap_or("-"));
+        }
+        let _ = write!(line, " {} ", exchange.duration.as_millis());
+        quote(&mut line, exchange.request_id.as_deref().unwrap_or("-"));
+    }
+    line
+}
+
+/// Append `value` in double quotes, escaping quotes, backslashes and anything that
+/// is not printable so a client cannot forge the rest of the line.
+fn quote(line: &mut String, value: &str) {
+    line.push('"');
+    for c in value.chars() {
+        match c {
+            '"' | '\\' => {
+                line.push('\\');
+                line.push(c);
+            }
+            c if c.is_control() => {
+                let _ = write!(line, "\\x{:02x}", c as u32);
+            }
+            c => line.push(c),
+        }
+    }
+    line.push('"');
+}
+
+fn json(peer: Option<IpAddr>, exchange: &Exchange) -> String {
+    let request = exchange.request.as_ref();
+    let header = |name| request.and_then(|request| request.header(name));
+    let peer = peer.map(|peer| peer.to_string());
+    Object::new()
+        .string("ts", &timestamp::rfc3339(exchange.started))
+        .string("level", "info")
+        .optional_string("request_id", exchange.request_id.as_deref())
+        .optional_string("method", request.map(|request| request.method().as_str()))
+        .optional_string("path", request.map(Request::target))
+        .number("status", exchange.status.code())
+        .number("bytes", exchange.body_bytes as f64)
+        // To the microsecond, rather than with the noise of binary fractions.
+        .number("duration_ms", exchange.duration.as_micros() as f64 / 1000.0)
+        .optional_string("peer", peer.as_deref())
+        .optional_string("referer", header("Referer"))
+        .optional_string("user_agent", header("User-Agent"))
+        .finish()
+}
+
+/// Writes access log lines to a file, or to standard output.
+pub struct AccessLog {
+    format: Format,
+    out: Mutex<Output>,
+}
+
+impl AccessLog {
+    /// Append lines laid out as `format` to the file at `path`, which is created if
+    /// it does not exist and rotated as `rotation` says. A `path` of `-` writes them
+    /// to standard output.
+    pub fn open(path: &Path, format: Format, rotation: Rotation) -> io::Result<AccessLog> {
+        let out = if path == Path::new("-") {
+            Output::Stream(Box::new(io::stdout()))
+        } else {
+            Output::File(LogFile::open(path, rotation)?)
+        };
+        Ok(AccessLog {
+            format,
+            out: Mutex::new(out),
+        })
+    }
+
+    /// Open the file again, for when it has been moved to be rotated by another
+    /// program.
+    pub fn reopen(&self) -> io::Result<()> {
+        self.out.lock().unwrap().reopen()
+    }
+
+    /// Write the line for `exchange`, with a client at `peer`.
+    pub fn write(&self, peer: Option<IpAddr>, exchange: &Exchange) -> io::Result<()> {
+        let mut line = line(self.format, peer, exchange);
+        line.push('\n');
+        // At once, so a line is not split when the file rotates.
+        self.out.lock().unwrap().write_all(line.as_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn writes_combined_lines() {
+        let request = Request::read_from(
+            &mut &b"GET /a?b HTTP/1.1\r\nReferer: https://example.com/\r\n\
+                    User-Agent: say \"hi\" \\o/\r\n\r\n"[..],
+        )
+        .unwrap();
+        let exchange = Exchange {
+            request: Some(request),
+            request_id: Some(String::from("a1")),
+            status: Status::Ok,
+            body_bytes: 0,
+            started: SystemTime::UNIX_EPOCH,
+            duration: Duration::from_micros(12_500),
+        };
+
+        assert_eq!(
+            line(Format::Combined, Some("::1".parse().unwrap()), &exchange),
+            "::1 - - [01/Jan/1970:00:00:00 +0000] \"GET /a?b HTTP/1.1\" 200 - \
+             \"https://example.com/\" \"say \\\"hi\\\" \\\\o/\" 12 \"a1\""
+        );
+    }
+
+    #[test]
+    fn writes_json_lines() {
+        let exchange = Exchange {
+            request: None,
+            request_id: None,
+            status: Status::BadRequest,
+            body_bytes: 15,
+            started: SystemTime::UNIX_EPOCH,
+            duration: Duration::from_micros(1500),
+        };
+
+        assert_eq!(
+            line(Format::Json, None, &exchange),
+            "{\"ts\":\"1970-01-01T00:00:00.000Z\",\"level\":\"info\",\"request_id\":null,\
+             \"method\":null,\"path\":null,\"status\":400,\"bytes\":15,\"duration_ms\":1.5,\
+             \"peer\":null,\"referer\":null,\"user_agent\":null}"
+        );
+    }
+}
@@ -0,0 +1,350 @@
+//! Allowing and denying clients by address, with rules such as `deny 10.0.0.0/8` and
+//! `allow 192.168.1.0/24`.
+//!
+//! Rules apply to every path, or to those under a prefix such as `/admin`. A request
+//! is held to the rules of the longest prefix it is under first, then to those of
+//! shorter ones, then to those for every path, and the first rule that matches its
+//! client decides. A request that no rule matches is allowed, so a list that only
+//! lets some clients in ends with `deny all`.
+//! ```
+//! use ch20_web_server::acl::{Acl, Rule};
+//!
+//! let rules = |rules: &[&str]| -> Vec<Rule> {
+//!     rules.iter().map(|rule| rule.parse().unwrap()).collect()
+//! };
+//! let acl = Acl::new()
+//!     .rules(rules(&["deny 10.0.0.0/8"]))
+//!     .prefix("/admin", rules(&["allow 192.168.1.0/24", "deny all"]));
+//!
+//! let allowed = |path, ip: &str| acl.decide(path, Some(ip.parse().unwrap())).allowed;
+//! assert!(!allowed("/", "10.1.2.3"));
+//! assert!(allowed("/", "203.0.113.9"));
+//! assert!(allowed("/admin/users", "192.168.1.20"));
+//! assert!(!allowed("/admin/users", "203.0.113.9"));
+//! ```
+
+use std::{fmt, net::IpAddr, str::FromStr};
+
+use crate::{
+    request::Request,
+    response::{Response, Status},
+    router,
+};
+
+/// A block of addresses, such as `10.0.0.0/8`, or a single address.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cidr {
+    network: IpAddr,
+    /// How many of the leading bits of an address have to be the network's.
+    bits: u8,
+}
+
+impl Cidr {
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.network, ip) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => {
+                same_prefix(network.to_bits().into(), ip.to_bits().into(), 32, self.bits)
+            }
+            (IpAddr::V6(network), IpAddr::V6(ip)) => {
+                same_prefix(network.to_bits(), ip.to_bits(), 128, self.bits)
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Whether the leading `bits` of the `width`-bit numbers `a` and `b` are the same.
+fn same_prefix(a: u128, b: u128, width: u8, bits: u8) -> bool {
+    bits == 0 || (a ^ b) >> (width - bits) == 0
+}
+
+impl FromStr for Cidr {
+    type Err = ();
+
+    fn from_str(cidr: &str) -> Result<Cidr, ()> {
+        let (network, bits) = match cidr.split_once('/') {
+            Some((network, bits)) => (network, Some(bits)),
+            None => (cidr, None),
+        };
+        let network: IpAddr = network.parse().map_err(drop)?;
+        let width = if network.is_ipv4() { 32 } else { 128 };
+        let bits = match bits {
+            Some(bits) if bits.bytes().all(|byte| byte.is_ascii_digit()) => {
+                bits.parse().map_err(drop)?
+            }
+            Some(_) => return Err(()),
+            None => width,
+        };
+        if bits > width {
+            return Err(());
+        }
+        Ok(Cidr { network, bits })
+    }
+}
+
+impl fmt::Display for Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}", self.network, self.bits)
+    }
+}
+
+/// Allows or denies the clients in a block of addresses, or all of them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rule {
+    allow: bool,
+    /// `None` for every client.
+    clients: Option<Cidr>,
+}
+
+impl Rule {
+    pub fn allows(&self) -> bool {
+        self.allow
+    }
+
+    /// Whether the rule is for `client`.
+    pub fn matches(&self, client: IpAddr) -> bool {
+        self.clients.is_none_or(|clients| clients.contains(client))
+    }
+}
+
+impl FromStr for Rule {
+    type Err = ();
+
+    /// Parse `allow` or `deny`, then a block of addresses, an address, or `all`.
+    fn from_str(rule: &str) -> Result<Rule, ()> {
+        let (action, clients) = rule.trim().split_once(char::is_whitespace).ok_or(())?;
+        let allow = match action {
+            "allow" => true,
+            "deny" => false,
+            _ => return Err(()),
+        };
+        let clients = match clients.trim() {
+            "all" => None,
+            clients => Some(clients.parse()?),
+        };
+        Ok(Rule { allow, clients })
+    }
+}
+
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(if self.allow { "allow " } else { "deny " })?;
+        match &self.clients {
+            Some(clients) => clients.fmt(f),
+            None => f.write_str("all"),
+        }
+    }
+}
+
+/// The rules for every path, and for the paths under each prefix.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Acl {
+    /// The rules for every path, which are held to last.
+    rules: Vec<Rule>,
+    /// Each prefix with its segments and rules, longest first.
+    prefixes: Vec<(String, Vec<String>, Vec<Rule>)>,
+}
+
+/// What the rules say about a request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Decision<'a> {
+    pub allowed: bool,
+    /// The rule that decided, or `None` if no rule matched.
+    pub rule: Option<&'a Rule>,
+    /// The prefix the rule is for, or `None` if it is for every path.
+    pub prefix: Option<&'a str>,
+}
+
+impl Acl {
+    pub fn new() -> Acl {
+        Acl::default()
+    }
+
+    /// Add `rules` for every path.
+    pub fn rules(mut self, rules: impl IntoIterator<Item = Rule>) -> Acl {
+        self.rules.extend(rules);
+        self
+    }
+
+    /// Add `rules` for `prefix` and the paths under it, so `/admin` has them for
+    /// `/admin/users` but not for `/administrator`.
+    pub fn prefix(mut self, prefix: &str, rules: impl IntoIterator<Item = Rule>) -> Acl {
+        let path = prefix.trim_end_matches('/');
+        let segments = router::decode_path(path).unwrap_or_default();
+        match self
+            .prefixes
+            .iter_mut()
+            .find(|(_, existing, _)| *existing == segments)
+        {
+            Some((_, _, existing)) => existing.extend(rules),
+            None => {
+                let rules = rules.into_iter().collect();
+                self.prefixes.push((prefix.to_owned(), segments, rules));
+                self.prefixes
+                    .sort_by_key(|(_, segments, _)| std::cmp::Reverse(segments.len()));
+            }
+        }
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty() && self.prefixes.is_empty()
+    }
+
+    /// Decide whether `client` may request `path`. Requests without a client
+    /// address, such as those over a Unix socket, are allowed.
+    pub fn decide(&self, path: &str, client: Option<IpAddr>) -> Decision<'_> {
+        let undecided = Decision {
+            allowed: true,
+            rule: None,
+            prefix: None,
+        };
+        let Some(client) = client else {
+            return undecided;
+        };
+        // A path that cannot be decoded is held to the rules for every path.
+        let segments = router::decode_path(path).unwrap_or_default();
+        let scopes = self
+            .prefixes
+            .iter()
+            .filter(|(_, prefix, _)| segments.starts_with(prefix))
+            .map(|(prefix, _, rules)| (Some(prefix.as_str()), rules))
+            .chain([(None, &self.rules)]);
+        for (prefix, rules) in scopes {
+            if let Some(rule) = rules.iter().find(|rule| rule.matches(client)) {
+                return Decision {
+                    allowed: rule.allows(),
+                    rule: Some(rule),
+                    prefix,
+                };
+            }
+        }
+        undecided
+    }
+}
+
+/// Middleware that answers requests `acl` denies with `403 Forbidden`, giving `log`
+/// each request a rule decided, and what it decided.
+pub fn enforce<L>(
+    acl: Acl,
+    log: L,
+) -> impl Fn(&Request, &dyn Fn(&Request) -> Response) -> Response + Send + Sync + 'static
+where
+    L: Fn(&Request, &Decision) + Send + Sync + 'static,
+{
+    move |request, next| {
+        let decision = acl.decide(request.path(), request.peer());
+        if decision.rule.is_some() {
+            log(request, &decision);
+        }
+        if decision.allowed {
+            next(request)
+        } else {
+            Response::error(Status::Forbidden)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::router::Router;
+    use std::sync::{Arc, Mutex};
+
+    fn cidr(cidr: &str) -> Cidr {
+        cidr.parse().unwrap()
+    }
+
+    fn ip(ip: &str) -> IpAddr {
+        ip.parse().unwrap()
+    }
+
+    #[test]
+    fn matches_blocks_of_addresses() {
+        assert!(cidr("10.0.0.0/8").contains(ip("10.255.0.1")));
+        assert!(!cidr("10.0.0.0/8").contains(ip("11.0.0.1")));
+        assert!(cidr("192.168.1.7").contains(ip("192.168.1.7")));
+        assert!(!cidr("192.168.1.7").contains(ip("192.168.1.8")));
+        assert!(cidr("0.0.0.0/0").contains(ip("203.0.113.9")));
+        assert!(cidr("2001:db8::/32").contains(ip("2001:db8:1::1")));
+        assert!(!cidr("2001:db8::/32").contains(ip("2001:db9::1")));
+        assert!(!cidr("0.0.0.0/0").contains(ip("::1")));
+
+        for invalid in ["10.0.0.0/33", "10.0.0.0/", "10.0.0.0/+8", "ten/8", "::/129"] {
+            assert_eq!(invalid.parse::<Cidr>(), Err(()), "{invalid}");
+        }
+        assert_eq!(cidr("10.0.0.0/8").to_string(), "10.0.0.0/8");
+    }
+
+    #[test]
+    fn parses_rules() {
+        let rule: Rule = "deny  10.0.0.0/8".parse().unwrap();
+        assert!(!rule.allows());
+        assert_eq!(rule.to_string(), "deny 10.0.0.0/8");
+        assert_eq!(
+            "allow all".parse::<Rule>().unwrap().to_string(),
+            "allow all"
+        );
+        for invalid in ["allow", "permit 10.0.0.0/8", "deny localhost"] {
+            assert_eq!(invalid.parse::<Rule>(), Err(()), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn longer_prefixes_decide_first() {
+        let rule = |rule: &str| rule.parse::<Rule>().unwrap();
+        let acl = Acl::new()
+            .rules([rule("deny 10.0.0.0/8")])
+            .prefix("/admin", [rule("allow 10.1.0.0/16"), rule("deny all")])
+            .prefix("/admin/public/", [rule("allow all")]);
+
+        let decision = acl.decide("/admin/users", Some(ip("10.1.2.3")));
+        assert!(decision.allowed);
+        assert_eq!(decision.prefix, Some("/admin"));
+        assert!(!acl.decide("/admin", Some(ip("203.0.113.9"))).allowed);
+        assert!(
+            acl.decide("/%61dmin/public/a", Some(ip("10.2.0.1")))
+                .allowed
+        );
+        assert!(!acl.decide("/%61dmin", Some(ip("10.2.0.1"))).allowed);
+        assert!(!acl.decide("/administrator", Some(ip("10.1.2.3"))).allowed);
+
+        let undecided = acl.decide("/index.html", Some(ip("203.0.113.9")));
+        assert!(undecided.allowed);
+        assert_eq!(undecided.rule, None);
+        assert!(acl.decide("/admin", None).allowed);
+    }
+
+    #[test]
+    fn forbids_denied_requests_and_logs_them() {
+        let logged = Arc::new(Mutex::new(Vec::new()));
+        let acl = Acl::new().rules(["deny 192.0.2.0/24".parse().unwrap()]);
+        let router = Router::new()
+            .get("/", |_| Response::new(Status::Ok))
+            .wrap(enforce(acl, {
+                let logged = Arc::clone(&logged);
+                move |request, decision| {
+                    let rule = decision.rule.unwrap().to_string();
+                    logged.lock().unwrap().push((request.peer(), rule));
+                }
+            }));
+        let request = |peer: &str| {
+            let mut request = Request::read_from(&mut &b"GET / HTTP/1.1\r\n\r\n"[..]).unwrap();
+            request.set_peer(Some(ip(peer)));
+            request
+        };
+
+        assert_eq!(
+            router.dispatch(request("192.0.2.1")).status(),
+            Status::Forbidden
+        );
+        assert_eq!(
+            router.dispatch(request("198.51.100.1")).status(),
+            Status::Ok
+        );
+        assert_eq!(
+            *logged.lock().unwrap(),
+            [(Some(ip("192.0.2.1")), String::from("deny 192.0.2.0/24"))]
+        );
+    }
+}
@@ -0,0 +1,253 @@
+//! An API for looking at and changing a running server without restarting it,
+//! served on a listener of its own that should only be reachable from the host.
+//!
+//! - `GET /status` gives the log level, the open connections, and what each pool
+//!   and its workers are doing, as JSON.
+//! - `PUT /log-level` sets the log level to the one in the body, such as `debug`.
+//! - `PUT /threads` resizes the pools to the number of workers in the body.
+//! - `POST /drain` stops the server taking connections, and it exits once those open
+//!   have finished, as on SIGTERM.
+//!
+//! If there is a token, every request has to carry it as `Authorization: Bearer`.
+
+use std::sync::Arc;
+
+use threadpool::PoolSnapshot;
+
+use crate::{
+    config::LogLevel,
+    json::{self, Object},
+    password,
+    request::{Method, Request},
+    response::{Response, Status},
+    router::Router,
+};
+
+/// What the admin API acts on, which the server provides.
+pub trait Controls: Send + Sync {
+    fn log_level(&self) -> LogLevel;
+
+    /// Log at `level` until the settings are reloaded.
+    fn set_log_level(&self, level: LogLevel);
+
+    /// Resize every pool to `threads` workers, failing with a reason if they cannot
+    /// be.
+    fn set_threads(&self, threads: usize) -> Result<(), String>;
+
+    /// The number of connections open.
+    fn connections(&self) -> usize;
+
+    /// Each pool, by name.
+    fn pools(&self) -> Vec<(String, PoolSnapshot)>;
+
+    /// Stop taking connections and exit once the open ones have finished, returning
+    /// `false` if the server is stopping already.
+    fn drain(&self) -> bool;
+}
+
+/// The router that serves the admin API with `controls`, requiring `token` if there
+/// is one.
+pub fn router(controls: Arc<dyn Controls>, token: Option<String>) -> Router {
+    let router = Router::new()
+        .get("/status", {
+            let controls = Arc::clone(&controls);
+            move |_| json(Status::Ok, status(controls.as_ref()))
+        })
+        .route(Method::Put, "/log-level", {
+            let controls = Arc::clone(&controls);
+            move |request| match body(request).parse() {
+                Ok(level) => {
+                    controls.set_log_level(level);
+                    Response::new(Status::NoContent)
+                }
+                Err(()) => message(
+                    Status::BadRequest,
+                    "the log level must be off, error, warn, info or debug",
+                ),
+            }
+        })
+        .route(Method::Put, "/threads", {
+            let controls = Arc::clone(&controls);
+            move |request| match body(request).parse() {
+                Ok(threads) if threads > 0 => match controls.set_threads(threads) {
+                    Ok(()) => Response::new(Status::NoContent),
+                    Err(reason) => message(Status::InternalServerError, &reason),
+                },
+                _ => message(Status::BadRequest, "the threads must be a positive number"),
+            }
+        })
+        .post("/drain", move |_| {
+            if controls.drain() {
+                message(Status::Accepted, "draining")
+            } else {
+                message(Status::Conflict, "the server is stopping already")
+            }
+        });
+
+    match token {
+        Some(token) => router.wrap(move |request, next| {
+            // The scheme is not case-sensitive.
+            let given = request.header("Authorization").and_then(|value| {
+                let (scheme, given) = value.trim().split_once(' ')?;
+                scheme.eq_ignore_ascii_case("Bearer").then(|| given.trim())
+            });
+            if given.is_some_and(|given| password::same(given.as_bytes(), token.as_bytes())) {
+                next(request)
+            } else {
+                message(Status::Unauthorized, "a valid token is required")
+                    .header("WWW-Authenticate", "Bearer")
+            }
+        }),
+        None => router,
+    }
+}
+
+fn body(request: &Request) -> &str {
+    std::str::from_utf8(request.body()).unwrap_or("").trim()
+}
+
+fn status(controls: &dyn Controls) -> String {
+    let pools = controls.pools().into_iter().map(|(name, pool)| {
+        let workers = pool.workers.iter().map(|worker| {
+            let job = worker.job.as_ref().map(|job| {
+                Object::new()
+                    .optional_string("label", job.label.as_deref())
+                    .number("running_ms", job.running_for.as_millis() as f64)
+                    .finish()
+            });
+            Object::new()
+                .number("id", worker.id as f64)
+                .number("uptime_ms", worker.uptime.as_millis() as f64)
+                .raw("job", job.as_deref().unwrap_or("null"))
+                .finish()
+        });
+        Object::new()
+            .string("name", &name)
+            .number("size", pool.size as f64)
+            .number("queued", pool.queued as f64)
+            .number("in_flight", pool.in_flight as f64)
+            .number("completed", pool.completed as f64)
+            .number("panicked", pool.panicked as f64)
+            .boolean("paused", pool.paused)
+            .boolean("closed", pool.closed)
+            .raw("workers", &json::array(workers))
+            .finish()
+    });
+    Object::new()
+        .string("log_level", controls.log_level().as_str())
+        .number("connections", controls.connections() as f64)
+        .raw("pools", &json::array(pools))
+        .finish()
+}
+
+fn json(status: Status, body: String) -> Response {
+    Response::new(status)
+        .header("Content-Type", "application/json")
+        .header("Cache-Control", "no-store")
+        .body(body)
+}
+
+/// A JSON answer of `{"message": ...}`.
+fn message(status: Status, message: &str) -> Response {
+    json(status, Object::new().string("message", message).finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Mutex,
+    };
+
+    #[derive(Default)]
+    struct Fake {
+        level: Mutex<Option<LogLevel>>,
+        threads: AtomicUsize,
+        stopping: AtomicBool,
+    }
+
+    impl Controls for Fake {
+        fn log_level(&self) -> LogLevel {
+            self.level.lock().unwrap().unwrap_or(LogLevel::Info)
+        }
+
+        fn set_log_level(&self, level: LogLevel) {
+            *self.level.lock().unwrap() = Some(level);
+        }
+
+        fn set_threads(&self, threads: usize) -> Result<(), String> {
+            self.threads.store(threads, Ordering::SeqCst);
+            Ok(())
+        }
+
+        fn connections(&self) -> usize {
+            3
+        }
+
+        fn pools(&self) -> Vec<(String, PoolSnapshot)> {
+            Vec::new()
+        }
+
+        fn drain(&self) -> bool {
+            !self.stopping.swap(true, Ordering::SeqCst)
+        }
+    }
+
+    fn send(router: &Router, head: &str, body: &str) -> Response {
+        let raw = format!("{head}\r\nContent-Length: {}\r\n\r\n{body}", body.len());
+        router.dispatch(Request::read_from(&mut raw.as_bytes()).unwrap())
+    }
+
+    #[test]
+    fn changes_the_server() {
+        let fake = Arc::new(Fake::default());
+        let router = router(Arc::clone(&fake) as Arc<dyn Controls>, None);
+
+        let response = send(&router, "PUT /log-level HTTP/1.1", "debug\n");
+        assert_eq!(response.status(), Status::NoContent);
+        assert_eq!(fake.log_level(), LogLevel::Debug);
+        let response = send(&router, "PUT /log-level HTTP/1.1", "loud");
+        assert_eq!(response.status(), Status::BadRequest);
+
+        assert_eq!(
+            send(&router, "PUT /threads HTTP/1.1", "32").status(),
+            Status::NoContent
+        );
+        assert_eq!(fake.threads.load(Ordering::SeqCst), 32);
+        assert_eq!(
+            send(&router, "PUT /threads HTTP/1.1", "0").status(),
+            Status::BadRequest
+        );
+
+        let status = send(&router, "GET /status HTTP/1.1", "");
+        assert_eq!(
+            status.body_bytes(),
+            br#"{"log_level":"debug","connections":3,"pools":[]}"#
+        );
+
+        assert_eq!(
+            send(&router, "POST /drain HTTP/1.1", "").status(),
+            Status::Accepted
+        );
+        assert_eq!(
+            send(&router, "POST /drain HTTP/1.1", "").status(),
+            Status::Conflict
+        );
+    }
+
+    #[test]
+    fn requires_the_token_if_there_is_one() {
+        let router = router(Arc::new(Fake::default()), Some(String::from("s3cret")));
+
+        let response = send(&router, "GET /status HTTP/1.1", "");
+        assert_eq!(response.status(), Status::Unauthorized);
+        assert_eq!(response.headers().get("WWW-Authenticate"), Some("Bearer"));
+        let wrong = "GET /status HTTP/1.1\r\nAuthorization: Bearer s3cre7";
+        assert_eq!(send(&router, wrong, "").status(), Status::Unauthorized);
+        let right = "GET /status HTTP/1.1\r\nAuthorization: Bearer s3cret";
+        assert_eq!(send(&router, right, "").status(), Status::Ok);
+        let lowercase = "GET /status HTTP/1.1\r\nAuthorization: bearer s3cret";
+        assert_eq!(send(&router, lowercase, "").status(), Status::Ok);
+    }
+}
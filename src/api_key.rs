@@ -0,0 +1,264 @@
+//! Authenticating requests by the key or token they carry, in `Authorization: Bearer`
+//! or in `X-Api-Key`, for the paths under chosen prefixes.
+//!
+//! Keys are looked up in a `KeyStore`, which gives the principal a key belongs to:
+//! a list of `Keys` built in code or read from a file with a `principal:key` line for
+//! each, or a closure that looks them up elsewhere. Handlers find the principal of
+//! the request they are answering with `Request::principal`.
+//! ```
+//! use ch20_web_server::api_key::{KeyStore, Keys};
+//!
+//! let keys = Keys::new().key("ci", "k3y-for-ci").key("ops", "k3y-for-ops");
+//! assert_eq!(keys.principal("k3y-for-ops").as_deref(), Some("ops"));
+//! assert_eq!(keys.principal("guess"), None);
+//!
+//! let lookup = |key: &str| (key == "env").then(|| String::from("deploy"));
+//! assert_eq!(lookup.principal("env").as_deref(), Some("deploy"));
+//! ```
+
+use std::{fmt, fs, io, path::Path, str::FromStr};
+
+use crate::{
+    password,
+    request::Request,
+    response::{Response, Status},
+    router,
+};
+
+/// Where the principal a key belongs to is looked up.
+pub trait KeyStore: Send + Sync {
+    /// The principal `key` belongs to, or `None` if it is not a key.
+    fn principal(&self, key: &str) -> Option<String>;
+}
+
+impl<F> KeyStore for F
+where
+    F: Fn(&str) -> Option<String> + Send + Sync,
+{
+    fn principal(&self, key: &str) -> Option<String> {
+        self(key)
+    }
+}
+
+/// A list of keys, each with the principal it belongs to. A principal may have more
+/// than one, so a key can be replaced without a time when neither works.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Keys {
+    /// Each principal and its key.
+    keys: Vec<(String, String)>,
+}
+
+impl Keys {
+    pub fn new() -> Keys {
+        Keys::default()
+    }
+
+    /// Add `key`, belonging to `principal`.
+    pub fn key(mut self, principal: &str, key: &str) -> Keys {
+        self.keys.push((principal.to_owned(), key.to_owned()));
+        self
+    }
+
+    /// Read the keys in the file at `path`.
+    pub fn load(path: &Path) -> Result<Keys, KeysError> {
+        fs::read_to_string(path).map_err(KeysError::Io)?.parse()
+    }
+
+    pub fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+}
+
+impl KeyStore for Keys {
+    /// Every key is compared, each in constant time, so how long it takes does not
+    /// tell how much of a key was right, or which of them it was.
+    fn principal(&self, key: &str) -> Option<String> {
+        let mut found = None;
+        for (principal, candidate) in &self.keys {
+            if password::same(candidate.as_bytes(), key.as_bytes()) && found.is_none() {
+                found = Some(principal);
+            }
+        }
+        found.cloned()
+    }
+}
+
+impl FromStr for Keys {
+    type Err = KeysError;
+
+    /// Read a `principal:key` line for each key, skipping blank lines and those
+    /// starting with `#`.
+    fn from_str(text: &str) -> Result<Keys, KeysError> {
+        let mut keys = Keys::new();
+        for (index, line) in text.lines().enumerate() {
+            let invalid = |message: &str| KeysError::Invalid {
+                line: index + 1,
+                message: message.to_owned(),
+            };
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (principal, key) = line
+                .split_once(':')
+                .filter(|(principal, key)| !principal.is_empty() && !key.is_empty())
+                .ok_or_else(|| invalid("must be a principal and a key, as principal:key"))?;
+            if keys.keys.iter().any(|(_, existing)| existing == key) {
+                return Err(invalid("has a key that is on an earlier line too"));
+            }
+            keys = keys.key(principal, key);
+        }
+        Ok(keys)
+    }
+}
+
+/// Why a file of keys could not be loaded.
+#[derive(Debug)]
+pub enum KeysError {
+    /// The file could not be read.
+    Io(io::Error),
+    /// A line is not a principal and a key.
+    Invalid { line: usize, message: String },
+}
+
+impl fmt::Display for KeysError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KeysError::Io(error) => error.fmt(f),
+            KeysError::Invalid { line, message } => write!(f, "line {line}: {message}"),
+        }
+    }
+}
+
+impl std::error::Error for KeysError {}
+
+/// The key a request carries, as `Authorization: Bearer`, whose scheme is not
+/// case-sensitive, or else as `X-Api-Key`.
+fn key(request: &Request) -> Option<&str> {
+    let bearer = request.header("Authorization").and_then(|value| {
+        let (scheme, token) = value.trim().split_once(' ')?;
+        scheme.eq_ignore_ascii_case("Bearer").then(|| token.trim())
+    });
+    bearer
+        .or_else(|| request.header("X-Api-Key").map(str::trim))
+        .filter(|key| !key.is_empty())
+}
+
+/// Middleware that answers requests under any of `prefixes` that carry no key of
+/// `store` with `401 Unauthorized`, and gives those that do the key's principal as
+/// their `principal`. A prefix of `/` is every path.
+pub fn require<S>(
+    store: S,
+    prefixes: &[&str],
+) -> impl Fn(&Request, &dyn Fn(&Request) -> Response) -> Response + Send + Sync + 'static
+where
+    S: KeyStore + 'static,
+{
+    let prefixes: Vec<Vec<String>> = prefixes
+        .iter()
+        .map(|prefix| router::decode_path(prefix.trim_end_matches('/')).unwrap_or_default())
+        .collect();
+    move |request, next| {
+        // A path that cannot be decoded is served nothing, so it needs no key.
+        let segments = router::decode_path(request.path()).unwrap_or_default();
+        if !prefixes.iter().any(|prefix| segments.starts_with(prefix)) {
+            return next(request);
+        }
+        let Some(key) = key(request) else {
+            return Response::error(Status::Unauthorized).header("WWW-Authenticate", "Bearer");
+        };
+        match store.principal(key) {
+            Some(principal) => {
+                let mut request = request.clone();
+                request.set_principal(principal);
+                next(&request)
+            }
+            None => Response::error(Status::Unauthorized)
+                .header("WWW-Authenticate", "Bearer error=\"invalid_token\""),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::router::Router;
+
+    #[test]
+    fn reads_files_of_keys() {
+        let keys: Keys = "# Deploys\nci:0123abcd\n\nci:4567efgh\nops:a:b\n"
+            .parse()
+            .unwrap();
+        assert_eq!(keys.len(), 3);
+        assert_eq!(keys.principal("4567efgh").as_deref(), Some("ci"));
+        assert_eq!(keys.principal("a:b").as_deref(), Some("ops"));
+        assert_eq!(keys.principal("0123abc"), None);
+
+        let error = |text: &str| text.parse::<Keys>().unwrap_err().to_string();
+        assert_eq!(
+            error("0123abcd"),
+            "line 1: must be a principal and a key, as principal:key"
+        );
+        assert_eq!(
+            error("ci:"),
+            "line 1: must be a principal and a key, as principal:key"
+        );
+        assert_eq!(
+            error("ci:0123abcd\nops:0123abcd"),
+            "line 2: has a key that is on an earlier line too"
+        );
+    }
+
+    #[test]
+    fn authenticates_requests_under_the_prefixes() {
+        let router = Router::new()
+            .get("/*path", |request| {
+                Response::new(Status::Ok).body(request.principal().unwrap_or_default().to_owned())
+            })
+            .wrap(require(Keys::new().key("ci", "s3cret"), &["/api/"]));
+        let request = |path: &str, header: &str| {
+            let text = format!("GET {path} HTTP/1.1\r\n{header}\r\n");
+            Request::read_from(&mut text.as_bytes()).unwrap()
+        };
+        let body = |response: Response| {
+            assert_eq!(response.status(), Status::Ok);
+            String::from_utf8(response.body_bytes().to_vec()).unwrap()
+        };
+
+        let bearer = "Authorization: bearer s3cret\r\n";
+        assert_eq!(body(router.dispatch(request("/api/builds", bearer))), "ci");
+        let header = "X-Api-Key: s3cret\r\n";
+        assert_eq!(body(router.dispatch(request("/%61pi", header))), "ci");
+        assert_eq!(body(router.dispatch(request("/apiary", ""))), "");
+
+        let missing = router.dispatch(request("/api", "Authorization: Basic Y2k6czNjcmV0\r\n"));
+        assert_eq!(missing.status(), Status::Unauthorized);
+        assert_eq!(missing.headers().get("WWW-Authenticate"), Some("Bearer"));
+        let wrong = router.dispatch(request("/api", "Authorization: Bearer guess\r\n"));
+        assert_eq!(wrong.status(), Status::Unauthorized);
+        assert_eq!(
+            wrong.headers().get("WWW-Authenticate"),
+            Some("Bearer error=\"invalid_token\"")
+        );
+    }
+
+    #[test]
+    fn looks_keys_up_with_a_closure() {
+        let router = Router::new()
+            .get("/", |_| Response::new(Status::Ok))
+            .wrap(require(
+                |key: &str| key.strip_prefix("user-").map(str::to_owned),
+                &["/"],
+            ));
+        let request = |key: &str| {
+            let text = format!("GET / HTTP/1.1\r\nX-Api-Key: {key}\r\n\r\n");
+            Request::read_from(&mut text.as_bytes()).unwrap()
+        };
+        assert_eq!(router.dispatch(request("user-7")).status(), Status::Ok);
+        assert_eq!(router.dispatch(request("7")).status(), Status::Unauthorized);
+    }
+}
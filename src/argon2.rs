@@ -0,0 +1,383 @@
+//! Argon2 (RFC 9106), the memory-hard password hash, in the PHC string format that
+//! tools such as `argon2` and libsodium write: `$argon2id$v=19$m=65536,t=3,p=4$`, then
+//! the salt and the hash in base64 without padding.
+//!
+//! The tests check hashes from the reference implementation for each variant, and
+//! sizes that reach its less common paths.
+
+use crate::{
+    base64,
+    blake2b::{self, Blake2b},
+    password,
+};
+
+/// The version of Argon2 hashes are checked with, which is 1.3.
+const VERSION: u32 = 0x13;
+/// The most memory a hash may ask for, in KiB, so that a hash in a file cannot make the
+/// server allocate without bound.
+const MAX_MEMORY: u32 = 1 << 20;
+
+/// A kibibyte of memory, as the words Argon2 works on.
+type Block = [u64; 128];
+
+/// How the blocks each block is made from are chosen: from the data in memory, from a
+/// sequence that depends only on the parameters, or the second for the first half of
+/// the first pass and the first after that.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Variant {
+    D = 0,
+    I = 1,
+    Id = 2,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Params {
+    variant: Variant,
+    /// KiB, of which at least 8 for each lane.
+    memory: u32,
+    passes: u32,
+    lanes: u32,
+}
+
+/// An Argon2 hash, read from its PHC string.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Hash {
+    params: Params,
+    salt: Vec<u8>,
+    digest: Vec<u8>,
+}
+
+impl Hash {
+    /// Read `hash`, or return `None` if it is not an Argon2 hash of version 1.3, or
+    /// asks for more than a GiB of memory.
+    pub(crate) fn parse(hash: &str) -> Option<Hash> {
+        parse(hash)
+    }
+
+    /// Whether `password` is the one the hash was made from.
+    pub(crate) fn verify(&self, password: &[u8]) -> bool {
+        let digest = digest(password, &self.salt, self.digest.len(), self.params);
+        password::same(&digest, &self.digest)
+    }
+}
+
+fn parse(hash: &str) -> Option<Hash> {
+    let mut fields = hash.strip_prefix('$')?.split('$');
+    let variant = match fields.next()? {
+        "argon2d" => Variant::D,
+        "argon2i" => Variant::I,
+        "argon2id" => Variant::Id,
+        _ => return None,
+    };
+    if fields.next()? != "v=19" {
+        return None;
+    }
+    let (mut memory, mut passes, mut lanes) = (None, None, None);
+    for param in fields.next()?.split(',') {
+        let (name, value) = param.split_once('=')?;
+        let value = Some(value.parse().ok()?);
+        match name {
+            "m" => memory = value,
+            "t" => passes = value,
+            "p" => lanes = value,
+            _ => return None,
+        }
+    }
+    let salt = base64::decode(base64::STANDARD, fields.next()?)?;
+    let digest = base64::decode(base64::STANDARD, fields.next()?)?;
+    if fields.next().is_some() {
+        return None;
+    }
+
+    let params = Params {
+        variant,
+        memory: memory?,
+        passes: passes?,
+        lanes: lanes?,
+    };
+    let valid = (1..=0xff_ffff).contains(&params.lanes)
+        && params.passes >= 1
+        && (8 * params.lanes..=MAX_MEMORY).contains(&params.memory)
+        && salt.len() >= 8
+        && digest.len() >= 4;
+    valid.then_some(Hash {
+        params,
+        salt,
+        digest,
+    })
+}
+
+/// The hash of `length` bytes of `password` with `salt`.
+fn digest(password: &[u8], salt: &[u8], length: usize, params: Params) -> Vec<u8> {
+    let mut h0 = Blake2b::new(64);
+    for value in [
+        params.lanes,
+        length as u32,
+        params.memory,
+        params.passes,
+        VERSION,
+        params.variant as u32,
+    ] {
+        h0.update(&value.to_le_bytes());
+    }
+    // The secret key and the associated data, which PHC strings do not have.
+    for data in [password, salt, &[], &[]] {
+        h0.update(&(data.len() as u32).to_le_bytes()).update(data);
+    }
+    let h0 = h0.finish();
+
+    // Memory is rounded down to a whole number of blocks for each slice of each lane.
+    let lanes = params.lanes as usize;
+    let lane_length = (params.memory / (4 * params.lanes) * 4) as usize;
+    let mut memory = vec![[0; 128]; lanes * lane_length];
+    for lane in 0..lanes {
+        for column in 0..2 {
+            let mut input = h0.clone();
+            input.extend_from_slice(&(column as u32).to_le_bytes());
+            input.extend_from_slice(&(lane as u32).to_le_bytes());
+            memory[lane * lane_length + column] = block(&long_hash(1024, &input));
+        }
+    }
+    // The lanes of a slice could be filled at once, but a password check is not worth
+    // the threads.
+    for pass in 0..params.passes as usize {
+        for slice in 0..4 {
+            for lane in 0..lanes {
+                let segment = Segment { pass, slice, lane };
+                fill_segment(&mut memory, lane_length, params, segment);
+            }
+        }
+    }
+
+    let mut last = memory[lane_length - 1];
+    for lane in 1..lanes {
+        for (word, other) in last.iter_mut().zip(&memory[(lane + 1) * lane_length - 1]) {
+            *word ^= other;
+        }
+    }
+    let bytes: Vec<u8> = last.iter().flat_map(|word| word.to_le_bytes()).collect();
+    long_hash(length, &bytes)
+}
+
+/// A quarter of a lane, in one pass over memory.
+#[derive(Clone, Copy)]
+struct Segment {
+    pass: usize,
+    slice: usize,
+    lane: usize,
+}
+
+fn fill_segment(memory: &mut [Block], lane_length: usize, params: Params, segment: Segment) {
+    let Segment { pass, slice, lane } = segment;
+    let segment_length = lane_length / 4;
+    let independent =
+        params.variant == Variant::I || (params.variant == Variant::Id && pass == 0 && slice < 2);
+    let (mut input, mut addresses) = ([0; 128], [0; 128]);
+    if independent {
+        input[..6].copy_from_slice(&[
+            pass as u64,
+            lane as u64,
+            slice as u64,
+            memory.len() as u64,
+            u64::from(params.passes),
+            params.variant as u64,
+        ]);
+    }
+    // The first two blocks of each lane are made from the password.
+    let start = if pass == 0 && slice == 0 { 2 } else { 0 };
+    if independent && start != 0 {
+        next_addresses(&mut input, &mut addresses);
+    }
+
+    for index in start..segment_length {
+        let column = slice * segment_length + index;
+        let current = lane * lane_length + column;
+        let previous = if column == 0 {
+            current + lane_length - 1
+        } else {
+            current - 1
+        };
+        let random = if independent {
+            if index % 128 == 0 {
+                next_addresses(&mut input, &mut addresses);
+            }
+            addresses[index % 128]
+        } else {
+            memory[previous][0]
+        };
+
+        let reference_lane = if pass == 0 && slice == 0 {
+            lane
+        } else {
+            (random >> 32) as usize % params.lanes as usize
+        };
+        // The blocks the reference may be taken from: those finished in this lane,
+        // or in the others, those finished before this slice.
+        let same_lane = reference_lane == lane;
+        let finished = if pass == 0 {
+            slice * segment_length
+        } else {
+            lane_length - segment_length
+        };
+        let area = match (same_lane, index) {
+            (true, _) => finished + index - 1,
+            (false, 0) => finished - 1,
+            (false, _) => finished,
+        };
+        let x = (random & 0xffff_ffff).pow(2) >> 32;
+        let relative = area - 1 - ((area as u64 * x) >> 32) as usize;
+        let first = if pass == 0 || slice == 3 {
+            0
+        } else {
+            (slice + 1) * segment_length
+        };
+        let reference = reference_lane * lane_length + (first + relative) % lane_length;
+
+        let (previous, reference) = (memory[previous], memory[reference]);
+        compress(&previous, &reference, &mut memory[current], pass > 0);
+    }
+}
+
+/// Make the next 128 pseudo-random words for the data-independent variants.
+fn next_addresses(input: &mut Block, addresses: &mut Block) {
+    input[6] += 1;
+    let mut once = [0; 128];
+    compress(&[0; 128], input, &mut once, false);
+    compress(&[0; 128], &once, addresses, false);
+}
+
+/// Argon2's compression function of `x` and `y`, written to `out`, or XORed into it
+/// after the first pass.
+fn compress(x: &Block, y: &Block, out: &mut Block, xor: bool) {
+    let mut r = [0; 128];
+    for (r, (x, y)) in r.iter_mut().zip(x.iter().zip(y)) {
+        *r = x ^ y;
+    }
+    let mut q = r;
+    for row in 0..8 {
+        permute(&mut q, std::array::from_fn(|i| 16 * row + i));
+    }
+    for column in 0..8 {
+        permute(
+            &mut q,
+            std::array::from_fn(|i| 2 * column + i % 2 + i / 2 * 16),
+        );
+    }
+    for (out, (r, q)) in out.iter_mut().zip(r.iter().zip(&q)) {
+        *out = if xor { *out ^ r ^ q } else { r ^ q };
+    }
+}
+
+/// BLAKE2b's round, on the words of `v` at `at`, with multiplications to make it
+/// costlier to compute in hardware.
+fn permute(v: &mut Block, at: [usize; 16]) {
+    for [a, b, c, d] in [
+        [0, 4, 8, 12],
+        [1, 5, 9, 13],
+        [2, 6, 10, 14],
+        [3, 7, 11, 15],
+        [0, 5, 10, 15],
+        [1, 6, 11, 12],
+        [2, 7, 8, 13],
+        [3, 4, 9, 14],
+    ] {
+        let [a, b, c, d] = [at[a], at[b], at[c], at[d]];
+        let add = |x: u64, y: u64| {
+            let low = u64::from(x as u32) * u64::from(y as u32);
+            x.wrapping_add(y).wrapping_add(low.wrapping_mul(2))
+        };
+        v[a] = add(v[a], v[b]);
+        v[d] = (v[d] ^ v[a]).rotate_right(32);
+        v[c] = add(v[c], v[d]);
+        v[b] = (v[b] ^ v[c]).rotate_right(24);
+        v[a] = add(v[a], v[b]);
+        v[d] = (v[d] ^ v[a]).rotate_right(16);
+        v[c] = add(v[c], v[d]);
+        v[b] = (v[b] ^ v[c]).rotate_right(63);
+    }
+}
+
+/// Argon2's hash of `input` that can be longer than BLAKE2b's, made of the first
+/// halves of a chain of hashes.
+fn long_hash(length: usize, input: &[u8]) -> Vec<u8> {
+    let mut hash = Blake2b::new(length.min(64));
+    hash.update(&(length as u32).to_le_bytes()).update(input);
+    let mut v = hash.finish();
+    if length <= 64 {
+        return v;
+    }
+
+    let halves = length.div_ceil(32) - 2;
+    let mut out = Vec::with_capacity(length);
+    for _ in 1..halves {
+        out.extend_from_slice(&v[..32]);
+        v = blake2b::hash(64, &v);
+    }
+    out.extend_from_slice(&v[..32]);
+    out.extend_from_slice(&blake2b::hash(length - 32 * halves, &v));
+    out
+}
+
+fn block(bytes: &[u8]) -> Block {
+    let mut block = [0; 128];
+    for (word, bytes) in block.iter_mut().zip(bytes.chunks_exact(8)) {
+        *word = u64::from_le_bytes(bytes.try_into().unwrap());
+    }
+    block
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn verify(password: &[u8], hash: &str) -> Option<bool> {
+        Hash::parse(hash).map(|hash| hash.verify(password))
+    }
+
+    #[test]
+    fn verifies_hashes_of_each_variant() {
+        let hashes = [
+            (
+                "password",
+                "$argon2id$v=19$m=64,t=2,p=1$c29tZXNhbHQ$FqGkmHNGCd0BRW2kBt6fPZ2pPmyGwwChL8FGUhTOSSI",
+            ),
+            (
+                "password",
+                "$argon2id$v=19$m=300,t=1,p=2$MDEyMzQ1Njc4OWFiY2RlZg$zuL0++cR/SNqq/tS0lNoQF/ISIMbayane1dfyBU7TtQ",
+            ),
+            (
+                "password",
+                "$argon2i$v=19$m=32,t=3,p=2$c2FsdHNhbHRzYWx0$CW7u+GRg/SI8yhDQl7jNhw",
+            ),
+            // Enough blocks in a segment to need a second run of addresses.
+            (
+                "",
+                "$argon2i$v=19$m=1024,t=2,p=1$c2FsdHNhbHRzYWx0$6LWJk3Xh2EwZDv04OeP6N3YBy6ar61xRGU4h7RDYTTI",
+            ),
+            // Memory that is not a multiple of four blocks a lane is rounded down.
+            (
+                "password",
+                "$argon2d$v=19$m=40,t=1,p=4$c2FsdHNhbHRzYWx0$08qotct4ZbqWuViahrgttfGxOWNj7ZpS",
+            ),
+        ];
+        for (password, hash) in hashes {
+            assert_eq!(verify(password.as_bytes(), hash), Some(true), "{hash}");
+            assert_eq!(verify(b"wrong", hash), Some(false), "{hash}");
+        }
+    }
+
+    #[test]
+    fn rejects_what_it_cannot_check() {
+        for hash in [
+            "$argon2x$v=19$m=64,t=2,p=1$c29tZXNhbHQ$FqGkmHNGCd0BRW2kBt6fPZ2pPmyGwwChL8FGUhTOSSI",
+            "$argon2id$m=64,t=2,p=1$c29tZXNhbHQ$FqGkmHNGCd0BRW2kBt6fPZ2pPmyGwwChL8FGUhTOSSI",
+            "$argon2id$v=19$m=4,t=2,p=1$c29tZXNhbHQ$FqGkmHNGCd0BRW2kBt6fPZ2pPmyGwwChL8FGUhTOSSI",
+            "$argon2id$v=19$m=4194304,t=1,p=1$c29tZXNhbHQ$FqGkmHNGCd0BRW2kBt6fPZ2pPmyGwwChL8FGUhTOSSI",
+            "$argon2id$v=19$m=64,t=0,p=1$c29tZXNhbHQ$FqGkmHNGCd0BRW2kBt6fPZ2pPmyGwwChL8FGUhTOSSI",
+            "$argon2id$v=19$m=64,t=2,p=1,x=1$c29tZXNhbHQ$FqGkmHNGCd0BRW2kBt6fPZ2pPmyGwwChL8FGUhTOSSI",
+            "$argon2id$v=19$m=64,t=2,p=1$c29tZXNhbHQ",
+        ] {
+            assert_eq!(verify(b"password", hash), None, "{hash}");
+        }
+    }
+}
@@ -0,0 +1,264 @@
+//! The command line of the server binary, whose options override the settings in
+//! its configuration file.
+
+use std::{fmt, net::IpAddr, path::PathBuf};
+
+use crate::config::{Config, LogLevel, UnixSocket};
+
+/// What `--help` prints.
+pub const USAGE: &str = "\
+Usage: ch20-web-server [OPTIONS] [ROOT]
+
+Serves the files in ROOT, or in the configured document root.
+
+Options:
+  -c, --config <PATH>      Read settings from PATH instead of ./server.toml
+  -b, --bind <ADDRESS>     Listen on ADDRESS, an IP address
+  -p, --port <PORT>        Listen on PORT
+  -u, --unix <PATH>        Listen on the Unix socket at PATH instead
+  -r, --root <DIR>         Serve the files in DIR
+  -t, --threads <COUNT>    Keep COUNT workers running
+  -l, --log-level <LEVEL>  Log at LEVEL: off, error, warn, info or debug
+  -h, --help               Print this help
+";
+
+/// What the command line asks the binary to do.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    Serve(Args),
+    Help,
+}
+
+/// The options given to serve with. Those left out are `None`, and leave the
+/// configured settings as they are.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Args {
+    pub config: Option<PathBuf>,
+    pub bind: Option<IpAddr>,
+    pub port: Option<u16>,
+    pub unix: Option<PathBuf>,
+    pub root: Option<PathBuf>,
+    pub threads: Option<usize>,
+    pub log_level: Option<LogLevel>,
+}
+
+impl Args {
+    /// Parse the arguments after the program name, given either as `--port 80` or
+    /// `--port=80`.
+    /// ```
+    /// use ch20_web_server::args::{Args, Command};
+    ///
+    /// let Ok(Command::Serve(args)) = Args::parse(["--port=8080", "-r", "www"]) else {
+    ///     panic!("not a valid command line");
+    /// };
+    /// assert_eq!(args.port, Some(8080));
+    /// ```
+    pub fn parse<I>(args: I) -> Result<Command, ArgsError>
+    where
+        I: IntoIterator,
+        I::Item: Into<String>,
+    {
+        let mut parsed = Args::default();
+        let mut args = args.into_iter().map(Into::into);
+
+        while let Some(arg) = args.next() {
+            let (name, inline) = match arg.split_once('=') {
+                Some((name, value)) if name.starts_with("--") => (name, Some(value.to_owned())),
+                _ => (&arg[..], None),
+            };
+            let option = match name {
+                "-h" | "--help" => return Ok(Command::Help),
+                "-c" | "--config" => "--config",
+                "-b" | "--bind" => "--bind",
+                "-p" | "--port" => "--port",
+                "-u" | "--unix" => "--unix",
+                "-r" | "--root" => "--root",
+                "-t" | "--threads" => "--threads",
+                "-l" | "--log-level" => "--log-level",
+                _ if name.starts_with('-') => return Err(ArgsError::UnknownOption(arg)),
+                // The document root may also be given on its own.
+                _ if parsed.root.is_none() => {
+                    parsed.root = Some(PathBuf::from(arg));
+                    continue;
+                }
+                _ => return Err(ArgsError::UnexpectedArgument(arg)),
+            };
+
+            let value = inline
+                .or_else(|| args.next())
+                .ok_or(ArgsError::MissingValue(option))?;
+            let invalid = |expected| ArgsError::InvalidValue {
+                option,
+                value: value.clone(),
+                expected,
+            };
+            match option {
+                "--config" => parsed.config = Some(PathBuf::from(&value)),
+                "--bind" => {
+                    parsed.bind = Some(value.parse().map_err(|_| invalid("an IP address"))?)
+                }
+                "--port" => {
+                    let port = value.parse().ok().filter(|&port| port != 0);
+                    parsed.port = Some(port.ok_or_else(|| invalid("a port from 1 to 65535"))?);
+                }
+                "--unix" => parsed.unix = Some(PathBuf::from(&value)),
+                "--root" => parsed.root = Some(PathBuf::from(&value)),
+                "--threads" => {
+                    let threads = value.parse().ok().filter(|&threads| threads != 0);
+                    parsed.threads = Some(threads.ok_or_else(|| invalid("at least 1"))?);
+                }
+                _ => {
+                    let level = value
+                        .parse()
+                        .map_err(|_| invalid("one of off, error, warn, info or debug"))?;
+                    parsed.log_level = Some(level);
+                }
+            }
+        }
+        Ok(Command::Serve(parsed))
+    }
+
+    /// Override the settings in `config` with the options that were given. The
+    /// pool's maximum size is raised to `--threads` if it is below it, and giving an
+    /// address to listen on replaces the configured `[[listen]]` tables.
+    pub fn apply(&self, config: &mut Config) {
+        if self.bind.is_some() || self.port.is_some() || self.unix.is_some() {
+            config.listen.clear();
+        }
+        if let Some(bind) = self.bind {
+            config.bind = bind;
+        }
+        if let Some(port) = self.port {
+            config.port = port;
+        }
+        if let Some(path) = &self.unix {
+            let mode = config.unix_socket.as_ref().and_then(|socket| socket.mode);
+            config.unix_socket = Some(UnixSocket {
+                path: path.clone(),
+                mode,
+            });
+        }
+        if let Some(root) = &self.root {
+            config.root = root.clone();
+        }
+        if let Some(threads) = self.threads {
+            config.threads = threads;
+            config.max_threads = config.max_threads.max(threads);
+        }
+        if let Some(level) = self.log_level {
+            config.log_level = level;
+        }
+    }
+}
+
+/// Why a command line could not be parsed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ArgsError {
+    UnknownOption(String),
+    /// An argument was given after the document root.
+    UnexpectedArgument(String),
+    /// The option was last, without its value.
+    MissingValue(&'static str),
+    InvalidValue {
+        option: &'static str,
+        value: String,
+        /// What the value has to be.
+        expected: &'static str,
+    },
+}
+
+impl fmt::Display for ArgsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ArgsError::UnknownOption(option) => write!(f, "Unknown option `{option}`"),
+            ArgsError::UnexpectedArgument(arg) => write!(f, "Unexpected argument `{arg}`"),
+            ArgsError::MissingValue(option) => write!(f, "`{option}` needs a value"),
+            ArgsError::InvalidValue {
+                option,
+                value,
+                expected,
+            } => write!(
+                f,
+                "Invalid value `{value}` for `{option}`: must be {expected}"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ArgsError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Command, ArgsError> {
+        Args::parse(args.iter().copied())
+    }
+
+    #[test]
+    fn parses_every_option() {
+        assert_eq!(
+            parse(&[
+                "--config",
+                "/etc/web.toml",
+                "-b",
+                "0.0.0.0",
+                "--port=80",
+                "-u",
+                "web.sock",
+                "--threads",
+                "4",
+                "-l",
+                "debug",
+                "www",
+            ]),
+            Ok(Command::Serve(Args {
+                config: Some(PathBuf::from("/etc/web.toml")),
+                bind: Some("0.0.0.0".parse().unwrap()),
+                port: Some(80),
+                unix: Some(PathBuf::from("web.sock")),
+                root: Some(PathBuf::from("www")),
+                threads: Some(4),
+                log_level: Some(LogLevel::Debug),
+            }))
+        );
+        assert_eq!(parse(&["--port", "80", "--help"]), Ok(Command::Help));
+        assert_eq!(parse(&[]), Ok(Command::Serve(Args::default())));
+    }
+
+    #[test]
+    fn rejects_bad_command_lines() {
+        let error = |args| parse(args).unwrap_err().to_string();
+
+        assert_eq!(error(&["--verbose"]), "Unknown option `--verbose`");
+        assert_eq!(error(&["www", "more"]), "Unexpected argument `more`");
+        assert_eq!(error(&["-p"]), "`--port` needs a value");
+        assert_eq!(
+            error(&["--port=0"]),
+            "Invalid value `0` for `--port`: must be a port from 1 to 65535"
+        );
+        assert_eq!(
+            error(&["--log-level", "loud"]),
+            "Invalid value `loud` for `--log-level`: must be one of off, error, warn, info or debug"
+        );
+    }
+
+    #[test]
+    fn overrides_the_configured_settings() {
+        let Ok(Command::Serve(args)) = parse(&["--port", "80", "--threads", "512"]) else {
+            panic!();
+        };
+        let mut config = Config::default();
+        args.apply(&mut config);
+
+        assert_eq!(
+            config,
+            Config {
+                port: 80,
+                threads: 512,
+                max_threads: 512,
+                ..Config::default()
+            }
+        );
+    }
+}
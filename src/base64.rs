@@ -0,0 +1,41 @@
+//! Decoding base64, in the standard alphabet or in another, such as bcrypt's.
+
+/// The alphabet of RFC 4648, which Basic credentials and PHC password hashes are
+/// written in.
+pub(crate) const STANDARD: &[u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Decode `text`, written in `alphabet` with or without `=` padding, or return `None`
+/// if it has characters outside of it. Bits left over past the last whole byte are
+/// dropped.
+pub(crate) fn decode(alphabet: &[u8; 64], text: &str) -> Option<Vec<u8>> {
+    let mut decoded = Vec::with_capacity(text.len() * 3 / 4);
+    let (mut bits, mut count) = (0u32, 0);
+
+    for byte in text.trim_end_matches('=').bytes() {
+        let sextet = alphabet.iter().position(|&letter| letter == byte)?;
+        bits = (bits << 6 | sextet as u32) & 0xfff;
+        count += 6;
+        if count >= 8 {
+            count -= 8;
+            decoded.push((bits >> count) as u8);
+        }
+    }
+    Some(decoded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_with_and_without_padding() {
+        assert_eq!(
+            decode(STANDARD, "QWxhZGRpbjpvcGVuIHNlc2FtZQ==").unwrap(),
+            b"Aladdin:open sesame"
+        );
+        assert_eq!(decode(STANDARD, "c29tZXNhbHQ").unwrap(), b"somesalt");
+        assert_eq!(decode(STANDARD, "").unwrap(), b"");
+        assert_eq!(decode(STANDARD, "c29t*"), None);
+    }
+}
@@ -0,0 +1,331 @@
+//! HTTP Basic authentication (RFC 7617) for the paths under chosen prefixes, with the
+//! users and their password hashes in a file in the format of Apache's `htpasswd`:
+//! a `user:hash` line for each, where the hash is bcrypt, as `htpasswd -B` writes, or
+//! Argon2. Blank lines and those starting with `#` are skipped.
+//!
+//! A request under a prefix is held to the longest one it is under, and answered with
+//! `401 Unauthorized` and a challenge for that prefix's realm unless it carries the
+//! name and password of one of its users.
+//! ```
+//! use ch20_web_server::basic_auth::{BasicAuth, Htpasswd};
+//!
+//! let users: Htpasswd = "alice:$2y$05$N9qo8uLOickgx2ZMRZoMyeySTQZHYTvN5Lzja/W0KswX.y7D.SAs6"
+//!     .parse()
+//!     .unwrap();
+//! let auth = BasicAuth::new().protect("/private", "Staff", users);
+//!
+//! // alice:correct horse
+//! let credentials = Some("Basic YWxpY2U6Y29ycmVjdCBob3JzZQ==");
+//! assert_eq!(auth.authenticate("/private/plans", credentials), Ok(Some("alice")));
+//! assert_eq!(auth.authenticate("/private/plans", None), Err("Staff"));
+//! assert_eq!(auth.authenticate("/public", None), Ok(None));
+//! ```
+
+use std::{collections::BTreeMap, fmt, fs, io, path::Path, str::FromStr};
+
+use crate::{
+    base64,
+    password::PasswordHash,
+    request::Request,
+    response::{Response, Status},
+    router,
+};
+
+/// The users of a credential file, with the hashes of their passwords.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Htpasswd {
+    users: BTreeMap<String, PasswordHash>,
+}
+
+impl Htpasswd {
+    /// Read the credential file at `path`.
+    pub fn load(path: &Path) -> Result<Htpasswd, HtpasswdError> {
+        fs::read_to_string(path).map_err(HtpasswdError::Io)?.parse()
+    }
+
+    /// Whether `user` is in the file and `password` is theirs. A password is checked
+    /// against some hash even when there is no such user, so that how long it takes
+    /// does not tell which users there are.
+    pub fn verify(&self, user: &str, password: &[u8]) -> bool {
+        match self.users.get(user) {
+            Some(hash) => hash.verify(password),
+            None => {
+                if let Some(hash) = self.users.values().next() {
+                    hash.verify(password);
+                }
+                false
+            }
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.users.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.users.is_empty()
+    }
+}
+
+impl FromStr for Htpasswd {
+    type Err = HtpasswdError;
+
+    fn from_str(text: &str) -> Result<Htpasswd, HtpasswdError> {
+        let mut users = BTreeMap::new();
+        for (index, line) in text.lines().enumerate() {
+            let invalid = |message: String| HtpasswdError::Invalid {
+                line: index + 1,
+                message,
+            };
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (user, hash) = line
+                .split_once(':')
+                .filter(|(user, _)| !user.is_empty())
+                .ok_or_else(|| invalid(String::from("must be a user and a hash, as user:hash")))?;
+            let hash = hash
+                .parse()
+                .map_err(|error| invalid(format!("`{user}` has {error}")))?;
+            if users.insert(user.to_owned(), hash).is_some() {
+                return Err(invalid(format!("`{user}` is on an earlier line too")));
+            }
+        }
+        Ok(Htpasswd { users })
+    }
+}
+
+/// Why a credential file could not be loaded.
+#[derive(Debug)]
+pub enum HtpasswdError {
+    /// The file could not be read.
+    Io(io::Error),
+    /// A line is not a user and a hash in a supported format.
+    Invalid { line: usize, message: String },
+}
+
+impl fmt::Display for HtpasswdError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HtpasswdError::Io(error) => error.fmt(f),
+            HtpasswdError::Invalid { line, message } => write!(f, "line {line}: {message}"),
+        }
+    }
+}
+
+impl std::error::Error for HtpasswdError {}
+
+/// The prefixes that need a user's name and password, each with its realm and users.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BasicAuth {
+    /// Each prefix's segments, realm and users, longest first.
+    areas: Vec<(Vec<String>, String, Htpasswd)>,
+}
+
+impl BasicAuth {
+    pub fn new() -> BasicAuth {
+        BasicAuth::default()
+    }
+
+    /// Require one of `users` for `prefix` and the paths under it, so `/private`
+    /// covers `/private/plans` but not `/privateer`. `realm` is shown to the user by
+    /// browsers when they ask for a password. Protecting a prefix again replaces what
+    /// it was protected with.
+    pub fn protect(mut self, prefix: &str, realm: &str, users: Htpasswd) -> BasicAuth {
+        let segments = router::decode_path(prefix.trim_end_matches('/')).unwrap_or_default();
+        self.areas.retain(|(existing, _, _)| *existing != segments);
+        self.areas.push((segments, realm.to_owned(), users));
+        self.areas
+            .sort_by_key(|(segments, _, _)| std::cmp::Reverse(segments.len()));
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.areas.is_empty()
+    }
+
+    /// Check a request for `path` with the `Authorization` header `authorization`:
+    /// `Ok` with the user it authenticated as, or `None` if the path is not
+    /// protected, or `Err` with the realm to challenge it for.
+    pub fn authenticate<'a>(
+        &'a self,
+        path: &str,
+        authorization: Option<&str>,
+    ) -> Result<Option<&'a str>, &'a str> {
+        // A path that cannot be decoded is served nothing, so it needs no password.
+        let segments = router::decode_path(path).unwrap_or_default();
+        let Some((_, realm, users)) = self
+            .areas
+            .iter()
+            .find(|(prefix, _, _)| segments.starts_with(prefix))
+        else {
+            return Ok(None);
+        };
+        let (user, password) = authorization.and_then(credentials).ok_or(realm.as_str())?;
+        if users.verify(&user, &password) {
+            Ok(users
+                .users
+                .get_key_value(&user)
+                .map(|(user, _)| user.as_str()))
+        } else {
+            Err(realm)
+        }
+    }
+}
+
+/// The user and password of an `Authorization: Basic` header, whose scheme is not
+/// case-sensitive.
+fn credentials(authorization: &str) -> Option<(String, Vec<u8>)> {
+    let (scheme, encoded) = authorization.trim().split_once(' ')?;
+    if !scheme.eq_ignore_ascii_case("Basic") {
+        return None;
+    }
+    let decoded = base64::decode(base64::STANDARD, encoded.trim())?;
+    let colon = decoded.iter().position(|&byte| byte == b':')?;
+    let user = String::from_utf8(decoded[..colon].to_vec()).ok()?;
+    Some((user, decoded[colon + 1..].to_vec()))
+}
+
+/// The `WWW-Authenticate` challenge for `realm`, as a quoted string.
+fn challenge(realm: &str) -> String {
+    let realm = realm.replace('\\', "\\\\").replace('"', "\\\"");
+    format!("Basic realm=\"{realm}\", charset=\"UTF-8\"")
+}
+
+/// Middleware that answers requests under a prefix of `auth` without the name and
+/// password of one of its users with `401 Unauthorized`, and gives those with them
+/// the user as their `principal`.
+pub fn require(
+    auth: BasicAuth,
+) -> impl Fn(&Request, &dyn Fn(&Request) -> Response) -> Response + Send + Sync + 'static {
+    move |request, next| match auth.authenticate(request.path(), request.header("Authorization")) {
+        Ok(None) => next(request),
+        Ok(Some(user)) => {
+            let mut request = request.clone();
+            request.set_principal(user.to_owned());
+            next(&request)
+        }
+        Err(realm) => {
+            Response::error(Status::Unauthorized).header("WWW-Authenticate", challenge(realm))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::router::Router;
+
+    /// `alice` with `correct horse`, and `bob` with `password`.
+    const USERS: &str = "\
+# Staff
+alice:$2y$05$N9qo8uLOickgx2ZMRZoMyeySTQZHYTvN5Lzja/W0KswX.y7D.SAs6
+
+bob:$argon2id$v=19$m=64,t=2,p=1$c29tZXNhbHQ$FqGkmHNGCd0BRW2kBt6fPZ2pPmyGwwChL8FGUhTOSSI
+";
+
+    /// `bob:password`, with a `Basic` scheme.
+    const BOB: &str = "Basic Ym9iOnBhc3N3b3Jk";
+
+    #[test]
+    fn reads_credential_files() {
+        let users: Htpasswd = USERS.parse().unwrap();
+        assert_eq!(users.len(), 2);
+        assert!(users.verify("alice", b"correct horse"));
+        assert!(!users.verify("alice", b""));
+        assert!(!users.verify("carol", b"correct horse"));
+
+        let error = |text: &str| text.parse::<Htpasswd>().unwrap_err().to_string();
+        assert_eq!(
+            error("alice"),
+            "line 1: must be a user and a hash, as user:hash"
+        );
+        assert_eq!(
+            error("\n:$2y$05$N9qo8uLOickgx2ZMRZoMyeySTQZHYTvN5Lzja/W0KswX.y7D.SAs6"),
+            "line 2: must be a user and a hash, as user:hash"
+        );
+        assert_eq!(
+            error("alice:$apr1$salt$hash"),
+            "line 1: `alice` has not a bcrypt or Argon2 (version 1.3) password hash"
+        );
+        assert_eq!(
+            error(&format!(
+                "{USERS}alice:$2b$04$abcdefghijklmnopqrstuubyCG3zY1GIXMyxfivm.ClDiInHzxjiq"
+            )),
+            "line 5: `alice` is on an earlier line too"
+        );
+    }
+
+    #[test]
+    fn authenticates_requests_under_the_longest_prefix() {
+        let users: Htpasswd = USERS.parse().unwrap();
+        let alice: Htpasswd = USERS
+            .lines()
+            .take(2)
+            .collect::<Vec<_>>()
+            .join("\n")
+            .parse()
+            .unwrap();
+        let auth = BasicAuth::new()
+            .protect("/private", "Staff", users)
+            .protect("/private/alice/", "Alice's \"things\"", alice);
+
+        assert_eq!(auth.authenticate("/private", Some(BOB)), Ok(Some("bob")));
+        assert_eq!(
+            auth.authenticate("/%70rivate/alice/notes", Some(BOB)),
+            Err("Alice's \"things\"")
+        );
+        // alice:correct horse
+        let alice = "bASIC YWxpY2U6Y29ycmVjdCBob3JzZQ==";
+        assert_eq!(
+            auth.authenticate("/private/alice/notes", Some(alice)),
+            Ok(Some("alice"))
+        );
+        assert_eq!(auth.authenticate("/privateer", None), Ok(None));
+
+        // alice:wrong, carol:, alice, and alicecorrect horse, then ones that are not
+        // Basic credentials at all.
+        for wrong in [
+            "Basic YWxpY2U6d3Jvbmc=",
+            "Basic Y2Fyb2w6",
+            "Basic YWxpY2U=",
+            "Basic YWxpY2Vjb3JyZWN0IGhvcnNl",
+            "Bearer Ym9iOg==",
+            "Basic",
+            "Basic !!!!",
+        ] {
+            assert_eq!(
+                auth.authenticate("/private", Some(wrong)),
+                Err("Staff"),
+                "{wrong}"
+            );
+        }
+    }
+
+    #[test]
+    fn challenges_requests_without_credentials_and_names_the_user() {
+        let auth = BasicAuth::new().protect("/", "The \"back\" office", USERS.parse().unwrap());
+        let router = Router::new()
+            .get("/", |request| {
+                Response::new(Status::Ok).body(request.principal().unwrap_or_default().to_owned())
+            })
+            .wrap(require(auth));
+        let request = |authorization: Option<&str>| {
+            let header =
+                authorization.map_or(String::new(), |value| format!("Authorization: {value}\r\n"));
+            let text = format!("GET / HTTP/1.1\r\n{header}\r\n");
+            Request::read_from(&mut text.as_bytes()).unwrap()
+        };
+
+        let response = router.dispatch(request(None));
+        assert_eq!(response.status(), Status::Unauthorized);
+        assert_eq!(
+            response.headers().get("WWW-Authenticate"),
+            Some("Basic realm=\"The \\\"back\\\" office\", charset=\"UTF-8\"")
+        );
+        let response = router.dispatch(request(Some(BOB)));
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.body_bytes(), b"bob");
+    }
+}
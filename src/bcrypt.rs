@@ -0,0 +1,124 @@
+//! bcrypt, the password hash `htpasswd -B` writes: `$2y$`, a cost of two digits,
+//! `$`, and then 22 characters of salt and 31 of hash in bcrypt's base64.
+//!
+//! The tests check hashes made by other implementations, with passwords that are
+//! empty, longer than bcrypt reads and not ASCII.
+
+use crate::{base64, blowfish::Blowfish, password};
+
+/// bcrypt's base64 alphabet, which is in another order than the standard one.
+const ALPHABET: &[u8; 64] = b"./ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789";
+
+/// A bcrypt hash, read from its text.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Hash {
+    cost: u32,
+    salt: [u8; 16],
+    digest: Vec<u8>,
+}
+
+impl Hash {
+    /// Read `hash`, or return `None` if it is not a bcrypt hash. The `$2a$`, `$2b$`
+    /// and `$2y$` variants are the same for passwords of up to 255 bytes.
+    pub(crate) fn parse(hash: &str) -> Option<Hash> {
+        let rest = ["$2a$", "$2b$", "$2y$"]
+            .iter()
+            .find_map(|variant| hash.strip_prefix(variant))?;
+        let (cost, rest) = rest.split_once('$')?;
+        if cost.len() != 2 || rest.len() != 53 || !rest.is_ascii() {
+            return None;
+        }
+        Some(Hash {
+            cost: cost.parse().ok().filter(|cost| (4..=31).contains(cost))?,
+            salt: base64::decode(ALPHABET, &rest[..22])?.try_into().ok()?,
+            digest: base64::decode(ALPHABET, &rest[22..])?,
+        })
+    }
+
+    /// Whether `password` is the one the hash was made from.
+    pub(crate) fn verify(&self, password: &[u8]) -> bool {
+        password::same(&digest(password, self.cost, &self.salt), &self.digest)
+    }
+}
+
+/// The 23 bytes bcrypt keeps of the hash of `password` with `salt`, which takes
+/// `2^cost` rounds of Blowfish's key schedule.
+fn digest(password: &[u8], cost: u32, salt: &[u8; 16]) -> Vec<u8> {
+    // Only the first 72 bytes of the password count, with the NUL that ends it.
+    let key: Vec<u8> = password.iter().copied().chain([0]).take(72).collect();
+    let mut blowfish = Blowfish::initial();
+    blowfish.expand(&key, salt);
+    for _ in 0..1u64 << cost {
+        blowfish.expand(&key, &[]);
+        blowfish.expand(salt, &[]);
+    }
+
+    let mut text = [0; 6];
+    for (word, bytes) in text.iter_mut().zip(b"OrpheanBeholderScryDoubt".chunks(4)) {
+        *word = u32::from_be_bytes(bytes.try_into().unwrap());
+    }
+    for _ in 0..64 {
+        for pair in text.chunks_exact_mut(2) {
+            (pair[0], pair[1]) = blowfish.encrypt(pair[0], pair[1]);
+        }
+    }
+    let mut digest: Vec<u8> = text.iter().flat_map(|word| word.to_be_bytes()).collect();
+    digest.truncate(23);
+    digest
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn verify(password: &[u8], hash: &str) -> Option<bool> {
+        Hash::parse(hash).map(|hash| hash.verify(password))
+    }
+
+    #[test]
+    fn verifies_hashes_made_by_crypt() {
+        let hashes = [
+            (
+                "U*U",
+                "$2a$05$CCCCCCCCCCCCCCCCCCCCC.E5YPO9kmyuRGyh0XouQYb4YMJKvyOeW",
+            ),
+            (
+                "",
+                "$2b$04$abcdefghijklmnopqrstuubyCG3zY1GIXMyxfivm.ClDiInHzxjiq",
+            ),
+            (
+                "correct horse",
+                "$2y$05$N9qo8uLOickgx2ZMRZoMyeySTQZHYTvN5Lzja/W0KswX.y7D.SAs6",
+            ),
+            (
+                "pässword",
+                "$2b$06$N9qo8uLOickgx2ZMRZoMyelwg9NK0Zy9KUYl7p8O46j126mCb.WeS",
+            ),
+        ];
+        for (password, hash) in hashes {
+            assert_eq!(verify(password.as_bytes(), hash), Some(true), "{hash}");
+            assert_eq!(verify(b"wrong", hash), Some(false), "{hash}");
+        }
+    }
+
+    #[test]
+    fn ignores_all_but_72_bytes_of_the_password() {
+        let hash = "$2b$04$N9qo8uLOickgx2ZMRZoMyeF1J3tt0w8.ywyH26Gg0MaR3.85io4ma";
+        assert_eq!(verify(&[b'a'; 72], hash), Some(true));
+        assert_eq!(verify(&[b'a'; 80], hash), Some(true));
+        assert_eq!(verify(&[b'a'; 71], hash), Some(false));
+    }
+
+    #[test]
+    fn rejects_what_is_not_bcrypt() {
+        for hash in [
+            "$1$salt$hash",
+            "$2b$3$N9qo8uLOickgx2ZMRZoMyeF1J3tt0w8.ywyH26Gg0MaR3.85io4ma",
+            "$2b$32$N9qo8uLOickgx2ZMRZoMyeF1J3tt0w8.ywyH26Gg0MaR3.85io4ma",
+            "$2b$04$N9qo8uLOickgx2ZMRZoMyeF1J3tt0w8.ywyH26Gg0MaR3.85io4m",
+            "$2b$04$N9qo8uLOickgx2ZMRZoMyeF1J3tt0w8.ywyH26Gg0MaR3.85io4m*",
+        ] {
+            assert_eq!(verify(b"a", hash), None, "{hash}");
+        }
+    }
+}
@@ -0,0 +1,174 @@
+//! BLAKE2b (RFC 7693), the hash Argon2 is built on, tested with the RFC's example and
+//! digests from the reference implementation.
+
+const IV: [u64; 8] = [
+    0x6a09e667f3bcc908,
+    0xbb67ae8584caa73b,
+    0x3c6ef372fe94f82b,
+    0xa54ff53a5f1d36f1,
+    0x510e527fade682d1,
+    0x9b05688c2b3e6c1f,
+    0x1f83d9abfb41bd6b,
+    0x5be0cd19137e2179,
+];
+
+/// The order the words of a block are mixed in, in each round.
+const SIGMA: [[usize; 16]; 10] = [
+    [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15],
+    [14, 10, 4, 8, 9, 15, 13, 6, 1, 12, 0, 2, 11, 7, 5, 3],
+    [11, 8, 12, 0, 5, 2, 15, 13, 10, 14, 3, 6, 7, 1, 9, 4],
+    [7, 9, 3, 1, 13, 12, 11, 14, 2, 6, 5, 10, 4, 0, 15, 8],
+    [9, 0, 5, 7, 2, 4, 10, 15, 14, 1, 11, 12, 6, 8, 3, 13],
+    [2, 12, 6, 10, 0, 11, 8, 3, 4, 13, 7, 5, 15, 14, 1, 9],
+    [12, 5, 1, 15, 14, 13, 4, 10, 0, 7, 6, 3, 9, 2, 8, 11],
+    [13, 11, 7, 14, 12, 1, 3, 9, 5, 0, 15, 4, 8, 6, 2, 10],
+    [6, 15, 14, 9, 11, 3, 0, 8, 12, 2, 13, 7, 1, 4, 10, 5],
+    [10, 2, 8, 4, 7, 6, 1, 5, 15, 11, 9, 14, 3, 12, 13, 0],
+];
+
+const BLOCK: usize = 128;
+
+/// A hash being computed, without a key.
+pub(crate) struct Blake2b {
+    state: [u64; 8],
+    block: [u8; BLOCK],
+    filled: usize,
+    /// The bytes hashed before those in `block`.
+    counter: u128,
+    length: usize,
+}
+
+impl Blake2b {
+    /// Start a hash of `length` bytes, from 1 to 64.
+    pub(crate) fn new(length: usize) -> Blake2b {
+        assert!(
+            (1..=64).contains(&length),
+            "BLAKE2b hashes are 1 to 64 bytes"
+        );
+        let mut state = IV;
+        state[0] ^= 0x0101_0000 ^ length as u64;
+        Blake2b {
+            state,
+            block: [0; BLOCK],
+            filled: 0,
+            counter: 0,
+            length,
+        }
+    }
+
+    pub(crate) fn update(&mut self, mut data: &[u8]) -> &mut Blake2b {
+        while !data.is_empty() {
+            // The last block is compressed differently, so a full one waits until
+            // there is more.
+            if self.filled == BLOCK {
+                self.counter += BLOCK as u128;
+                self.compress(false);
+                self.filled = 0;
+            }
+            let taken = data.len().min(BLOCK - self.filled);
+            self.block[self.filled..self.filled + taken].copy_from_slice(&data[..taken]);
+            self.filled += taken;
+            data = &data[taken..];
+        }
+        self
+    }
+
+    pub(crate) fn finish(&mut self) -> Vec<u8> {
+        self.counter += self.filled as u128;
+        self.block[self.filled..].fill(0);
+        self.compress(true);
+        self.state
+            .iter()
+            .flat_map(|word| word.to_le_bytes())
+            .take(self.length)
+            .collect()
+    }
+
+    fn compress(&mut self, last: bool) {
+        let mut m = [0u64; 16];
+        for (word, bytes) in m.iter_mut().zip(self.block.chunks_exact(8)) {
+            *word = u64::from_le_bytes(bytes.try_into().unwrap());
+        }
+        let mut v = [0u64; 16];
+        v[..8].copy_from_slice(&self.state);
+        v[8..].copy_from_slice(&IV);
+        v[12] ^= self.counter as u64;
+        v[13] ^= (self.counter >> 64) as u64;
+        if last {
+            v[14] = !v[14];
+        }
+
+        for round in 0..12 {
+            let s = &SIGMA[round % 10];
+            mix(&mut v, [0, 4, 8, 12], m[s[0]], m[s[1]]);
+            mix(&mut v, [1, 5, 9, 13], m[s[2]], m[s[3]]);
+            mix(&mut v, [2, 6, 10, 14], m[s[4]], m[s[5]]);
+            mix(&mut v, [3, 7, 11, 15], m[s[6]], m[s[7]]);
+            mix(&mut v, [0, 5, 10, 15], m[s[8]], m[s[9]]);
+            mix(&mut v, [1, 6, 11, 12], m[s[10]], m[s[11]]);
+            mix(&mut v, [2, 7, 8, 13], m[s[12]], m[s[13]]);
+            mix(&mut v, [3, 4, 9, 14], m[s[14]], m[s[15]]);
+        }
+        for (i, word) in self.state.iter_mut().enumerate() {
+            *word ^= v[i] ^ v[i + 8];
+        }
+    }
+}
+
+/// The hash of `data`, `length` bytes long.
+pub(crate) fn hash(length: usize, data: &[u8]) -> Vec<u8> {
+    Blake2b::new(length).update(data).finish()
+}
+
+/// BLAKE2b's G function, on the words of `v` at `[a, b, c, d]`.
+fn mix(v: &mut [u64; 16], [a, b, c, d]: [usize; 4], x: u64, y: u64) {
+    v[a] = v[a].wrapping_add(v[b]).wrapping_add(x);
+    v[d] = (v[d] ^ v[a]).rotate_right(32);
+    v[c] = v[c].wrapping_add(v[d]);
+    v[b] = (v[b] ^ v[c]).rotate_right(24);
+    v[a] = v[a].wrapping_add(v[b]).wrapping_add(y);
+    v[d] = (v[d] ^ v[a]).rotate_right(16);
+    v[c] = v[c].wrapping_add(v[d]);
+    v[b] = (v[b] ^ v[c]).rotate_right(63);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+    }
+
+    #[test]
+    fn hashes_like_the_reference() {
+        assert_eq!(
+            hex(&hash(64, b"abc")),
+            "ba80a53f981c4d0d6a2797b69f12f6e94c212f14685ac4b74b12bb6fdbffa2d1\
+             7d87c5392aab792dc252d5de4533cc9518d38aa8dbf1925ab92386edd4009923"
+        );
+        assert_eq!(
+            hex(&hash(32, b"")),
+            "0e5751c026e543b2e8ab2eb06099daa1d1e5df47778f7787faab45cdf12fe3a8"
+        );
+        // Exactly one block, which is only compressed as the last one.
+        assert_eq!(
+            hex(&hash(20, &[0; 128])),
+            "8d26f158f564e3293b42f5e3d34263cb173aa9c9"
+        );
+    }
+
+    #[test]
+    fn hashes_the_same_in_pieces() {
+        let data: Vec<u8> = (0..=255).cycle().take(512).collect();
+        let mut pieces = Blake2b::new(64);
+        for piece in data.chunks(100) {
+            pieces.update(piece);
+        }
+        assert_eq!(
+            hex(&pieces.finish()),
+            "c59ab1095ca4579525338b6b74689ff234bc3fe9765fe26dfb04ddceaee0ab84\
+             dfd8967594cb261fcd88687f4454d80f718116c1b3c32f9f7e169357468cbe67"
+        );
+    }
+}
@@ -0,0 +1,883 @@
+//! A brotli encoder for response bodies: LZ77 matching with a prefix code built for
+//! each meta-block, which fits the text it codes far better than the fixed codes the
+//! gzip encoder uses.
+
+use std::{
+    cmp::Reverse,
+    collections::BinaryHeap,
+    io::{self, Read},
+};
+
+use crate::lz77::{self, BitWriter, Token, BLOCK_SIZE};
+
+/// The quality used unless another is configured.
+pub const DEFAULT_QUALITY: u32 = 5;
+/// The highest quality. Higher qualities try more matches, which is slower.
+pub const MAX_QUALITY: u32 = 11;
+
+const LITERAL_ALPHABET_BITS: u32 = 8;
+const COMMAND_ALPHABET_SIZE: usize = 704;
+const COMMAND_ALPHABET_BITS: u32 = 10;
+/// 16 special codes, then 48 for plain distances, with no postfix or direct codes.
+const DISTANCE_ALPHABET_SIZE: usize = 64;
+const DISTANCE_ALPHABET_BITS: u32 = 6;
+const MAX_CODE_LENGTH: u8 = 15;
+const MAX_CODE_LENGTH_CODE_LENGTH: u8 = 5;
+
+const INSERT_BASE: [u32; 24] = [
+    0, 1, 2, 3, 4, 5, 6, 8, 10, 14, 18, 26, 34, 50, 66, 98, 130, 194, 322, 578, 1090, 2114, 6210,
+    22594,
+];
+const INSERT_EXTRA: [u32; 24] = [
+    0, 0, 0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 7, 8, 9, 10, 12, 14, 24,
+];
+const COPY_BASE: [u32; 24] = [
+    2, 3, 4, 5, 6, 7, 8, 9, 10, 12, 14, 18, 22, 30, 38, 54, 70, 102, 134, 198, 326, 582, 1094, 2118,
+];
+const COPY_EXTRA: [u32; 24] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 7, 8, 9, 10, 24,
+];
+
+/// The order code length code lengths are stored in.
+const CODE_LENGTH_ORDER: [usize; 18] =
+    [1, 2, 3, 4, 0, 5, 17, 6, 16, 7, 8, 9, 10, 11, 12, 13, 14, 15];
+/// The static code code length code lengths are stored with, as value and bits.
+const CODE_LENGTH_CODE_CODES: [(u32, u32); 6] = [(0, 2), (7, 4), (3, 3), (2, 2), (1, 2), (15, 4)];
+
+/// Compress `data` into a brotli stream at `quality`, from 0 to `MAX_QUALITY`.
+pub fn compress(data: &[u8], quality: u32) -> Vec<u8> {
+    let mut compressed = Vec::new();
+    BrotliEncoder::new(data, quality)
+        .read_to_end(&mut compressed)
+        .expect("reading from a slice cannot fail");
+    compressed
+}
+
+/// Compresses what it reads from another reader into a brotli stream, one meta-block
+/// at a time, so bodies of any length can be compressed as they are sent.
+pub struct BrotliEncoder<R> {
+    inner: R,
+    max_chain: usize,
+    input: Vec<u8>,
+    bits: BitWriter,
+    /// Compressed bytes not yet returned, from `position` on.
+    position: usize,
+    finished: bool,
+}
+
+impl<R: Read> BrotliEncoder<R> {
+    /// Compress at `quality`, from 0 to `MAX_QUALITY`. Quality 0 only codes literals,
+    /// and each step after that tries twice as many matches.
+    pub fn new(inner: R, quality: u32) -> BrotliEncoder<R> {
+        let quality = quality.min(MAX_QUALITY);
+        let mut bits = BitWriter::default();
+        // A window of 64 KiB, which covers a whole block.
+        bits.write(0, 1);
+
+        BrotliEncoder {
+            inner,
+            max_chain: if quality == 0 { 0 } else { 1 << (quality - 1) },
+            input: Vec::with_capacity(BLOCK_SIZE),
+            bits,
+            position: 0,
+            finished: false,
+        }
+    }
+
+    /// Read and compress the next block of input, or finish the stream at its end.
+    fn fill(&mut self) -> io::Result<()> {
+        let at_end = lz77::fill_block(&mut self.inner, &mut self.input)?;
+        if !self.input.is_empty() {
+            write_meta_block(&self.input, self.max_chain, &mut self.bits);
+        }
+        if at_end {
+            // An empty last meta-block.
+            self.bits.write(1, 1);
+            self.bits.write(1, 1);
+            self.bits.align();
+            self.finished = true;
+        }
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for BrotliEncoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.position == self.bits.out.len() {
+            if self.finished {
+                return Ok(0);
+            }
+            self.bits.out.clear();
+            self.position = 0;
+            self.fill()?;
+        }
+
+        let pending = &self.bits.out[self.position..];
+        let read = pending.len().min(buf.len());
+        buf[..read].copy_from_slice(&pending[..read]);
+        self.position += read;
+        Ok(read)
+    }
+}
+
+/// Some literals followed by a copy, the unit brotli codes a meta-block in.
+struct Command {
+    literals: std::ops::Range<usize>,
+    /// The copy's length and distance, or `None` for the literals at the end.
+    copy: Option<(usize, usize)>,
+}
+
+impl Command {
+    fn symbol(&self) -> u16 {
+        let insert = code_for(&INSERT_BASE, self.literals.len());
+        // The copy of the last command is never reached, so any length will do.
+        let copy = self
+            .copy
+            .map_or(0, |(length, _)| code_for(&COPY_BASE, length));
+        let cell = match (insert >> 3, copy >> 3) {
+            (0, 0) => 128,
+            (0, 1) => 192,
+            (1, 0) => 256,
+            (1, 1) => 320,
+            (0, _) => 384,
+            (_, 0) => 448,
+            (1, _) => 512,
+            (_, 1) => 576,
+            _ => 640,
+        };
+        (cell + ((insert & 7) << 3 | (copy & 7))) as u16
+    }
+}
+
+/// Return the index of the last entry of `base` that is at most `value`.
+fn code_for(base: &[u32], value: usize) -> usize {
+    base.partition_point(|&base| base as usize <= value) - 1
+}
+
+/// Return the code for `distance`, with its extra bits as value and count.
+fn distance_code(distance: usize) -> (u16, u32, u32) {
+    let shifted = distance + 3;
+    let extra_bits = shifted.ilog2() - 1;
+    let high = (shifted >> extra_bits) & 1;
+    let code = 16 + 2 * (extra_bits - 1) + high as u32;
+    let extra = shifted - ((2 + high) << extra_bits);
+    (code as u16, extra as u32, extra_bits)
+}
+
+/// Write `data` as one meta-block that is not the last.
+fn write_meta_block(data: &[u8], max_chain: usize, bits: &mut BitWriter) {
+    let mut commands = Vec::new();
+    let mut start = 0;
+    let mut position = 0;
+    lz77::tokens(data, max_chain, |token| match token {
+        Token::Literal(_) => position += 1,
+        Token::Copy { length, distance } => {
+            commands.push(Command {
+                literals: start..position,
+                copy: Some((length, distance)),
+            });
+            position += length;
+            start = position;
+        }
+    });
+    if start < data.len() {
+        commands.push(Command {
+            literals: start..data.len(),
+            copy: None,
+        });
+    }
+
+    let mut literal_counts = vec![0; 256];
+    let mut command_counts = vec![0; COMMAND_ALPHABET_SIZE];
+    let mut distance_counts = vec![0; DISTANCE_ALPHABET_SIZE];
+    for command in &commands {
+        command_counts[command.symbol() as usize] += 1;
+        for &byte in &data[command.literals.clone()] {
+            literal_counts[byte as usize] += 1;
+        }
+        if let Some((_, distance)) = command.copy {
+            distance_counts[distance_code(distance).0 as usize] += 1;
+        }
+    }
+
+    // Not the last, empty or uncompressed, with a length in four nibbles.
+    bits.write(0, 1);
+    bits.write(0, 2);
+    bits.write(data.len() as u32 - 1, 16);
+    bits.write(0, 1);
+    // One block type each for literals, commands and distances, no postfix or direct
+    // distance codes, the first context mode, and one prefix code each for literals
+    // and distances.
+    bits.write(0, 3);
+    bits.write(0, 2);
+    bits.write(0, 4);
+    bits.write(0, 2);
+    bits.write(0, 2);
+
+    let literal_code = PrefixCode::store(&literal_counts, LITERAL_ALPHABET_BITS, bits);
+    let command_code = PrefixCode::store(&command_counts, COMMAND_ALPHABET_BITS, bits);
+    let distance_code_ = PrefixCode::store(&distance_counts, DISTANCE_ALPHABET_BITS, bits);
+
+    for command in &commands {
+        command_code.write(command.symbol() as usize, bits);
+        let insert = code_for(&INSERT_BASE, command.literals.len());
+        bits.write(
+            command.literals.len() as u32 - INSERT_BASE[insert],
+            INSERT_EXTRA[insert],
+        );
+        let (length, distance) = command.copy.unwrap_or((2, 0));
+        let copy = code_for(&COPY_BASE, length);
+        bits.write(length as u32 - COPY_BASE[copy], COPY_EXTRA[copy]);
+
+        for &byte in &data[command.literals.clone()] {
+            literal_code.write(byte as usize, bits);
+        }
+        if command.copy.is_some() {
+            let (code, extra, extra_bits) = distance_code(distance);
+            distance_code_.write(code as usize, bits);
+            bits.write(extra, extra_bits);
+        }
+    }
+}
+
+/// A canonical prefix code, as the length and code of each symbol.
+struct PrefixCode {
+    lengths: Vec<u8>,
+    codes: Vec<u32>,
+}
+
+impl PrefixCode {
+    fn new(lengths: Vec<u8>) -> PrefixCode {
+        let mut next = [0; MAX_CODE_LENGTH as usize + 2];
+        for &length in lengths.iter().filter(|&&length| length > 0) {
+            next[length as usize + 1] += 1;
+        }
+        for length in 1..next.len() {
+            next[length] = (next[length] + next[length - 1]) << 1;
+        }
+
+        let codes = lengths
+            .iter()
+            .map(|&length| {
+                let code = next[length as usize];
+                next[length as usize] += 1;
+                code
+            })
+            .collect();
+        PrefixCode { lengths, codes }
+    }
+
+    fn write(&self, symbol: usize, bits: &mut BitWriter) {
+        let length = self.lengths[symbol] as u32;
+        if length > 0 {
+            bits.write_code(self.codes[symbol], length);
+        }
+    }
+
+    /// Build a code for symbols occurring `counts` times and write its description.
+    fn store(counts: &[u32], alphabet_bits: u32, bits: &mut BitWriter) -> PrefixCode {
+        let used: Vec<_> = (0..counts.len())
+            .filter(|&symbol| counts[symbol] > 0)
+            .collect();
+
+        if used.len() <= 1 {
+            // A simple code of one symbol, which takes no bits to write.
+            bits.write(1, 2);
+            bits.write(0, 2);
+            bits.write(used.first().copied().unwrap_or(0) as u32, alphabet_bits);
+            return PrefixCode::new(vec![0; counts.len()]);
+        }
+
+        let lengths = code_lengths(counts, MAX_CODE_LENGTH);
+        let runs = run_lengths(&lengths);
+        let mut run_counts = vec![0; 18];
+        for &(symbol, _) in &runs {
+            run_counts[symbol as usize] += 1;
+        }
+
+        let single = run_counts.iter().filter(|&&count| count > 0).count() == 1;
+        let run_code = if single {
+            // Stored with any length, the only code length symbol takes no bits.
+            let lengths = run_counts.iter().map(|&count| (count > 0) as u8).collect();
+            PrefixCode {
+                lengths,
+                codes: vec![0; 18],
+            }
+        } else {
+            PrefixCode::new(code_lengths(&run_counts, MAX_CODE_LENGTH_CODE_LENGTH))
+        };
+
+        let stored = |i: usize| run_code.lengths[CODE_LENGTH_ORDER[i]];
+        let skip = match (stored(0), stored(1), stored(2)) {
+            (0, 0, 0) => 3,
+            (0, 0, _) => 2,
+            _ => 0,
+        };
+        // The rest are read as zeros once the lengths add up to a complete code.
+        let end = if single {
+            CODE_LENGTH_ORDER.len()
+        } else {
+            (0..CODE_LENGTH_ORDER.len())
+                .rfind(|&i| stored(i) > 0)
+                .unwrap()
+                + 1
+        };
+        bits.write(skip, 2);
+        for i in skip as usize..end {
+            let (value, length) = CODE_LENGTH_CODE_CODES[stored(i) as usize];
+            bits.write(value, length);
+        }
+
+        for (symbol, extra) in runs {
+            if !single {
+                run_code.write(symbol as usize, bits);
+            }
+            match symbol {
+                16 => bits.write(extra, 2),
+                17 => bits.write(extra, 3),
+                _ => {}
+            }
+        }
+        PrefixCode::new(lengths)
+    }
+}
+
+/// Describe `lengths`, up to the last non-zero one, as code length symbols and the
+/// value of their extra bits. Runs use 16 to repeat the last non-zero length 3 to 6
+/// times and 17 to repeat zero 3 to 10 times, but never twice in a row, since a
+/// second one would multiply the first instead of adding to it.
+fn run_lengths(lengths: &[u8]) -> Vec<(u8, u32)> {
+    let end = lengths
+        .iter()
+        .rposition(|&length| length > 0)
+        .map_or(0, |i| i + 1);
+    let mut runs = Vec::new();
+    let mut previous = 8;
+    let mut i = 0;
+
+    while i < end {
+        let length = lengths[i];
+        let run = lengths[i..end].iter().take_while(|&&l| l == length).count();
+        let last = runs.last().map(|&(symbol, _)| symbol);
+
+        if length == 0 && run >= 3 && last != Some(17) {
+            let run = run.min(10);
+            runs.push((17, run as u32 - 3));
+            i += run;
+        } else if length != 0 && length == previous && run >= 3 && last != Some(16) {
+            let run = run.min(6);
+            runs.push((16, run as u32 - 3));
+            i += run;
+        } else {
+            runs.push((length, 0));
+            if length != 0 {
+                previous = length;
+            }
+            i += 1;
+        }
+    }
+    runs
+}
+
+/// Return the lengths of a prefix code for symbols occurring `counts` times, none
+/// longer than `max_length`. Symbols that never occur get no code.
+fn code_lengths(counts: &[u32], max_length: u8) -> Vec<u8> {
+    // Raising the rarest counts flattens the tree until it is short enough.
+    for floor in (0..).map(|shift| 1u32 << shift) {
+        let lengths = huffman_lengths(counts, floor);
+        if lengths.iter().all(|&length| length <= max_length) {
+            return lengths;
+        }
+    }
+    unreachable!()
+}
+
+fn huffman_lengths(counts: &[u32], floor: u32) -> Vec<u8> {
+    // Leaves are the symbols, followed by the internal nodes as they are made.
+    let mut parents = vec![usize::MAX; counts.len()];
+    let mut heap: BinaryHeap<_> = counts
+        .iter()
+        .enumerate()
+        .filter(|(_, &count)| count > 0)
+        .map(|(symbol, &count)| Reverse((count.max(floor) as u64, symbol)))
+        .collect();
+
+    if heap.len() == 1 {
+        let Reverse((_, symbol)) = heap.pop().unwrap();
+        let mut lengths = vec![0; counts.len()];
+        lengths[symbol] = 1;
+        return lengths;
+    }
+    while heap.len() > 1 {
+        let Reverse((first, a)) = heap.pop().unwrap();
+        let Reverse((second, b)) = heap.pop().unwrap();
+        let node = parents.len();
+        parents.push(usize::MAX);
+        parents[a] = node;
+        parents[b] = node;
+        heap.push(Reverse((first + second, node)));
+    }
+
+    (0..counts.len())
+        .map(|symbol| {
+            if counts[symbol] == 0 {
+                return 0;
+            }
+            let mut depth = 0;
+            let mut node = symbol;
+            while parents[node] != usize::MAX {
+                node = parents[node];
+                depth += 1;
+            }
+            depth
+        })
+        .collect()
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    /// Reads bits least significant first, for decoding what the encoder wrote.
+    struct BitReader<'a> {
+        data: &'a [u8],
+        position: usize,
+    }
+
+    impl BitReader<'_> {
+        fn bits(&mut self, count: u32) -> u32 {
+            let mut value = 0;
+            for i in 0..count {
+                let byte = self
+                    .data
+                    .get(self.position / 8)
+                    .expect("stream is truncated");
+                value |= ((byte >> (self.position % 8) & 1) as u32) << i;
+                self.position += 1;
+            }
+            value
+        }
+
+        /// Skip to the next byte, which must only take zero bits.
+        fn align(&mut self) {
+            let padding = (8 - self.position % 8) % 8;
+            assert_eq!(self.bits(padding as u32), 0, "padding is not zero");
+        }
+
+        /// A number from 1 to 256, as block type and tree counts are stored.
+        fn var_len_u8(&mut self) -> u32 {
+            if self.bits(1) == 0 {
+                return 1;
+            }
+            match self.bits(3) {
+                0 => 2,
+                bits => (1 << bits) + self.bits(bits) + 1,
+            }
+        }
+    }
+
+    /// A prefix code read from a stream, by the length and bits of each code.
+    struct Decoder {
+        symbols: HashMap<(u32, u32), usize>,
+        /// The symbol of a code with only one, which takes no bits.
+        only: Option<usize>,
+    }
+
+    impl Decoder {
+        /// Assign canonical codes to the symbols with lengths, in order of length and
+        /// then of symbol.
+        fn new(lengths: &[u32]) -> Decoder {
+            let mut coded: Vec<_> = (0..lengths.len()).filter(|&s| lengths[s] > 0).collect();
+            coded.sort_by_key(|&symbol| (lengths[symbol], symbol));
+            if let [only] = coded[..] {
+                return Decoder {
+                    symbols: HashMap::new(),
+                    only: Some(only),
+                };
+            }
+            let mut symbols = HashMap::new();
+            let (mut code, mut length) = (0, 0);
+            for symbol in coded {
+                code <<= lengths[symbol] - length;
+                length = lengths[symbol];
+                symbols.insert((length, code), symbol);
+                code += 1;
+            }
+            assert_eq!(code, 1 << length, "prefix code is not complete");
+            Decoder {
+                symbols,
+                only: None,
+            }
+        }
+
+        fn read(&self, reader: &mut BitReader) -> usize {
+            if let Some(only) = self.only {
+                return only;
+            }
+            let mut code = 0;
+            for length in 1..=MAX_CODE_LENGTH as u32 {
+                code = code << 1 | reader.bits(1);
+                if let Some(&symbol) = self.symbols.get(&(length, code)) {
+                    return symbol;
+                }
+            }
+            panic!("invalid prefix code");
+        }
+
+        /// Read the description of a prefix code over `alphabet_size` symbols.
+        fn read_from(reader: &mut BitReader, alphabet_size: usize) -> Decoder {
+            let skip = reader.bits(2);
+            let mut lengths = vec![0; alphabet_size];
+            if skip == 1 {
+                let bits = (alphabet_size - 1).ilog2() + 1;
+                let count = reader.bits(2) as usize + 1;
+                let symbols: Vec<_> = (0..count).map(|_| reader.bits(bits) as usize).collect();
+                let shape: &[u32] = match count {
+                    1 => &[1],
+                    2 => &[1, 1],
+                    3 => &[1, 2, 2],
+                    _ if reader.bits(1) == 0 => &[2, 2, 2, 2],
+                    _ => &[1, 2, 3, 3],
+                };
+                for (&symbol, &length) in symbols.iter().zip(shape) {
+                    assert!(
+                        lengths.get(symbol) == Some(&0),
+                        "symbol is out of range or repeated"
+                    );
+                    lengths[symbol] = length;
+                }
+                return Decoder::new(&lengths);
+            }
+
+            let mut code_lengths = [0; 18];
+            let (mut space, mut coded) = (32, 0);
+            for &symbol in &CODE_LENGTH_ORDER[skip as usize..] {
+                let length = match reader.bits(2) {
+                    0 => 0,
+                    1 => 4,
+                    2 => 3,
+                    _ if reader.bits(1) == 0 => 2,
+                    _ if reader.bits(1) == 0 => 1,
+                    _ => 5,
+                };
+                code_lengths[symbol] = length;
+                if length > 0 {
+                    coded += 1;
+                    space -= 32 >> length;
+                    if space <= 0 {
+                        break;
+                    }
+                }
+            }
+            assert!(coded == 1 || space == 0, "code length code is not complete");
+            let code_length_code = Decoder::new(&code_lengths);
+
+            let (mut space, mut previous, mut symbol) = (32768, 8, 0);
+            let (mut repeat, mut repeated) = (0, 0);
+            while symbol < alphabet_size && space > 0 {
+                let code = code_length_code.read(reader) as u32;
+                if code < 16 {
+                    repeat = 0;
+                    lengths[symbol] = code;
+                    if code > 0 {
+                        previous = code;
+                        space -= 32768 >> code;
+                    }
+                    symbol += 1;
+                    continue;
+                }
+                let (extra, length) = if code == 16 { (2, previous) } else { (3, 0) };
+                if repeated != length {
+                    (repeat, repeated) = (0, length);
+                }
+                let before = repeat;
+                if repeat > 0 {
+                    repeat = (repeat - 2) << extra;
+                }
+                repeat += reader.bits(extra) as usize + 3;
+                let added = repeat - before;
+                assert!(
+                    symbol + added <= alphabet_size,
+                    "repeat runs past the alphabet"
+                );
+                lengths[symbol..symbol + added].fill(length);
+                symbol += added;
+                if length > 0 {
+                    space -= added as i32 * (32768 >> length);
+                }
+            }
+            assert_eq!(space, 0, "prefix code is not complete");
+            Decoder::new(&lengths)
+        }
+    }
+
+    /// Decode a brotli stream, as long as it uses one block type and one prefix code
+    /// for each kind of symbol and no dictionary words, which is all the encoder
+    /// writes. Panics on anything else, and on streams that are not valid.
+    pub(crate) fn decompress(brotli: &[u8]) -> Vec<u8> {
+        let mut reader = BitReader {
+            data: brotli,
+            position: 0,
+        };
+        let window_bits = match reader.bits(1) {
+            0 => 16,
+            _ => match reader.bits(3) {
+                0 => match reader.bits(3) {
+                    1 => panic!("invalid window size"),
+                    0 => 17,
+                    bits => 8 + bits,
+                },
+                bits => 17 + bits,
+            },
+        };
+        let window = (1 << window_bits) - 16;
+        let mut out: Vec<u8> = Vec::new();
+        // The last distances, most recent first.
+        let mut distances = [4, 11, 15, 16];
+
+        loop {
+            let last = reader.bits(1) == 1;
+            if last && reader.bits(1) == 1 {
+                break;
+            }
+            let nibbles = match reader.bits(2) {
+                3 => {
+                    assert!(!last, "the last meta-block holds metadata");
+                    assert_eq!(reader.bits(1), 0, "reserved bit is set");
+                    let bytes = reader.bits(2);
+                    let skip = reader.bits(8 * bytes) as usize + (bytes > 0) as usize;
+                    reader.align();
+                    reader.position += 8 * skip;
+                    continue;
+                }
+                nibbles => nibbles + 4,
+            };
+            let length = reader.bits(4 * nibbles) as usize + 1;
+            assert!(
+                nibbles == 4 || (length - 1) >> (4 * nibbles - 4) > 0,
+                "length has a needless nibble"
+            );
+            if !last && reader.bits(1) == 1 {
+                reader.align();
+                let start = reader.position / 8;
+                out.extend_from_slice(&brotli[start..start + length]);
+                reader.position += 8 * length;
+                continue;
+            }
+
+            for kind in ["literal", "command", "distance"] {
+                assert_eq!(reader.var_len_u8(), 1, "{kind} block switching");
+            }
+            let postfix_bits = reader.bits(2);
+            let direct = reader.bits(4) << postfix_bits;
+            reader.bits(2);
+            assert_eq!(reader.var_len_u8(), 1, "literal context map");
+            assert_eq!(reader.var_len_u8(), 1, "distance context map");
+            let literals = Decoder::read_from(&mut reader, 256);
+            let commands = Decoder::read_from(&mut reader, COMMAND_ALPHABET_SIZE);
+            let distance_alphabet = 16 + direct as usize + (48 << postfix_bits);
+            let distance_codes = Decoder::read_from(&mut reader, distance_alphabet);
+
+            let end = out.len() + length;
+            while out.len() < end {
+                let command = commands.read(&mut reader);
+                let (insert_range, copy_range) = match command >> 6 {
+                    0 | 2 => (0, 0),
+                    1 | 3 => (0, 1),
+                    4 => (1, 0),
+                    5 => (1, 1),
+                    6 => (0, 2),
+                    7 => (2, 0),
+                    8 => (1, 2),
+                    9 => (2, 1),
+                    _ => (2, 2),
+                };
+                let insert = insert_range * 8 + (command >> 3 & 7);
+                let copy = copy_range * 8 + (command & 7);
+                let insert = INSERT_BASE[insert] + reader.bits(INSERT_EXTRA[insert]);
+                let copy = COPY_BASE[copy] + reader.bits(COPY_EXTRA[copy]);
+
+                assert!(
+                    out.len() + insert as usize <= end,
+                    "literals run past the end"
+                );
+                for _ in 0..insert {
+                    out.push(literals.read(&mut reader) as u8);
+                }
+                if out.len() == end {
+                    break;
+                }
+
+                let code = if command < 128 {
+                    0
+                } else {
+                    distance_codes.read(&mut reader) as u32
+                };
+                let distance = match code {
+                    0..=3 => distances[code as usize],
+                    4..=9 => {
+                        let offset = [-1, 1, -2, 2, -3, 3][code as usize - 4];
+                        (distances[0] as i64 + offset) as usize
+                    }
+                    10..=15 => {
+                        let offset = [-1, 1, -2, 2, -3, 3][code as usize - 10];
+                        (distances[1] as i64 + offset) as usize
+                    }
+                    _ if code < 16 + direct => (code - 15) as usize,
+                    _ => {
+                        let code = code - direct - 16;
+                        let extra_bits = 1 + (code >> (postfix_bits + 1));
+                        let high = code >> postfix_bits & 1;
+                        let low = code & ((1 << postfix_bits) - 1);
+                        let offset = ((2 + high) << extra_bits) - 4;
+                        let extra = reader.bits(extra_bits);
+                        (((offset + extra) << postfix_bits) + low + direct + 1) as usize
+                    }
+                };
+                assert!(distance > 0, "distance is not positive");
+                assert!(
+                    distance <= out.len().min(window),
+                    "dictionary words are not supported"
+                );
+                if code > 0 {
+                    distances = [distance, distances[0], distances[1], distances[2]];
+                }
+                assert!(out.len() + copy as usize <= end, "copy runs past the end");
+                for _ in 0..copy {
+                    out.push(out[out.len() - distance]);
+                }
+            }
+            if last {
+                break;
+            }
+        }
+        reader.align();
+        assert_eq!(reader.position / 8, brotli.len(), "bytes after the stream");
+        out
+    }
+
+    /// The sum of 2^-length over the coded symbols, scaled so a complete code gives
+    /// exactly `1 << MAX_CODE_LENGTH`.
+    fn kraft_sum(lengths: &[u8]) -> u32 {
+        lengths
+            .iter()
+            .filter(|&&length| length > 0)
+            .map(|&length| 1 << (MAX_CODE_LENGTH - length))
+            .sum()
+    }
+
+    #[test]
+    fn limits_code_lengths_and_keeps_codes_complete() {
+        // Fibonacci counts make the deepest possible tree.
+        let mut counts: Vec<u32> = vec![1, 1];
+        while counts.len() < 30 {
+            counts.push(counts[counts.len() - 1] + counts[counts.len() - 2]);
+        }
+        counts.extend([0; 10]);
+
+        let lengths = code_lengths(&counts, MAX_CODE_LENGTH);
+        assert!(lengths.iter().all(|&length| length <= MAX_CODE_LENGTH));
+        assert_eq!(kraft_sum(&lengths), 1 << MAX_CODE_LENGTH);
+        assert!(lengths[30..].iter().all(|&length| length == 0));
+
+        let lengths = code_lengths(&[5, 0, 1, 1], MAX_CODE_LENGTH);
+        assert_eq!(lengths, [1, 0, 2, 2]);
+        let codes = PrefixCode::new(lengths).codes;
+        assert_eq!((codes[0], codes[2], codes[3]), (0b0, 0b10, 0b11));
+    }
+
+    #[test]
+    fn describes_code_lengths_with_runs() {
+        let lengths = [8, 8, 8, 8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 3, 3, 0];
+        assert_eq!(
+            run_lengths(&lengths),
+            // The first run repeats the initial length of 8, and zeros after ten need
+            // a literal zero before the next run.
+            [(16, 1), (17, 7), (0, 0), (0, 0), (3, 0), (3, 0)]
+        );
+        assert_eq!(
+            run_lengths(&[0, 0, 0, 0, 5, 5, 5, 5, 5, 5, 5, 5, 5]),
+            [(17, 1), (5, 0), (16, 3), (5, 0), (5, 0)]
+        );
+    }
+
+    #[test]
+    fn maps_lengths_and_distances_to_codes() {
+        assert_eq!(distance_code(1), (16, 0, 1));
+        assert_eq!(distance_code(2), (16, 1, 1));
+        assert_eq!(distance_code(3), (17, 0, 1));
+        assert_eq!(distance_code(5), (18, 0, 2));
+        assert_eq!(distance_code(32768), (16 + 2 * 13, 3, 14));
+
+        let command = |literals: usize, copy| Command {
+            literals: 0..literals,
+            copy: Some((copy, 1)),
+        };
+        assert_eq!(command(0, 2).symbol(), 128);
+        assert_eq!(command(5, 9).symbol(), 128 + (5 << 3 | 7));
+        assert_eq!(command(6, 10).symbol(), 192 + (6 << 3));
+        assert_eq!(command(100, 300).symbol(), 512 + (7 << 3 | 3));
+        assert_eq!(command(200, 400).symbol(), 640 + (1 << 3 | 4));
+        assert_eq!(
+            Command {
+                literals: 0..65536,
+                copy: None
+            }
+            .symbol(),
+            448 + (7 << 3)
+        );
+    }
+
+    #[test]
+    fn round_trips_at_each_quality_and_across_meta_blocks() {
+        let text: Vec<u8> = (0..BLOCK_SIZE * 2 + 100)
+            .map(|i| b"the quick brown fox "[i % 20] ^ (i / 997) as u8)
+            .collect();
+        // Bytes from a linear congruential generator, which hardly repeat.
+        let mut state = 1u32;
+        let noise: Vec<u8> = (0..BLOCK_SIZE + 1)
+            .map(|_| {
+                state = state.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+                (state >> 16) as u8
+            })
+            .collect();
+
+        for quality in [0, 1, 5, MAX_QUALITY] {
+            assert_eq!(compress(b"", quality), [0x06]);
+            for data in [&b"a"[..], b"abcabcabcabc", &text, &noise] {
+                let compressed = compress(data, quality);
+                assert_eq!(decompress(&compressed), data, "quality {quality}");
+            }
+            if quality > 0 {
+                assert!(compress(&text, quality).len() < text.len() / 4);
+            }
+        }
+
+        let mut streamed = Vec::new();
+        BrotliEncoder::new(&text[..], DEFAULT_QUALITY)
+            .read_to_end(&mut streamed)
+            .unwrap();
+        assert_eq!(decompress(&streamed), text);
+    }
+
+    #[test]
+    fn decodes_streams_from_the_reference_encoder() {
+        // From the reference encoder with a 64 KiB window, at quality 0 for the empty
+        // stream and 2 and 5 for the others, none of them using dictionary words.
+        assert_eq!(decompress(&[0x33]), b"");
+        let text = b"abcabcabcabc xyzzy abcabc xyzzy xyzzy abcabcabc!";
+        let quality_2 = [
+            0xe2, 0x05, 0x00, 0x00, 0x50, 0x55, 0x55, 0x55, 0xfd, 0x9f, 0xae, 0xcc, 0xe7, 0xe3,
+            0x72, 0xb9, 0x2c, 0x03, 0x70, 0x30, 0x16, 0x44, 0x62, 0x92, 0x18, 0xe0, 0x4e, 0x1b,
+            0xf9, 0x72, 0x10, 0x8f, 0xa3, 0xfb, 0x07, 0x05, 0x42, 0x78, 0x21, 0x8a, 0x15, 0xc9,
+            0x21,
+        ];
+        assert_eq!(decompress(&quality_2), text);
+        let quality_5 = [
+            0xe2, 0x05, 0x00, 0x80, 0x78, 0xc0, 0xd5, 0xd2, 0x89, 0x19, 0xf4, 0x27, 0xa2, 0x04,
+            0x13, 0x8f, 0x22, 0x28, 0xe4, 0x78, 0x74, 0xff, 0x10, 0x69, 0xe5, 0x10,
+        ];
+        assert_eq!(decompress(&quality_5), text);
+    }
+}
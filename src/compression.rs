@@ -0,0 +1,351 @@
+#[cfg(feature = "brotli")]
+use crate::brotli::{self, BrotliEncoder};
+use crate::{
+    gzip::GzipEncoder,
+    request::Request,
+    response::{Response, Status},
+};
+
+/// Compresses response bodies for clients that accept it, for use as router
+/// middleware.
+///
+/// Only responses whose `Content-Type` is on the allowlist are compressed, since
+/// images, archives and fonts are compressed already and would only get bigger.
+///
+/// With the `brotli` feature, clients that accept `br` get brotli, which makes text
+/// smaller than gzip does, and the rest get gzip.
+/// ```
+/// use ch20_web_server::{compression::Compression, router::Router};
+///
+/// let compression = Compression::default()
+///     .min_size(256)
+///     .content_type("application/x-ndjson");
+/// let router = Router::new().wrap(move |request, next| {
+///     compression.apply(request, next(request))
+/// });
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Compression {
+    min_size: u64,
+    content_types: Vec<String>,
+    #[cfg(feature = "brotli")]
+    brotli_quality: u32,
+    /// Brotli qualities by type pattern, with later ones taking precedence.
+    #[cfg(feature = "brotli")]
+    brotli_qualities: Vec<(String, u32)>,
+}
+
+impl Default for Compression {
+    /// Compress text, JSON, JavaScript, XML, SVG and WebAssembly bodies of at least 1
+    /// KiB.
+    fn default() -> Compression {
+        Compression {
+            min_size: 1024,
+            content_types: [
+                "text/*",
+                "application/json",
+                "application/javascript",
+                "application/xml",
+                "application/manifest+json",
+                "application/wasm",
+                "image/svg+xml",
+            ]
+            .map(String::from)
+            .to_vec(),
+            #[cfg(feature = "brotli")]
+            brotli_quality: brotli::DEFAULT_QUALITY,
+            #[cfg(feature = "brotli")]
+            brotli_qualities: Vec::new(),
+        }
+    }
+}
+
+impl Compression {
+    /// Leave bodies shorter than `bytes` uncompressed, as the saving would not be worth
+    /// the work. Bodies of unknown length are always compressed.
+    pub fn min_size(mut self, bytes: u64) -> Compression {
+        self.min_size = bytes;
+        self
+    }
+
+    /// Add `mime` to the allowlist of types that are compressed. A type ending in `/*`,
+    /// such as `text/*`, allows every subtype.
+    pub fn content_type(mut self, mime: impl Into<String>) -> Compression {
+        self.content_types.push(mime.into().to_ascii_lowercase());
+        self
+    }
+
+    /// Remove every type from the allowlist, so only those added afterwards with
+    /// `content_type` are compressed.
+    pub fn clear_content_types(mut self) -> Compression {
+        self.content_types.clear();
+        self
+    }
+
+    /// Compress with brotli at `quality`, from 0 to `brotli::MAX_QUALITY`, unless
+    /// `brotli_quality_for` sets another for the type. It is `brotli::DEFAULT_QUALITY`
+    /// otherwise.
+    #[cfg(feature = "brotli")]
+    pub fn brotli_quality(mut self, quality: u32) -> Compression {
+        self.brotli_quality = quality.min(brotli::MAX_QUALITY);
+        self
+    }
+
+    /// Compress types matching `mime` with brotli at `quality`, such as a high one for
+    /// `text/*` files that are served often and a low one for large JSON responses
+    /// made on the fly. When several patterns match, the last one added is used.
+    /// ```
+    /// use ch20_web_server::compression::Compression;
+    ///
+    /// let compression = Compression::default()
+    ///     .brotli_quality_for("text/*", 9)
+    ///     .brotli_quality_for("application/json", 2);
+    /// ```
+    #[cfg(feature = "brotli")]
+    pub fn brotli_quality_for(mut self, mime: impl Into<String>, quality: u32) -> Compression {
+        self.brotli_qualities.push((
+            mime.into().to_ascii_lowercase(),
+            quality.min(brotli::MAX_QUALITY),
+        ));
+        self
+    }
+
+    /// Compress `response` with the best encoding `request` accepts, if its type is on
+    /// the allowlist and it is not compressed already.
+    pub fn apply(&self, request: &Request, response: Response) -> Response {
+        if !self.compressible(&response) {
+            return response;
+        }
+        // The response now depends on Accept-Encoding, whether or not it is compressed.
+        let response = add_vary(response, "Accept-Encoding");
+
+        if response
+            .body_len()
+            .is_some_and(|length| length < self.min_size)
+        {
+            return response;
+        }
+        let coding = request
+            .header("Accept-Encoding")
+            .and_then(|accepted| preferred(accepted, CODINGS));
+        match coding {
+            #[cfg(feature = "brotli")]
+            Some(coding @ "br") => {
+                let quality = self.brotli_quality_of(&response);
+                response
+                    .encode_body(move |body| Box::new(BrotliEncoder::new(body, quality)))
+                    .header("Content-Encoding", coding)
+            }
+            Some(coding) => response
+                .encode_body(|body| Box::new(GzipEncoder::new(body)))
+                .header("Content-Encoding", coding),
+            None => response,
+        }
+    }
+
+    fn compressible(&self, response: &Response) -> bool {
+        let status = response.status();
+        if status.forbids_body()
+            || status == Status::PartialContent
+            || response.headers().contains("Content-Encoding")
+        {
+            return false;
+        }
+
+        let Some(mime) = media_type(response) else {
+            return false;
+        };
+        self.content_types
+            .iter()
+            .any(|allowed| matches_type(allowed, &mime))
+    }
+
+    #[cfg(feature = "brotli")]
+    fn brotli_quality_of(&self, response: &Response) -> u32 {
+        let mime = media_type(response).unwrap_or_default();
+        self.brotli_qualities
+            .iter()
+            .rev()
+            .find(|(pattern, _)| matches_type(pattern, &mime))
+            .map_or(self.brotli_quality, |&(_, quality)| quality)
+    }
+}
+
+/// Return the lowercase media type of `response`, without parameters.
+fn media_type(response: &Response) -> Option<String> {
+    let content_type = response.headers().get("Content-Type")?;
+    let mime = content_type.split(';').next().unwrap_or_default();
+    Some(mime.trim().to_ascii_lowercase())
+}
+
+/// The codings responses can be compressed with, in order of preference.
+#[cfg(feature = "brotli")]
+const CODINGS: &[&str] = &["br", "gzip"];
+#[cfg(not(feature = "brotli"))]
+const CODINGS: &[&str] = &["gzip"];
+
+/// Whether the lowercase media type `mime` matches `pattern`, which may end in `/*`
+/// to match every subtype.
+fn matches_type(pattern: &str, mime: &str) -> bool {
+    match pattern.strip_suffix("/*") {
+        Some(kind) => mime.split_once('/').is_some_and(|(other, _)| other == kind),
+        None => pattern == mime,
+    }
+}
+
+/// Add `name` to the response's `Vary` header.
+pub(crate) fn add_vary(response: Response, name: &str) -> Response {
+    let vary = match response.headers().get("Vary") {
+        None => name.to_owned(),
+        Some(vary)
+            if vary
+                .split(',')
+                .any(|field| field.trim() == "*" || field.trim().eq_ignore_ascii_case(name)) =>
+        {
+            return response
+        }
+        Some(vary) => format!("{vary}, {name}"),
+    };
+    response.header("Vary", vary)
+}
+
+/// Pick the coding from `supported`, in order of preference, that the
+/// `Accept-Encoding` value `accepted` gives the highest weight, if it accepts any.
+fn preferred<'a>(accepted: &str, supported: &[&'a str]) -> Option<&'a str> {
+    let mut weights = Vec::new();
+    for entry in accepted.split(',') {
+        let mut parts = entry.split(';');
+        let coding = parts.next().unwrap_or_default().trim();
+        let weight = parts
+            .find_map(|parameter| {
+                let (name, value) = parameter.split_once('=')?;
+                name.trim()
+                    .eq_ignore_ascii_case("q")
+                    .then(|| value.trim().parse::<f32>().ok())?
+            })
+            .unwrap_or(1.0);
+        weights.push((coding, weight));
+    }
+
+    let weight = |coding: &str| {
+        let explicit = weights
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(coding));
+        explicit
+            .or_else(|| weights.iter().find(|(name, _)| *name == "*"))
+            .map_or(0.0, |(_, weight)| *weight)
+    };
+
+    let mut best: Option<(&str, f32)> = None;
+    for &coding in supported {
+        let weight = weight(coding);
+        if weight > 0.0 && best.is_none_or(|(_, best)| weight > best) {
+            best = Some((coding, weight));
+        }
+    }
+    best.map(|(coding, _)| coding)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(accept_encoding: &str) -> Request {
+        let raw = format!("GET / HTTP/1.1\r\nAccept-Encoding: {accept_encoding}\r\n\r\n");
+        Request::read_from(&mut raw.as_bytes()).unwrap()
+    }
+
+    fn page(length: usize) -> Response {
+        Response::new(Status::Ok)
+            .header("Content-Type", "text/html; charset=utf-8")
+            .body("<p>hello</p>".repeat(length / 12 + 1))
+    }
+
+    #[test]
+    fn negotiates_codings_by_weight() {
+        assert_eq!(preferred("gzip, deflate", &["gzip"]), Some("gzip"));
+        assert_eq!(preferred("GZIP;q=0.5", &["gzip"]), Some("gzip"));
+        assert_eq!(preferred("gzip;q=0", &["gzip"]), None);
+        assert_eq!(preferred("*;q=0.1", &["gzip"]), Some("gzip"));
+        assert_eq!(preferred("*, gzip;q=0", &["gzip"]), None);
+        assert_eq!(preferred("identity", &["gzip"]), None);
+        assert_eq!(preferred("gzip;q=0.5, br", &["br", "gzip"]), Some("br"));
+        assert_eq!(preferred("gzip, br;q=0.5", &["br", "gzip"]), Some("gzip"));
+        assert_eq!(preferred("gzip, br", &["br", "gzip"]), Some("br"));
+    }
+
+    #[test]
+    fn compresses_allowed_types_above_the_minimum_size() {
+        let compression = Compression::default();
+
+        let response = compression.apply(&request("gzip"), page(4096));
+        assert_eq!(response.headers().get("Content-Encoding"), Some("gzip"));
+        assert_eq!(response.headers().get("Vary"), Some("Accept-Encoding"));
+        assert!(response.body_len().unwrap() < 4096);
+
+        let small = compression.apply(&request("gzip"), page(100));
+        assert!(!small.headers().contains("Content-Encoding"));
+        assert_eq!(small.headers().get("Vary"), Some("Accept-Encoding"));
+
+        let refused = compression.apply(&request("identity"), page(4096));
+        assert!(!refused.headers().contains("Content-Encoding"));
+
+        let image = Response::new(Status::Ok)
+            .header("Content-Type", "image/png")
+            .body(vec![0; 4096]);
+        let image = compression.apply(&request("gzip"), image);
+        assert!(!image.headers().contains("Content-Encoding"));
+        assert!(!image.headers().contains("Vary"));
+    }
+
+    #[test]
+    fn compresses_streamed_bodies_in_chunks() {
+        let text = "line\n".repeat(1000);
+        let response = Response::new(Status::Ok)
+            .header("Content-Type", "text/plain")
+            .header("Vary", "Origin")
+            .stream_body(std::io::Cursor::new(text.clone()), text.len() as u64);
+
+        let response = Compression::default().apply(&request("gzip"), response);
+        assert!(response.is_chunked());
+        assert_eq!(
+            response.headers().get("Vary"),
+            Some("Origin, Accept-Encoding")
+        );
+
+        let mut bytes = Vec::new();
+        response.write_to(&mut bytes).unwrap();
+        let head_end = bytes.windows(4).position(|w| w == b"\r\n\r\n").unwrap();
+        assert!(String::from_utf8_lossy(&bytes[..head_end]).contains("Content-Encoding: gzip"));
+        // The gzip magic opens the first chunk.
+        let body = &bytes[head_end + 4..];
+        let chunk = body.windows(2).position(|w| w == b"\r\n").unwrap();
+        assert_eq!(&body[chunk + 2..chunk + 4], [0x1f, 0x8b]);
+    }
+
+    #[cfg(feature = "brotli")]
+    #[test]
+    fn prefers_brotli_with_qualities_by_type() {
+        let compression = Compression::default()
+            .brotli_quality(3)
+            .brotli_quality_for("text/*", 9)
+            .brotli_quality_for("text/plain", 1);
+
+        let response = compression.apply(&request("gzip, br"), page(4096));
+        assert_eq!(response.headers().get("Content-Encoding"), Some("br"));
+        assert_eq!(
+            brotli::tests::decompress(response.body_bytes()),
+            page(4096).body_bytes()
+        );
+        let response = compression.apply(&request("gzip, br;q=0.5"), page(4096));
+        assert_eq!(response.headers().get("Content-Encoding"), Some("gzip"));
+
+        let quality = |mime| {
+            let response = Response::new(Status::Ok).header("Content-Type", mime);
+            compression.brotli_quality_of(&response)
+        };
+        assert_eq!(quality("text/html; charset=utf-8"), 9);
+        assert_eq!(quality("Text/Plain"), 1);
+        assert_eq!(quality("application/json"), 3);
+    }
+}
@@ -0,0 +1,1463 @@
+//! Server settings, read from a TOML file such as `server.toml`:
+//! ```toml
+//! bind = "::"
+//! port = 8080
+//! only_v6 = false
+//! acceptors = 4
+//! threads = 8
+//! max_threads = 128
+//! root = "/srv/www"
+//!
+//! [log]
+//! level = "warn"
+//! format = "json"
+//! file = "/var/log/web/server.log"
+//! max_size = 10485760
+//! keep = 3
+//!
+//! [access_log]
+//! file = "/var/log/web/access.log"
+//! format = "common"
+//! daily = true
+//! keep = 30
+//! compress = true
+//!
+//! [trace]
+//! file = "/var/log/web/spans.log"
+//! max_size = 104857600
+//!
+//! [metrics]
+//! path = "/metrics"
+//!
+//! [health]
+//! enabled = false
+//!
+//! [admin]
+//! address = "127.0.0.1:9090"
+//! token = "change me"
+//!
+//! [api_keys]
+//! file = "/etc/web/api_keys"
+//! prefixes = ["/api"]
+//!
+//! [timeouts]
+//! idle = 15
+//! read = 10
+//! write = 60
+//! head = 10
+//! min_body_rate = 1024
+//! drain = 30
+//!
+//! [keep_alive]
+//! max_requests = 1000
+//!
+//! [rate_limit]
+//! rate = 20
+//! burst = 50
+//! clients = 10000
+//!
+//! [cors]
+//! origins = ["https://app.example.com"]
+//! methods = ["GET", "POST"]
+//! headers = ["Content-Type"]
+//! max_age = 600
+//! credentials = false
+//!
+//! # "" leaves a header out.
+//! [security_headers]
+//! strict_transport_security = "max-age=31536000"
+//! content_type_options = "nosniff"
+//! frame_options = "DENY"
+//! referrer_policy = "no-referrer"
+//! content_security_policy = "default-src 'self'"
+//!
+//! [[security_headers.route]]
+//! prefix = "/embed"
+//! frame_options = ""
+//!
+//! [limits]
+//! connections = 512
+//! retry_after = 5
+//! request_line = 4096
+//! headers = 50
+//! header_size = 16384
+//! body = 1048576
+//!
+//! [socket]
+//! backlog = 1024
+//! nodelay = true
+//! keepalive = 60
+//! send_buffer = 262144
+//! receive_buffer = 262144
+//!
+//! # Listen here instead of on `bind` and `port`.
+//! [unix_socket]
+//! path = "/run/web/server.sock"
+//! mode = 0o660
+//! ```
+//! To listen in several places at once, each is given its own table, and `bind`,
+//! `port` and `unix_socket` are ignored:
+//! ```toml
+//! [[listen]]
+//! address = "0.0.0.0:80"
+//!
+//! [[listen]]
+//! address = "0.0.0.0:8080"
+//! http2 = false
+//!
+//! [[listen]]
+//! path = "/run/web/server.sock"
+//! mode = 0o660
+//! ```
+//! Which clients may send requests is set by `[[access]]` tables, each with rules for
+//! every path, or for those under its `prefix`:
+//! ```toml
+//! [[access]]
+//! rules = ["deny 10.0.0.0/8"]
+//!
+//! [[access]]
+//! prefix = "/admin"
+//! rules = ["allow 192.168.1.0/24", "deny all"]
+//! ```
+//! Paths that need a user's name and password are set by `[[basic_auth]]` tables,
+//! each with a credential file in the format of `htpasswd`:
+//! ```toml
+//! [[basic_auth]]
+//! prefix = "/private"
+//! file = "/etc/web/staff.htpasswd"
+//! realm = "Staff"
+//! ```
+//! Every key is optional, and keys that are left out keep the values of
+//! `Config::default`.
+
+use std::{
+    collections::BTreeMap,
+    fmt, fs, io,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+
+use crate::{
+    access_log::Format,
+    acl::{Acl, Rule},
+    connection::{KeepAlive, Timeouts},
+    cors::Cors,
+    listener::StreamOptions,
+    log_file::Rotation,
+    rate_limit::RateLimit,
+    request::{Limits, Method},
+    security_headers::SecurityHeaders,
+    toml::{self, Entry, Value},
+};
+
+/// How much the server reports about what it is doing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LogLevel {
+    Off,
+    Error,
+    Warn,
+    Info,
+    Debug,
+}
+
+impl LogLevel {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LogLevel::Off => "off",
+            LogLevel::Error => "error",
+            LogLevel::Warn => "warn",
+            LogLevel::Info => "info",
+            LogLevel::Debug => "debug",
+        }
+    }
+}
+
+impl FromStr for LogLevel {
+    type Err = ();
+
+    fn from_str(level: &str) -> Result<LogLevel, ()> {
+        match level.to_ascii_lowercase().as_str() {
+            "off" => Ok(LogLevel::Off),
+            "error" => Ok(LogLevel::Error),
+            "warn" => Ok(LogLevel::Warn),
+            "info" => Ok(LogLevel::Info),
+            "debug" => Ok(LogLevel::Debug),
+            _ => Err(()),
+        }
+    }
+}
+
+/// How each message is written to the log.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogFormat {
+    /// The message as it is, after the id of the request it was written for in
+    /// brackets if there is one.
+    Text,
+    /// An object with `ts`, `level`, `request_id` and `msg`.
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = ();
+
+    fn from_str(format: &str) -> Result<LogFormat, ()> {
+        match format.to_ascii_lowercase().as_str() {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(()),
+        }
+    }
+}
+
+/// The settings of a server.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Config {
+    /// The address to listen on.
+    pub bind: IpAddr,
+    pub port: u16,
+    /// Whether an IPv6 `bind` address refuses IPv4 clients, or `None` to leave it to
+    /// the system.
+    pub only_v6: Option<bool>,
+    /// How many sockets each TCP address is listened on with, each accepted on by
+    /// its own thread. More than one share the address with `SO_REUSEPORT`.
+    pub acceptors: usize,
+    /// How many workers the pool keeps running.
+    pub threads: usize,
+    /// How many workers the pool grows to under load.
+    pub max_threads: usize,
+    /// The directory files are served from.
+    pub root: PathBuf,
+    pub log_level: LogLevel,
+    pub log_format: LogFormat,
+    /// The file to append log lines to, or `None` for standard error.
+    pub log_file: Option<PathBuf>,
+    pub log_rotation: Rotation,
+    /// The file to append a line to for every request, `-` for standard output, or
+    /// `None` for no access log.
+    pub access_log: Option<PathBuf>,
+    pub access_log_format: Format,
+    pub access_log_rotation: Rotation,
+    /// The file to append a line of JSON to for every span that finishes, `-` for
+    /// standard output, or `None` to record no spans.
+    pub trace: Option<PathBuf>,
+    pub trace_rotation: Rotation,
+    /// The path Prometheus metrics are served at, or `None` to serve none and keep no
+    /// request counts.
+    pub metrics: Option<String>,
+    /// Whether `/healthz` and `/readyz` are answered, for load balancers and
+    /// Kubernetes probes to check the server with.
+    pub health: bool,
+    /// The address the admin API listens on, or `None` for no admin API. One that is
+    /// not a loopback address needs a token.
+    pub admin: Option<SocketAddr>,
+    /// The token requests to the admin API have to carry, if there is one.
+    pub admin_token: Option<String>,
+    /// The file of API keys, with a `principal:key` line for each, or `None` to
+    /// require no key.
+    pub api_keys: Option<PathBuf>,
+    /// The prefixes of the paths that need an API key.
+    pub api_key_prefixes: Vec<String>,
+    /// How fast each client address may send requests, or `None` for as fast as it
+    /// likes.
+    pub rate_limit: Option<RateLimit>,
+    /// The origins whose scripts may call the server, and what they may send, or
+    /// `None` to leave requests from other origins to browsers to refuse.
+    pub cors: Option<Cors>,
+    /// The headers added to responses to hold browsers to stricter rules, or `None`
+    /// to add none.
+    pub security_headers: Option<SecurityHeaders>,
+    /// Which clients may send requests, to every path and to those under prefixes,
+    /// set by `[[access]]` tables.
+    pub access: Acl,
+    /// The prefixes that need a user's name and password, set by `[[basic_auth]]`
+    /// tables.
+    pub basic_auth: Vec<Protected>,
+    pub keep_alive: KeepAlive,
+    pub timeouts: Timeouts,
+    /// How long a server that is stopping waits for open connections to finish.
+    pub drain_timeout: Duration,
+    /// How many connections are served at once. Those over it are answered with
+    /// `503 Service Unavailable`, rather than waiting for a worker.
+    pub max_connections: usize,
+    /// How long clients turned away are told to wait before trying again.
+    pub retry_after: Duration,
+    /// How large requests may be.
+    pub limits: Limits,
+    /// How many connections may wait to be accepted on each TCP listener.
+    pub backlog: u32,
+    /// The buffer sizes of TCP connections, or `None` for the system's defaults.
+    pub send_buffer: Option<usize>,
+    pub receive_buffer: Option<usize>,
+    /// The options set on each TCP connection.
+    pub stream_options: StreamOptions,
+    /// The Unix socket to listen on instead of `bind` and `port`.
+    pub unix_socket: Option<UnixSocket>,
+    /// The places set by `[[listen]]` tables, which replace `bind`, `port` and
+    /// `unix_socket` if there are any.
+    pub listen: Vec<Listen>,
+}
+
+/// A prefix whose paths need the name and password of a user in a credential file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Protected {
+    pub prefix: String,
+    /// The file of users and their password hashes, in the format of `htpasswd`.
+    pub file: PathBuf,
+    /// What browsers show the user when they ask for a password.
+    pub realm: String,
+}
+
+/// A place to listen, and how the connections accepted there are served.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Listen {
+    pub endpoint: Endpoint,
+    /// Whether to speak HTTP/2 to clients that ask for it.
+    pub http2: bool,
+    /// Whether an IPv6 address refuses IPv4 clients, or `None` to leave it to the
+    /// system.
+    pub only_v6: Option<bool>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Endpoint {
+    Tcp(SocketAddr),
+    Unix(UnixSocket),
+}
+
+impl fmt::Display for Endpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Endpoint::Tcp(address) => address.fmt(f),
+            Endpoint::Unix(socket) => write!(f, "unix:{}", socket.path.display()),
+        }
+    }
+}
+
+/// A Unix socket file to listen on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnixSocket {
+    pub path: PathBuf,
+    /// The permissions to give the file, or `None` to leave them to the umask.
+    pub mode: Option<u32>,
+}
+
+impl Default for Config {
+    /// Listen on 127.0.0.1:7878 with 16 to 256 workers, serving `public` and logging
+    /// at `info` to standard error. Up to 256 connections are served at once, and
+    /// stopping waits up to 10 seconds.
+    fn default() -> Config {
+        Config {
+            bind: IpAddr::V4(Ipv4Addr::LOCALHOST),
+            port: 7878,
+            only_v6: None,
+            acceptors: 1,
+            threads: 16,
+            max_threads: 256,
+            root: PathBuf::from("public"),
+            log_level: LogLevel::Info,
+            log_format: LogFormat::Text,
+            log_file: None,
+            log_rotation: Rotation::default(),
+            access_log: None,
+            access_log_format: Format::Combined,
+            access_log_rotation: Rotation::default(),
+            trace: None,
+            trace_rotation: Rotation::default(),
+            metrics: None,
+            health: true,
+            admin: None,
+            admin_token: None,
+            api_keys: None,
+            api_key_prefixes: vec![String::from("/")],
+            rate_limit: None,
+            cors: None,
+            security_headers: Some(SecurityHeaders::default()),
+            access: Acl::new(),
+            basic_auth: Vec::new(),
+            keep_alive: KeepAlive::default(),
+            timeouts: Timeouts::default(),
+            drain_timeout: Duration::from_secs(10),
+            max_connections: 256,
+            retry_after: Duration::from_secs(1),
+            limits: Limits::default(),
+            backlog: 128,
+            send_buffer: None,
+            receive_buffer: None,
+            stream_options: StreamOptions::default(),
+            unix_socket: None,
+            listen: Vec::new(),
+        }
+    }
+}
+
+impl Config {
+    /// Read the settings in the file at `path`.
+    pub fn load(path: impl AsRef<Path>) -> Result<Config, ConfigError> {
+        fs::read_to_string(path).map_err(ConfigError::Io)?.parse()
+    }
+
+    /// The socket address to listen on if there are no `[[listen]]` tables.
+    pub fn address(&self) -> SocketAddr {
+        SocketAddr::new(self.bind, self.port)
+    }
+
+    /// Every place to listen: those in `listen`, or else the one `bind`, `port` and
+    /// `unix_socket` describe.
+    pub fn listeners(&self) -> Vec<Listen> {
+        if !self.listen.is_empty() {
+            return self.listen.clone();
+        }
+        let endpoint = match &self.unix_socket {
+            Some(socket) => Endpoint::Unix(socket.clone()),
+            None => Endpoint::Tcp(self.address()),
+        };
+        vec![Listen {
+            endpoint,
+            http2: true,
+            only_v6: self.only_v6,
+        }]
+    }
+}
+
+impl FromStr for Config {
+    type Err = ConfigError;
+
+    fn from_str(text: &str) -> Result<Config, ConfigError> {
+        let entries = toml::parse(text).map_err(|error| ConfigError::Syntax {
+            line: error.line,
+            message: error.message,
+        })?;
+        let mut config = Config::default();
+        let (mut unix_path, mut unix_mode) = (None, None);
+        let (mut rate, mut burst, mut clients) = (None, None, None);
+        let mut api_key_prefixes = None;
+        let mut cors_entries = Vec::new();
+        let mut header_entries = Vec::new();
+        let mut header_routes: BTreeMap<usize, Vec<&Entry>> = BTreeMap::new();
+        // The entries of each `[[listen]]`, `[[access]]` and `[[basic_auth]]` table, by
+        // its index.
+        let mut tables: BTreeMap<usize, Vec<&Entry>> = BTreeMap::new();
+        let mut access_tables: BTreeMap<usize, Vec<&Entry>> = BTreeMap::new();
+        let mut basic_auth_tables: BTreeMap<usize, Vec<&Entry>> = BTreeMap::new();
+
+        for entry in &entries {
+            if let Some(index) = table_index("listen", &entry.key) {
+                tables.entry(index).or_default().push(entry);
+                continue;
+            }
+            if let Some(index) = table_index("access", &entry.key) {
+                access_tables.entry(index).or_default().push(entry);
+                continue;
+            }
+            if let Some(index) = table_index("security_headers.route", &entry.key) {
+                header_routes.entry(index).or_default().push(entry);
+                continue;
+            }
+            if let Some(index) = table_index("basic_auth", &entry.key) {
+                basic_auth_tables.entry(index).or_default().push(entry);
+                continue;
+            }
+            match &entry.key[..] {
+                "bind" => {
+                    config.bind = string(entry)?
+                        .parse()
+                        .map_err(|_| invalid(entry, "must be an IP address"))?
+                }
+                "port" => {
+                    config.port = integer(entry)?
+                        .try_into()
+                        .ok()
+                        .filter(|&port| port != 0)
+                        .ok_or_else(|| invalid(entry, "must be a port from 1 to 65535"))?
+                }
+                "only_v6" => config.only_v6 = Some(boolean(entry)?),
+                "acceptors" => config.acceptors = count(entry)?,
+                "threads" => config.threads = count(entry)?,
+                "max_threads" => config.max_threads = count(entry)?,
+                "root" => config.root = PathBuf::from(string(entry)?),
+                "log.level" => {
+                    config.log_level = string(entry)?.parse().map_err(|_| {
+                        invalid(entry, "must be one of off, error, warn, info or debug")
+                    })?
+                }
+                "log.format" => {
+                    config.log_format = string(entry)?
+                        .parse()
+                        .map_err(|_| invalid(entry, "must be text or json"))?
+                }
+                "log.file" => config.log_file = Some(PathBuf::from(string(entry)?)),
+                "log.max_size" | "log.daily" | "log.keep" | "log.compress" => {
+                    config.log_rotation = rotation(config.log_rotation, entry)?
+                }
+                "access_log.max_size"
+                | "access_log.daily"
+                | "access_log.keep"
+                | "access_log.compress" => {
+                    config.access_log_rotation = rotation(config.access_log_rotation, entry)?
+                }
+                "access_log.file" => config.access_log = Some(PathBuf::from(string(entry)?)),
+                "access_log.format" => {
+                    config.access_log_format = string(entry)?
+                        .parse()
+                        .map_err(|_| invalid(entry, "must be common, combined or json"))?
+                }
+                "trace.file" => config.trace = Some(PathBuf::from(string(entry)?)),
+                "trace.max_size" | "trace.daily" | "trace.keep" | "trace.compress" => {
+                    config.trace_rotation = rotation(config.trace_rotation, entry)?
+                }
+                "metrics.path" => {
+                    let path = string(entry)?;
+                    if !path.starts_with('/') {
+                        return Err(invalid(entry, "must be a path starting with /"));
+                    }
+                    config.metrics = Some(path.to_owned());
+                }
+                "health.enabled" => config.health = boolean(entry)?,
+                "admin.address" => {
+                    config.admin = Some(
+                        string(entry)?
+                            .parse()
+                            .map_err(|_| invalid(entry, "must be an IP address and port"))?,
+                    )
+                }
+                "admin.token" => config.admin_token = Some(string(entry)?.to_owned()),
+                "api_keys.file" => config.api_keys = Some(PathBuf::from(string(entry)?)),
+                "api_keys.prefixes" => api_key_prefixes = Some((entry, prefixes(entry)?)),
+                "timeouts.idle" => {
+                    config.keep_alive = config.keep_alive.idle_timeout(duration(entry)?)
+                }
+                "timeouts.read" => config.timeouts = config.timeouts.read(duration(entry)?),
+                "timeouts.write" => config.timeouts = config.timeouts.write(duration(entry)?),
+                "timeouts.head" => config.timeouts = config.timeouts.head(duration(entry)?),
+                "timeouts.min_body_rate" => {
+                    config.timeouts = config.timeouts.min_body_rate(bytes(entry)? as u64)
+                }
+                "timeouts.drain" => config.drain_timeout = duration(entry)?,
+                "rate_limit.rate" => rate = Some(per_second(entry)?),
+                "rate_limit.burst" => {
+                    let requests = count(entry)?
+                        .try_into()
+                        .map_err(|_| invalid(entry, "is too large"))?;
+                    burst = Some((entry, requests))
+                }
+                "rate_limit.clients" => clients = Some((entry, count(entry)?)),
+                key if key.starts_with("cors.") => cors_entries.push(entry),
+                key if key.starts_with("security_headers.") => header_entries.push(entry),
+                "limits.connections" => config.max_connections = count(entry)?,
+                "limits.retry_after" => config.retry_after = duration(entry)?,
+                "limits.request_line" => config.limits = config.limits.request_line(count(entry)?),
+                "limits.headers" => config.limits = config.limits.headers(count(entry)?),
+                "limits.header_size" => config.limits = config.limits.header_size(count(entry)?),
+                "limits.body" => config.limits = config.limits.body(bytes(entry)?),
+                "keep_alive.max_requests" => {
+                    config.keep_alive = config.keep_alive.max_requests(count(entry)?)
+                }
+                "socket.backlog" => {
+                    config.backlog = count(entry)?
+                        .try_into()
+                        .map_err(|_| invalid(entry, "is too large"))?
+                }
+                "socket.nodelay" => {
+                    config.stream_options = config.stream_options.nodelay(boolean(entry)?)
+                }
+                "socket.keepalive" => {
+                    config.stream_options = config.stream_options.keepalive(Some(duration(entry)?))
+                }
+                "socket.send_buffer" => config.send_buffer = Some(count(entry)?),
+                "socket.receive_buffer" => config.receive_buffer = Some(count(entry)?),
+                "unix_socket.path" => unix_path = Some(PathBuf::from(string(entry)?)),
+                "unix_socket.mode" => unix_mode = Some((entry, permissions(entry)?)),
+                _ => return Err(invalid(entry, "is not a known setting")),
+            }
+        }
+
+        match (unix_path, unix_mode) {
+            (Some(path), mode) => {
+                config.unix_socket = Some(UnixSocket {
+                    path,
+                    mode: mode.map(|(_, mode)| mode),
+                })
+            }
+            (None, Some((entry, _))) => {
+                return Err(invalid(entry, "needs `unix_socket.path` to be set"))
+            }
+            (None, None) => {}
+        }
+        match (&config.api_keys, api_key_prefixes) {
+            (Some(_), Some((_, prefixes))) => config.api_key_prefixes = prefixes,
+            (None, Some((entry, _))) => {
+                return Err(invalid(entry, "needs `api_keys.file` to be set"))
+            }
+            (_, None) => {}
+        }
+        match (rate, burst, clients) {
+            (Some(rate), burst, clients) => {
+                let mut limit = RateLimit::new(rate);
+                if let Some((_, requests)) = burst {
+                    limit = limit.burst(requests);
+                }
+                if let Some((_, clients)) = clients {
+                    limit = limit.clients(clients);
+                }
+                config.rate_limit = Some(limit);
+            }
+            (None, Some((entry, _)), _) | (None, None, Some((entry, _))) => {
+                return Err(invalid(entry, "needs `rate_limit.rate` to be set"))
+            }
+            (None, None, None) => {}
+        }
+        if !cors_entries.is_empty() {
+            config.cors = Some(cors(&cors_entries)?);
+        }
+        config.security_headers = security_headers(&header_entries, &header_routes)?;
+        for table in tables.values() {
+            config.listen.push(listen(table)?);
+        }
+        for table in access_tables.values() {
+            config.access = access(config.access, table)?;
+        }
+        for table in basic_auth_tables.values() {
+            config.basic_auth.push(basic_auth(table)?);
+        }
+        if config.max_threads < config.threads {
+            let find = |key| entries.iter().find(|entry| entry.key == key);
+            return Err(match find("max_threads") {
+                Some(entry) => invalid(entry, "must be at least `threads`"),
+                None => invalid(find("threads").unwrap(), "must be at most `max_threads`"),
+            });
+        }
+        Ok(config)
+    }
+}
+
+fn invalid(entry: &Entry, message: &str) -> ConfigError {
+    ConfigError::Invalid {
+        line: entry.line,
+        key: entry.key.clone(),
+        message: message.to_owned(),
+    }
+}
+
+fn mismatched(entry: &Entry, expected: &str) -> ConfigError {
+    invalid(
+        entry,
+        &format!("must be {expected}, not {}", entry.value.kind()),
+    )
+}
+
+fn string(entry: &Entry) -> Result<&str, ConfigError> {
+    match &entry.value {
+        Value::String(string) => Ok(string),
+        _ => Err(mismatched(entry, "a string")),
+    }
+}
+
+fn boolean(entry: &Entry) -> Result<bool, ConfigError> {
+    match entry.value {
+        Value::Boolean(boolean) => Ok(boolean),
+        _ => Err(mismatched(entry, "a boolean")),
+    }
+}
+
+fn integer(entry: &Entry) -> Result<i64, ConfigError> {
+    match entry.value {
+        Value::Integer(integer) => Ok(integer),
+        _ => Err(mismatched(entry, "an integer")),
+    }
+}
+
+/// A number of things, of which there has to be at least one.
+fn count(entry: &Entry) -> Result<usize, ConfigError> {
+    integer(entry)?
+        .try_into()
+        .ok()
+        .filter(|&count| count > 0)
+        .ok_or_else(|| invalid(entry, "must be at least 1"))
+}
+
+/// A number of bytes, which may be none.
+fn bytes(entry: &Entry) -> Result<usize, ConfigError> {
+    integer(entry)?
+        .try_into()
+        .map_err(|_| invalid(entry, "must not be negative"))
+}
+
+/// Change the setting of `rotation` that `entry` is for.
+fn rotation(rotation: Rotation, entry: &Entry) -> Result<Rotation, ConfigError> {
+    let (_, key) = entry.key.rsplit_once('.').unwrap();
+    Ok(match key {
+        "max_size" => rotation.max_size(Some(count(entry)? as u64)),
+        "daily" => rotation.daily(boolean(entry)?),
+        "keep" => rotation.keep(bytes(entry)?),
+        _ => rotation.compress(boolean(entry)?),
+    })
+}
+
+/// The permissions of a file, such as `0o660`.
+fn permissions(entry: &Entry) -> Result<u32, ConfigError> {
+    Some(integer(entry)?)
+        .filter(|mode| (0..=0o777).contains(mode))
+        .map(|mode| mode as u32)
+        .ok_or_else(|| invalid(entry, "must be permissions such as 0o660"))
+}
+
+/// The index of the table of the array of tables `array` that `key` is in, if it is in
+/// one.
+fn table_index(array: &str, key: &str) -> Option<usize> {
+    let (index, _) = key
+        .strip_prefix(array)?
+        .strip_prefix('.')?
+        .split_once('.')?;
+    index.parse().ok()
+}
+
+/// Read the entries of a `[[listen]]` table, of which there is at least one.
+fn listen(table: &[&Entry]) -> Result<Listen, ConfigError> {
+    let (mut endpoint, mut mode, mut http2, mut only_v6) = (None, None, true, None);
+
+    for &entry in table {
+        // Skip `listen.` and the table's index.
+        let (_, key) = entry.key["listen.".len()..].split_once('.').unwrap();
+        let set = match key {
+            "address" => Endpoint::Tcp(
+                string(entry)?
+                    .parse()
+                    .map_err(|_| invalid(entry, "must be an IP address and port"))?,
+            ),
+            "path" => Endpoint::Unix(UnixSocket {
+                path: PathBuf::from(string(entry)?),
+                mode: None,
+            }),
+            "mode" => {
+                mode = Some((entry, permissions(entry)?));
+                continue;
+            }
+            "http2" => {
+                http2 = boolean(entry)?;
+                continue;
+            }
+            "only_v6" => {
+                only_v6 = Some((entry, boolean(entry)?));
+                continue;
+            }
+            _ => return Err(invalid(entry, "is not a known setting")),
+        };
+        if endpoint.replace(set).is_some() {
+            return Err(invalid(
+                entry,
+                "cannot be set with both `address` and `path`",
+            ));
+        }
+    }
+
+    match (&mut endpoint, mode) {
+        (Some(Endpoint::Unix(socket)), Some((_, mode))) => socket.mode = Some(mode),
+        (_, Some((entry, _))) => return Err(invalid(entry, "needs `path` to be set")),
+        _ => {}
+    }
+    let endpoint = endpoint.ok_or_else(|| {
+        invalid(
+            table[0],
+            "is in a `[[listen]]` table without `address` or `path`",
+        )
+    })?;
+    match (&endpoint, only_v6) {
+        (Endpoint::Tcp(SocketAddr::V6(_)), _) | (_, None) => {}
+        (_, Some((entry, _))) => {
+            return Err(invalid(entry, "needs `address` to be an IPv6 address"))
+        }
+    }
+    Ok(Listen {
+        endpoint,
+        http2,
+        only_v6: only_v6.map(|(_, only)| only),
+    })
+}
+
+/// An array of strings.
+fn strings(entry: &Entry) -> Result<Vec<&str>, ConfigError> {
+    let Value::Array(values) = &entry.value else {
+        return Err(mismatched(entry, "an array of strings"));
+    };
+    values
+        .iter()
+        .map(|value| match value {
+            Value::String(string) => Ok(string.as_str()),
+            _ => Err(mismatched(entry, "an array of strings")),
+        })
+        .collect()
+}
+
+/// An array of paths, each starting with `/`.
+fn prefixes(entry: &Entry) -> Result<Vec<String>, ConfigError> {
+    let paths = strings(entry)?;
+    if paths.iter().all(|path| path.starts_with('/')) {
+        Ok(paths.into_iter().map(str::to_owned).collect())
+    } else {
+        Err(invalid(entry, "must be paths starting with /"))
+    }
+}
+
+/// The `[cors]` table, whose `origins` are `*` for any, or a scheme and host with
+/// no path, such as `https://app.example.com`.
+fn cors(entries: &[&Entry]) -> Result<Cors, ConfigError> {
+    let mut cors = Cors::new();
+    let mut origins = false;
+
+    for &entry in entries {
+        match &entry.key["cors.".len()..] {
+            "origins" => {
+                for origin in strings(entry)? {
+                    let host = origin
+                        .strip_prefix("https://")
+                        .or_else(|| origin.strip_prefix("http://"));
+                    cors = match host {
+                        _ if origin == "*" => cors.any_origin(),
+                        Some(host) if !host.is_empty() && !host.contains('/') => {
+                            cors.origin(origin)
+                        }
+                        _ => {
+                            return Err(invalid(
+                                entry,
+                                "must be origins such as \"https://example.com\", or \"*\"",
+                            ))
+                        }
+                    };
+                }
+                origins = true;
+            }
+            "methods" => {
+                let methods: Option<Vec<Method>> = strings(entry)?
+                    .into_iter()
+                    .map(|method| method.parse().ok())
+                    .collect();
+                cors = cors.methods(methods.ok_or_else(|| {
+                    invalid(entry, "must be methods such as \"GET\" or \"POST\"")
+                })?);
+            }
+            "headers" => cors = cors.headers(strings(entry)?),
+            "max_age" => cors = cors.max_age(Some(duration(entry)?)),
+            "credentials" => cors = cors.credentials(boolean(entry)?),
+            _ => return Err(invalid(entry, "is not a known setting")),
+        }
+    }
+
+    if origins {
+        Ok(cors)
+    } else {
+        Err(invalid(entries[0], "needs `cors.origins` to be set"))
+    }
+}
+
+/// The header set by a key of `[security_headers]` or `[[security_headers.route]]`.
+fn security_header(key: &str) -> Option<&'static str> {
+    Some(match key {
+        "strict_transport_security" => "Strict-Transport-Security",
+        "content_type_options" => "X-Content-Type-Options",
+        "frame_options" => "X-Frame-Options",
+        "referrer_policy" => "Referrer-Policy",
+        "content_security_policy" => "Content-Security-Policy",
+        _ => return None,
+    })
+}
+
+/// The value of a security header, or `None` if it is `""` to leave the header out.
+fn header_value(entry: &Entry) -> Result<Option<&str>, ConfigError> {
+    let value = string(entry)?;
+    if value.chars().any(char::is_control) {
+        return Err(invalid(entry, "must not have control characters"));
+    }
+    Ok(Some(value).filter(|value| !value.is_empty()))
+}
+
+/// The `[security_headers]` table and the `[[security_headers.route]]` tables.
+fn security_headers(
+    entries: &[&Entry],
+    routes: &BTreeMap<usize, Vec<&Entry>>,
+) -> Result<Option<SecurityHeaders>, ConfigError> {
+    let mut headers = SecurityHeaders::default();
+    let mut enabled = true;
+
+    for &entry in entries {
+        let key = &entry.key["security_headers.".len()..];
+        if key == "enabled" {
+            enabled = boolean(entry)?;
+            continue;
+        }
+        let name = security_header(key).ok_or_else(|| invalid(entry, "is not a known setting"))?;
+        headers = match header_value(entry)? {
+            Some(value) => headers.set(name, value),
+            None => headers.remove(name),
+        };
+    }
+
+    for table in routes.values() {
+        let mut prefix = None;
+        let mut changes = Vec::new();
+        for &entry in table {
+            // Skip `security_headers.route.` and the table's index.
+            let (_, key) = entry.key["security_headers.route.".len()..]
+                .split_once('.')
+                .unwrap();
+            if key == "prefix" {
+                let path = string(entry)?;
+                if !path.starts_with('/') {
+                    return Err(invalid(entry, "must be a path starting with /"));
+                }
+                prefix = Some(path);
+                continue;
+            }
+            let name =
+                security_header(key).ok_or_else(|| invalid(entry, "is not a known setting"))?;
+            changes.push((name, header_value(entry)?));
+        }
+        let prefix = prefix.ok_or_else(|| {
+            invalid(
+                table[0],
+                "is in a `[[security_headers.route]]` table without `prefix`",
+            )
+        })?;
+        headers = headers.route(prefix, changes);
+    }
+
+    Ok(Some(headers).filter(|_| enabled))
+}
+
+/// Add the rules of an `[[access]]` table to `acl`.
+fn access(acl: Acl, table: &[&Entry]) -> Result<Acl, ConfigError> {
+    let (mut prefix, mut rules) = (None, None);
+
+    for &entry in table {
+        // Skip `access.` and the table's index.
+        let (_, key) = entry.key["access.".len()..].split_once('.').unwrap();
+        match key {
+            "prefix" => {
+                let path = string(entry)?;
+                if !path.starts_with('/') {
+                    return Err(invalid(entry, "must be a path starting with /"));
+                }
+                prefix = Some(path);
+            }
+            "rules" => {
+                let Value::Array(values) = &entry.value else {
+                    return Err(mismatched(entry, "an array of strings"));
+                };
+                let parsed: Option<Vec<Rule>> = values
+                    .iter()
+                    .map(|value| match value {
+                        Value::String(rule) => rule.parse().ok(),
+                        _ => None,
+                    })
+                    .collect();
+                rules = Some(parsed.ok_or_else(|| {
+                    invalid(
+                        entry,
+                        "must be rules such as \"allow 192.168.1.0/24\" or \"deny all\"",
+                    )
+                })?);
+            }
+            _ => return Err(invalid(entry, "is not a known setting")),
+        }
+    }
+
+    let rules =
+        rules.ok_or_else(|| invalid(table[0], "is in an `[[access]]` table without `rules`"))?;
+    Ok(match prefix {
+        Some(prefix) => acl.prefix(prefix, rules),
+        None => acl.rules(rules),
+    })
+}
+
+fn basic_auth(table: &[&Entry]) -> Result<Protected, ConfigError> {
+    let (mut prefix, mut file, mut realm) = (None, None, None);
+
+    for &entry in table {
+        // Skip `basic_auth.` and the table's index.
+        let (_, key) = entry.key["basic_auth.".len()..].split_once('.').unwrap();
+        match key {
+            "prefix" => {
+                let path = string(entry)?;
+                if !path.starts_with('/') {
+                    return Err(invalid(entry, "must be a path starting with /"));
+                }
+                prefix = Some(path.to_owned());
+            }
+            "file" => file = Some(PathBuf::from(string(entry)?)),
+            "realm" => {
+                let name = string(entry)?;
+                if name.chars().any(char::is_control) {
+                    return Err(invalid(entry, "must not have control characters"));
+                }
+                realm = Some(name.to_owned());
+            }
+            _ => return Err(invalid(entry, "is not a known setting")),
+        }
+    }
+
+    let missing = |key| {
+        invalid(
+            table[0],
+            &format!("is in a `[[basic_auth]]` table without `{key}`"),
+        )
+    };
+    Ok(Protected {
+        prefix: prefix.ok_or_else(|| missing("prefix"))?,
+        file: file.ok_or_else(|| missing("file"))?,
+        realm: realm.unwrap_or_else(|| String::from("Restricted")),
+    })
+}
+
+/// A positive number of things a second.
+fn per_second(entry: &Entry) -> Result<f64, ConfigError> {
+    let rate = match entry.value {
+        Value::Integer(rate) => rate as f64,
+        Value::Float(rate) => rate,
+        _ => return Err(mismatched(entry, "a number")),
+    };
+    if rate > 0.0 && rate.is_finite() {
+        Ok(rate)
+    } else {
+        Err(invalid(entry, "must be a positive number"))
+    }
+}
+
+/// A duration in seconds, which may be fractional.
+fn duration(entry: &Entry) -> Result<Duration, ConfigError> {
+    let seconds = match entry.value {
+        Value::Integer(seconds) => seconds as f64,
+        Value::Float(seconds) => seconds,
+        _ => return Err(mismatched(entry, "a number of seconds")),
+    };
+    Duration::try_from_secs_f64(seconds)
+        .ok()
+        .filter(|duration| !duration.is_zero())
+        .ok_or_else(|| invalid(entry, "must be a positive number of seconds"))
+}
+
+/// A value that can be replaced while it is in use, such as settings that are
+/// reloaded. Those holding on to the old value keep it until they let go of it.
+/// ```
+/// use ch20_web_server::config::Swap;
+///
+/// let limit = Swap::new(100);
+/// let old = limit.get();
+/// limit.set(200);
+/// assert_eq!((*old, *limit.get()), (100, 200));
+/// ```
+#[derive(Debug)]
+pub struct Swap<T> {
+    current: RwLock<Arc<T>>,
+}
+
+impl<T> Swap<T> {
+    pub fn new(value: T) -> Swap<T> {
+        Swap {
+            current: RwLock::new(Arc::new(value)),
+        }
+    }
+
+    /// The value as it is now.
+    pub fn get(&self) -> Arc<T> {
+        Arc::clone(&self.current.read().unwrap())
+    }
+
+    pub fn set(&self, value: T) {
+        *self.current.write().unwrap() = Arc::new(value);
+    }
+}
+
+/// Why a configuration could not be loaded.
+#[derive(Debug)]
+pub enum ConfigError {
+    /// The file could not be read.
+    Io(io::Error),
+    /// The file is not valid TOML, or uses parts of it that are not supported.
+    Syntax { line: usize, message: &'static str },
+    /// A key is not a setting, or its value is not valid for it.
+    Invalid {
+        line: usize,
+        key: String,
+        message: String,
+    },
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Io(error) => write!(f, "Failed to read config: {error}"),
+            ConfigError::Syntax { line, message } => write!(f, "line {line}: {message}"),
+            ConfigError::Invalid { line, key, message } => {
+                write!(f, "line {line}: `{key}` {message}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_every_setting() {
+        let config: Config = "\
+bind = \"::1\"
+port = 8080
+only_v6 = false
+acceptors = 4
+threads = 2
+max_threads = 4
+root = \"/srv/www\"
+
+[log]
+level = \"WARN\"
+format = \"json\"
+file = \"server.log\"
+max_size = 1024
+
+[access_log]
+file = \"access.log\"
+format = \"Common\"
+daily = true
+keep = 0
+compress = true
+
+[trace]
+file = \"-\"
+keep = 2
+
+[metrics]
+path = \"/_metrics\"
+
+[health]
+enabled = false
+
+[admin]
+address = \"127.0.0.1:9090\"
+token = \"s3cret\"
+
+[api_keys]
+file = \"api_keys\"
+prefixes = [\"/api\", \"/uploads\"]
+
+[timeouts]
+idle = 1.5
+read = 10
+write = 60
+head = 5
+min_body_rate = 0
+drain = 20
+
+[keep_alive]
+max_requests = 10
+
+[rate_limit]
+rate = 0.5
+burst = 5
+
+[cors]
+origins = [\"https://app.example.com\"]
+methods = [\"GET\", \"PUT\"]
+headers = [\"Content-Type\"]
+max_age = 600
+credentials = true
+
+[security_headers]
+strict_transport_security = \"max-age=31536000\"
+referrer_policy = \"\"
+
+[[security_headers.route]]
+prefix = \"/embed\"
+frame_options = \"\"
+content_security_policy = \"frame-ancestors *\"
+
+[[access]]
+rules = [\"deny 10.0.0.0/8\"]
+
+[[access]]
+prefix = \"/admin\"
+rules = [\"allow 192.168.1.0/24\", \"deny all\"]
+
+[[basic_auth]]
+prefix = \"/private\"
+file = \"staff.htpasswd\"
+realm = \"Staff\"
+
+[[basic_auth]]
+prefix = \"/reports\"
+file = \"reports.htpasswd\"
+
+[limits]
+connections = 32
+retry_after = 2
+request_line = 1024
+headers = 10
+header_size = 2048
+body = 0
+
+[socket]
+backlog = 1024
+nodelay = false
+keepalive = 60
+send_buffer = 65536
+receive_buffer = 131072
+
+[unix_socket]
+path = \"/run/web.sock\"
+mode = 0o660
+
+[[listen]]
+address = \"[::]:443\"
+http2 = false
+only_v6 = true
+
+[[listen]]
+path = \"/run/web.sock\"
+"
+        .parse()
+        .unwrap();
+
+        assert_eq!(
+            config,
+            Config {
+                bind: "::1".parse().unwrap(),
+                port: 8080,
+                only_v6: Some(false),
+                acceptors: 4,
+                threads: 2,
+                max_threads: 4,
+                root: PathBuf::from("/srv/www"),
+                log_level: LogLevel::Warn,
+                log_format: LogFormat::Json,
+                log_file: Some(PathBuf::from("server.log")),
+                log_rotation: Rotation::default().max_size(Some(1024)),
+                access_log: Some(PathBuf::from("access.log")),
+                access_log_format: Format::Common,
+                access_log_rotation: Rotation::default().daily(true).keep(0).compress(true),
+                trace: Some(PathBuf::from("-")),
+                trace_rotation: Rotation::default().keep(2),
+                metrics: Some(String::from("/_metrics")),
+                health: false,
+                admin: Some("127.0.0.1:9090".parse().unwrap()),
+                admin_token: Some(String::from("s3cret")),
+                api_keys: Some(PathBuf::from("api_keys")),
+                api_key_prefixes: vec![String::from("/api"), String::from("/uploads")],
+                rate_limit: Some(RateLimit::new(0.5).burst(5)),
+                cors: Some(
+                    Cors::new()
+                        .origin("https://app.example.com")
+                        .methods([Method::Get, Method::Put])
+                        .headers(["Content-Type"])
+                        .max_age(Some(Duration::from_secs(600)))
+                        .credentials(true)
+                ),
+                security_headers: Some(
+                    SecurityHeaders::default()
+                        .set("Strict-Transport-Security", "max-age=31536000")
+                        .remove("Referrer-Policy")
+                        .route(
+                            "/embed",
+                            [
+                                ("X-Frame-Options", None),
+                                ("Content-Security-Policy", Some("frame-ancestors *"))
+                            ]
+                        )
+                ),
+                access: Acl::new()
+                    .rules(["deny 10.0.0.0/8".parse().unwrap()])
+                    .prefix(
+                        "/admin",
+                        [
+                            "allow 192.168.1.0/24".parse().unwrap(),
+                            "deny all".parse().unwrap(),
+                        ]
+                    ),
+                basic_auth: vec![
+                    Protected {
+                        prefix: String::from("/private"),
+                        file: PathBuf::from("staff.htpasswd"),
+                        realm: String::from("Staff"),
+                    },
+                    Protected {
+                        prefix: String::from("/reports"),
+                        file: PathBuf::from("reports.htpasswd"),
+                        realm: String::from("Restricted"),
+                    },
+                ],
+                keep_alive: KeepAlive::default()
+                    .idle_timeout(Duration::from_millis(1500))
+                    .max_requests(10),
+                timeouts: Timeouts::default()
+                    .read(Duration::from_secs(10))
+                    .write(Duration::from_secs(60))
+                    .head(Duration::from_secs(5))
+                    .min_body_rate(0),
+                drain_timeout: Duration::from_secs(20),
+                max_connections: 32,
+                retry_after: Duration::from_secs(2),
+                limits: Limits::default()
+                    .request_line(1024)
+                    .headers(10)
+                    .header_size(2048)
+                    .body(0),
+                backlog: 1024,
+                send_buffer: Some(65536),
+                receive_buffer: Some(131072),
+                stream_options: StreamOptions::default()
+                    .nodelay(false)
+                    .keepalive(Some(Duration::from_secs(60))),
+                unix_socket: Some(UnixSocket {
+                    path: PathBuf::from("/run/web.sock"),
+                    mode: Some(0o660),
+                }),
+                listen: vec![
+                    Listen {
+                        endpoint: Endpoint::Tcp("[::]:443".parse().unwrap()),
+                        http2: false,
+                        only_v6: Some(true),
+                    },
+                    Listen {
+                        endpoint: Endpoint::Unix(UnixSocket {
+                            path: PathBuf::from("/run/web.sock"),
+                            mode: None,
+                        }),
+                        http2: true,
+                        only_v6: None,
+                    },
+                ],
+            }
+        );
+        assert_eq!(config.address().to_string(), "[::1]:8080");
+    }
+
+    #[test]
+    fn keeps_defaults_for_missing_keys() {
+        assert_eq!("".parse::<Config>().unwrap(), Config::default());
+        assert_eq!(
+            Config::default().listeners(),
+            [Listen {
+                endpoint: Endpoint::Tcp("127.0.0.1:7878".parse().unwrap()),
+                http2: true,
+                only_v6: None,
+            }]
+        );
+        assert_eq!(
+            "port = 80".parse::<Config>().unwrap(),
+            Config {
+                port: 80,
+                ..Config::default()
+            }
+        );
+    }
+
+    #[test]
+    fn errors_point_at_the_offending_key() {
+        let error = |text: &str| text.parse::<Config>().unwrap_err().to_string();
+
+        assert_eq!(
+            error("port = 80\n[log]\nlevl = \"info\""),
+            "line 3: `log.levl` is not a known setting"
+        );
+        assert_eq!(
+            error("port = \"80\""),
+            "line 1: `port` must be an integer, not a string"
+        );
+        assert_eq!(
+            error("port = 70000"),
+            "line 1: `port` must be a port from 1 to 65535"
+        );
+        assert_eq!(
+            error("bind = \"localhost\""),
+            "line 1: `bind` must be an IP address"
+        );
+        assert_eq!(
+            error("[timeouts]\nidle = 0"),
+            "line 2: `timeouts.idle` must be a positive number of seconds"
+        );
+        assert_eq!(
+            error("threads = 8\nmax_threads = 4"),
+            "line 2: `max_threads` must be at least `threads`"
+        );
+        assert_eq!(
+            error("threads = 300"),
+            "line 1: `threads` must be at most `max_threads`"
+        );
+        assert_eq!(
+            error("[unix_socket]\nmode = 0o600"),
+            "line 2: `unix_socket.mode` needs `unix_socket.path` to be set"
+        );
+        assert_eq!(
+            error("[[access]]\nrules = [\"deny 10.0.0.0/33\"]"),
+            "line 2: `access.0.rules` must be rules such as \"allow 192.168.1.0/24\" or \"deny all\""
+        );
+        assert_eq!(
+            error("[[access]]\nprefix = \"/admin\""),
+            "line 2: `access.0.prefix` is in an `[[access]]` table without `rules`"
+        );
+        assert_eq!(
+            error("[[basic_auth]]\nprefix = \"/private\""),
+            "line 2: `basic_auth.0.prefix` is in a `[[basic_auth]]` table without `file`"
+        );
+        assert_eq!(
+            error("[[basic_auth]]\nprefix = \"private\""),
+            "line 2: `basic_auth.0.prefix` must be a path starting with /"
+        );
+        assert_eq!(
+            error("[api_keys]\nprefixes = [\"/api\"]"),
+            "line 2: `api_keys.prefixes` needs `api_keys.file` to be set"
+        );
+        assert_eq!(
+            error("[api_keys]\nfile = \"keys\"\nprefixes = [\"api\"]"),
+            "line 3: `api_keys.prefixes` must be paths starting with /"
+        );
+        assert_eq!(
+            error("[cors]\nmax_age = 60"),
+            "line 2: `cors.max_age` needs `cors.origins` to be set"
+        );
+        assert_eq!(
+            error("[cors]\norigins = [\"app.example.com\"]"),
+            "line 2: `cors.origins` must be origins such as \"https://example.com\", or \"*\""
+        );
+        assert_eq!(
+            error("[cors]\norigins = [\"*\"]\nmethods = [\"get\"]"),
+            "line 3: `cors.methods` must be methods such as \"GET\" or \"POST\""
+        );
+        assert_eq!(
+            error("[security_headers]\nframe_options = 1"),
+            "line 2: `security_headers.frame_options` must be a string, not an integer"
+        );
+        assert_eq!(
+            error("[[security_headers.route]]\nframe_options = \"DENY\""),
+            "line 2: `security_headers.route.0.frame_options` is in a `[[security_headers.route]]` table without `prefix`"
+        );
+        assert_eq!(
+            error("[rate_limit]\nburst = 10"),
+            "line 2: `rate_limit.burst` needs `rate_limit.rate` to be set"
+        );
+        assert_eq!(
+            error("[rate_limit]\nrate = -1"),
+            "line 2: `rate_limit.rate` must be a positive number"
+        );
+        assert_eq!(
+            error("[[listen]]\naddress = \"0.0.0.0:80\"\npath = \"web.sock\""),
+            "line 3: `listen.0.path` cannot be set with both `address` and `path`"
+        );
+        assert_eq!(
+            error("[[listen]]\nhttp2 = false"),
+            "line 2: `listen.0.http2` is in a `[[listen]]` table without `address` or `path`"
+        );
+        assert_eq!(
+            error("[[listen]]\naddress = \"0.0.0.0:80\"\nonly_v6 = true"),
+            "line 3: `listen.0.only_v6` needs `address` to be an IPv6 address"
+        );
+        assert_eq!(
+            error("[listen]\nport = 80"),
+            "line 2: `listen.port` is not a known setting"
+        );
+        assert_eq!(error("port = 80 80"), "line 1: unexpected text after value");
+    }
+}
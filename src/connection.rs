@@ -0,0 +1,965 @@
+use std::{
+    cell::Cell,
+    io::{self, BufRead, BufReader, Read, Write},
+    net::{IpAddr, TcpStream},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant, SystemTime},
+};
+
+use threadpool::Threadpool;
+
+use crate::{
+    access_log::{Exchange, Sink},
+    http2,
+    request::{Limits, Method, ParseError, Request, Version},
+    request_id,
+    response::{Response, Status},
+    router::Router,
+    trace,
+};
+
+/// How connections are kept open for further requests.
+/// ```
+/// use std::time::Duration;
+/// use ch20_web_server::connection::KeepAlive;
+///
+/// let keep_alive = KeepAlive::default()
+///     .idle_timeout(Duration::from_secs(15))
+///     .max_requests(1000);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeepAlive {
+    idle_timeout: Duration,
+    max_requests: usize,
+}
+
+impl Default for KeepAlive {
+    /// Keep connections open for 5 seconds between requests, and for at most 100
+    /// requests.
+    fn default() -> KeepAlive {
+        KeepAlive {
+            idle_timeout: Duration::from_secs(5),
+            max_requests: 100,
+        }
+    }
+}
+
+impl KeepAlive {
+    /// Answer a single request per connection.
+    pub fn disabled() -> KeepAlive {
+        KeepAlive::default().max_requests(1)
+    }
+
+    /// Close connections that have not started a new request within `timeout`.
+    pub fn idle_timeout(mut self, timeout: Duration) -> KeepAlive {
+        self.idle_timeout = timeout;
+        self
+    }
+
+    /// Close connections after they have served `max` requests. A `max` of 0 is
+    /// treated as 1.
+    pub fn max_requests(mut self, max: usize) -> KeepAlive {
+        self.max_requests = max.max(1);
+        self
+    }
+}
+
+/// How long the server waits on a client while it sends a request or takes a
+/// response.
+/// ```
+/// use std::time::Duration;
+/// use ch20_web_server::connection::Timeouts;
+///
+/// let timeouts = Timeouts::default()
+///     .read(Duration::from_secs(10))
+///     .write(Duration::from_secs(60));
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Timeouts {
+    read: Duration,
+    write: Duration,
+    head: Duration,
+    min_body_rate: u64,
+}
+
+impl Default for Timeouts {
+    /// Wait 30 seconds for reads and for writes, and 20 seconds for the head of a
+    /// request. Bodies have to arrive at 240 bytes a second.
+    fn default() -> Timeouts {
+        Timeouts {
+            read: Duration::from_secs(30),
+            write: Duration::from_secs(30),
+            head: Duration::from_secs(20),
+            min_body_rate: 240,
+        }
+    }
+}
+
+impl Timeouts {
+    /// Answer `408 Request Timeout` and close the connection if a read of a request
+    /// waits for longer than `timeout`, as does waiting for the first request of a
+    /// connection. Waits between requests are up to `KeepAlive` instead.
+    pub fn read(mut self, timeout: Duration) -> Timeouts {
+        self.read = timeout;
+        self
+    }
+
+    /// Drop the connection if a write of a response waits for longer than `timeout`,
+    /// with the client not taking what it has been sent.
+    pub fn write(mut self, timeout: Duration) -> Timeouts {
+        self.write = timeout;
+        self
+    }
+
+    /// Answer `408 Request Timeout` if the request line and headers have not all
+    /// arrived within `timeout` of the request starting, however steadily they trickle
+    /// in. Otherwise a client sending a byte at a time holds a worker for as long as
+    /// it likes.
+    pub fn head(mut self, timeout: Duration) -> Timeouts {
+        self.head = timeout;
+        self
+    }
+
+    /// Answer `408 Request Timeout` if a body arrives at fewer than `bytes` a second
+    /// on average, once it has had 5 seconds to get going. A `bytes` of 0 lets bodies
+    /// take as long as each read allows.
+    pub fn min_body_rate(mut self, bytes: u64) -> Timeouts {
+        self.min_body_rate = bytes;
+        self
+    }
+}
+
+/// Counts the connections being served, so those over a limit can be turned away
+/// instead of waiting for a worker behind the rest.
+/// ```
+/// use std::sync::Arc;
+/// use ch20_web_server::connection::Connections;
+///
+/// let connections = Arc::new(Connections::new());
+/// let first = connections.admit(1).unwrap();
+/// assert!(connections.admit(1).is_none());
+/// drop(first);
+/// assert!(connections.admit(1).is_some());
+/// ```
+#[derive(Debug, Default)]
+pub struct Connections {
+    open: AtomicUsize,
+}
+
+impl Connections {
+    pub fn new() -> Connections {
+        Connections::default()
+    }
+
+    /// Count a connection in if fewer than `limit` are open, until the returned
+    /// `Admitted` is dropped.
+    pub fn admit(self: &Arc<Self>, limit: usize) -> Option<Admitted> {
+        self.open
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |open| {
+                (open < limit).then_some(open + 1)
+            })
+            .ok()
+            .map(|_| Admitted(Arc::clone(self)))
+    }
+
+    /// How many connections are open.
+    pub fn open(&self) -> usize {
+        self.open.load(Ordering::SeqCst)
+    }
+}
+
+/// A connection counted by `Connections`, for as long as it is held.
+#[derive(Debug)]
+pub struct Admitted(Arc<Connections>);
+
+impl Drop for Admitted {
+    fn drop(&mut self) {
+        self.0.open.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// How long a body has before it is held to the minimum rate.
+const BODY_GRACE: Duration = Duration::from_secs(5);
+
+/// What a `Paced` transport holds reads to, beyond the read timeout.
+#[derive(Debug, Clone, Copy)]
+enum Pace {
+    Free,
+    /// Everything has to be read by the deadline.
+    Until(Instant),
+    /// Reads have to keep up `rate` bytes a second from `start`, after the grace.
+    Rate {
+        start: Instant,
+        rate: u64,
+        read: u64,
+    },
+}
+
+/// Wraps a transport to hold requests to the deadlines in `Timeouts`, by cutting the
+/// timeout of each read short as they near.
+struct Paced<T> {
+    inner: T,
+    /// The timeout given to `set_read_timeout`.
+    timeout: Cell<Option<Duration>>,
+    /// The timeout last set on `inner`, to only set it again when it changes.
+    applied: Cell<Option<Duration>>,
+    pace: Cell<Pace>,
+}
+
+impl<T: Transport> Paced<T> {
+    fn new(inner: T) -> Paced<T> {
+        Paced {
+            inner,
+            timeout: Cell::new(None),
+            applied: Cell::new(None),
+            pace: Cell::new(Pace::Free),
+        }
+    }
+
+    fn pace(&self, pace: Pace) {
+        self.pace.set(pace);
+    }
+}
+
+impl<T: Transport> Read for Paced<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let deadline = match self.pace.get() {
+            Pace::Free => None,
+            Pace::Until(deadline) => Some(deadline),
+            Pace::Rate { start, rate, read } => {
+                Some(start + BODY_GRACE + Duration::from_secs_f64(read as f64 / rate as f64))
+            }
+        };
+        let timeout = match deadline {
+            Some(deadline) => {
+                let left = deadline
+                    .checked_duration_since(Instant::now())
+                    .filter(|left| !left.is_zero())
+                    .ok_or(io::ErrorKind::TimedOut)?;
+                Some(self.timeout.get().map_or(left, |timeout| timeout.min(left)))
+            }
+            None => self.timeout.get(),
+        };
+        if timeout != self.applied.get() {
+            self.inner.set_read_timeout(timeout)?;
+            self.applied.set(timeout);
+        }
+
+        let count = self.inner.read(buf)?;
+        if let Pace::Rate { start, rate, read } = self.pace.get() {
+            let read = read + count as u64;
+            self.pace.set(Pace::Rate { start, rate, read });
+        }
+        Ok(count)
+    }
+}
+
+impl<T: Transport> Write for Paced<T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.inner.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<T: Transport> Transport for Paced<T> {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.timeout.set(timeout);
+        Ok(())
+    }
+
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.inner.set_write_timeout(timeout)
+    }
+
+    fn try_clone(&self) -> io::Result<Paced<T>> {
+        self.inner.try_clone().map(Paced::new)
+    }
+
+    fn peer_ip(&self) -> Option<IpAddr> {
+        self.inner.peer_ip()
+    }
+}
+
+/// A stream requests can be served on: a TCP socket, or a session layered over one,
+/// such as TLS.
+pub trait Transport: Read + Write + Send {
+    /// Make reads fail with `WouldBlock` or `TimedOut` once they have waited for
+    /// `timeout`, or never if it is `None`.
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()>;
+
+    /// Make writes fail with `WouldBlock` or `TimedOut` once they have waited for
+    /// `timeout`, or never if it is `None`. Does nothing unless implemented.
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        let _ = timeout;
+        Ok(())
+    }
+
+    /// Return a second handle to the stream, so responses can be written from other
+    /// threads while this one reads. Only HTTP/2 needs it, and it fails with
+    /// `Unsupported` unless implemented.
+    fn try_clone(&self) -> io::Result<Self>
+    where
+        Self: Sized,
+    {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+
+    /// The address of the client, if it has one. IPv4 clients of a socket listening
+    /// on IPv6 are given their IPv4 address. `None` unless implemented.
+    fn peer_ip(&self) -> Option<IpAddr> {
+        None
+    }
+}
+
+impl Transport for TcpStream {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        TcpStream::set_read_timeout(self, timeout)
+    }
+
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        TcpStream::set_write_timeout(self, timeout)
+    }
+
+    fn try_clone(&self) -> io::Result<TcpStream> {
+        TcpStream::try_clone(self)
+    }
+
+    fn peer_ip(&self) -> Option<IpAddr> {
+        self.peer_addr()
+            .ok()
+            .map(|address| address.ip().to_canonical())
+    }
+}
+
+/// Answer the requests sent on `stream` with `router` until the client closes it,
+/// asks for it to be closed, the limits in `keep_alive` are reached, or it takes
+/// longer than `timeouts` allow. Requests are held to `limits`, and each that is
+/// answered is given to `log`.
+///
+/// Requests that cannot be parsed are answered with the status from
+/// `ParseError::status` before the connection is closed. An error is only returned if
+/// the connection fails.
+pub fn serve(
+    stream: impl Transport,
+    router: &Router,
+    keep_alive: KeepAlive,
+    timeouts: Timeouts,
+    limits: Limits,
+    log: Option<Sink>,
+) -> io::Result<()> {
+    serve_with(stream, router, keep_alive, timeouts, limits, log, None)
+}
+
+/// Like `serve`, but also speak HTTP/2 to clients that open with its preface, or
+/// that ask to upgrade to it with `Upgrade: h2c`. Every HTTP/2 stream is answered by
+/// a job on `pool`, so a slow response does not hold up the others on its
+/// connection.
+///
+/// HTTP/2 connections are closed once idle for the `keep_alive` timeout, but not
+/// after a number of requests.
+pub fn serve_with_http2(
+    stream: impl Transport,
+    router: &Router,
+    keep_alive: KeepAlive,
+    timeouts: Timeouts,
+    limits: Limits,
+    log: Option<Sink>,
+    pool: &Threadpool,
+) -> io::Result<()> {
+    serve_with(
+        stream,
+        router,
+        keep_alive,
+        timeouts,
+        limits,
+        log,
+        Some(pool),
+    )
+}
+
+/// Turn the client away with `503 Service Unavailable`, asking it to try again
+/// after `retry_after`, without waiting on it for more than a moment.
+pub fn refuse(mut stream: impl Transport, retry_after: Duration) -> io::Result<()> {
+    let moment = Some(Duration::from_millis(1));
+    stream.set_read_timeout(moment)?;
+    stream.set_write_timeout(moment)?;
+    // Closing with a request unread resets the connection, which may discard the
+    // response, so what has arrived of it is read first.
+    let _ = stream.read(&mut [0; 8192]);
+    let seconds = retry_after.as_secs_f64().ceil() as u64;
+    Response::error(Status::ServiceUnavailable)
+        .header("Retry-After", seconds.to_string())
+        .header("Connection", "close")
+        .write_to(&mut stream)
+}
+
+fn serve_with(
+    stream: impl Transport,
+    router: &Router,
+    keep_alive: KeepAlive,
+    timeouts: Timeouts,
+    limits: Limits,
+    log: Option<Sink>,
+    pool: Option<&Threadpool>,
+) -> io::Result<()> {
+    stream.set_write_timeout(Some(timeouts.write))?;
+    let peer = stream.peer_ip();
+    // Responses are written past the buffer, which keeps any pipelined requests.
+    let mut reader = BufReader::new(Paced::new(stream));
+
+    for served in 1.. {
+        // The first request is waited for as a read, and a 408 is sent if it is
+        // late. Later ones are waited for as long as the connection is kept
+        // alive, and it is closed quietly once they are late.
+        let first = served == 1;
+        let wait = if first {
+            timeouts.read
+        } else {
+            keep_alive.idle_timeout
+        };
+        reader.get_ref().set_read_timeout(Some(wait))?;
+        let waited = Started::now();
+        match reader.fill_buf() {
+            Ok([]) => return Ok(()),
+            Ok(start) => {
+                if let (true, Some(pool)) = (first, pool) {
+                    if start.starts_with(&http2::PREFACE[..4]) {
+                        reader
+                            .get_ref()
+                            .set_read_timeout(Some(keep_alive.idle_timeout))?;
+                        return http2::serve(reader, router, limits, log, pool, None);
+                    }
+                }
+            }
+            Err(error) if first && timed_out(&error) => {
+                let _span = trace::span("request");
+                let response =
+                    Response::error(Status::RequestTimeout).header("Connection", "close");
+                return answer(reader.get_mut(), response, true, None, None, waited, log);
+            }
+            Err(_) => return Ok(()),
+        }
+
+        reader.get_ref().set_read_timeout(Some(timeouts.read))?;
+        let started = Started::now();
+        let span = trace::span("request");
+        reader
+            .get_ref()
+            .pace(Pace::Until(started.instant + timeouts.head));
+        // The head is kept for the log, even if the body cannot be read.
+        let mut head = None;
+        let mut id = None;
+        // Answers to HEAD have no body, even those that are errors.
+        let mut with_body = true;
+        let request = Request::read_head(&mut reader, limits).and_then(|mut request| {
+            let chosen = request_id::choose(&request);
+            request.set_id(chosen.clone());
+            request.set_peer(peer);
+            id = Some(chosen);
+            span.record("method", request.method().as_str());
+            with_body = request.method() != Method::Head;
+            span.record("path", request.path());
+            if log.is_some() {
+                head = Some(request.head());
+            }
+            if timeouts.min_body_rate > 0 {
+                reader.get_ref().pace(Pace::Rate {
+                    start: Instant::now(),
+                    rate: timeouts.min_body_rate,
+                    read: 0,
+                });
+            }
+            request.read_body(&mut reader, limits).map(|()| request)
+        });
+        reader.get_ref().pace(Pace::Free);
+        // Requests that could not be read are given an id too, for clients to report.
+        let id = id.unwrap_or_else(request_id::generate);
+        span.record("request_id", &id);
+        let entered = request_id::enter(&id);
+
+        let request = match request {
+            Ok(request) => request,
+            Err(ParseError::ConnectionClosed) => return Ok(()),
+            Err(error) => {
+                if let Some(status) = error.status() {
+                    let response = Response::error(status).header("Connection", "close");
+                    answer(
+                        reader.get_mut(),
+                        response,
+                        with_body,
+                        head,
+                        Some(&id),
+                        started,
+                        log,
+                    )?;
+                }
+                return Ok(());
+            }
+        };
+
+        if let Some(pool) = pool {
+            if let Some(settings) = h2c_upgrade(&request) {
+                Response::new(Status::SwitchingProtocols)
+                    .header("Connection", "Upgrade")
+                    .header("Upgrade", "h2c")
+                    .write_to(reader.get_mut())?;
+                span.record("status", Status::SwitchingProtocols.code());
+                // The request is answered again on stream 1, with a span of its own.
+                drop(entered);
+                drop(span);
+                reader
+                    .get_ref()
+                    .set_read_timeout(Some(keep_alive.idle_timeout))?;
+                return http2::serve(reader, router, limits, log, pool, Some((request, settings)));
+            }
+        }
+
+        let version = request.version();
+        let mut open = request.keep_alive() && served < keep_alive.max_requests;
+        let mut response = router.dispatch(request);
+
+        if response
+            .headers()
+            .get_all("Connection")
+            .any(|value| value.eq_ignore_ascii_case("close"))
+        {
+            open = false;
+        }
+        // HTTP/1.0 clients do not understand chunks, so the end of the body has to be
+        // marked by closing the connection.
+        if version == Version::Http10 && response.is_chunked() {
+            response = response.until_close();
+            open = false;
+        }
+        response = match (open, version) {
+            (false, _) => response.header("Connection", "close"),
+            (true, Version::Http10) => response.header("Connection", "keep-alive"),
+            (true, Version::Http11 | Version::Http2) => response,
+        };
+
+        answer(
+            reader.get_mut(),
+            response,
+            with_body,
+            head,
+            Some(&id),
+            started,
+            log,
+        )?;
+        drop(entered);
+        drop(span);
+        if !open {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// When a request started to arrive, by the clock and for measuring how long it
+/// takes.
+#[derive(Clone, Copy)]
+struct Started {
+    time: SystemTime,
+    instant: Instant,
+}
+
+impl Started {
+    fn now() -> Started {
+        Started {
+            time: SystemTime::now(),
+            instant: Instant::now(),
+        }
+    }
+}
+
+/// Write `response` to the request with the head `request` and the id `request_id`,
+/// which is sent back unless the response has one already. Record its status on the
+/// request's span, and give `log` what was written of it.
+fn answer(
+    writer: &mut impl Write,
+    mut response: Response,
+    with_body: bool,
+    request: Option<Request>,
+    request_id: Option<&str>,
+    started: Started,
+    log: Option<Sink>,
+) -> io::Result<()> {
+    if let Some(id) = request_id {
+        if !response.headers().contains(request_id::HEADER) {
+            response = response.header(request_id::HEADER, id);
+        }
+    }
+    let status = response.status();
+    trace::record("status", status.code());
+    let mut body_bytes = 0;
+    let written = response.write_counted(writer, with_body, &mut body_bytes);
+    if let Some(log) = log {
+        log(Exchange {
+            request,
+            request_id: request_id.map(String::from),
+            status,
+            body_bytes,
+            started: started.time,
+            duration: started.instant.elapsed(),
+        });
+    }
+    written
+}
+
+fn timed_out(error: &io::Error) -> bool {
+    matches!(
+        error.kind(),
+        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+    )
+}
+
+/// Return the settings sent with `request` if it asks to upgrade to HTTP/2.
+fn h2c_upgrade(request: &Request) -> Option<Vec<u8>> {
+    let upgrade = request
+        .headers()
+        .get_all("Upgrade")
+        .flat_map(|value| value.split(','))
+        .any(|protocol| protocol.trim().eq_ignore_ascii_case("h2c"));
+    let mut settings = request.headers().get_all("HTTP2-Settings");
+
+    match (upgrade, settings.next(), settings.next()) {
+        (true, Some(settings), None) => http2::decode_settings(settings),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::request::Method;
+    use std::{
+        io::{Read, Write},
+        net::TcpListener,
+        thread,
+    };
+
+    /// Serve one connection on a background thread, returning the client end.
+    fn connect(keep_alive: KeepAlive) -> (TcpStream, thread::JoinHandle<()>) {
+        connect_with(keep_alive, Timeouts::default())
+    }
+
+    fn connect_with(
+        keep_alive: KeepAlive,
+        timeouts: Timeouts,
+    ) -> (TcpStream, thread::JoinHandle<()>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let router = Router::new()
+                .get("/", |_| Response::new(Status::Ok).body("hi"))
+                .get("/chunked", |_| {
+                    Response::new(Status::Ok).chunked_body(&b"hi"[..])
+                });
+            let (stream, _) = listener.accept().unwrap();
+            serve(
+                stream,
+                &router,
+                keep_alive,
+                timeouts,
+                Limits::default(),
+                None,
+            )
+            .unwrap();
+        });
+
+        (TcpStream::connect(address).unwrap(), server)
+    }
+
+    /// Send `requests` and read everything until the server closes the connection.
+    fn exchange(client: &mut TcpStream, requests: &str) -> String {
+        client.write_all(requests.as_bytes()).unwrap();
+        let mut responses = String::new();
+        client.read_to_string(&mut responses).unwrap();
+        responses
+    }
+
+    #[test]
+    fn serves_several_requests_per_connection() {
+        let (mut client, server) = connect(KeepAlive::default().max_requests(3));
+
+        let responses = exchange(
+            &mut client,
+            "GET / HTTP/1.1\r\n\r\n\
+             GET / HTTP/1.0\r\nConnection: keep-alive\r\n\r\n\
+             GET / HTTP/1.1\r\n\r\n",
+        );
+        server.join().unwrap();
+
+        assert_eq!(responses.matches("HTTP/1.1 200 OK").count(), 3);
+        let mut responses = responses.split("HTTP/1.1 ").skip(1);
+        assert!(!responses.next().unwrap().contains("Connection"));
+        assert!(responses.next().unwrap().contains("Connection: keep-alive"));
+        assert!(responses.next().unwrap().contains("Connection: close"));
+    }
+
+    #[test]
+    fn answers_head_without_a_body() {
+        let (mut client, server) = connect(KeepAlive::default().max_requests(4));
+
+        let responses = exchange(
+            &mut client,
+            "HEAD / HTTP/1.1\r\n\r\n\
+             HEAD /chunked HTTP/1.1\r\n\r\n\
+             HEAD /missing HTTP/1.1\r\n\r\n\
+             GET / HTTP/1.1\r\n\r\n",
+        );
+        server.join().unwrap();
+
+        let responses: Vec<&str> = responses.split("HTTP/1.1 ").skip(1).collect();
+        assert_eq!(responses.len(), 4);
+        assert!(responses[0].starts_with("200 OK\r\n"));
+        assert!(responses[0].contains("Content-Length: 2\r\n"));
+        assert!(responses[0].ends_with("\r\n\r\n"));
+        assert!(responses[1].contains("Transfer-Encoding: chunked\r\n"));
+        assert!(responses[1].ends_with("\r\n\r\n"));
+        assert!(responses[2].starts_with("404 Not Found\r\n"));
+        assert!(responses[2].ends_with("\r\n\r\n"));
+        assert!(responses[3].starts_with("200 OK\r\n"));
+        assert!(responses[3].ends_with("\r\n\r\nhi"));
+    }
+
+    #[test]
+    fn closes_when_asked_to_or_idle() {
+        let (mut client, server) = connect(KeepAlive::default());
+        let responses = exchange(&mut client, "GET / HTTP/1.1\r\nConnection: close\r\n\r\n");
+        server.join().unwrap();
+        assert!(responses.contains("Connection: close"));
+
+        let (mut client, server) = connect(KeepAlive::default());
+        assert_eq!(
+            exchange(&mut client, "GET / HTTP/1.0\r\n\r\n")
+                .matches("200 OK")
+                .count(),
+            1
+        );
+        server.join().unwrap();
+
+        let (mut client, server) =
+            connect(KeepAlive::default().idle_timeout(Duration::from_millis(50)));
+        let responses = exchange(&mut client, "GET / HTTP/1.1\r\n\r\n");
+        server.join().unwrap();
+        assert!(responses.ends_with("hi"));
+    }
+
+    #[test]
+    fn times_out_requests_that_are_slow_to_arrive() {
+        let timeouts = Timeouts::default().read(Duration::from_millis(50));
+
+        // Halfway through a request, and before one starts.
+        for start in ["GET / HTTP/1.1\r\nHost:", ""] {
+            let (mut client, server) = connect_with(KeepAlive::default(), timeouts);
+            let response = exchange(&mut client, start);
+            server.join().unwrap();
+            assert!(response.starts_with("HTTP/1.1 408 Request Timeout\r\n"));
+            assert!(response.contains("Connection: close"));
+        }
+    }
+
+    #[test]
+    fn times_out_heads_that_trickle_in() {
+        let timeouts = Timeouts::default().head(Duration::from_millis(100));
+        let (mut client, server) = connect_with(KeepAlive::default(), timeouts);
+        let start = Instant::now();
+
+        // Each byte arrives well within the read timeout, but the head never ends.
+        client.write_all(b"GET / HTTP/1.1\r\n").unwrap();
+        let trickle = thread::spawn(move || {
+            while client.write_all(b"X").is_ok() {
+                thread::sleep(Duration::from_millis(20));
+            }
+        });
+        server.join().unwrap();
+        assert!(start.elapsed() < Duration::from_secs(1));
+        trickle.join().unwrap();
+    }
+
+    #[test]
+    fn http_1_0_clients_get_chunked_bodies_until_close() {
+        let (mut client, server) = connect(KeepAlive::default());
+        let responses = exchange(
+            &mut client,
+            "GET /chunked HTTP/1.0\r\nConnection: keep-alive\r\n\r\n",
+        );
+        server.join().unwrap();
+
+        assert!(responses.contains("Connection: close"));
+        assert!(!responses.contains("Transfer-Encoding"));
+        assert!(responses.ends_with("\r\n\r\nhi"));
+    }
+
+    /// A transport reading from a fixed input, as a TLS session would after
+    /// decrypting it.
+    struct Scripted<'a> {
+        input: &'a [u8],
+        output: &'a mut Vec<u8>,
+    }
+
+    impl Read for Scripted<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.input.read(buf)
+        }
+    }
+
+    impl Write for Scripted<'_> {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.output.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Transport for Scripted<'_> {
+        fn set_read_timeout(&self, _: Option<Duration>) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn holds_bodies_to_the_minimum_rate() {
+        let mut output = Vec::new();
+        let mut paced = Paced::new(Scripted {
+            input: &[0; 100],
+            output: &mut output,
+        });
+        let start = Instant::now() - BODY_GRACE - Duration::from_secs(1);
+
+        // A second past the grace, 100 bytes a second means 100 bytes read by now.
+        paced.pace(Pace::Rate {
+            start,
+            rate: 100,
+            read: 200,
+        });
+        assert_eq!(paced.read(&mut [0; 10]).unwrap(), 10);
+        paced.pace(Pace::Rate {
+            start,
+            rate: 100,
+            read: 50,
+        });
+        assert_eq!(
+            paced.read(&mut [0; 10]).unwrap_err().kind(),
+            io::ErrorKind::TimedOut
+        );
+    }
+
+    #[test]
+    fn refuses_with_a_time_to_retry_after() {
+        let mut output = Vec::new();
+        let transport = Scripted {
+            input: b"GET / HTTP/1.1\r\n\r\n",
+            output: &mut output,
+        };
+
+        refuse(transport, Duration::from_millis(1500)).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.starts_with("HTTP/1.1 503 Service Unavailable\r\n"));
+        assert!(output.contains("Retry-After: 2\r\n"));
+        assert!(output.contains("Connection: close\r\n"));
+    }
+
+    #[test]
+    fn serves_any_transport() {
+        let router = Router::new().get("/", |_| Response::new(Status::Ok).body("hi"));
+        let mut output = Vec::new();
+        let transport = Scripted {
+            input: b"GET / HTTP/1.1\r\n\r\nGET / HTTP/1.1\r\n\r\n",
+            output: &mut output,
+        };
+
+        serve(
+            transport,
+            &router,
+            KeepAlive::default(),
+            Timeouts::default(),
+            Limits::default(),
+            None,
+        )
+        .unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert_eq!(output.matches("HTTP/1.1 200 OK").count(), 2);
+    }
+
+    #[test]
+    fn logs_each_exchange() {
+        let router = Router::new().get("/", |_| Response::new(Status::Ok).body("hi"));
+        let mut output = Vec::new();
+        let transport = Scripted {
+            input: b"GET / HTTP/1.1\r\n\r\nPOST / HTTP/1.1\r\nContent-Length: 9\r\n\r\n",
+            output: &mut output,
+        };
+        let exchanges = std::sync::Mutex::new(Vec::new());
+        let log = |exchange: Exchange| exchanges.lock().unwrap().push(exchange);
+
+        serve(
+            transport,
+            &router,
+            KeepAlive::default(),
+            Timeouts::default(),
+            Limits::default().body(8),
+            Some(&log),
+        )
+        .unwrap();
+        let exchanges = exchanges.into_inner().unwrap();
+        let logged: Vec<_> = exchanges
+            .iter()
+            .map(|exchange| {
+                let request = exchange.request.as_ref().unwrap();
+                (request.method(), exchange.status, exchange.body_bytes)
+            })
+            .collect();
+        assert_eq!(
+            logged,
+            [
+                (Method::Get, Status::Ok, 2),
+                (Method::Post, Status::PayloadTooLarge, 21)
+            ]
+        );
+    }
+
+    #[test]
+    fn gives_each_request_an_id() {
+        let router = Router::new().get("/", |request| {
+            let id = request.id().unwrap();
+            assert_eq!(request_id::current().as_deref(), Some(id));
+            Response::new(Status::Ok).body(id.to_owned())
+        });
+        let mut output = Vec::new();
+        let transport = Scripted {
+            input: b"GET / HTTP/1.1\r\nX-Request-Id: client-1\r\n\r\n\
+                     GET / HTTP/1.1\r\nX-Request-Id: not one\r\nConnection: close\r\n\r\n",
+            output: &mut output,
+        };
+
+        serve(
+            transport,
+            &router,
+            KeepAlive::default(),
+            Timeouts::default(),
+            Limits::default(),
+            None,
+        )
+        .unwrap();
+        let output = String::from_utf8(output).unwrap();
+        let mut responses = output.split("HTTP/1.1 200 OK").skip(1);
+        assert!(responses
+            .next()
+            .unwrap()
+            .contains("X-Request-Id: client-1\r\n"));
+        let second = responses.next().unwrap();
+        let id = second.rsplit("\r\n\r\n").next().unwrap();
+        assert_eq!(id.len(), 16);
+        assert!(second.contains(&format!("X-Request-Id: {id}\r\n")));
+    }
+}
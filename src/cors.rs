@@ -0,0 +1,274 @@
+//! Cross-origin resource sharing, so that scripts on the pages of other origins can
+//! call the server.
+//!
+//! A request with an `Origin` that is not allowed is answered with `403 Forbidden`
+//! and goes no further. A preflight from an allowed one, an `OPTIONS` request with
+//! `Access-Control-Request-Method`, is answered with `204 No Content` and the methods
+//! and headers that may be used. Other requests from allowed origins are served as
+//! usual, with `Access-Control-Allow-Origin` added, and requests without an `Origin`
+//! are left alone.
+//! ```
+//! use ch20_web_server::{cors::{self, Cors}, request::Method, router::Router};
+//!
+//! let cors = Cors::new()
+//!     .origin("https://app.example.com")
+//!     .methods([Method::Get, Method::Post])
+//!     .headers(["Content-Type"]);
+//! assert!(cors.allows("https://app.example.com"));
+//! assert!(!cors.allows("https://evil.example"));
+//! let router = Router::new().wrap(cors::handle(cors));
+//! ```
+
+use std::time::Duration;
+
+use crate::{
+    compression,
+    request::{Method, Request},
+    response::{Response, Status},
+};
+
+/// The origins that may use the server, and what they may send.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cors {
+    /// The origins allowed, or `None` for any.
+    origins: Option<Vec<String>>,
+    methods: Vec<Method>,
+    headers: Vec<String>,
+    max_age: Option<Duration>,
+    credentials: bool,
+}
+
+impl Default for Cors {
+    /// No origins, which may use `GET`, `HEAD` and `POST` with no headers beyond
+    /// those every request may have.
+    fn default() -> Cors {
+        Cors {
+            origins: Some(Vec::new()),
+            methods: vec![Method::Get, Method::Head, Method::Post],
+            headers: Vec::new(),
+            max_age: None,
+            credentials: false,
+        }
+    }
+}
+
+impl Cors {
+    pub fn new() -> Cors {
+        Cors::default()
+    }
+
+    /// Allow `origin`, such as `https://app.example.com`. It is compared without
+    /// regard to case, as scheme and host names are.
+    pub fn origin(mut self, origin: &str) -> Cors {
+        if let Some(origins) = &mut self.origins {
+            origins.push(origin.trim_end_matches('/').to_owned());
+        }
+        self
+    }
+
+    /// Allow every origin.
+    pub fn any_origin(mut self) -> Cors {
+        self.origins = None;
+        self
+    }
+
+    /// The methods allowed origins may use, in place of the default ones.
+    pub fn methods(mut self, methods: impl IntoIterator<Item = Method>) -> Cors {
+        self.methods = methods.into_iter().collect();
+        self
+    }
+
+    /// The request headers allowed origins may send.
+    pub fn headers<S: Into<String>>(mut self, headers: impl IntoIterator<Item = S>) -> Cors {
+        self.headers = headers.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// How long browsers may keep the answer to a preflight, or `None` to leave it
+    /// to them, which for most is a few seconds.
+    pub fn max_age(mut self, max_age: Option<Duration>) -> Cors {
+        self.max_age = max_age;
+        self
+    }
+
+    /// Whether scripts may send cookies and credentials, and read the answers to
+    /// requests that had them.
+    pub fn credentials(mut self, credentials: bool) -> Cors {
+        self.credentials = credentials;
+        self
+    }
+
+    /// Whether `origin` may use the server.
+    pub fn allows(&self, origin: &str) -> bool {
+        match &self.origins {
+            Some(origins) => origins
+                .iter()
+                .any(|allowed| allowed.eq_ignore_ascii_case(origin)),
+            None => true,
+        }
+    }
+
+    /// The `Access-Control-Allow-Origin` for `origin`, which is `*` if every origin
+    /// is allowed, unless credentials are, which need the origin itself.
+    fn allow_origin<'a>(&self, origin: &'a str) -> &'a str {
+        if self.origins.is_none() && !self.credentials {
+            "*"
+        } else {
+            origin
+        }
+    }
+
+    /// Add the headers every answer to `origin` has.
+    fn allow(&self, response: Response, origin: &str) -> Response {
+        let response = response.header("Access-Control-Allow-Origin", self.allow_origin(origin));
+        let response = if self.credentials {
+            response.header("Access-Control-Allow-Credentials", "true")
+        } else {
+            response
+        };
+        // Which origin the answer is for depends on the request's, unless it is any.
+        if self.allow_origin(origin) == "*" {
+            response
+        } else {
+            compression::add_vary(response, "Origin")
+        }
+    }
+
+    fn preflight(&self, origin: &str) -> Response {
+        let methods: Vec<&str> = self.methods.iter().map(Method::as_str).collect();
+        let mut response = Response::new(Status::NoContent)
+            .header("Access-Control-Allow-Methods", methods.join(", "));
+        if !self.headers.is_empty() {
+            response = response.header("Access-Control-Allow-Headers", self.headers.join(", "));
+        }
+        if let Some(max_age) = self.max_age {
+            response = response.header("Access-Control-Max-Age", max_age.as_secs().to_string());
+        }
+        self.allow(response, origin)
+    }
+}
+
+/// Middleware that answers preflights and adds the headers of `cors` to the answers
+/// to allowed origins, and forbids the rest.
+pub fn handle(
+    cors: Cors,
+) -> impl Fn(&Request, &dyn Fn(&Request) -> Response) -> Response + Send + Sync + 'static {
+    move |request, next| {
+        let Some(origin) = request.header("Origin") else {
+            return next(request);
+        };
+        if !cors.allows(origin) {
+            return Response::error(Status::Forbidden);
+        }
+        if request.method() == Method::Options
+            && request.header("Access-Control-Request-Method").is_some()
+        {
+            cors.preflight(origin)
+        } else {
+            cors.allow(next(request), origin)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::router::Router;
+
+    fn request(method: &str, headers: &[(&str, &str)]) -> Request {
+        let mut text = format!("{method} /api HTTP/1.1\r\n");
+        for (name, value) in headers {
+            text.push_str(&format!("{name}: {value}\r\n"));
+        }
+        text.push_str("\r\n");
+        Request::read_from(&mut text.as_bytes()).unwrap()
+    }
+
+    fn router(cors: Cors) -> Router {
+        Router::new()
+            .get("/api", |_| Response::new(Status::Ok))
+            .wrap(handle(cors))
+    }
+
+    #[test]
+    fn answers_preflights_from_allowed_origins() {
+        let router = router(
+            Cors::new()
+                .origin("https://app.example.com/")
+                .methods([Method::Get, Method::Put])
+                .headers(["Content-Type", "X-Api-Key"])
+                .max_age(Some(Duration::from_secs(600))),
+        );
+        let preflight = router.dispatch(request(
+            "OPTIONS",
+            &[
+                ("Origin", "https://APP.example.com"),
+                ("Access-Control-Request-Method", "PUT"),
+            ],
+        ));
+        assert_eq!(preflight.status(), Status::NoContent);
+        let header = |name| preflight.headers().get(name);
+        assert_eq!(
+            header("Access-Control-Allow-Origin"),
+            Some("https://APP.example.com")
+        );
+        assert_eq!(header("Access-Control-Allow-Methods"), Some("GET, PUT"));
+        assert_eq!(
+            header("Access-Control-Allow-Headers"),
+            Some("Content-Type, X-Api-Key")
+        );
+        assert_eq!(header("Access-Control-Max-Age"), Some("600"));
+        assert_eq!(header("Vary"), Some("Origin"));
+        assert_eq!(header("Access-Control-Allow-Credentials"), None);
+
+        // An OPTIONS request that is not a preflight goes to the router.
+        let options = router.dispatch(request("OPTIONS", &[("Origin", "https://app.example.com")]));
+        assert_eq!(options.status(), Status::MethodNotAllowed);
+    }
+
+    #[test]
+    fn forbids_other_origins() {
+        let router = router(Cors::new().origin("https://app.example.com"));
+        for method in ["GET", "OPTIONS"] {
+            let response = router.dispatch(request(
+                method,
+                &[
+                    ("Origin", "https://app.example.com.evil"),
+                    ("Access-Control-Request-Method", "GET"),
+                ],
+            ));
+            assert_eq!(response.status(), Status::Forbidden, "{method}");
+            assert!(!response.headers().contains("Access-Control-Allow-Origin"));
+        }
+        let same_origin = router.dispatch(request("GET", &[]));
+        assert_eq!(same_origin.status(), Status::Ok);
+        assert!(!same_origin
+            .headers()
+            .contains("Access-Control-Allow-Origin"));
+    }
+
+    #[test]
+    fn allows_any_origin() {
+        let response = router(Cors::new().any_origin())
+            .dispatch(request("GET", &[("Origin", "https://anywhere.example")]));
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(
+            response.headers().get("Access-Control-Allow-Origin"),
+            Some("*")
+        );
+        assert!(!response.headers().contains("Vary"));
+
+        // `*` is not allowed with credentials, so the origin is named instead.
+        let response = router(Cors::new().any_origin().credentials(true))
+            .dispatch(request("GET", &[("Origin", "https://anywhere.example")]));
+        assert_eq!(
+            response.headers().get("Access-Control-Allow-Origin"),
+            Some("https://anywhere.example")
+        );
+        assert_eq!(
+            response.headers().get("Access-Control-Allow-Credentials"),
+            Some("true")
+        );
+        assert_eq!(response.headers().get("Vary"), Some("Origin"));
+    }
+}
@@ -0,0 +1,282 @@
+//! A gzip encoder for response bodies: LZ77 matching with the fixed DEFLATE Huffman
+//! codes, which compresses text well without the cost of building a code per block.
+
+use std::io::{self, Read};
+
+use crate::lz77::{self, BitWriter, Token, BLOCK_SIZE};
+
+/// How many earlier positions with the same hash are tried for each match.
+const MAX_CHAIN: usize = 32;
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DISTANCE_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+
+const CRC_TABLE: [u32; 256] = crc_table();
+
+const fn crc_table() -> [u32; 256] {
+    let mut table = [0; 256];
+    let mut n = 0;
+
+    while n < 256 {
+        let mut crc = n as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 == 1 {
+                0xedb8_8320 ^ (crc >> 1)
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[n] = crc;
+        n += 1;
+    }
+    table
+}
+
+fn update_crc(crc: u32, data: &[u8]) -> u32 {
+    !data.iter().fold(!crc, |crc, &byte| {
+        CRC_TABLE[((crc ^ byte as u32) & 0xff) as usize] ^ (crc >> 8)
+    })
+}
+
+/// Compress `data` into a gzip member.
+pub fn compress(data: &[u8]) -> Vec<u8> {
+    let mut compressed = Vec::new();
+    GzipEncoder::new(data)
+        .read_to_end(&mut compressed)
+        .expect("reading from a slice cannot fail");
+    compressed
+}
+
+/// Compresses what it reads from another reader into a gzip stream, one block at a
+/// time, so bodies of any length can be compressed as they are sent.
+pub struct GzipEncoder<R> {
+    inner: R,
+    input: Vec<u8>,
+    bits: BitWriter,
+    /// Compressed bytes not yet returned, from `position` on.
+    position: usize,
+    crc: u32,
+    size: u32,
+    finished: bool,
+}
+
+impl<R: Read> GzipEncoder<R> {
+    pub fn new(inner: R) -> GzipEncoder<R> {
+        let mut bits = BitWriter::default();
+        // Magic, deflate, no flags, no modification time, no extra flags, unknown OS.
+        bits.out
+            .extend_from_slice(&[0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 0, 0xff]);
+
+        GzipEncoder {
+            inner,
+            input: Vec::with_capacity(BLOCK_SIZE),
+            bits,
+            position: 0,
+            crc: 0,
+            size: 0,
+            finished: false,
+        }
+    }
+
+    /// Read and compress the next block of input, or finish the stream at its end.
+    fn fill(&mut self) -> io::Result<()> {
+        let at_end = lz77::fill_block(&mut self.inner, &mut self.input)?;
+        if !self.input.is_empty() {
+            self.crc = update_crc(self.crc, &self.input);
+            self.size = self.size.wrapping_add(self.input.len() as u32);
+            compress_block(&self.input, false, &mut self.bits);
+        }
+        if at_end {
+            compress_block(&[], true, &mut self.bits);
+            self.bits.align();
+            self.bits.out.extend_from_slice(&self.crc.to_le_bytes());
+            self.bits.out.extend_from_slice(&self.size.to_le_bytes());
+            self.finished = true;
+        }
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for GzipEncoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.position == self.bits.out.len() {
+            if self.finished {
+                return Ok(0);
+            }
+            self.bits.out.clear();
+            self.position = 0;
+            self.fill()?;
+        }
+
+        let pending = &self.bits.out[self.position..];
+        let read = pending.len().min(buf.len());
+        buf[..read].copy_from_slice(&pending[..read]);
+        self.position += read;
+        Ok(read)
+    }
+}
+
+/// Writes the fixed Huffman codes of DEFLATE.
+trait FixedCodes {
+    fn literal(&mut self, symbol: u16);
+    fn copy(&mut self, length: usize, distance: usize);
+}
+
+impl FixedCodes for BitWriter {
+    fn literal(&mut self, symbol: u16) {
+        let symbol = symbol as u32;
+        match symbol {
+            0..=143 => self.write_code(0x30 + symbol, 8),
+            144..=255 => self.write_code(0x190 + symbol - 144, 9),
+            256..=279 => self.write_code(symbol - 256, 7),
+            _ => self.write_code(0xc0 + symbol - 280, 8),
+        }
+    }
+
+    fn copy(&mut self, length: usize, distance: usize) {
+        let code = LENGTH_BASE.partition_point(|&base| base as usize <= length) - 1;
+        self.literal(257 + code as u16);
+        self.write(
+            (length - LENGTH_BASE[code] as usize) as u32,
+            LENGTH_EXTRA[code] as u32,
+        );
+
+        let code = DISTANCE_BASE.partition_point(|&base| base as usize <= distance) - 1;
+        self.write_code(code as u32, 5);
+        self.write(
+            (distance - DISTANCE_BASE[code] as usize) as u32,
+            DISTANCE_EXTRA[code] as u32,
+        );
+    }
+}
+
+/// Write `data` as one block with the fixed Huffman codes.
+fn compress_block(data: &[u8], last: bool, bits: &mut BitWriter) {
+    bits.write(last as u32, 1);
+    bits.write(1, 2);
+
+    lz77::tokens(data, MAX_CHAIN, |token| match token {
+        Token::Literal(byte) => bits.literal(byte as u16),
+        Token::Copy { length, distance } => bits.copy(length, distance),
+    });
+    bits.literal(256);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Reads bits least significant first, for decoding what the encoder wrote.
+    struct BitReader<'a> {
+        data: &'a [u8],
+        position: usize,
+    }
+
+    impl BitReader<'_> {
+        fn bits(&mut self, count: usize) -> u32 {
+            let mut value = 0;
+            for i in 0..count {
+                let bit = self.data[self.position / 8] >> (self.position % 8) & 1;
+                value |= (bit as u32) << i;
+                self.position += 1;
+            }
+            value
+        }
+
+        /// Read a fixed Huffman literal or length symbol.
+        fn symbol(&mut self) -> u16 {
+            let mut code = 0;
+            for length in 1..=9 {
+                code = code << 1 | self.bits(1);
+                let symbol = match (length, code) {
+                    (7, 0..=23) => code + 256,
+                    (8, 0x30..=0xbf) => code - 0x30,
+                    (8, 0xc0..=0xc7) => code - 0xc0 + 280,
+                    (9, 0x190..=0x1ff) => code - 0x190 + 144,
+                    _ => continue,
+                };
+                return symbol as u16;
+            }
+            panic!("invalid code");
+        }
+    }
+
+    /// Decode a gzip member made of fixed Huffman blocks.
+    fn decompress(gzip: &[u8]) -> Vec<u8> {
+        assert_eq!(gzip[..10], [0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 0, 0xff]);
+        let mut reader = BitReader {
+            data: &gzip[10..],
+            position: 0,
+        };
+        let mut out: Vec<u8> = Vec::new();
+
+        loop {
+            let last = reader.bits(1) == 1;
+            assert_eq!(reader.bits(2), 1, "not a fixed Huffman block");
+            loop {
+                let symbol = reader.symbol() as usize;
+                match symbol {
+                    0..=255 => out.push(symbol as u8),
+                    256 => break,
+                    _ => {
+                        let code = symbol - 257;
+                        let length = LENGTH_BASE[code] as usize
+                            + reader.bits(LENGTH_EXTRA[code] as usize) as usize;
+                        let code = reader.bits(5).reverse_bits() >> 27;
+                        let code = code as usize;
+                        let distance = DISTANCE_BASE[code] as usize
+                            + reader.bits(DISTANCE_EXTRA[code] as usize) as usize;
+                        for _ in 0..length {
+                            out.push(out[out.len() - distance]);
+                        }
+                    }
+                }
+            }
+            if last {
+                break;
+            }
+        }
+
+        let trailer = 10 + reader.position.div_ceil(8);
+        assert_eq!(
+            gzip[trailer..trailer + 4],
+            update_crc(0, &out).to_le_bytes()
+        );
+        assert_eq!(gzip[trailer + 4..], (out.len() as u32).to_le_bytes());
+        out
+    }
+
+    #[test]
+    fn computes_the_gzip_crc() {
+        assert_eq!(update_crc(0, b"123456789"), 0xcbf4_3926);
+        assert_eq!(update_crc(update_crc(0, b"1234"), b"56789"), 0xcbf4_3926);
+    }
+
+    #[test]
+    fn round_trips_across_blocks() {
+        let text: Vec<u8> = (0..BLOCK_SIZE * 2 + 100)
+            .map(|i| b"the quick brown fox "[i % 20] ^ (i / 997) as u8)
+            .collect();
+
+        for data in [&b""[..], b"a", b"abcabcabcabc", &text] {
+            let compressed = compress(data);
+            assert_eq!(decompress(&compressed), data);
+        }
+        assert!(compress(&text).len() < text.len() / 4);
+    }
+}
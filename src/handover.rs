@@ -0,0 +1,163 @@
+//! Handing the listening socket over to a new process of the server, so it can be
+//! restarted or upgraded without refusing a single connection.
+//!
+//! The old process starts the new one with its sockets open in it, and with their
+//! numbers in `SERVER_LISTEN_FDS`. Once the new process has loaded its settings and is
+//! about to accept, it says so on a second socket, given in `SERVER_READY_FD`, and
+//! the old process stops accepting and drains. Connections that arrive in between
+//! wait in the listen queue, which both processes share.
+
+use std::{
+    env,
+    ffi::c_int,
+    io::{self, Read, Write},
+    os::unix::{
+        io::{AsRawFd, FromRawFd, OwnedFd, RawFd},
+        net::UnixStream,
+    },
+    process::{Child, Command},
+    time::Duration,
+};
+
+use crate::listener::Listener;
+
+/// The variable holding the numbers of the listening sockets, separated by commas.
+pub const LISTEN_FDS: &str = "SERVER_LISTEN_FDS";
+/// The variable holding the number of the socket readiness is reported on.
+pub const READY_FD: &str = "SERVER_READY_FD";
+
+extern "C" {
+    fn fcntl(fd: c_int, cmd: c_int, ...) -> c_int;
+}
+
+const F_GETFD: c_int = 1;
+const F_SETFD: c_int = 2;
+const FD_CLOEXEC: c_int = 1;
+
+/// Set whether `fd` is closed when a new program is executed. Rust opens every
+/// descriptor with the flag set, so it has to be cleared for a child to inherit one.
+pub(crate) fn set_cloexec(fd: RawFd, cloexec: bool) -> io::Result<()> {
+    // SAFETY: F_GETFD and F_SETFD only read and write the flags of `fd`, and fail
+    // harmlessly if it is not open.
+    unsafe {
+        let flags = fcntl(fd, F_GETFD);
+        if flags < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let flags = if cloexec {
+            flags | FD_CLOEXEC
+        } else {
+            flags & !FD_CLOEXEC
+        };
+        if fcntl(fd, F_SETFD, flags) < 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+/// Run this program again with the same arguments, handing it `listeners`, and
+/// return once it has taken over accepting on them.
+///
+/// Fails if the new process exits first, such as when its settings are invalid, or
+/// if it is not ready within `timeout`. `listeners` can go on being used either way.
+pub fn hand_over(listeners: &[Listener], timeout: Duration) -> io::Result<Child> {
+    let (mut ready, child_ready) = UnixStream::pair()?;
+    let fds: Vec<_> = listeners.iter().map(AsRawFd::as_raw_fd).collect();
+    let numbers: Vec<_> = fds.iter().map(RawFd::to_string).collect();
+
+    for &fd in fds.iter().chain([&child_ready.as_raw_fd()]) {
+        set_cloexec(fd, false)?;
+    }
+    let spawned = Command::new(env::current_exe()?)
+        .args(env::args_os().skip(1))
+        .env(LISTEN_FDS, numbers.join(","))
+        .env(READY_FD, child_ready.as_raw_fd().to_string())
+        .spawn();
+    // Any other process started from here on should not get the sockets.
+    for &fd in &fds {
+        set_cloexec(fd, true)?;
+    }
+    let mut child = spawned?;
+    drop(child_ready);
+
+    ready.set_read_timeout(Some(timeout))?;
+    match ready.read(&mut [0]) {
+        Ok(1) => Ok(child),
+        Ok(_) => Err(io::Error::other(
+            "the new process exited before taking over",
+        )),
+        Err(error) => {
+            let _ = child.kill();
+            Err(error)
+        }
+    }
+}
+
+/// Take the listeners handed over by the process that started this one, if there
+/// are any, and tell that process to stop accepting on them.
+pub fn inherit() -> io::Result<Option<Vec<Listener>>> {
+    let invalid = |name| io::Error::other(format!("{name} is not a list of descriptors"));
+    let Ok(listen) = env::var(LISTEN_FDS) else {
+        return Ok(None);
+    };
+    let listen = listen
+        .split(',')
+        .map(|fd| fd.parse::<RawFd>().map_err(|_| invalid(LISTEN_FDS)))
+        .collect::<io::Result<Vec<_>>>()?;
+    let ready = match env::var(READY_FD) {
+        Ok(fd) => Some(fd.parse::<RawFd>().map_err(|_| invalid(READY_FD))?),
+        Err(_) => None,
+    };
+    // Processes this one starts should not see them.
+    env::remove_var(LISTEN_FDS);
+    env::remove_var(READY_FD);
+
+    let mut listeners = Vec::new();
+    for fd in listen {
+        set_cloexec(fd, true)?;
+        // SAFETY: The process that set the variable left the socket open for this
+        // one, which now owns it.
+        listeners.push(Listener::from(unsafe { OwnedFd::from_raw_fd(fd) }));
+    }
+    if let Some(ready) = ready {
+        set_cloexec(ready, true)?;
+        // SAFETY: As above.
+        let mut ready = unsafe { UnixStream::from_raw_fd(ready) };
+        ready.write_all(&[1])?;
+    }
+    Ok(Some(listeners))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::listener::Address;
+    use std::{net::TcpListener, os::unix::io::IntoRawFd};
+
+    #[test]
+    fn inherits_the_listeners_and_reports_readiness() {
+        assert!(inherit().unwrap().is_none());
+
+        let listeners = [
+            TcpListener::bind("127.0.0.1:0").unwrap(),
+            TcpListener::bind("127.0.0.1:0").unwrap(),
+        ];
+        let addresses = listeners
+            .each_ref()
+            .map(|listener| listener.local_addr().unwrap());
+        let fds = listeners.map(|listener| listener.into_raw_fd().to_string());
+        let (mut ready, child_ready) = UnixStream::pair().unwrap();
+        env::set_var(LISTEN_FDS, fds.join(","));
+        env::set_var(READY_FD, child_ready.into_raw_fd().to_string());
+
+        let listeners = inherit().unwrap().unwrap();
+        let inherited: Vec<_> = listeners
+            .iter()
+            .map(|listener| listener.local_addr().unwrap())
+            .collect();
+        assert_eq!(inherited, addresses.map(Address::Tcp));
+        assert_eq!(ready.read(&mut [0]).unwrap(), 1);
+        assert!(env::var(LISTEN_FDS).is_err());
+    }
+}
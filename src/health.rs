@@ -0,0 +1,113 @@
+//! Health checks for Kubernetes probes and load balancers: whether the process is
+//! alive at all, and whether it is ready to be sent traffic.
+//! ```
+//! use ch20_web_server::{
+//!     health::{self, Readiness},
+//!     request::Method,
+//!     router::Router,
+//! };
+//! use std::sync::Arc;
+//!
+//! let readiness = Arc::new(Readiness::new());
+//! readiness.check("root", || health::readable("public".as_ref()));
+//!
+//! let router = Router::new()
+//!     .bare_route(Method::Get, "/healthz", health::alive)
+//!     .bare_route(Method::Get, "/readyz", move |_| readiness.respond());
+//! ```
+
+use std::{fs, path::Path, sync::Mutex};
+
+use crate::{
+    request::Request,
+    response::{Response, Status},
+};
+
+type Check = Box<dyn Fn() -> Result<(), String> + Send + Sync>;
+
+/// Answer a liveness probe, which only has to show that requests are being answered.
+pub fn alive(_: &Request) -> Response {
+    plain(Status::Ok, String::from("ok\n"))
+}
+
+/// The named checks a server has to pass to be ready for traffic.
+#[derive(Default)]
+pub struct Readiness {
+    checks: Mutex<Vec<(String, Check)>>,
+}
+
+impl Readiness {
+    pub fn new() -> Readiness {
+        Readiness::default()
+    }
+
+    /// Add a check called `name`, which fails with a reason if the server is not
+    /// ready.
+    pub fn check<F>(&self, name: &str, check: F)
+    where
+        F: Fn() -> Result<(), String> + Send + Sync + 'static,
+    {
+        self.checks
+            .lock()
+            .unwrap()
+            .push((name.to_owned(), Box::new(check)));
+    }
+
+    /// Run every check, returning `name: reason` for each that fails.
+    pub fn failures(&self) -> Vec<String> {
+        self.checks
+            .lock()
+            .unwrap()
+            .iter()
+            .filter_map(|(name, check)| check().err().map(|reason| format!("{name}: {reason}")))
+            .collect()
+    }
+
+    /// Answer a readiness probe with `200 OK`, or with `503 Service Unavailable` and a
+    /// line for each check that failed.
+    pub fn respond(&self) -> Response {
+        let failures = self.failures();
+        if failures.is_empty() {
+            return plain(Status::Ok, String::from("ready\n"));
+        }
+        let mut body = failures.join("\n");
+        body.push('\n');
+        plain(Status::ServiceUnavailable, body)
+    }
+}
+
+/// Check that the directory at `path` can be listed, as a document root has to be.
+pub fn readable(path: &Path) -> Result<(), String> {
+    fs::read_dir(path)
+        .map(drop)
+        .map_err(|error| format!("{} cannot be read: {error}", path.display()))
+}
+
+/// A response that is never cached, so every probe reaches the server.
+fn plain(status: Status, body: String) -> Response {
+    Response::new(status)
+        .header("Content-Type", "text/plain; charset=utf-8")
+        .header("Cache-Control", "no-store")
+        .body(body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn is_ready_once_every_check_passes() {
+        let readiness = Readiness::new();
+        readiness.check("pool", || Ok(()));
+        assert_eq!(readiness.respond().status(), Status::Ok);
+
+        readiness.check("root", || readable(Path::new("/nonexistent/root")));
+        readiness.check("stopping", || Err(String::from("the server is stopping")));
+        let response = readiness.respond();
+        assert_eq!(response.status(), Status::ServiceUnavailable);
+        let body = std::str::from_utf8(response.body_bytes()).unwrap();
+        let lines: Vec<_> = body.lines().collect();
+        assert!(lines[0].starts_with("root: /nonexistent/root cannot be read: "));
+        assert_eq!(lines[1], "stopping: the server is stopping");
+    }
+}
@@ -0,0 +1,493 @@
+//! HPACK, the header compression of HTTP/2 (RFC 7541).
+//!
+//! The decoder supports everything a client may send. The encoder never adds to its
+//! dynamic table and sends strings as they are, which keeps responses independent of
+//! each other at the cost of a few bytes.
+
+use std::collections::VecDeque;
+
+/// A header field as a name and value, which need not be UTF-8.
+pub(crate) type Field = (Vec<u8>, Vec<u8>);
+
+/// The entries every HPACK table starts with, from index 1.
+const STATIC_TABLE: [(&str, &str); 61] = [
+    (":authority", ""),
+    (":method", "GET"),
+    (":method", "POST"),
+    (":path", "/"),
+    (":path", "/index.html"),
+    (":scheme", "http"),
+    (":scheme", "https"),
+    (":status", "200"),
+    (":status", "204"),
+    (":status", "206"),
+    (":status", "304"),
+    (":status", "400"),
+    (":status", "404"),
+    (":status", "500"),
+    ("accept-charset", ""),
+    ("accept-encoding", "gzip, deflate"),
+    ("accept-language", ""),
+    ("accept-ranges", ""),
+    ("accept", ""),
+    ("access-control-allow-origin", ""),
+    ("age", ""),
+    ("allow", ""),
+    ("authorization", ""),
+    ("cache-control", ""),
+    ("content-disposition", ""),
+    ("content-encoding", ""),
+    ("content-language", ""),
+    ("content-length", ""),
+    ("content-location", ""),
+    ("content-range", ""),
+    ("content-type", ""),
+    ("cookie", ""),
+    ("date", ""),
+    ("etag", ""),
+    ("expect", ""),
+    ("expires", ""),
+    ("from", ""),
+    ("host", ""),
+    ("if-match", ""),
+    ("if-modified-since", ""),
+    ("if-none-match", ""),
+    ("if-range", ""),
+    ("if-unmodified-since", ""),
+    ("last-modified", ""),
+    ("link", ""),
+    ("location", ""),
+    ("max-forwards", ""),
+    ("proxy-authenticate", ""),
+    ("proxy-authorization", ""),
+    ("range", ""),
+    ("referer", ""),
+    ("refresh", ""),
+    ("retry-after", ""),
+    ("server", ""),
+    ("set-cookie", ""),
+    ("strict-transport-security", ""),
+    ("transfer-encoding", ""),
+    ("user-agent", ""),
+    ("vary", ""),
+    ("via", ""),
+    ("www-authenticate", ""),
+];
+
+/// The length of the Huffman code of each byte, and of the end of string at 256.
+/// The code is canonical, so the codes themselves follow from these.
+const HUFFMAN_LENGTHS: [u8; 257] = [
+    13, 23, 28, 28, 28, 28, 28, 28, 28, 24, 30, 28, 28, 30, 28, 28, 28, 28, 28, 28, 28, 28, 30, 28,
+    28, 28, 28, 28, 28, 28, 28, 28, 6, 10, 10, 12, 13, 6, 8, 11, 10, 10, 8, 11, 8, 6, 6, 6, 5, 5,
+    5, 6, 6, 6, 6, 6, 6, 6, 7, 8, 15, 6, 12, 10, 13, 6, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7,
+    7, 7, 7, 7, 7, 7, 7, 7, 8, 7, 8, 13, 19, 13, 14, 6, 15, 5, 6, 5, 6, 5, 6, 6, 6, 5, 7, 7, 6, 6,
+    6, 5, 6, 7, 6, 5, 5, 6, 7, 7, 7, 7, 7, 15, 11, 14, 13, 28, 20, 22, 20, 20, 22, 22, 22, 23, 22,
+    23, 23, 23, 23, 23, 24, 23, 24, 24, 22, 23, 24, 23, 23, 23, 23, 21, 22, 23, 22, 23, 23, 24, 22,
+    21, 20, 22, 22, 23, 23, 21, 23, 22, 22, 24, 21, 22, 23, 23, 21, 21, 22, 21, 23, 22, 23, 23, 20,
+    22, 22, 22, 23, 22, 22, 23, 26, 26, 20, 19, 22, 23, 22, 25, 26, 26, 26, 27, 27, 26, 24, 25, 19,
+    21, 26, 27, 27, 26, 27, 24, 21, 21, 26, 26, 28, 27, 27, 27, 20, 24, 20, 21, 22, 21, 21, 23, 22,
+    22, 25, 25, 24, 24, 26, 23, 26, 27, 26, 26, 27, 27, 27, 27, 27, 28, 27, 27, 27, 27, 27, 26, 30,
+];
+
+const MAX_HUFFMAN_LENGTH: usize = 30;
+const END_OF_STRING: u16 = 256;
+
+/// The symbols ordered by code, then for each length the first code of that length,
+/// the number of codes and where they start in the order.
+struct HuffmanTable {
+    symbols: [u16; 257],
+    first_code: [u32; MAX_HUFFMAN_LENGTH + 1],
+    count: [u32; MAX_HUFFMAN_LENGTH + 1],
+    offset: [u32; MAX_HUFFMAN_LENGTH + 1],
+}
+
+const HUFFMAN: HuffmanTable = huffman_table();
+
+const fn huffman_table() -> HuffmanTable {
+    let mut table = HuffmanTable {
+        symbols: [0; 257],
+        first_code: [0; MAX_HUFFMAN_LENGTH + 1],
+        count: [0; MAX_HUFFMAN_LENGTH + 1],
+        offset: [0; MAX_HUFFMAN_LENGTH + 1],
+    };
+    let mut code = 0;
+    let mut next = 0;
+    let mut length = 1;
+
+    while length <= MAX_HUFFMAN_LENGTH {
+        table.first_code[length] = code;
+        table.offset[length] = next as u32;
+        let mut symbol = 0;
+        while symbol < 257 {
+            if HUFFMAN_LENGTHS[symbol] as usize == length {
+                table.symbols[next] = symbol as u16;
+                next += 1;
+                code += 1;
+                table.count[length] += 1;
+            }
+            symbol += 1;
+        }
+        code <<= 1;
+        length += 1;
+    }
+    table
+}
+
+/// A header block that cannot be decoded, which is fatal to the whole connection
+/// since the tables of both ends no longer agree.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct DecodeError;
+
+/// Each entry counts its name and value plus this towards the size of a table.
+const ENTRY_OVERHEAD: usize = 32;
+
+/// Decodes the header blocks of one connection.
+pub(crate) struct Decoder {
+    /// The newest entry first.
+    table: VecDeque<Field>,
+    size: usize,
+    max_size: usize,
+    /// The largest size the encoder may set the table to.
+    limit: usize,
+}
+
+impl Decoder {
+    /// Create a decoder whose table may grow to `limit` bytes, as advertised in the
+    /// `SETTINGS_HEADER_TABLE_SIZE` setting.
+    pub(crate) fn new(limit: usize) -> Decoder {
+        Decoder {
+            table: VecDeque::new(),
+            size: 0,
+            max_size: limit,
+            limit,
+        }
+    }
+
+    /// Decode one complete header block into its fields as names and values.
+    pub(crate) fn decode(&mut self, mut block: &[u8]) -> Result<Vec<Field>, DecodeError> {
+        let mut fields = Vec::new();
+
+        while let Some(&first) = block.first() {
+            if first & 0x80 != 0 {
+                let index = integer(&mut block, 7)?;
+                let (name, value) = self.entry(index)?;
+                fields.push((name.to_vec(), value.to_vec()));
+            } else if first & 0x40 != 0 {
+                let field = self.literal(&mut block, 6)?;
+                self.insert(field.clone());
+                fields.push(field);
+            } else if first & 0x20 != 0 {
+                // Size updates may only open a block.
+                if !fields.is_empty() {
+                    return Err(DecodeError);
+                }
+                let size = integer(&mut block, 5)?;
+                if size > self.limit {
+                    return Err(DecodeError);
+                }
+                self.max_size = size;
+                self.evict(0);
+            } else {
+                // Without indexing, or never indexed, which only matters to proxies.
+                fields.push(self.literal(&mut block, 4)?);
+            }
+        }
+        Ok(fields)
+    }
+
+    fn entry(&self, index: usize) -> Result<(&[u8], &[u8]), DecodeError> {
+        match index {
+            0 => Err(DecodeError),
+            1..=61 => {
+                let (name, value) = STATIC_TABLE[index - 1];
+                Ok((name.as_bytes(), value.as_bytes()))
+            }
+            _ => self
+                .table
+                .get(index - 62)
+                .map(|(name, value)| (&name[..], &value[..]))
+                .ok_or(DecodeError),
+        }
+    }
+
+    /// Read a literal field whose name index has a `prefix` bit prefix.
+    fn literal(&self, block: &mut &[u8], prefix: u32) -> Result<Field, DecodeError> {
+        let name = match integer(block, prefix)? {
+            0 => string(block)?,
+            index => self.entry(index)?.0.to_vec(),
+        };
+        Ok((name, string(block)?))
+    }
+
+    fn insert(&mut self, entry: Field) {
+        let size = entry.0.len() + entry.1.len() + ENTRY_OVERHEAD;
+        self.evict(size);
+        // An entry larger than the whole table just empties it.
+        if size <= self.max_size {
+            self.size += size;
+            self.table.push_front(entry);
+        }
+    }
+
+    /// Evict the oldest entries until `room` more bytes fit.
+    fn evict(&mut self, room: usize) {
+        while self.size + room > self.max_size {
+            let Some((name, value)) = self.table.pop_back() else {
+                break;
+            };
+            self.size -= name.len() + value.len() + ENTRY_OVERHEAD;
+        }
+    }
+}
+
+/// Read an integer with a `prefix` bit prefix from the start of `block`.
+fn integer(block: &mut &[u8], prefix: u32) -> Result<usize, DecodeError> {
+    let (&first, mut rest) = block.split_first().ok_or(DecodeError)?;
+    let mask = (1 << prefix) - 1;
+    let mut value = (first & mask) as usize;
+
+    if value == mask as usize {
+        let mut shift = 0;
+        loop {
+            let (&byte, tail) = rest.split_first().ok_or(DecodeError)?;
+            rest = tail;
+            // More than 28 bits is far beyond any length or index worth accepting.
+            if shift > 21 {
+                return Err(DecodeError);
+            }
+            value += ((byte & 0x7f) as usize) << shift;
+            shift += 7;
+            if byte & 0x80 == 0 {
+                break;
+            }
+        }
+    }
+    *block = rest;
+    Ok(value)
+}
+
+/// Read a string literal, Huffman coded or not, from the start of `block`.
+fn string(block: &mut &[u8]) -> Result<Vec<u8>, DecodeError> {
+    let huffman = block.first().ok_or(DecodeError)? & 0x80 != 0;
+    let length = integer(block, 7)?;
+    if length > block.len() {
+        return Err(DecodeError);
+    }
+    let (string, rest) = block.split_at(length);
+    *block = rest;
+
+    if huffman {
+        huffman_decode(string)
+    } else {
+        Ok(string.to_vec())
+    }
+}
+
+fn huffman_decode(data: &[u8]) -> Result<Vec<u8>, DecodeError> {
+    let mut decoded = Vec::with_capacity(data.len() * 8 / 5);
+    let mut code = 0;
+    let mut length = 0;
+
+    for bit in data
+        .iter()
+        .flat_map(|&byte| (0..8).rev().map(move |i| (byte >> i) & 1))
+    {
+        code = code << 1 | bit as u32;
+        length += 1;
+        if length > MAX_HUFFMAN_LENGTH {
+            return Err(DecodeError);
+        }
+        let index = code.wrapping_sub(HUFFMAN.first_code[length]);
+        if index < HUFFMAN.count[length] {
+            match HUFFMAN.symbols[(HUFFMAN.offset[length] + index) as usize] {
+                END_OF_STRING => return Err(DecodeError),
+                symbol => decoded.push(symbol as u8),
+            }
+            code = 0;
+            length = 0;
+        }
+    }
+
+    // What is left over has to be padding: fewer than 8 bits of the end of string
+    // code, which is all ones.
+    if length >= 8 || code != (1 << length) - 1 {
+        return Err(DecodeError);
+    }
+    Ok(decoded)
+}
+
+/// Encodes the header blocks of one connection.
+pub(crate) struct Encoder {
+    /// Whether the next block has to tell the decoder the table is empty.
+    size_update: bool,
+}
+
+impl Encoder {
+    pub(crate) fn new() -> Encoder {
+        Encoder { size_update: true }
+    }
+
+    /// Note that the peer changed `SETTINGS_HEADER_TABLE_SIZE`, which has to be
+    /// acknowledged at the start of the next block.
+    pub(crate) fn table_size_changed(&mut self) {
+        self.size_update = true;
+    }
+
+    /// Encode `fields`, whose names must be lowercase, as one header block.
+    pub(crate) fn encode<'a>(
+        &mut self,
+        fields: impl IntoIterator<Item = (&'a str, &'a str)>,
+    ) -> Vec<u8> {
+        let mut block = Vec::new();
+        if self.size_update {
+            // The table is always empty, since nothing is ever added to it.
+            write_integer(&mut block, 0x20, 5, 0);
+            self.size_update = false;
+        }
+
+        for (name, value) in fields {
+            let exact = STATIC_TABLE
+                .iter()
+                .position(|&entry| entry == (name, value));
+            let named = STATIC_TABLE.iter().position(|&(entry, _)| entry == name);
+
+            match (exact, named) {
+                (Some(index), _) => write_integer(&mut block, 0x80, 7, index + 1),
+                (None, Some(index)) => {
+                    write_integer(&mut block, 0, 4, index + 1);
+                    write_string(&mut block, value);
+                }
+                (None, None) => {
+                    block.push(0);
+                    write_string(&mut block, name);
+                    write_string(&mut block, value);
+                }
+            }
+        }
+        block
+    }
+}
+
+/// Write `value` with a `prefix` bit prefix, in a first byte starting with `flags`.
+fn write_integer(block: &mut Vec<u8>, flags: u8, prefix: u32, mut value: usize) {
+    let mask = (1 << prefix) - 1;
+    if value < mask {
+        block.push(flags | value as u8);
+        return;
+    }
+
+    block.push(flags | mask as u8);
+    value -= mask;
+    while value >= 0x80 {
+        block.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    block.push(value as u8);
+}
+
+fn write_string(block: &mut Vec<u8>, string: &str) {
+    write_integer(block, 0, 7, string.len());
+    block.extend_from_slice(string.as_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(hex: &str) -> Vec<u8> {
+        let hex: String = hex.split_whitespace().collect();
+        (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    fn strings(fields: Vec<Field>) -> Vec<(String, String)> {
+        fields
+            .into_iter()
+            .map(|(name, value)| {
+                (
+                    String::from_utf8(name).unwrap(),
+                    String::from_utf8(value).unwrap(),
+                )
+            })
+            .collect()
+    }
+
+    fn fields(fields: &[(&str, &str)]) -> Vec<(String, String)> {
+        fields
+            .iter()
+            .map(|&(name, value)| (name.to_owned(), value.to_owned()))
+            .collect()
+    }
+
+    #[test]
+    fn decodes_the_rfc_examples() {
+        let first = fields(&[
+            (":method", "GET"),
+            (":scheme", "http"),
+            (":path", "/"),
+            (":authority", "www.example.com"),
+        ]);
+        let mut second = first.clone();
+        second.push(("cache-control".to_owned(), "no-cache".to_owned()));
+
+        // C.3, then C.4, which codes the strings with Huffman.
+        for blocks in [
+            [
+                "8286 8441 0f77 7777 2e65 7861 6d70 6c65 2e63 6f6d",
+                "8286 84be 5808 6e6f 2d63 6163 6865",
+            ],
+            [
+                "8286 8441 8cf1 e3c2 e5f2 3a6b a0ab 90f4 ff",
+                "8286 84be 5886 a8eb 1064 9cbf",
+            ],
+        ] {
+            let mut decoder = Decoder::new(4096);
+            assert_eq!(strings(decoder.decode(&hex(blocks[0])).unwrap()), first);
+            assert_eq!(strings(decoder.decode(&hex(blocks[1])).unwrap()), second);
+            assert_eq!(decoder.size, 57 + 53);
+        }
+    }
+
+    #[test]
+    fn evicts_entries_to_fit_the_table() {
+        let mut decoder = Decoder::new(4096);
+        // A size update to 60 bytes, then two entries of 36 bytes each.
+        let block = hex("3f1d 4003 6b65 7901 61 4003 6b65 7901 62 be");
+        let decoded = strings(decoder.decode(&block).unwrap());
+        assert_eq!(decoded.last().unwrap(), &("key".to_owned(), "b".to_owned()));
+        assert_eq!(decoder.table.len(), 1);
+        assert_eq!(decoder.decode(&hex("bf")), Err(DecodeError));
+
+        // Above the limit, or after a field.
+        assert_eq!(decoder.decode(&hex("3fe2 1f")), Err(DecodeError));
+        assert_eq!(decoder.decode(&hex("82 20")), Err(DecodeError));
+    }
+
+    #[test]
+    fn rejects_bad_huffman_padding() {
+        // "a" is 00011, padded with ones.
+        assert_eq!(huffman_decode(&[0b0001_1111]), Ok(b"a".to_vec()));
+        assert_eq!(huffman_decode(&[0b0001_1011]), Err(DecodeError));
+        assert_eq!(huffman_decode(&[0b0001_1111, 0xff]), Err(DecodeError));
+    }
+
+    #[test]
+    fn round_trips_encoded_blocks() {
+        let sent = [
+            (":status", "200"),
+            ("content-type", "text/html"),
+            ("content-length", "1234567"),
+            ("x-long", &"v".repeat(300)),
+        ];
+        let mut encoder = Encoder::new();
+        let mut decoder = Decoder::new(4096);
+
+        for _ in 0..2 {
+            let block = encoder.encode(sent);
+            assert_eq!(strings(decoder.decode(&block).unwrap()), fields(&sent));
+        }
+        assert_eq!(decoder.max_size, 0);
+    }
+}
@@ -0,0 +1,1230 @@
+//! HTTP/2 over cleartext connections (RFC 9113): the frame layer, flow control, and
+//! streams answered concurrently as jobs on a threadpool.
+
+use std::{
+    collections::HashMap,
+    io::{self, BufRead, BufReader, Read, Write},
+    panic::{self, AssertUnwindSafe},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Condvar, Mutex, MutexGuard,
+    },
+    time::{Instant, SystemTime},
+};
+
+use threadpool::Threadpool;
+
+use crate::{
+    access_log::{Exchange, Sink},
+    connection::Transport,
+    headers::Headers,
+    hpack,
+    request::{Limits, Method, Request, Version},
+    request_id,
+    response::{Response, Status},
+    router::Router,
+    trace,
+};
+
+/// What a client sends before its first frame.
+pub(crate) const PREFACE: &[u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+
+const DATA: u8 = 0x0;
+const HEADERS: u8 = 0x1;
+const PRIORITY: u8 = 0x2;
+const RST_STREAM: u8 = 0x3;
+const SETTINGS: u8 = 0x4;
+const PUSH_PROMISE: u8 = 0x5;
+const PING: u8 = 0x6;
+const GOAWAY: u8 = 0x7;
+const WINDOW_UPDATE: u8 = 0x8;
+const CONTINUATION: u8 = 0x9;
+
+const END_STREAM: u8 = 0x1;
+const ACK: u8 = 0x1;
+const END_HEADERS: u8 = 0x4;
+const PADDED: u8 = 0x8;
+const PRIORITY_FLAG: u8 = 0x20;
+
+const NO_ERROR: u32 = 0x0;
+const PROTOCOL_ERROR: u32 = 0x1;
+const INTERNAL_ERROR: u32 = 0x2;
+const FLOW_CONTROL_ERROR: u32 = 0x3;
+const STREAM_CLOSED: u32 = 0x5;
+const FRAME_SIZE_ERROR: u32 = 0x6;
+const REFUSED_STREAM: u32 = 0x7;
+const COMPRESSION_ERROR: u32 = 0x9;
+const ENHANCE_YOUR_CALM: u32 = 0xb;
+
+const SETTINGS_HEADER_TABLE_SIZE: u16 = 0x1;
+const SETTINGS_ENABLE_PUSH: u16 = 0x2;
+const SETTINGS_MAX_CONCURRENT_STREAMS: u16 = 0x3;
+const SETTINGS_INITIAL_WINDOW_SIZE: u16 = 0x4;
+const SETTINGS_MAX_FRAME_SIZE: u16 = 0x5;
+
+/// The largest frame payload either end may send until told otherwise. The server
+/// never raises it, so it is also the largest it accepts.
+const DEFAULT_MAX_FRAME_SIZE: usize = 16_384;
+const DEFAULT_WINDOW: i64 = 65_535;
+const MAX_WINDOW: i64 = (1 << 31) - 1;
+const HEADER_TABLE_SIZE: usize = 4096;
+/// How many streams a client may have open at once.
+const MAX_CONCURRENT_STREAMS: usize = 100;
+/// The largest header block accepted, across all of its frames.
+const MAX_HEADER_BLOCK: usize = 64 * 1024;
+
+/// Header fields that only mean something to an HTTP/1 connection.
+const CONNECTION_SPECIFIC: [&str; 5] = [
+    "connection",
+    "keep-alive",
+    "proxy-connection",
+    "transfer-encoding",
+    "upgrade",
+];
+
+/// Why a connection has to end.
+#[derive(Debug)]
+enum Error {
+    Io(io::Error),
+    /// The client broke the protocol, or the connection is closed for being idle.
+    /// The code is sent to it in a GOAWAY frame.
+    Connection(u32),
+}
+
+impl From<io::Error> for Error {
+    fn from(error: io::Error) -> Error {
+        Error::Io(error)
+    }
+}
+
+struct Frame {
+    kind: u8,
+    flags: u8,
+    stream: u32,
+    payload: Vec<u8>,
+}
+
+impl Frame {
+    /// The payload without its padding, if the frame is padded.
+    fn unpadded(&self) -> Result<&[u8], Error> {
+        if self.flags & PADDED == 0 {
+            return Ok(&self.payload);
+        }
+        match self.payload.split_first() {
+            Some((&padding, rest)) if (padding as usize) <= rest.len() => {
+                Ok(&rest[..rest.len() - padding as usize])
+            }
+            _ => Err(Error::Connection(PROTOCOL_ERROR)),
+        }
+    }
+}
+
+fn read_frame(reader: &mut impl Read) -> Result<Frame, Error> {
+    let mut header = [0; 9];
+    reader.read_exact(&mut header)?;
+    let length = u32::from_be_bytes([0, header[0], header[1], header[2]]) as usize;
+    if length > DEFAULT_MAX_FRAME_SIZE {
+        return Err(Error::Connection(FRAME_SIZE_ERROR));
+    }
+
+    let mut payload = vec![0; length];
+    reader.read_exact(&mut payload)?;
+    Ok(Frame {
+        kind: header[3],
+        flags: header[4],
+        stream: u32::from_be_bytes([header[5], header[6], header[7], header[8]]) & 0x7fff_ffff,
+        payload,
+    })
+}
+
+/// The writing end of a connection, shared by the jobs answering its streams.
+struct Writer<T> {
+    stream: T,
+    encoder: hpack::Encoder,
+}
+
+impl<T: Write> Writer<T> {
+    fn frame(&mut self, kind: u8, flags: u8, stream: u32, payload: &[u8]) -> io::Result<()> {
+        let mut frame = Vec::with_capacity(9 + payload.len());
+        frame.extend_from_slice(&(payload.len() as u32).to_be_bytes()[1..]);
+        frame.extend_from_slice(&[kind, flags]);
+        frame.extend_from_slice(&stream.to_be_bytes());
+        frame.extend_from_slice(payload);
+        self.stream.write_all(&frame)
+    }
+
+    /// Send `fields` as a HEADERS frame, followed by as many CONTINUATION frames as
+    /// it takes to fit frames of `max_frame_size`.
+    fn headers(
+        &mut self,
+        stream: u32,
+        fields: &[(String, String)],
+        end_stream: bool,
+        max_frame_size: usize,
+    ) -> io::Result<()> {
+        let block = self
+            .encoder
+            .encode(fields.iter().map(|(name, value)| (&name[..], &value[..])));
+        let mut fragments = block.chunks(max_frame_size).peekable();
+        let mut kind = HEADERS;
+        let mut flags = if end_stream { END_STREAM } else { 0 };
+
+        while let Some(fragment) = fragments.next() {
+            if fragments.peek().is_none() {
+                flags |= END_HEADERS;
+            }
+            self.frame(kind, flags, stream, fragment)?;
+            kind = CONTINUATION;
+            flags = 0;
+        }
+        if block.is_empty() {
+            self.frame(HEADERS, flags | END_HEADERS, stream, &[])?;
+        }
+        Ok(())
+    }
+
+    fn window_update(&mut self, stream: u32, increment: usize) -> io::Result<()> {
+        self.frame(WINDOW_UPDATE, 0, stream, &(increment as u32).to_be_bytes())
+    }
+}
+
+/// How much the server may still send on the connection, and on each stream that
+/// is open.
+struct Windows {
+    connection: i64,
+    streams: HashMap<u32, i64>,
+    /// The window new streams start with.
+    initial: i64,
+    /// Set once the connection is over, so responses stop waiting for room.
+    closed: bool,
+}
+
+/// What is shared between the thread reading a connection and the jobs answering
+/// its streams.
+struct Shared<T> {
+    writer: Mutex<Writer<T>>,
+    windows: Mutex<Windows>,
+    window_opened: Condvar,
+    max_frame_size: AtomicUsize,
+}
+
+impl<T: Write> Shared<T> {
+    fn writer(&self) -> MutexGuard<'_, Writer<T>> {
+        self.writer.lock().unwrap()
+    }
+
+    fn windows(&self) -> MutexGuard<'_, Windows> {
+        self.windows.lock().unwrap()
+    }
+
+    fn open(&self, stream: u32) {
+        let mut windows = self.windows();
+        let initial = windows.initial;
+        windows.streams.insert(stream, initial);
+    }
+
+    fn open_streams(&self) -> usize {
+        self.windows().streams.len()
+    }
+
+    /// Forget `stream`, so a response still being sent on it stops.
+    fn forget(&self, stream: u32) {
+        self.windows().streams.remove(&stream);
+        self.window_opened.notify_all();
+    }
+
+    fn close(&self) {
+        self.windows().closed = true;
+        self.window_opened.notify_all();
+    }
+
+    fn reset(&self, stream: u32, code: u32) -> io::Result<()> {
+        self.forget(stream);
+        self.writer()
+            .frame(RST_STREAM, 0, stream, &code.to_be_bytes())
+    }
+
+    fn headers(
+        &self,
+        stream: u32,
+        fields: &[(String, String)],
+        end_stream: bool,
+    ) -> io::Result<()> {
+        let max_frame_size = self.max_frame_size.load(Ordering::Relaxed);
+        self.writer()
+            .headers(stream, fields, end_stream, max_frame_size)
+    }
+
+    /// Send `data` on `stream` in DATA frames, waiting for the client to open its
+    /// windows whenever they are used up. Returns `false` if the client reset the
+    /// stream meanwhile.
+    fn data(&self, stream: u32, mut data: &[u8]) -> io::Result<bool> {
+        while !data.is_empty() {
+            let length = {
+                let mut windows = self.windows();
+                let available = loop {
+                    if windows.closed {
+                        return Err(io::ErrorKind::ConnectionAborted.into());
+                    }
+                    let Some(&window) = windows.streams.get(&stream) else {
+                        return Ok(false);
+                    };
+                    let available = window.min(windows.connection);
+                    if available > 0 {
+                        break available as usize;
+                    }
+                    windows = self.window_opened.wait(windows).unwrap();
+                };
+
+                let length = available
+                    .min(data.len())
+                    .min(self.max_frame_size.load(Ordering::Relaxed));
+                windows.connection -= length as i64;
+                *windows.streams.get_mut(&stream).unwrap() -= length as i64;
+                length
+            };
+
+            self.writer().frame(DATA, 0, stream, &data[..length])?;
+            data = &data[length..];
+        }
+        Ok(true)
+    }
+}
+
+/// Answer `stream` with the response `router` gives `request`, or with the error
+/// status the request could not be read because of, and give `log` what was sent.
+fn respond<T: Write>(
+    shared: &Shared<T>,
+    router: &Router,
+    stream: u32,
+    request: Result<Request, Status>,
+    log: Option<Sink>,
+) {
+    let (time, instant) = (SystemTime::now(), Instant::now());
+    let span = trace::span("request");
+    span.record("stream", stream);
+    // An upgraded request was given its id when it was read from HTTP/1.1.
+    let id = match &request {
+        Ok(request) => request
+            .id()
+            .map_or_else(|| request_id::choose(request), String::from),
+        Err(_) => request_id::generate(),
+    };
+    span.record("request_id", &id);
+    let _entered = request_id::enter(&id);
+    let mut head = None;
+    // Answers to HEAD have no body, even those that are errors.
+    let mut with_body = true;
+    let response = match request {
+        // A panicking handler only takes down its own stream.
+        Ok(mut request) => {
+            request.set_id(id.clone());
+            span.record("method", request.method().as_str());
+            span.record("path", request.path());
+            with_body = request.method() != Method::Head;
+            if log.is_some() {
+                head = Some(request.head());
+            }
+            match panic::catch_unwind(AssertUnwindSafe(|| router.dispatch(request))) {
+                Ok(response) => response,
+                Err(_) => {
+                    let _ = shared.reset(stream, INTERNAL_ERROR);
+                    return;
+                }
+            }
+        }
+        Err(status) => Response::error(status),
+    };
+    let response = if response.headers().contains(request_id::HEADER) {
+        response
+    } else {
+        response.header(request_id::HEADER, id.as_str())
+    };
+
+    let status = response.status();
+    span.record("status", status.code());
+    let mut body_bytes = 0;
+    match send_response(shared, stream, response, with_body, &mut body_bytes) {
+        Ok(()) => shared.forget(stream),
+        // The connection is gone if writing failed, but not if the body could not be
+        // read.
+        Err(_) => {
+            let _ = shared.reset(stream, INTERNAL_ERROR);
+        }
+    }
+    if let Some(log) = log {
+        log(Exchange {
+            request: head,
+            request_id: Some(id),
+            status,
+            body_bytes,
+            started: time,
+            duration: instant.elapsed(),
+        });
+    }
+}
+
+/// Send `response` on `stream`, adding the bytes of its body to `sent` as they are.
+/// Without `with_body`, as for HEAD, the stream ends with the headers, which keep
+/// `content-length`, and the body is not read.
+fn send_response<T: Write>(
+    shared: &Shared<T>,
+    stream: u32,
+    response: Response,
+    with_body: bool,
+    sent: &mut u64,
+) -> io::Result<()> {
+    let mut parts = response.into_parts();
+
+    let mut fields = vec![(String::from(":status"), parts.status.code().to_string())];
+    fields.extend(lowercase_fields(&parts.headers).filter(|(name, _)| name != "content-length"));
+    if let Some(length) = parts.length {
+        fields.push((String::from("content-length"), length.to_string()));
+    }
+    if !with_body {
+        return shared.headers(stream, &fields, true);
+    }
+
+    let mut buffer = vec![0; DEFAULT_MAX_FRAME_SIZE];
+    let mut read = read_some(&mut parts.body, &mut buffer)?;
+    if read == 0 && parts.trailers.is_none() {
+        return shared.headers(stream, &fields, true);
+    }
+    shared.headers(stream, &fields, false)?;
+
+    let start = *sent;
+    while read > 0 {
+        if !shared.data(stream, &buffer[..read])? {
+            return Ok(());
+        }
+        *sent += read as u64;
+        read = read_some(&mut parts.body, &mut buffer)?;
+    }
+    if parts.length.is_some_and(|length| *sent - start < length) {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+
+    let trailers = parts
+        .trailers
+        .map(|trailers| trailers())
+        .unwrap_or_default();
+    if trailers.is_empty() {
+        shared.writer().frame(DATA, END_STREAM, stream, &[])
+    } else {
+        let trailers: Vec<_> = lowercase_fields(&trailers).collect();
+        shared.headers(stream, &trailers, true)
+    }
+}
+
+/// The fields of `headers` that can be sent over HTTP/2, with lowercase names.
+fn lowercase_fields(headers: &Headers) -> impl Iterator<Item = (String, String)> + '_ {
+    headers
+        .iter()
+        .map(|(name, value)| (name.to_ascii_lowercase(), value.to_owned()))
+        .filter(|(name, _)| !CONNECTION_SPECIFIC.contains(&&name[..]))
+}
+
+fn read_some(reader: &mut impl Read, buffer: &mut [u8]) -> io::Result<usize> {
+    loop {
+        match reader.read(buffer) {
+            Err(error) if error.kind() == io::ErrorKind::Interrupted => continue,
+            result => return result,
+        }
+    }
+}
+
+/// A stream whose request has not been received in full.
+#[derive(Default)]
+struct Incoming {
+    headers: Vec<hpack::Field>,
+    body: Vec<u8>,
+    trailers: Vec<hpack::Field>,
+}
+
+impl Incoming {
+    /// Assemble the request. Fails with `None` if it is malformed, so the stream has
+    /// to be reset, or with the status to answer it with otherwise.
+    fn into_request(self, limits: Limits) -> Result<Request, Option<Status>> {
+        let too_large = |fields: &[hpack::Field]| {
+            let size: usize = fields
+                .iter()
+                .map(|(name, value)| name.len() + value.len())
+                .sum();
+            fields.len() > limits.headers || size > limits.header_size
+        };
+        if too_large(&self.headers) || too_large(&self.trailers) {
+            return Err(Some(Status::RequestHeaderFieldsTooLarge));
+        }
+        let mut method = None;
+        let mut scheme = None;
+        let mut path = None;
+        let mut authority = None;
+        let mut headers = Headers::new();
+        let mut cookies = Vec::new();
+
+        for (name, value) in self.headers {
+            let (Ok(name), Ok(value)) = (String::from_utf8(name), String::from_utf8(value)) else {
+                return Err(Some(Status::BadRequest));
+            };
+
+            if let Some(pseudo) = name.strip_prefix(':') {
+                // Pseudo-header fields come first, once each.
+                let slot = match pseudo {
+                    "method" => &mut method,
+                    "scheme" => &mut scheme,
+                    "path" => &mut path,
+                    "authority" => &mut authority,
+                    _ => return Err(None),
+                };
+                if !headers.is_empty() || !cookies.is_empty() || slot.replace(value).is_some() {
+                    return Err(None);
+                }
+            } else if name.bytes().any(|byte| byte.is_ascii_uppercase())
+                || CONNECTION_SPECIFIC.contains(&&name[..])
+                || (name == "te" && value != "trailers")
+            {
+                return Err(None);
+            } else if name == "cookie" {
+                // Cookies may be split into several fields to compress better.
+                cookies.push(value);
+            } else {
+                headers.append(name, value);
+            }
+        }
+        if !cookies.is_empty() {
+            headers.append("cookie", cookies.join("; "));
+        }
+
+        let method: Method = method
+            .ok_or(None)?
+            .parse()
+            .map_err(|_| Some(Status::NotImplemented))?;
+        let target = if method == Method::Connect {
+            authority.clone().ok_or(None)?
+        } else {
+            scheme.ok_or(None)?;
+            path.filter(|path| !path.is_empty()).ok_or(None)?
+        };
+        if target.len() > limits.request_line {
+            return Err(Some(Status::UriTooLong));
+        }
+        if let Some(authority) = authority {
+            if !headers.contains("host") {
+                headers.append("host", authority);
+            }
+        }
+        if let Some(length) = headers.get("content-length") {
+            if length.parse() != Ok(self.body.len()) {
+                return Err(None);
+            }
+        }
+
+        let mut trailers = Headers::new();
+        for (name, value) in self.trailers {
+            let (Ok(name), Ok(value)) = (String::from_utf8(name), String::from_utf8(value)) else {
+                return Err(Some(Status::BadRequest));
+            };
+            trailers.append(name, value);
+        }
+        Ok(Request::from_parts(
+            method,
+            target,
+            Version::Http2,
+            headers,
+            self.body,
+            trailers,
+        ))
+    }
+}
+
+/// What the reader does after a frame.
+enum Event {
+    None,
+    /// Answer a stream whose request has been received, which is boxed so that the
+    /// other events stay small.
+    Dispatch(u32, Result<Box<Request>, Status>),
+    /// Stop reading, as the client is going away.
+    Stop,
+}
+
+/// The state of a connection kept by the thread reading it.
+struct Connection<'a, T> {
+    shared: &'a Shared<T>,
+    limits: Limits,
+    decoder: hpack::Decoder,
+    incoming: HashMap<u32, Incoming>,
+    /// The highest stream the client has opened.
+    last_stream: u32,
+    /// A header block waiting for CONTINUATION frames: its stream, the flags of the
+    /// HEADERS frame, and what has arrived so far.
+    continuation: Option<(u32, u8, Vec<u8>)>,
+}
+
+impl<T: Write> Connection<'_, T> {
+    fn handle(&mut self, frame: Frame) -> Result<Event, Error> {
+        if let Some((stream, ..)) = self.continuation {
+            if frame.kind != CONTINUATION || frame.stream != stream {
+                return Err(Error::Connection(PROTOCOL_ERROR));
+            }
+        }
+        let on_connection = frame.stream == 0;
+
+        match frame.kind {
+            DATA | HEADERS | PRIORITY | RST_STREAM | CONTINUATION if on_connection => {
+                Err(Error::Connection(PROTOCOL_ERROR))
+            }
+            SETTINGS | PING | GOAWAY if !on_connection => Err(Error::Connection(PROTOCOL_ERROR)),
+            DATA => self.data(frame),
+            HEADERS => self.headers(frame),
+            RST_STREAM => {
+                if frame.payload.len() != 4 {
+                    return Err(Error::Connection(FRAME_SIZE_ERROR));
+                }
+                if frame.stream > self.last_stream {
+                    return Err(Error::Connection(PROTOCOL_ERROR));
+                }
+                self.incoming.remove(&frame.stream);
+                self.shared.forget(frame.stream);
+                Ok(Event::None)
+            }
+            SETTINGS => {
+                if frame.flags & ACK != 0 {
+                    return match frame.payload.len() {
+                        0 => Ok(Event::None),
+                        _ => Err(Error::Connection(FRAME_SIZE_ERROR)),
+                    };
+                }
+                self.apply_settings(&frame.payload)?;
+                self.shared.writer().frame(SETTINGS, ACK, 0, &[])?;
+                Ok(Event::None)
+            }
+            // The server never pushes, and clients may not.
+            PUSH_PROMISE => Err(Error::Connection(PROTOCOL_ERROR)),
+            PING => {
+                if frame.payload.len() != 8 {
+                    return Err(Error::Connection(FRAME_SIZE_ERROR));
+                }
+                if frame.flags & ACK == 0 {
+                    self.shared.writer().frame(PING, ACK, 0, &frame.payload)?;
+                }
+                Ok(Event::None)
+            }
+            GOAWAY => Ok(Event::Stop),
+            WINDOW_UPDATE => self.window_update(frame),
+            CONTINUATION => {
+                let Some((stream, flags, mut block)) = self.continuation.take() else {
+                    return Err(Error::Connection(PROTOCOL_ERROR));
+                };
+                block.extend_from_slice(&frame.payload);
+                if block.len() > MAX_HEADER_BLOCK {
+                    return Err(Error::Connection(ENHANCE_YOUR_CALM));
+                }
+                if frame.flags & END_HEADERS == 0 {
+                    self.continuation = Some((stream, flags, block));
+                    return Ok(Event::None);
+                }
+                self.header_block(stream, flags, &block)
+            }
+            // Priorities are only advice, and unknown frames are to be ignored.
+            _ => Ok(Event::None),
+        }
+    }
+
+    fn data(&mut self, frame: Frame) -> Result<Event, Error> {
+        let stream = frame.stream;
+        // Padding counts against the window too, so all of it is given back.
+        let length = frame.payload.len();
+        if length > 0 {
+            self.shared.writer().window_update(0, length)?;
+        }
+
+        let data = frame.unpadded()?;
+        let Some(incoming) = self.incoming.get_mut(&stream) else {
+            if stream > self.last_stream {
+                return Err(Error::Connection(PROTOCOL_ERROR));
+            }
+            self.shared.reset(stream, STREAM_CLOSED)?;
+            return Ok(Event::None);
+        };
+        if incoming.body.len() + data.len() > self.limits.body {
+            // The rest of the body is refused as for a closed stream.
+            self.incoming.remove(&stream);
+            return Ok(Event::Dispatch(stream, Err(Status::PayloadTooLarge)));
+        }
+        incoming.body.extend_from_slice(data);
+
+        if frame.flags & END_STREAM != 0 {
+            return self.finish(stream);
+        }
+        if length > 0 {
+            self.shared.writer().window_update(stream, length)?;
+        }
+        Ok(Event::None)
+    }
+
+    fn headers(&mut self, frame: Frame) -> Result<Event, Error> {
+        if frame.stream.is_multiple_of(2) {
+            return Err(Error::Connection(PROTOCOL_ERROR));
+        }
+        let mut block = frame.unpadded()?;
+        if frame.flags & PRIORITY_FLAG != 0 {
+            block = block.get(5..).ok_or(Error::Connection(FRAME_SIZE_ERROR))?;
+        }
+
+        if frame.flags & END_HEADERS == 0 {
+            self.continuation = Some((frame.stream, frame.flags, block.to_vec()));
+            return Ok(Event::None);
+        }
+        self.header_block(frame.stream, frame.flags, block)
+    }
+
+    /// Act on a complete header block, which opens a stream or ends it with trailers.
+    fn header_block(&mut self, stream: u32, flags: u8, block: &[u8]) -> Result<Event, Error> {
+        // Blocks are decoded even for streams that are refused, to keep the table in
+        // step with the client's.
+        let fields = self
+            .decoder
+            .decode(block)
+            .map_err(|_| Error::Connection(COMPRESSION_ERROR))?;
+        let end_stream = flags & END_STREAM != 0;
+
+        if let Some(incoming) = self.incoming.get_mut(&stream) {
+            if !end_stream {
+                self.incoming.remove(&stream);
+                self.shared.reset(stream, PROTOCOL_ERROR)?;
+                return Ok(Event::None);
+            }
+            incoming.trailers = fields;
+            return self.finish(stream);
+        }
+        if stream <= self.last_stream {
+            self.shared.reset(stream, STREAM_CLOSED)?;
+            return Ok(Event::None);
+        }
+
+        self.last_stream = stream;
+        if self.shared.open_streams() >= MAX_CONCURRENT_STREAMS {
+            self.shared.reset(stream, REFUSED_STREAM)?;
+            return Ok(Event::None);
+        }
+        self.shared.open(stream);
+        self.incoming.insert(
+            stream,
+            Incoming {
+                headers: fields,
+                ..Incoming::default()
+            },
+        );
+
+        if end_stream {
+            return self.finish(stream);
+        }
+        Ok(Event::None)
+    }
+
+    /// Dispatch `stream`, whose request has been received in full.
+    fn finish(&mut self, stream: u32) -> Result<Event, Error> {
+        let incoming = self.incoming.remove(&stream).unwrap();
+        match incoming.into_request(self.limits) {
+            Ok(request) => Ok(Event::Dispatch(stream, Ok(Box::new(request)))),
+            Err(Some(status)) => Ok(Event::Dispatch(stream, Err(status))),
+            Err(None) => {
+                self.shared.reset(stream, PROTOCOL_ERROR)?;
+                Ok(Event::None)
+            }
+        }
+    }
+
+    fn apply_settings(&mut self, settings: &[u8]) -> Result<(), Error> {
+        if !settings.len().is_multiple_of(6) {
+            return Err(Error::Connection(FRAME_SIZE_ERROR));
+        }
+
+        for setting in settings.chunks_exact(6) {
+            let id = u16::from_be_bytes([setting[0], setting[1]]);
+            let value = u32::from_be_bytes([setting[2], setting[3], setting[4], setting[5]]);
+
+            match id {
+                SETTINGS_HEADER_TABLE_SIZE => self.shared.writer().encoder.table_size_changed(),
+                SETTINGS_ENABLE_PUSH if value > 1 => return Err(Error::Connection(PROTOCOL_ERROR)),
+                SETTINGS_INITIAL_WINDOW_SIZE => {
+                    let value = value as i64;
+                    if value > MAX_WINDOW {
+                        return Err(Error::Connection(FLOW_CONTROL_ERROR));
+                    }
+                    // The change applies to the windows of open streams as well.
+                    let mut windows = self.shared.windows();
+                    let change = value - windows.initial;
+                    windows.initial = value;
+                    for window in windows.streams.values_mut() {
+                        *window += change;
+                        if *window > MAX_WINDOW {
+                            return Err(Error::Connection(FLOW_CONTROL_ERROR));
+                        }
+                    }
+                    self.shared.window_opened.notify_all();
+                }
+                SETTINGS_MAX_FRAME_SIZE => {
+                    if !(DEFAULT_MAX_FRAME_SIZE as u32..=(1 << 24) - 1).contains(&value) {
+                        return Err(Error::Connection(PROTOCOL_ERROR));
+                    }
+                    self.shared
+                        .max_frame_size
+                        .store(value as usize, Ordering::Relaxed);
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn window_update(&mut self, frame: Frame) -> Result<Event, Error> {
+        let Ok(increment) = <[u8; 4]>::try_from(&frame.payload[..]) else {
+            return Err(Error::Connection(FRAME_SIZE_ERROR));
+        };
+        let increment = (u32::from_be_bytes(increment) & 0x7fff_ffff) as i64;
+
+        let mut windows = self.shared.windows();
+        let window = match frame.stream {
+            0 => &mut windows.connection,
+            stream => match windows.streams.get_mut(&stream) {
+                Some(window) => window,
+                None if stream > self.last_stream => {
+                    return Err(Error::Connection(PROTOCOL_ERROR));
+                }
+                // The stream has closed, which the client may not know yet.
+                None => return Ok(Event::None),
+            },
+        };
+        *window += increment;
+        let overflowed = *window > MAX_WINDOW;
+        drop(windows);
+        self.shared.window_opened.notify_all();
+
+        match (frame.stream, increment == 0 || overflowed) {
+            (_, false) => Ok(Event::None),
+            (0, true) if increment == 0 => Err(Error::Connection(PROTOCOL_ERROR)),
+            (0, true) => Err(Error::Connection(FLOW_CONTROL_ERROR)),
+            (stream, true) if increment == 0 => {
+                self.shared.reset(stream, PROTOCOL_ERROR)?;
+                Ok(Event::None)
+            }
+            (stream, true) => {
+                self.shared.reset(stream, FLOW_CONTROL_ERROR)?;
+                Ok(Event::None)
+            }
+        }
+    }
+}
+
+/// Serve HTTP/2 on a connection whose client preface has not been read yet, answering
+/// each stream with a job on `pool`.
+///
+/// `upgrade` is the HTTP/1.1 request that asked to upgrade to `h2c`, with the
+/// settings it sent, which is answered on stream 1.
+pub(crate) fn serve<T: Transport>(
+    mut reader: BufReader<T>,
+    router: &Router,
+    limits: Limits,
+    log: Option<Sink>,
+    pool: &Threadpool,
+    upgrade: Option<(Request, Vec<u8>)>,
+) -> io::Result<()> {
+    let shared = Shared {
+        writer: Mutex::new(Writer {
+            stream: reader.get_ref().try_clone()?,
+            encoder: hpack::Encoder::new(),
+        }),
+        windows: Mutex::new(Windows {
+            connection: DEFAULT_WINDOW,
+            streams: HashMap::new(),
+            initial: DEFAULT_WINDOW,
+            closed: false,
+        }),
+        window_opened: Condvar::new(),
+        max_frame_size: AtomicUsize::new(DEFAULT_MAX_FRAME_SIZE),
+    };
+    let mut connection = Connection {
+        shared: &shared,
+        limits,
+        decoder: hpack::Decoder::new(HEADER_TABLE_SIZE),
+        incoming: HashMap::new(),
+        last_stream: 0,
+        continuation: None,
+    };
+
+    // The server's preface, which does not have to wait for the client's.
+    let mut settings = SETTINGS_MAX_CONCURRENT_STREAMS.to_be_bytes().to_vec();
+    settings.extend_from_slice(&(MAX_CONCURRENT_STREAMS as u32).to_be_bytes());
+    shared.writer().frame(SETTINGS, 0, 0, &settings)?;
+
+    let result = pool.scope(|scope| {
+        let result = run(&mut connection, &mut reader, router, log, scope, upgrade);
+        // Nothing more is read, so no window will open for responses waiting on one.
+        shared.close();
+        if let Err(Error::Connection(code)) = result {
+            let mut payload = connection.last_stream.to_be_bytes().to_vec();
+            payload.extend_from_slice(&code.to_be_bytes());
+            let _ = shared.writer().frame(GOAWAY, 0, 0, &payload);
+        }
+        result
+    });
+
+    match result {
+        Ok(()) | Err(Error::Connection(_)) => Ok(()),
+        // Clients often close connections with frames they have not read, which resets
+        // them rather than ending them.
+        Err(Error::Io(error))
+            if matches!(
+                error.kind(),
+                io::ErrorKind::UnexpectedEof | io::ErrorKind::ConnectionReset
+            ) =>
+        {
+            Ok(())
+        }
+        Err(Error::Io(error)) => Err(error),
+    }
+}
+
+/// Read and act on frames until the connection ends, starting with the client's
+/// preface.
+fn run<'scope, 'env, T: Transport>(
+    connection: &mut Connection<'env, T>,
+    reader: &mut BufReader<T>,
+    router: &'env Router,
+    log: Option<Sink<'env>>,
+    scope: &'scope threadpool::Scope<'scope, 'env>,
+    upgrade: Option<(Request, Vec<u8>)>,
+) -> Result<(), Error> {
+    let shared = connection.shared;
+    let peer = reader.get_ref().peer_ip();
+    if let Some((request, settings)) = upgrade {
+        connection.apply_settings(&settings)?;
+        connection.last_stream = 1;
+        shared.open(1);
+        scope.execute(trace::bind(move || {
+            respond(shared, router, 1, Ok(request), log)
+        }));
+    }
+
+    let mut preface = [0; PREFACE.len()];
+    reader.read_exact(&mut preface)?;
+    let settings = read_frame(reader)?;
+    if preface != PREFACE || settings.kind != SETTINGS {
+        return Err(Error::Connection(PROTOCOL_ERROR));
+    }
+    connection.handle(settings)?;
+
+    loop {
+        match reader.fill_buf() {
+            Ok([]) => return Ok(()),
+            Ok(_) => {}
+            Err(error)
+                if matches!(
+                    error.kind(),
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                ) =>
+            {
+                // Only idle connections time out, not those waiting on a response.
+                if shared.open_streams() == 0 {
+                    return Err(Error::Connection(NO_ERROR));
+                }
+                continue;
+            }
+            Err(error) => return Err(error.into()),
+        }
+
+        match connection.handle(read_frame(reader)?)? {
+            Event::None => {}
+            Event::Dispatch(stream, request) => {
+                let mut request = request.map(|request| *request);
+                if let Ok(request) = &mut request {
+                    request.set_peer(peer);
+                }
+                scope.execute(trace::bind(move || {
+                    respond(shared, router, stream, request, log)
+                }))
+            }
+            Event::Stop => return Ok(()),
+        }
+    }
+}
+
+/// Decode the `HTTP2-Settings` header of an upgrade request, which is the payload of
+/// a SETTINGS frame in unpadded base64url.
+pub(crate) fn decode_settings(value: &str) -> Option<Vec<u8>> {
+    let mut settings = Vec::new();
+    let mut bits = 0u32;
+    let mut count = 0;
+
+    for byte in value.trim().trim_end_matches('=').bytes() {
+        let sextet = match byte {
+            b'A'..=b'Z' => byte - b'A',
+            b'a'..=b'z' => byte - b'a' + 26,
+            b'0'..=b'9' => byte - b'0' + 52,
+            b'-' => 62,
+            b'_' => 63,
+            _ => return None,
+        };
+        bits = (bits << 6 | sextet as u32) & 0xfff;
+        count += 6;
+        if count >= 8 {
+            count -= 8;
+            settings.push((bits >> count) as u8);
+        }
+    }
+    settings.len().is_multiple_of(6).then_some(settings)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::connection::{self, KeepAlive, Timeouts};
+    use std::{
+        net::{TcpListener, TcpStream},
+        thread,
+        time::Duration,
+    };
+
+    /// Serve one connection with HTTP/2 on a background thread, returning the client
+    /// end.
+    fn connect() -> (TcpStream, thread::JoinHandle<()>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let router = Router::new()
+                .get("/", |_| Response::new(Status::Ok).body("hi"))
+                .get("/slow", |_| {
+                    thread::sleep(Duration::from_millis(200));
+                    Response::new(Status::Ok).body("slow")
+                })
+                .post("/echo", |request| {
+                    Response::new(Status::Ok).body(request.body().to_vec())
+                });
+            let pool = threadpool::ThreadpoolBuilder::new(4).build().unwrap();
+            let (stream, _) = listener.accept().unwrap();
+            connection::serve_with_http2(
+                stream,
+                &router,
+                KeepAlive::default(),
+                Timeouts::default(),
+                Limits::default(),
+                None,
+                &pool,
+            )
+            .unwrap();
+        });
+
+        (TcpStream::connect(address).unwrap(), server)
+    }
+
+    fn send(client: &mut TcpStream, kind: u8, flags: u8, stream: u32, payload: &[u8]) {
+        let mut writer = Writer {
+            stream: client,
+            encoder: hpack::Encoder::new(),
+        };
+        writer.frame(kind, flags, stream, payload).unwrap();
+    }
+
+    fn request(client: &mut TcpStream, stream: u32, method: &str, path: &str, flags: u8) {
+        let block = hpack::Encoder::new().encode([
+            (":method", method),
+            (":scheme", "http"),
+            (":path", path),
+            (":authority", "example.com"),
+        ]);
+        send(client, HEADERS, flags | END_HEADERS, stream, &block);
+    }
+
+    /// Read frames until every one of `streams` has ended, returning each stream's
+    /// status and body in the order they ended.
+    fn responses(client: &mut TcpStream, streams: usize) -> Vec<(u32, String, String)> {
+        let mut decoder = hpack::Decoder::new(HEADER_TABLE_SIZE);
+        let mut open = HashMap::new();
+        let mut ended = Vec::new();
+
+        while ended.len() < streams {
+            let frame = read_frame(client).unwrap();
+            let (status, body) = open
+                .entry(frame.stream)
+                .or_insert((String::new(), String::new()));
+            match frame.kind {
+                HEADERS => {
+                    let fields = decoder.decode(&frame.payload).unwrap();
+                    *status = String::from_utf8(fields[0].1.clone()).unwrap();
+                }
+                DATA => body.push_str(std::str::from_utf8(&frame.payload).unwrap()),
+                _ => continue,
+            }
+            if frame.flags & END_STREAM != 0 {
+                let (status, body) = open.remove(&frame.stream).unwrap();
+                ended.push((frame.stream, status, body));
+            }
+        }
+        ended
+    }
+
+    fn start(client: &mut TcpStream, settings: &[u8]) {
+        client.write_all(PREFACE).unwrap();
+        send(client, SETTINGS, 0, 0, settings);
+    }
+
+    #[test]
+    fn answers_streams_concurrently() {
+        let (mut client, server) = connect();
+        start(&mut client, &[]);
+        request(&mut client, 1, "GET", "/slow", END_STREAM);
+        request(&mut client, 3, "GET", "/", END_STREAM);
+        request(&mut client, 5, "POST", "/echo", 0);
+        send(&mut client, DATA, END_STREAM, 5, b"echo");
+
+        let mut responses = responses(&mut client, 3);
+        assert_eq!(
+            responses.pop(),
+            Some((1, String::from("200"), String::from("slow")))
+        );
+        responses.sort();
+        assert_eq!(
+            responses,
+            [
+                (3, String::from("200"), String::from("hi")),
+                (5, String::from("200"), String::from("echo")),
+            ]
+        );
+
+        send(&mut client, GOAWAY, 0, 0, &[0; 8]);
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn answers_head_without_data() {
+        let (mut client, server) = connect();
+        start(&mut client, &[]);
+        request(&mut client, 1, "HEAD", "/", END_STREAM);
+        request(&mut client, 3, "HEAD", "/missing", END_STREAM);
+        request(&mut client, 5, "GET", "/", END_STREAM);
+
+        let mut responses = responses(&mut client, 3);
+        responses.sort();
+        assert_eq!(
+            responses,
+            [
+                (1, String::from("200"), String::new()),
+                (3, String::from("404"), String::new()),
+                (5, String::from("200"), String::from("hi")),
+            ]
+        );
+
+        send(&mut client, GOAWAY, 0, 0, &[0; 8]);
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn waits_for_the_client_to_open_its_window() {
+        let (mut client, server) = connect();
+        // An initial window of two bytes.
+        start(&mut client, &[0, 4, 0, 0, 0, 2]);
+        request(&mut client, 1, "POST", "/echo", 0);
+        send(&mut client, DATA, END_STREAM, 1, b"echo");
+
+        let data = loop {
+            let frame = read_frame(&mut client).unwrap();
+            if frame.kind == DATA {
+                break frame.payload;
+            }
+        };
+        assert_eq!(data, b"ec");
+
+        send(&mut client, WINDOW_UPDATE, 0, 1, &2u32.to_be_bytes());
+        assert_eq!(
+            responses(&mut client, 1),
+            [(1, String::new(), String::from("ho"))]
+        );
+
+        drop(client);
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn upgrades_from_http_1_1() {
+        let (mut client, server) = connect();
+        client
+            .write_all(
+                b"GET / HTTP/1.1\r\nHost: example.com\r\n\
+                  Connection: Upgrade, HTTP2-Settings\r\nUpgrade: h2c\r\n\
+                  HTTP2-Settings: AAMAAABkAAQAAP__\r\n\r\n",
+            )
+            .unwrap();
+
+        let mut head = Vec::new();
+        while !head.ends_with(b"\r\n\r\n") {
+            let mut byte = [0];
+            client.read_exact(&mut byte).unwrap();
+            head.push(byte[0]);
+        }
+        let head = String::from_utf8(head).unwrap();
+        assert!(head.starts_with("HTTP/1.1 101 Switching Protocols\r\n"));
+        assert!(head.contains("Upgrade: h2c\r\n"));
+
+        start(&mut client, &[]);
+        assert_eq!(
+            responses(&mut client, 1),
+            [(1, String::from("200"), String::from("hi"))]
+        );
+
+        drop(client);
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn goes_away_when_the_client_breaks_the_protocol() {
+        let (mut client, server) = connect();
+        start(&mut client, &[]);
+        // Clients may only open odd streams.
+        request(&mut client, 2, "GET", "/", END_STREAM);
+
+        let goaway = loop {
+            let frame = read_frame(&mut client).unwrap();
+            if frame.kind == GOAWAY {
+                break frame;
+            }
+        };
+        assert_eq!(goaway.payload[4..], PROTOCOL_ERROR.to_be_bytes());
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn holds_requests_to_their_limits() {
+        let incoming = |path: &str, fields: usize| Incoming {
+            headers: [(":method", "GET"), (":scheme", "http"), (":path", path)]
+                .into_iter()
+                .chain(std::iter::repeat_n(("x-field", "value"), fields))
+                .map(|(name, value)| (name.into(), value.into()))
+                .collect(),
+            ..Incoming::default()
+        };
+        let limits = Limits::default().request_line(10).headers(5);
+        let status = |incoming: Incoming| incoming.into_request(limits).err().flatten();
+
+        assert_eq!(status(incoming("/", 2)), None);
+        assert_eq!(
+            status(incoming("/a-long-path", 2)),
+            Some(Status::UriTooLong)
+        );
+        assert_eq!(
+            status(incoming("/", 3)),
+            Some(Status::RequestHeaderFieldsTooLarge)
+        );
+    }
+
+    #[test]
+    fn decodes_upgrade_settings() {
+        assert_eq!(
+            decode_settings("AAMAAABkAAQAAP__"),
+            Some(vec![0, 3, 0, 0, 0, 100, 0, 4, 0, 0, 255, 255])
+        );
+        assert_eq!(decode_settings(""), Some(Vec::new()));
+        assert_eq!(decode_settings("AAMA"), None);
+        assert_eq!(decode_settings("AAMAAABk+A=="), None);
+    }
+}
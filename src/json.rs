@@ -0,0 +1,144 @@
+//! Writing JSON, for logs and other output read by machines.
+
+use std::fmt::Write as _;
+
+/// A JSON object built up a member at a time, in the order they are added.
+/// ```
+/// use ch20_web_server::json::Object;
+///
+/// let json = Object::new()
+///     .string("path", "/a \"b\"")
+///     .number("status", 404)
+///     .optional_string("referer", None)
+///     .finish();
+/// assert_eq!(json, r#"{"path":"/a \"b\"","status":404,"referer":null}"#);
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Object {
+    json: String,
+}
+
+impl Object {
+    pub fn new() -> Object {
+        Object::default()
+    }
+
+    fn key(&mut self, key: &str) {
+        self.json.push(if self.json.is_empty() { '{' } else { ',' });
+        string(&mut self.json, key);
+        self.json.push(':');
+    }
+
+    pub fn string(mut self, key: &str, value: &str) -> Object {
+        self.key(key);
+        string(&mut self.json, value);
+        self
+    }
+
+    /// Add `value` as a string, or `null` if it is `None`.
+    pub fn optional_string(mut self, key: &str, value: Option<&str>) -> Object {
+        self.key(key);
+        match value {
+            Some(value) => string(&mut self.json, value),
+            None => self.json.push_str("null"),
+        }
+        self
+    }
+
+    pub fn number(mut self, key: &str, value: impl Into<f64>) -> Object {
+        self.key(key);
+        let value = value.into();
+        if value.is_finite() {
+            let _ = write!(self.json, "{value}");
+        } else {
+            self.json.push_str("null");
+        }
+        self
+    }
+
+    pub fn boolean(mut self, key: &str, value: bool) -> Object {
+        self.key(key);
+        let _ = write!(self.json, "{value}");
+        self
+    }
+
+    /// Add `value` as it is, which has to be JSON already, such as another object.
+    pub fn raw(mut self, key: &str, value: &str) -> Object {
+        self.key(key);
+        self.json.push_str(value);
+        self
+    }
+
+    /// The object's JSON.
+    pub fn finish(mut self) -> String {
+        if self.json.is_empty() {
+            self.json.push('{');
+        }
+        self.json.push('}');
+        self.json
+    }
+}
+
+/// A JSON array of `items`, each of which has to be JSON already.
+/// ```
+/// use ch20_web_server::json::{self, Object};
+///
+/// let items = [1, 2].map(|n| Object::new().number("n", n).finish());
+/// assert_eq!(json::array(items), r#"[{"n":1},{"n":2}]"#);
+/// ```
+pub fn array(items: impl IntoIterator<Item = String>) -> String {
+    let mut json = String::from("[");
+    for (i, item) in items.into_iter().enumerate() {
+        if i > 0 {
+            json.push(',');
+        }
+        json.push_str(&item);
+    }
+    json.push(']');
+    json
+}
+
+/// Append `value` as a JSON string, escaping what JSON requires to be.
+pub fn string(json: &mut String, value: &str) {
+    json.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            '\r' => json.push_str("\\r"),
+            '\t' => json.push_str("\\t"),
+            c if c < ' ' => {
+                let _ = write!(json, "\\u{:04x}", c as u32);
+            }
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escapes_strings() {
+        let mut json = String::new();
+        string(&mut json, "tab\there\nnul\0 é");
+        assert_eq!(json, r#""tab\there\nnul\u0000 é""#);
+    }
+
+    #[test]
+    fn writes_members_of_every_kind() {
+        assert_eq!(Object::new().finish(), "{}");
+        assert_eq!(
+            Object::new()
+                .number("ms", 1.5)
+                .number("count", 3u32)
+                .boolean("ok", true)
+                .raw("inner", "{}")
+                .finish(),
+            r#"{"ms":1.5,"count":3,"ok":true,"inner":{}}"#
+        );
+    }
+}
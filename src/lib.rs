@@ -0,0 +1,50 @@
+//! The web server built on top of the `threadpool` crate: parsing requests, routing
+//! them and answering them.
+
+pub mod access_log;
+pub mod acl;
+pub mod admin;
+pub mod api_key;
+mod argon2;
+pub mod args;
+mod base64;
+pub mod basic_auth;
+mod bcrypt;
+mod blake2b;
+mod blowfish;
+#[cfg(feature = "brotli")]
+pub mod brotli;
+pub mod compression;
+pub mod config;
+pub mod connection;
+pub mod cors;
+pub mod gzip;
+#[cfg(unix)]
+pub mod handover;
+pub mod headers;
+pub mod health;
+mod hpack;
+mod http2;
+pub mod json;
+pub mod listener;
+pub mod log_file;
+mod lz77;
+pub mod metrics;
+pub mod mime;
+pub mod password;
+pub mod rate_limit;
+pub mod request;
+pub mod request_id;
+pub mod response;
+pub mod router;
+pub mod security_headers;
+#[cfg(unix)]
+pub mod signal;
+#[cfg(target_os = "linux")]
+mod socket;
+pub mod static_files;
+#[cfg(unix)]
+pub mod systemd;
+pub mod timestamp;
+mod toml;
+pub mod trace;
@@ -0,0 +1,546 @@
+//! The sockets a server accepts connections on: TCP, or on Unix, a socket file that
+//! a proxy on the same host can connect to without going through the TCP stack.
+
+use std::{
+    fmt,
+    io::{self, Read, Write},
+    net::{IpAddr, SocketAddr, TcpListener, TcpStream},
+    time::Duration,
+};
+#[cfg(unix)]
+use std::{
+    fs,
+    os::unix::{
+        fs::{FileTypeExt, PermissionsExt},
+        io::{AsRawFd, OwnedFd, RawFd},
+        net::{UnixListener, UnixStream},
+    },
+    path::{Path, PathBuf},
+};
+
+use crate::connection::Transport;
+
+/// A socket accepting connections.
+#[derive(Debug)]
+pub enum Listener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(UnixListener),
+}
+
+impl Listener {
+    /// Listen on the Unix socket at `path`, with its file given the permissions in
+    /// `mode` if there is one.
+    ///
+    /// A socket file left behind by a server that has stopped is replaced, but it is
+    /// an error if a server is still listening on it or the file is not a socket.
+    #[cfg(unix)]
+    pub fn bind_unix(path: impl AsRef<Path>, mode: Option<u32>) -> io::Result<Listener> {
+        let path = path.as_ref();
+        remove_stale(path)?;
+        let listener = UnixListener::bind(path)?;
+        if let Some(mode) = mode {
+            fs::set_permissions(path, fs::Permissions::from_mode(mode))?;
+        }
+        Ok(Listener::Unix(listener))
+    }
+
+    /// Wait for the next connection.
+    pub fn accept(&self) -> io::Result<Stream> {
+        match self {
+            Listener::Tcp(listener) => listener.accept().map(|(stream, _)| Stream::Tcp(stream)),
+            #[cfg(unix)]
+            Listener::Unix(listener) => listener.accept().map(|(stream, _)| Stream::Unix(stream)),
+        }
+    }
+
+    /// Return a second handle to the socket.
+    pub fn try_clone(&self) -> io::Result<Listener> {
+        match self {
+            Listener::Tcp(listener) => listener.try_clone().map(Listener::Tcp),
+            #[cfg(unix)]
+            Listener::Unix(listener) => listener.try_clone().map(Listener::Unix),
+        }
+    }
+
+    /// Where the socket listens.
+    pub fn local_addr(&self) -> io::Result<Address> {
+        match self {
+            Listener::Tcp(listener) => listener.local_addr().map(Address::Tcp),
+            #[cfg(unix)]
+            Listener::Unix(listener) => {
+                let address = listener.local_addr()?;
+                let path = address.as_pathname().unwrap_or(Path::new(""));
+                Ok(Address::Unix(path.to_owned()))
+            }
+        }
+    }
+}
+
+impl From<TcpListener> for Listener {
+    fn from(listener: TcpListener) -> Listener {
+        Listener::Tcp(listener)
+    }
+}
+
+/// Take a listening socket that was opened elsewhere, such as by systemd, as
+/// whichever kind it is.
+#[cfg(unix)]
+impl From<OwnedFd> for Listener {
+    fn from(fd: OwnedFd) -> Listener {
+        let unix = UnixListener::from(fd);
+        // Only a Unix socket has a Unix address.
+        if unix.local_addr().is_ok() {
+            Listener::Unix(unix)
+        } else {
+            Listener::Tcp(TcpListener::from(OwnedFd::from(unix)))
+        }
+    }
+}
+
+#[cfg(unix)]
+impl AsRawFd for Listener {
+    fn as_raw_fd(&self) -> RawFd {
+        match self {
+            Listener::Tcp(listener) => listener.as_raw_fd(),
+            Listener::Unix(listener) => listener.as_raw_fd(),
+        }
+    }
+}
+
+/// Sets up a TCP listener with the options that have to be chosen before it binds.
+/// ```
+/// use ch20_web_server::listener::TcpListenerBuilder;
+///
+/// // Only IPv6 clients, rather than IPv4 ones too as dual-stack hosts default to.
+/// let listener = TcpListenerBuilder::new("[::1]:0".parse().unwrap())
+///     .only_v6(true)
+///     .bind();
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TcpListenerBuilder {
+    address: SocketAddr,
+    only_v6: Option<bool>,
+    reuse_port: bool,
+    backlog: u32,
+    send_buffer: Option<usize>,
+    receive_buffer: Option<usize>,
+}
+
+impl TcpListenerBuilder {
+    /// Listen on `address`, with the system's default options and room for 128
+    /// connections waiting to be accepted, as `TcpListener::bind` has.
+    pub fn new(address: SocketAddr) -> TcpListenerBuilder {
+        TcpListenerBuilder {
+            address,
+            only_v6: None,
+            reuse_port: false,
+            backlog: 128,
+            send_buffer: None,
+            receive_buffer: None,
+        }
+    }
+
+    /// Whether an IPv6 listener refuses IPv4 clients. If it does not, as by default
+    /// on most systems, they connect from IPv4-mapped addresses like
+    /// `::ffff:192.0.2.1`. Ignored for IPv4 addresses.
+    pub fn only_v6(mut self, only: bool) -> TcpListenerBuilder {
+        self.only_v6 = Some(only);
+        self
+    }
+
+    /// Let other sockets listen on the same address, if they set this too, with the
+    /// system spreading the connections between them. Each can then be accepted on by
+    /// its own thread, rather than every accept waiting on one.
+    ///
+    /// Any process of the same user can join in, so this is only for addresses that
+    /// are not shared with other programs.
+    pub fn reuse_port(mut self, reuse: bool) -> TcpListenerBuilder {
+        self.reuse_port = reuse;
+        self
+    }
+
+    /// How many connections may wait to be accepted before more are refused. The
+    /// system may lower it to its own limit, such as `net.core.somaxconn` on Linux.
+    pub fn backlog(mut self, backlog: u32) -> TcpListenerBuilder {
+        self.backlog = backlog;
+        self
+    }
+
+    /// The size in bytes of the send buffer of each connection accepted.
+    pub fn send_buffer(mut self, bytes: usize) -> TcpListenerBuilder {
+        self.send_buffer = Some(bytes);
+        self
+    }
+
+    /// The size in bytes of the receive buffer of each connection accepted. It is set
+    /// on the listener, since it has to be before the connection is made for sizes
+    /// over 64 KiB.
+    pub fn receive_buffer(mut self, bytes: usize) -> TcpListenerBuilder {
+        self.receive_buffer = Some(bytes);
+        self
+    }
+
+    /// Open the socket and start listening.
+    pub fn bind(self) -> io::Result<TcpListener> {
+        #[cfg(target_os = "linux")]
+        {
+            use crate::socket::{
+                self, IPPROTO_IPV6, IPV6_V6ONLY, SOL_SOCKET, SO_RCVBUF, SO_REUSEPORT, SO_SNDBUF,
+            };
+            let size = |bytes: usize| bytes.try_into().unwrap_or(i32::MAX);
+
+            let mut options = Vec::new();
+            if let (SocketAddr::V6(_), Some(only)) = (self.address, self.only_v6) {
+                options.push((IPPROTO_IPV6, IPV6_V6ONLY, only as _));
+            }
+            if self.reuse_port {
+                options.push((SOL_SOCKET, SO_REUSEPORT, 1));
+            }
+            if let Some(bytes) = self.send_buffer {
+                options.push((SOL_SOCKET, SO_SNDBUF, size(bytes)));
+            }
+            if let Some(bytes) = self.receive_buffer {
+                options.push((SOL_SOCKET, SO_RCVBUF, size(bytes)));
+            }
+            let backlog = self.backlog.try_into().unwrap_or(i32::MAX);
+            socket::bind_with(self.address, &options, backlog)
+        }
+        #[cfg(not(target_os = "linux"))]
+        if self == TcpListenerBuilder::new(self.address) {
+            TcpListener::bind(self.address)
+        } else {
+            Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "socket options are only supported on Linux",
+            ))
+        }
+    }
+}
+
+/// Options set on each TCP connection as it is accepted. Connections to Unix
+/// sockets have none of them.
+/// ```
+/// use std::time::Duration;
+/// use ch20_web_server::listener::StreamOptions;
+///
+/// let options = StreamOptions::default().keepalive(Some(Duration::from_secs(60)));
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StreamOptions {
+    nodelay: bool,
+    keepalive: Option<Duration>,
+}
+
+impl Default for StreamOptions {
+    /// Send small writes at once, without TCP keepalive.
+    fn default() -> StreamOptions {
+        StreamOptions {
+            nodelay: true,
+            keepalive: None,
+        }
+    }
+}
+
+impl StreamOptions {
+    /// Whether to set `TCP_NODELAY`, sending small writes at once rather than waiting
+    /// for more as Nagle's algorithm does. Responses are written whole, so holding
+    /// back their ends only adds latency.
+    pub fn nodelay(mut self, nodelay: bool) -> StreamOptions {
+        self.nodelay = nodelay;
+        self
+    }
+
+    /// Probe for peers that have gone away without closing, once a connection has
+    /// been idle for `interval` and then every `interval` until one answers, or not
+    /// at all if it is `None`.
+    pub fn keepalive(mut self, interval: Option<Duration>) -> StreamOptions {
+        self.keepalive = interval;
+        self
+    }
+
+    /// Set the options on `stream`.
+    pub fn apply(&self, stream: &Stream) -> io::Result<()> {
+        let Stream::Tcp(stream) = stream else {
+            return Ok(());
+        };
+        stream.set_nodelay(self.nodelay)?;
+
+        #[cfg(target_os = "linux")]
+        if let Some(interval) = self.keepalive {
+            use crate::socket::{
+                set_option, IPPROTO_TCP, SOL_SOCKET, SO_KEEPALIVE, TCP_KEEPIDLE, TCP_KEEPINTVL,
+            };
+
+            let fd = stream.as_raw_fd();
+            let seconds = interval.as_secs().clamp(1, i32::MAX as u64) as i32;
+            set_option(fd, SOL_SOCKET, SO_KEEPALIVE, 1)?;
+            set_option(fd, IPPROTO_TCP, TCP_KEEPIDLE, seconds)?;
+            set_option(fd, IPPROTO_TCP, TCP_KEEPINTVL, seconds)?;
+        }
+        #[cfg(not(target_os = "linux"))]
+        if self.keepalive.is_some() {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "socket options are only supported on Linux",
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Remove the socket file at `path` if no server is listening on it any more.
+#[cfg(unix)]
+fn remove_stale(path: &Path) -> io::Result<()> {
+    match fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.file_type().is_socket() => {}
+        Ok(_) => {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                "the file is not a socket",
+            ))
+        }
+        Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(error) => return Err(error),
+    }
+    match UnixStream::connect(path) {
+        Ok(_) => Err(io::Error::new(
+            io::ErrorKind::AddrInUse,
+            "a server is listening on the socket",
+        )),
+        Err(error) if error.kind() == io::ErrorKind::ConnectionRefused => fs::remove_file(path),
+        Err(error) => Err(error),
+    }
+}
+
+/// Where a listener listens.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Address {
+    Tcp(SocketAddr),
+    #[cfg(unix)]
+    Unix(PathBuf),
+}
+
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Address::Tcp(address) => address.fmt(f),
+            #[cfg(unix)]
+            Address::Unix(path) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
+/// A connection accepted by a `Listener`.
+#[derive(Debug)]
+pub enum Stream {
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(UnixStream),
+}
+
+impl Stream {
+    /// The address of the client, or `None` for one connected to a Unix socket,
+    /// which has none. IPv4 clients of a dual-stack listener are given as IPv4
+    /// addresses rather than as IPv4-mapped IPv6 ones.
+    pub fn peer_addr(&self) -> io::Result<Option<SocketAddr>> {
+        match self {
+            Stream::Tcp(stream) => {
+                let address = stream.peer_addr()?;
+                Ok(Some(match address {
+                    SocketAddr::V6(v6) => match v6.ip().to_ipv4_mapped() {
+                        Some(ip) => SocketAddr::new(ip.into(), v6.port()),
+                        None => address,
+                    },
+                    address => address,
+                }))
+            }
+            #[cfg(unix)]
+            Stream::Unix(_) => Ok(None),
+        }
+    }
+}
+
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Stream::Tcp(stream) => stream.read(buf),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Stream::Tcp(stream) => stream.write(buf),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Stream::Tcp(stream) => stream.flush(),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.flush(),
+        }
+    }
+}
+
+impl Transport for Stream {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        match self {
+            Stream::Tcp(stream) => stream.set_read_timeout(timeout),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.set_read_timeout(timeout),
+        }
+    }
+
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        match self {
+            Stream::Tcp(stream) => stream.set_write_timeout(timeout),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.set_write_timeout(timeout),
+        }
+    }
+
+    fn try_clone(&self) -> io::Result<Stream> {
+        match self {
+            Stream::Tcp(stream) => stream.try_clone().map(Stream::Tcp),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.try_clone().map(Stream::Unix),
+        }
+    }
+
+    fn peer_ip(&self) -> Option<IpAddr> {
+        match self {
+            Stream::Tcp(stream) => stream.peer_ip(),
+            #[cfg(unix)]
+            Stream::Unix(_) => None,
+        }
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::{env, net::Ipv6Addr, process};
+
+    #[test]
+    fn gives_ipv4_clients_of_dual_stack_listeners_as_ipv4() {
+        // Not every host has IPv6.
+        let Ok(listener) = TcpListenerBuilder::new((Ipv6Addr::UNSPECIFIED, 0).into())
+            .only_v6(false)
+            .bind()
+        else {
+            return;
+        };
+        let port = listener.local_addr().unwrap().port();
+        let client = TcpStream::connect(("127.0.0.1", port)).unwrap();
+
+        let stream = Listener::from(listener).accept().unwrap();
+        assert_eq!(
+            stream.peer_addr().unwrap(),
+            Some(client.local_addr().unwrap())
+        );
+    }
+
+    #[test]
+    fn shares_a_port_between_listeners() {
+        let builder = |address| TcpListenerBuilder::new(address).reuse_port(true);
+        let first = builder("127.0.0.1:0".parse().unwrap()).bind().unwrap();
+        let address = first.local_addr().unwrap();
+        let second = builder(address).bind().unwrap();
+        assert_eq!(second.local_addr().unwrap(), address);
+
+        // Without the option, the port is taken.
+        assert_eq!(
+            TcpListenerBuilder::new(address).bind().unwrap_err().kind(),
+            io::ErrorKind::AddrInUse
+        );
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn sets_options_on_accepted_connections() {
+        use crate::socket::{get_option, IPPROTO_TCP, SOL_SOCKET, SO_KEEPALIVE, TCP_KEEPIDLE};
+
+        let listener = TcpListenerBuilder::new("127.0.0.1:0".parse().unwrap())
+            .backlog(16)
+            .receive_buffer(256 * 1024)
+            .bind()
+            .unwrap();
+        let _client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let stream = Listener::from(listener).accept().unwrap();
+        StreamOptions::default()
+            .keepalive(Some(Duration::from_secs(30)))
+            .apply(&stream)
+            .unwrap();
+
+        let Stream::Tcp(stream) = stream else {
+            unreachable!()
+        };
+        assert!(stream.nodelay().unwrap());
+        let fd = stream.as_raw_fd();
+        assert_eq!(get_option(fd, SOL_SOCKET, SO_KEEPALIVE).unwrap(), 1);
+        assert_eq!(get_option(fd, IPPROTO_TCP, TCP_KEEPIDLE).unwrap(), 30);
+    }
+
+    #[test]
+    fn refuses_ipv4_clients_if_only_v6() {
+        let Ok(listener) = TcpListenerBuilder::new((Ipv6Addr::UNSPECIFIED, 0).into())
+            .only_v6(true)
+            .bind()
+        else {
+            return;
+        };
+        let port = listener.local_addr().unwrap().port();
+        assert!(TcpStream::connect(("127.0.0.1", port)).is_err());
+    }
+
+    #[test]
+    fn replaces_a_stale_socket_file() {
+        let path = env::temp_dir().join(format!("listener-{}.sock", process::id()));
+        let _ = fs::remove_file(&path);
+
+        let listener = Listener::bind_unix(&path, Some(0o600)).unwrap();
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        assert_eq!(
+            Listener::bind_unix(&path, None).unwrap_err().kind(),
+            io::ErrorKind::AddrInUse
+        );
+
+        // Dropping the listener leaves its file behind.
+        drop(listener);
+        let listener = Listener::bind_unix(&path, None).unwrap();
+        let mut client = UnixStream::connect(&path).unwrap();
+        let mut stream = listener.accept().unwrap();
+        client.write_all(b"ping").unwrap();
+        let mut ping = [0; 4];
+        stream.read_exact(&mut ping).unwrap();
+        assert_eq!(&ping, b"ping");
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn takes_either_kind_of_socket_from_a_descriptor() {
+        let tcp = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = tcp.local_addr().unwrap();
+        let listener = Listener::from(OwnedFd::from(tcp));
+        assert_eq!(listener.local_addr().unwrap(), Address::Tcp(address));
+
+        let path = env::temp_dir().join(format!("listener-fd-{}.sock", process::id()));
+        let _ = fs::remove_file(&path);
+        let unix = UnixListener::bind(&path).unwrap();
+        let listener = Listener::from(OwnedFd::from(unix));
+        assert_eq!(listener.local_addr().unwrap(), Address::Unix(path.clone()));
+        assert_eq!(
+            listener.local_addr().unwrap().to_string(),
+            format!("unix:{}", path.display())
+        );
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,264 @@
+//! Log files that rotate: once they reach a size or a new day starts, they are
+//! renamed to `name.1`, the one before that to `name.2`, and so on, and a new file
+//! is started. They can also be reopened after another program such as logrotate
+//! has moved them.
+
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::gzip::GzipEncoder;
+
+/// When a log file is rotated, and what is kept of the old ones.
+/// ```
+/// use ch20_web_server::log_file::Rotation;
+///
+/// let rotation = Rotation::default()
+///     .max_size(Some(10 * 1024 * 1024))
+///     .daily(true)
+///     .keep(14)
+///     .compress(true);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rotation {
+    max_size: Option<u64>,
+    daily: bool,
+    keep: usize,
+    compress: bool,
+}
+
+impl Default for Rotation {
+    /// Never rotate, but keep 7 old files uncompressed if rotation is turned on.
+    fn default() -> Rotation {
+        Rotation {
+            max_size: None,
+            daily: false,
+            keep: 7,
+            compress: false,
+        }
+    }
+}
+
+impl Rotation {
+    /// Rotate before a write would take the file past `bytes`, or never if `None`.
+    pub fn max_size(mut self, bytes: Option<u64>) -> Rotation {
+        self.max_size = bytes;
+        self
+    }
+
+    /// Rotate on the first write of each day, in UTC.
+    pub fn daily(mut self, daily: bool) -> Rotation {
+        self.daily = daily;
+        self
+    }
+
+    /// Keep `count` old files, removing the oldest beyond them.
+    pub fn keep(mut self, count: usize) -> Rotation {
+        self.keep = count;
+        self
+    }
+
+    /// Compress old files with gzip, as `name.1.gz` and so on.
+    pub fn compress(mut self, compress: bool) -> Rotation {
+        self.compress = compress;
+        self
+    }
+}
+
+/// A file that lines are appended to, rotated as its `Rotation` says.
+///
+/// Writes are passed straight to the file, so each line should be written at once
+/// to be kept whole when the file rotates.
+#[derive(Debug)]
+pub struct LogFile {
+    path: PathBuf,
+    rotation: Rotation,
+    file: File,
+    /// The length of the file.
+    size: u64,
+    /// The day, counted from 1970, the last write was on.
+    day: u64,
+}
+
+impl LogFile {
+    /// Open the file at `path` to append to, creating it if it does not exist.
+    pub fn open(path: impl Into<PathBuf>, rotation: Rotation) -> io::Result<LogFile> {
+        let path = path.into();
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        Ok(LogFile {
+            size: file.metadata()?.len(),
+            day: today(),
+            path,
+            rotation,
+            file,
+        })
+    }
+
+    /// Open the file at the path again, such as after it has been renamed so writes
+    /// go to a new one.
+    pub fn reopen(&mut self) -> io::Result<()> {
+        *self = LogFile::open(&self.path, self.rotation)?;
+        Ok(())
+    }
+
+    /// Make way for a new file by moving each old one along, dropping the oldest.
+    fn rotate(&mut self) -> io::Result<()> {
+        let suffix = if self.rotation.compress { ".gz" } else { "" };
+        let archive = |n: usize| {
+            let mut name = self.path.clone().into_os_string();
+            name.push(format!(".{n}{suffix}"));
+            PathBuf::from(name)
+        };
+
+        if self.rotation.keep == 0 {
+            fs::remove_file(&self.path)?;
+        } else {
+            match fs::remove_file(archive(self.rotation.keep)) {
+                Err(error) if error.kind() != io::ErrorKind::NotFound => return Err(error),
+                _ => {}
+            }
+            for n in (1..self.rotation.keep).rev() {
+                match fs::rename(archive(n), archive(n + 1)) {
+                    Err(error) if error.kind() != io::ErrorKind::NotFound => return Err(error),
+                    _ => {}
+                }
+            }
+            if self.rotation.compress {
+                compress(&self.path, &archive(1))?;
+            } else {
+                fs::rename(&self.path, archive(1))?;
+            }
+        }
+        self.reopen()
+    }
+}
+
+impl Write for LogFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let day = today();
+        let full = self
+            .rotation
+            .max_size
+            .is_some_and(|max| self.size > 0 && self.size + buf.len() as u64 > max);
+        if full || (self.rotation.daily && day != self.day) {
+            self.rotate()?;
+        }
+        self.day = day;
+
+        let written = self.file.write(buf)?;
+        self.size += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+fn today() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_secs() / 86400)
+}
+
+/// Write `path` compressed to `to`, then remove it.
+fn compress(path: &Path, to: &Path) -> io::Result<()> {
+    let mut encoder = GzipEncoder::new(File::open(path)?);
+    io::copy(&mut encoder, &mut File::create(to)?)?;
+    fs::remove_file(path)
+}
+
+/// Where a log is written: a log file, or a stream such as standard error.
+pub enum Output {
+    Stream(Box<dyn Write + Send>),
+    File(LogFile),
+}
+
+impl Output {
+    /// Open the log file again, if this is one.
+    pub fn reopen(&mut self) -> io::Result<()> {
+        match self {
+            Output::Stream(_) => Ok(()),
+            Output::File(file) => file.reopen(),
+        }
+    }
+}
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Output::Stream(stream) => stream.write(buf),
+            Output::File(file) => file.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Output::Stream(stream) => stream.flush(),
+            Output::File(file) => file.flush(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process;
+
+    fn directory(name: &str) -> PathBuf {
+        let directory = std::env::temp_dir().join(format!("log-file-{name}-{}", process::id()));
+        let _ = fs::remove_dir_all(&directory);
+        fs::create_dir_all(&directory).unwrap();
+        directory
+    }
+
+    #[test]
+    fn rotates_at_the_size_limit_keeping_a_few() {
+        let directory = directory("size");
+        let path = directory.join("access.log");
+        let rotation = Rotation::default().max_size(Some(8)).keep(2);
+        let mut log = LogFile::open(&path, rotation).unwrap();
+
+        for line in ["one\n", "two\n", "three\n", "four\n", "five\n"] {
+            log.write_all(line.as_bytes()).unwrap();
+        }
+        let read = |name: &str| fs::read_to_string(directory.join(name)).unwrap();
+        assert_eq!(read("access.log"), "five\n");
+        assert_eq!(read("access.log.1"), "four\n");
+        assert_eq!(read("access.log.2"), "three\n");
+        assert!(!directory.join("access.log.3").exists());
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn compresses_old_files() {
+        let directory = directory("gzip");
+        let path = directory.join("server.log");
+        let rotation = Rotation::default().max_size(Some(4)).compress(true);
+        let mut log = LogFile::open(&path, rotation).unwrap();
+
+        log.write_all(b"old\n").unwrap();
+        log.write_all(b"new\n").unwrap();
+        let archive = fs::read(directory.join("server.log.1.gz")).unwrap();
+        assert_eq!(archive, crate::gzip::compress(b"old\n"));
+        assert_eq!(fs::read_to_string(&path).unwrap(), "new\n");
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn reopens_a_file_that_was_moved() {
+        let directory = directory("reopen");
+        let path = directory.join("server.log");
+        let mut log = LogFile::open(&path, Rotation::default()).unwrap();
+
+        log.write_all(b"before\n").unwrap();
+        fs::rename(&path, directory.join("moved.log")).unwrap();
+        log.reopen().unwrap();
+        log.write_all(b"after\n").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "after\n");
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
@@ -0,0 +1,158 @@
+//! The parts shared by the gzip and brotli encoders: finding repeated strings, and
+//! packing values into bits.
+
+use std::io::{self, Read};
+
+/// Input compressed as one block. Matches do not reach across blocks.
+pub(crate) const BLOCK_SIZE: usize = 64 * 1024;
+const WINDOW_SIZE: usize = 32 * 1024;
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+const HASH_BITS: u32 = 15;
+
+/// A piece of input, as found by `tokens`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Token {
+    Literal(u8),
+    /// A copy of `length` bytes starting `distance` bytes back.
+    Copy {
+        length: usize,
+        distance: usize,
+    },
+}
+
+/// Split `data` into literals and copies of earlier bytes, trying up to `max_chain`
+/// earlier positions for each copy. A `max_chain` of 0 gives only literals.
+pub(crate) fn tokens(data: &[u8], max_chain: usize, mut emit: impl FnMut(Token)) {
+    // The most recent position with each hash, and the one before each position with
+    // the same hash, both offset by one so zero means none.
+    let mut head = vec![0; 1 << HASH_BITS];
+    let mut previous = vec![0; data.len()];
+    let insert = |position: usize, head: &mut [usize], previous: &mut [usize]| {
+        if position + MIN_MATCH <= data.len() {
+            let hash = hash(&data[position..]);
+            previous[position] = head[hash];
+            head[hash] = position + 1;
+        }
+    };
+
+    let mut position = 0;
+    while position < data.len() {
+        let (length, distance) = longest_match(data, position, max_chain, &head, &previous);
+        if length >= MIN_MATCH {
+            emit(Token::Copy { length, distance });
+            for skipped in position..position + length {
+                insert(skipped, &mut head, &mut previous);
+            }
+            position += length;
+        } else {
+            emit(Token::Literal(data[position]));
+            insert(position, &mut head, &mut previous);
+            position += 1;
+        }
+    }
+}
+
+fn hash(data: &[u8]) -> usize {
+    let value = u32::from_le_bytes([data[0], data[1], data[2], 0]);
+    (value.wrapping_mul(0x9e37_79b1) >> (32 - HASH_BITS)) as usize
+}
+
+/// Find the longest earlier match for the bytes at `position`, as its length and
+/// distance back.
+fn longest_match(
+    data: &[u8],
+    position: usize,
+    max_chain: usize,
+    head: &[usize],
+    previous: &[usize],
+) -> (usize, usize) {
+    if position + MIN_MATCH > data.len() {
+        return (0, 0);
+    }
+    let limit = (data.len() - position).min(MAX_MATCH);
+    let mut best = (0, 0);
+    let mut candidate = head[hash(&data[position..])];
+
+    for _ in 0..max_chain {
+        let Some(start) = candidate.checked_sub(1) else {
+            break;
+        };
+        let distance = position - start;
+        if distance > WINDOW_SIZE {
+            break;
+        }
+
+        let length = data[start..]
+            .iter()
+            .zip(&data[position..position + limit])
+            .take_while(|(a, b)| a == b)
+            .count();
+        if length > best.0 {
+            best = (length, distance);
+            if length == limit {
+                break;
+            }
+        }
+        candidate = previous[start];
+    }
+    best
+}
+
+/// Read from `reader` until `block` holds `BLOCK_SIZE` bytes or the reader ends.
+/// Returns whether it ended.
+pub(crate) fn fill_block(reader: &mut impl Read, block: &mut Vec<u8>) -> io::Result<bool> {
+    block.clear();
+    while block.len() < BLOCK_SIZE {
+        let filled = block.len();
+        block.resize(BLOCK_SIZE, 0);
+
+        match reader.read(&mut block[filled..]) {
+            Ok(read) => {
+                block.truncate(filled + read);
+                if read == 0 {
+                    return Ok(true);
+                }
+            }
+            Err(error) => {
+                block.truncate(filled);
+                if error.kind() != io::ErrorKind::Interrupted {
+                    return Err(error);
+                }
+            }
+        }
+    }
+    Ok(false)
+}
+
+/// Writes values least significant bit first, as DEFLATE packs them.
+#[derive(Default)]
+pub(crate) struct BitWriter {
+    pub(crate) out: Vec<u8>,
+    buffer: u64,
+    count: u32,
+}
+
+impl BitWriter {
+    pub(crate) fn write(&mut self, value: u32, bits: u32) {
+        self.buffer |= (value as u64) << self.count;
+        self.count += bits;
+        while self.count >= 8 {
+            self.out.push(self.buffer as u8);
+            self.buffer >>= 8;
+            self.count -= 8;
+        }
+    }
+
+    /// Write a Huffman code, which is packed most significant bit first.
+    pub(crate) fn write_code(&mut self, code: u32, bits: u32) {
+        self.write(code.reverse_bits() >> (32 - bits), bits);
+    }
+
+    /// Pad with zero bits to the next byte.
+    pub(crate) fn align(&mut self) {
+        if self.count > 0 {
+            self.write(0, 8 - self.count);
+        }
+    }
+}
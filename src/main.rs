@@ -1,43 +1,957 @@
+use ch20_web_server::{
+    access_log::{AccessLog, Exchange, Sink},
+    acl,
+    admin::{self, Controls},
+    api_key::{self, Keys},
+    args::{self, Args, Command},
+    basic_auth::{self, BasicAuth, Htpasswd},
+    compression::Compression,
+    config::{Config, ConfigError, Endpoint, Listen, LogFormat, LogLevel, Swap},
+    connection::{self, Connections, KeepAlive, Timeouts},
+    cors,
+    health::{self, Readiness},
+    json::Object,
+    listener::{Address, Listener, TcpListenerBuilder},
+    log_file::{LogFile, Output},
+    metrics::{self, Registry},
+    rate_limit::{self, RateLimiter},
+    request::{Limits, Method},
+    request_id,
+    response::{Response, Status},
+    router::Router,
+    security_headers,
+    static_files::StaticFiles,
+    timestamp,
+    trace::{self, Record, SpanLog},
+};
+#[cfg(unix)]
+use ch20_web_server::{
+    handover,
+    signal::{Signal, Signals},
+    systemd,
+};
 use std::{
-    fs,
-    io::{prelude::*, BufReader},
-    net::{TcpListener, TcpStream},
+    env,
+    fmt::Arguments,
+    io::{self, Write},
+    net::{SocketAddr, TcpListener},
+    path::Path,
+    process,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
     thread,
-    time::Duration,
+    time::{Duration, SystemTime},
 };
-use threadpool::Threadpool;
+use threadpool::{PoolSnapshot, Threadpool, ThreadpoolBuilder};
+
+/// The file settings are read from if it exists and no other is given.
+const CONFIG_PATH: &str = "server.toml";
+/// How long a new server started by SIGUSR2 has to be ready to take over.
+#[cfg(unix)]
+const HANDOVER_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Writes the messages at or above its level, one per line, with the id of the
+/// request being answered when they were written, if there is one.
+struct Log {
+    /// Changed by the admin API until the settings are reloaded.
+    level: Mutex<LogLevel>,
+    format: LogFormat,
+    out: Mutex<Output>,
+}
+
+impl Log {
+    fn open(config: &Config) -> io::Result<Log> {
+        let out = match &config.log_file {
+            Some(path) => Output::File(LogFile::open(path, config.log_rotation)?),
+            None => Output::Stream(Box::new(io::stderr())),
+        };
+        Ok(Log {
+            level: Mutex::new(config.log_level),
+            format: config.log_format,
+            out: Mutex::new(out),
+        })
+    }
+
+    fn level(&self) -> LogLevel {
+        *self.level.lock().unwrap()
+    }
+
+    fn set_level(&self, level: LogLevel) {
+        *self.level.lock().unwrap() = level;
+    }
+
+    fn write(&self, level: LogLevel, message: Arguments) {
+        if level > self.level() {
+            return;
+        }
+        let request_id = request_id::current();
+        let mut line = match (self.format, &request_id) {
+            (LogFormat::Text, Some(id)) => format!("[{id}] {message}"),
+            (LogFormat::Text, None) => message.to_string(),
+            (LogFormat::Json, _) => Object::new()
+                .string("ts", &timestamp::rfc3339(SystemTime::now()))
+                .string("level", level.as_str())
+                .optional_string("request_id", request_id.as_deref())
+                .string("msg", &message.to_string())
+                .finish(),
+        };
+        line.push('\n');
+        // At once, so a line is not split when the file rotates.
+        let _ = self.out.lock().unwrap().write_all(line.as_bytes());
+    }
+
+    /// Open the log file again, if there is one.
+    #[cfg(unix)]
+    fn reopen(&self) -> io::Result<()> {
+        self.out.lock().unwrap().reopen()
+    }
+}
+
+/// What the endpoints that report on the server read, which is kept as the site is
+/// reloaded so counts do not start again.
+#[derive(Default)]
+struct Shared {
+    metrics: Arc<Registry>,
+    readiness: Arc<Readiness>,
+}
+
+/// What is built from the settings, and replaced when they are reloaded.
+/// Connections keep the site they were accepted with until they close.
+struct Site {
+    config: Config,
+    router: Router,
+    log: Arc<Log>,
+    access_log: Option<AccessLog>,
+    spans: Option<SpanLog>,
+}
+
+impl Site {
+    /// Read the settings, with the command line overriding the file, and build the
+    /// site they describe. Fails with a message saying what is wrong.
+    fn load(args: &Args, shared: &Shared) -> Result<Site, String> {
+        let path = args.config.as_deref().unwrap_or(Path::new(CONFIG_PATH));
+        let mut config = match Config::load(path) {
+            Ok(config) => config,
+            // Only a file that was asked for has to exist.
+            Err(ConfigError::Io(error))
+                if error.kind() == io::ErrorKind::NotFound && args.config.is_none() =>
+            {
+                Config::default()
+            }
+            Err(error) => return Err(format!("{}: {error}", path.display())),
+        };
+        args.apply(&mut config);
+
+        let log = Log::open(&config)
+            .map(Arc::new)
+            .map_err(|error| format!("Failed to open the log file: {error}"))?;
+        let access_log = match &config.access_log {
+            Some(path) => Some(
+                AccessLog::open(path, config.access_log_format, config.access_log_rotation)
+                    .map_err(|error| format!("Failed to open the access log: {error}"))?,
+            ),
+            None => None,
+        };
+        let spans = match &config.trace {
+            Some(path) => Some(
+                SpanLog::open(path, config.trace_rotation)
+                    .map_err(|error| format!("Failed to open the span log: {error}"))?,
+            ),
+            None => None,
+        };
+        let mut auth = BasicAuth::new();
+        for protected in &config.basic_auth {
+            let users = Htpasswd::load(&protected.file)
+                .map_err(|error| format!("Failed to read {}: {error}", protected.file.display()))?;
+            auth = auth.protect(&protected.prefix, &protected.realm, users);
+        }
+        let keys = match &config.api_keys {
+            Some(path) => Some(
+                Keys::load(path)
+                    .map_err(|error| format!("Failed to read {}: {error}", path.display()))?,
+            ),
+            None => None,
+        };
+        Ok(Site {
+            router: router(&config, shared, &log, auth, keys),
+            config,
+            log,
+            access_log,
+            spans,
+        })
+    }
+
+    /// Open the log files again, once they have been moved.
+    #[cfg(unix)]
+    fn reopen(&self) -> io::Result<()> {
+        self.log.reopen()?;
+        if let Some(access_log) = &self.access_log {
+            access_log.reopen()?;
+        }
+        if let Some(spans) = &self.spans {
+            spans.reopen()?;
+        }
+        Ok(())
+    }
+}
+
+/// A builder for a pool of the size `config` asks for, whose jobs are recorded as
+/// spans if the server is built to.
+fn pool_builder(config: &Config) -> ThreadpoolBuilder {
+    let builder = ThreadpoolBuilder::new(config.threads).max_size(config.max_threads);
+    #[cfg(feature = "pool-spans")]
+    let builder = builder.observer(trace::pool_spans());
+    builder
+}
+
+/// Record spans in the span log of whichever site is current, or none at all if it
+/// has no span log.
+fn export_spans(site: &Arc<Swap<Site>>) {
+    if site.get().spans.is_none() {
+        trace::set_exporter(None);
+        return;
+    }
+    let site = Arc::clone(site);
+    trace::set_exporter(Some(Arc::new(move |record: &Record| {
+        let current = site.get();
+        let Some(spans) = &current.spans else {
+            return;
+        };
+        if let Err(error) = spans.write(record) {
+            current.log.write(
+                LogLevel::Warn,
+                format_args!("Failed to write to the span log: {error}"),
+            );
+        }
+    })));
+}
+
+fn router(
+    config: &Config,
+    shared: &Shared,
+    log: &Arc<Log>,
+    auth: BasicAuth,
+    keys: Option<Keys>,
+) -> Router {
+    let files = StaticFiles::new(config.root.clone()).not_found_page("404.html");
+    let compression = Compression::default();
+
+    let mut router = Router::new();
+    if config.health {
+        let readiness = Arc::clone(&shared.readiness);
+        router = router
+            .bare_route(Method::Get, "/healthz", health::alive)
+            .bare_route(Method::Get, "/readyz", move |_| readiness.respond());
+    }
+    if let Some(path) = &config.metrics {
+        let metrics = Arc::clone(&shared.metrics);
+        router = router.get(path, move |_| {
+            Response::new(Status::Ok)
+                .header("Content-Type", "text/plain; version=0.0.4; charset=utf-8")
+                .body(metrics.render())
+        });
+    }
+    let router = router
+        .get("/sleep", {
+            let files = files.clone();
+            move |_| {
+                thread::sleep(Duration::from_secs(5));
+                files.serve("")
+            }
+        })
+        .get("/*path", {
+            let files = files.clone();
+            move |request| files.serve(request.param("path").unwrap_or_default())
+        })
+        .not_found(move |_| files.not_found())
+        .wrap(move |request, next| compression.apply(request, next(request)));
+    // Inside the rate limit, so passwords cannot be guessed faster than it allows,
+    // each guess costing a hash.
+    let router = if auth.is_empty() {
+        router
+    } else {
+        router.wrap(basic_auth::require(auth))
+    };
+    let router = match keys {
+        Some(keys) => {
+            let prefixes: Vec<&str> = config.api_key_prefixes.iter().map(String::as_str).collect();
+            router.wrap(api_key::require(keys, &prefixes))
+        }
+        None => router,
+    };
+    // Outside authentication, since browsers send preflights without credentials.
+    let router = match &config.cors {
+        Some(cors) => router.wrap(cors::handle(cors.clone())),
+        None => router,
+    };
+    // Outside compression, authentication and CORS, so requests over the limit are
+    // turned away before any work is done on them, and inside the metrics, so they are
+    // counted.
+    let router = match config.rate_limit {
+        Some(limit) => router.wrap(rate_limit::limit_requests(RateLimiter::new(limit))),
+        None => router,
+    };
+    // Outside the rate limit, so denied clients do not use up tokens.
+    let router = if config.access.is_empty() {
+        router
+    } else {
+        let log = Arc::clone(log);
+        router.wrap(acl::enforce(
+            config.access.clone(),
+            move |request, decision| {
+                let (level, verb) = if decision.allowed {
+                    (LogLevel::Debug, "Allowed")
+                } else {
+                    (LogLevel::Info, "Denied")
+                };
+                let scope = decision
+                    .prefix
+                    .map_or_else(String::new, |prefix| format!(" for {prefix}"));
+                log.write(
+                    level,
+                    format_args!(
+                        "{verb} {} {} {} by `{}`{scope}",
+                        request.peer().unwrap(),
+                        request.method().as_str(),
+                        request.path(),
+                        decision.rule.unwrap()
+                    ),
+                );
+            },
+        ))
+    };
+    // Outside the checks above, so their refusals have the headers too.
+    let router = match &config.security_headers {
+        Some(headers) => router.wrap(security_headers::add(headers.clone())),
+        None => router,
+    };
+    match config.metrics {
+        Some(_) => router.wrap(metrics::record_requests(&shared.metrics)),
+        None => router,
+    }
+}
+
+/// Replace the site with one built from the settings as they are now, or keep it if
+/// they are not valid.
+#[cfg(unix)]
+fn reload(site: &Arc<Swap<Site>>, args: &Args, shared: &Shared) {
+    let old = site.get();
+    let new = match Site::load(args, shared) {
+        Ok(new) => new,
+        Err(message) => {
+            old.log.write(
+                LogLevel::Error,
+                format_args!("Keeping the old settings: {message}"),
+            );
+            return;
+        }
+    };
+
+    let fixed = |config: &Config| {
+        (
+            config.listeners(),
+            config.acceptors,
+            (config.backlog, config.send_buffer, config.receive_buffer),
+            config.threads,
+            config.max_threads,
+        )
+    };
+    if fixed(&new.config) != fixed(&old.config) {
+        new.log.write(
+            LogLevel::Warn,
+            format_args!("The listeners, socket sizes and worker counts only change on restart"),
+        );
+    }
+    new.log
+        .write(LogLevel::Info, format_args!("Reloaded the settings"));
+    site.set(new);
+    export_spans(site);
+}
 
 fn main() {
-    let listener = TcpListener::bind("127.0.0.1:7878").unwrap();
-    let pool = Threadpool::build(4).unwrap();
+    let args = match Args::parse(env::args().skip(1)) {
+        Ok(Command::Serve(args)) => args,
+        Ok(Command::Help) => {
+            print!("{}", args::USAGE);
+            return;
+        }
+        Err(error) => {
+            eprintln!("{error}\n\n{}", args::USAGE);
+            process::exit(2);
+        }
+    };
+    let shared = Arc::new(Shared::default());
+    let site = Arc::new(Swap::new(Site::load(&args, &shared).unwrap_or_else(
+        |message| {
+            eprintln!("{message}");
+            process::exit(1);
+        },
+    )));
+    let config = site.get().config.clone();
+    export_spans(&site);
 
-    for stream in listener.incoming() {
-        let stream = stream.unwrap();
+    let listeners = listen(&config).unwrap_or_else(|message| {
+        eprintln!("{message}");
+        process::exit(1);
+    });
+    let mut addresses: Vec<_> = listeners
+        .iter()
+        .map(|(listener, _)| listener.local_addr().unwrap())
+        .collect();
+    // Acceptors sharing an address are listed once.
+    addresses.dedup();
+    for address in &addresses {
+        site.get()
+            .log
+            .write(LogLevel::Info, format_args!("Listening on {address}"));
+    }
+    // Each connection holds a worker while it is kept alive, so the pool grows to
+    // handle more connections than it has core workers. Workers are named so spans
+    // can say which one they ran on.
+    let pool = Arc::new(
+        pool_builder(&config)
+            .thread_name("connection")
+            .build()
+            .unwrap(),
+    );
+    // HTTP/2 streams get their own pool, so connections waiting on their streams
+    // cannot take every worker the streams need.
+    let streams = Arc::new(pool_builder(&config).thread_name("stream").build().unwrap());
 
-        pool.execute(|| {
-            handle_connection(stream);
+    // SIGHUP reloads the settings, and SIGINT or SIGTERM stop the server once its
+    // connections are done. A second SIGINT or SIGTERM stops it at once. SIGUSR2
+    // starts a new server on the same sockets, and this one stops once it is ready.
+    // SIGUSR1 reopens the log files, once logrotate has moved them.
+    let stopping = Arc::new(AtomicBool::new(false));
+    let stopped = Arc::new(AtomicBool::new(false));
+    #[cfg(unix)]
+    {
+        let mut signals = Signals::listen(&[
+            Signal::Hangup,
+            Signal::Interrupt,
+            Signal::Terminate,
+            Signal::User1,
+            Signal::User2,
+        ])
+        .unwrap();
+        let site = Arc::clone(&site);
+        let shared = Arc::clone(&shared);
+        let stopping = Arc::clone(&stopping);
+        let stopped = Arc::clone(&stopped);
+        let handles: Vec<_> = listeners
+            .iter()
+            .map(|(listener, _)| listener.try_clone().unwrap())
+            .collect();
+        let addresses = addresses.clone();
+        thread::spawn(move || loop {
+            let stop = |message| stop(&site, &addresses, &stopped, message);
+            match signals.wait() {
+                Ok(Signal::Hangup) => {
+                    let _ = systemd::notify("RELOADING=1");
+                    reload(&site, &args, &shared);
+                    let _ = systemd::notify("READY=1");
+                }
+                Ok(Signal::User1) => {
+                    let current = site.get();
+                    match current.reopen() {
+                        Ok(()) => current
+                            .log
+                            .write(LogLevel::Info, format_args!("Reopened the log files")),
+                        Err(error) => current.log.write(
+                            LogLevel::Error,
+                            format_args!("Failed to reopen the log files: {error}"),
+                        ),
+                    }
+                }
+                Ok(Signal::User2) if stopping.load(Ordering::SeqCst) => {}
+                Ok(Signal::User2) => match handover::hand_over(&handles, HANDOVER_TIMEOUT) {
+                    Ok(child) => {
+                        stopping.store(true, Ordering::SeqCst);
+                        // systemd should follow the new process, not see this one exit.
+                        let _ = systemd::notify(&format!("MAINPID={}", child.id()));
+                        stop(format_args!(
+                            "Handed the sockets over to process {}",
+                            child.id()
+                        ));
+                    }
+                    Err(error) => site.get().log.write(
+                        LogLevel::Error,
+                        format_args!("Failed to hand the sockets over: {error}"),
+                    ),
+                },
+                Ok(_) if stopping.swap(true, Ordering::SeqCst) => process::exit(1),
+                Ok(_) => stop(format_args!("Shutting down")),
+                Err(_) => return,
+            }
         });
     }
+
+    // Counted across the listeners, and kept as the settings are reloaded.
+    let connections = Arc::new(Connections::new());
+    sample(
+        &shared.metrics,
+        &connections,
+        [("connection", &pool), ("stream", &streams)],
+    );
+    check_readiness(&shared.readiness, &site, &connections, &pool, &stopping);
+    if let Some(address) = config.admin {
+        let server = Server {
+            site: Arc::clone(&site),
+            connections: Arc::clone(&connections),
+            pools: [
+                ("connection", Arc::clone(&pool)),
+                ("stream", Arc::clone(&streams)),
+            ],
+            stopping: Arc::clone(&stopping),
+            stopped: Arc::clone(&stopped),
+            addresses: addresses.clone(),
+        };
+        serve_admin(address, config.admin_token.clone(), server).unwrap_or_else(|message| {
+            eprintln!("{message}");
+            process::exit(1);
+        });
+    }
+
+    #[cfg(unix)]
+    let _ = systemd::notify("READY=1");
+    thread::scope(|scope| {
+        for (listener, http2) in listeners {
+            let (site, pool, streams, connections, stopping) =
+                (&site, &pool, &streams, &connections, &stopping);
+            scope.spawn(move || {
+                let streams = http2.then_some(streams);
+                accept(listener, site, pool, streams, connections, stopping);
+            });
+        }
+    });
+    stopped.store(true, Ordering::SeqCst);
+
+    // New connections are refused from here on, while open ones are served until
+    // they close or the drain timeout runs out.
+    let site = site.get();
+    if !pool.shutdown_timeout(site.config.drain_timeout) {
+        site.log.write(
+            LogLevel::Warn,
+            format_args!("Stopping with connections still open"),
+        );
+    }
+}
+
+/// Log `message` and wake the accept loops, so they see the server is stopping and it
+/// exits once its connections are done.
+fn stop(site: &Swap<Site>, addresses: &[Address], stopped: &Arc<AtomicBool>, message: Arguments) {
+    site.get().log.write(LogLevel::Info, message);
+    #[cfg(unix)]
+    {
+        let _ = systemd::notify("STOPPING=1");
+        let (addresses, stopped) = (addresses.to_vec(), Arc::clone(stopped));
+        thread::spawn(move || wake(&addresses, &stopped));
+    }
+}
+
+/// What the admin API looks at and changes.
+struct Server {
+    site: Arc<Swap<Site>>,
+    connections: Arc<Connections>,
+    pools: [(&'static str, Arc<Threadpool>); 2],
+    stopping: Arc<AtomicBool>,
+    stopped: Arc<AtomicBool>,
+    addresses: Vec<Address>,
+}
+
+impl Controls for Server {
+    fn log_level(&self) -> LogLevel {
+        self.site.get().log.level()
+    }
+
+    fn set_log_level(&self, level: LogLevel) {
+        let site = self.site.get();
+        site.log.set_level(level);
+        site.log.write(
+            LogLevel::Info,
+            format_args!(
+                "Logging at {} until the settings are reloaded",
+                level.as_str()
+            ),
+        );
+    }
+
+    fn set_threads(&self, threads: usize) -> Result<(), String> {
+        for (name, pool) in &self.pools {
+            pool.set_size(threads)
+                .map_err(|error| format!("Failed to resize the {name} pool: {error}"))?;
+        }
+        self.site.get().log.write(
+            LogLevel::Info,
+            format_args!("Resized the pools to {threads} workers"),
+        );
+        Ok(())
+    }
+
+    fn connections(&self) -> usize {
+        self.connections.open()
+    }
+
+    fn pools(&self) -> Vec<(String, PoolSnapshot)> {
+        self.pools
+            .iter()
+            .map(|(name, pool)| (String::from(*name), pool.snapshot()))
+            .collect()
+    }
+
+    fn drain(&self) -> bool {
+        if self.stopping.swap(true, Ordering::SeqCst) {
+            return false;
+        }
+        stop(
+            &self.site,
+            &self.addresses,
+            &self.stopped,
+            format_args!("Draining, as the admin API asked"),
+        );
+        true
+    }
+}
+
+/// Serve the admin API for `server` on `address`, with a thread for each connection,
+/// apart from the pools so it answers even when they are busy. An address other than
+/// a loopback one needs a `token`, since anyone who can reach it could stop the
+/// server. Fails with a message saying what is wrong.
+fn serve_admin(address: SocketAddr, token: Option<String>, server: Server) -> Result<(), String> {
+    if token.is_none() && !address.ip().is_loopback() {
+        return Err(format!(
+            "The admin API needs a token to listen on {address}, which is not a loopback address"
+        ));
+    }
+    let listener = TcpListener::bind(address)
+        .map_err(|error| format!("Failed to listen on {address} for the admin API: {error}"))?;
+    let site = Arc::clone(&server.site);
+    site.get().log.write(
+        LogLevel::Info,
+        format_args!("Admin API listening on {}", listener.local_addr().unwrap()),
+    );
+    let router = Arc::new(admin::router(Arc::new(server), token));
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            let (router, site) = (Arc::clone(&router), Arc::clone(&site));
+            thread::spawn(move || {
+                let served = connection::serve(
+                    stream,
+                    &router,
+                    KeepAlive::default(),
+                    Timeouts::default(),
+                    Limits::default(),
+                    None,
+                );
+                if let Err(error) = served {
+                    site.get().log.write(
+                        LogLevel::Warn,
+                        format_args!("Admin connection failed: {error}"),
+                    );
+                }
+            });
+        }
+    });
+    Ok(())
+}
+
+/// Add gauges to `metrics` for the open `connections`, and for the jobs and workers
+/// of each of `pools`, labelled with its name.
+fn sample(
+    metrics: &Registry,
+    connections: &Arc<Connections>,
+    pools: [(&str, &Arc<Threadpool>); 2],
+) {
+    let connections = Arc::clone(connections);
+    metrics.gauge(
+        "connections_open",
+        "Connections being served.",
+        &[],
+        move || connections.open() as f64,
+    );
+    for (name, pool) in pools {
+        let labels = [("pool", name)];
+        let snapshot = {
+            let pool = Arc::clone(pool);
+            move || pool.snapshot()
+        };
+        let queued = snapshot.clone();
+        metrics.gauge(
+            "threadpool_queued_jobs",
+            "Jobs waiting for a worker.",
+            &labels,
+            move || queued().queued as f64,
+        );
+        let busy = snapshot.clone();
+        metrics.gauge(
+            "threadpool_busy_workers",
+            "Workers running a job.",
+            &labels,
+            move || {
+                busy()
+                    .workers
+                    .iter()
+                    .filter(|worker| worker.is_busy())
+                    .count() as f64
+            },
+        );
+        metrics.gauge(
+            "threadpool_workers",
+            "Workers running.",
+            &labels,
+            move || snapshot().workers.len() as f64,
+        );
+    }
+}
+
+/// Add the checks of whether the server is ready for traffic: that it is not stopping,
+/// that the document root can be read, and that it has room for more connections
+/// without them waiting for a worker.
+fn check_readiness(
+    readiness: &Readiness,
+    site: &Arc<Swap<Site>>,
+    connections: &Arc<Connections>,
+    pool: &Arc<Threadpool>,
+    stopping: &Arc<AtomicBool>,
+) {
+    let stopping = Arc::clone(stopping);
+    readiness.check("stopping", move || {
+        if stopping.load(Ordering::SeqCst) {
+            return Err(String::from("the server is stopping"));
+        }
+        Ok(())
+    });
+    let current = Arc::clone(site);
+    readiness.check("root", move || health::readable(&current.get().config.root));
+    let (current, connections) = (Arc::clone(site), Arc::clone(connections));
+    readiness.check("connections", move || {
+        let (open, limit) = (connections.open(), current.get().config.max_connections);
+        if open >= limit {
+            return Err(format!("{open} connections are open, the most allowed"));
+        }
+        Ok(())
+    });
+    let pool = Arc::clone(pool);
+    readiness.check("pool", move || match pool.snapshot().queued {
+        0 => Ok(()),
+        queued => Err(format!("{queued} connections are waiting for a worker")),
+    });
 }
 
-fn handle_connection(mut stream: TcpStream) {
-    let buf_reader = BufReader::new(&mut stream);
-    let request_line = buf_reader.lines().next().unwrap().unwrap();
+/// Accept connections on `listener` and serve each with a job on `pool`, until the
+/// server is stopping. HTTP/2 is only spoken if there is a pool for its streams.
+/// Connections over the limit are turned away at once, from this thread.
+fn accept(
+    listener: Listener,
+    site: &Swap<Site>,
+    pool: &Threadpool,
+    streams: Option<&Arc<Threadpool>>,
+    connections: &Arc<Connections>,
+    stopping: &AtomicBool,
+) {
+    loop {
+        match listener.accept() {
+            Ok(stream) => {
+                let current = site.get();
+                let Some(admitted) = connections.admit(current.config.max_connections) else {
+                    current.log.write(
+                        LogLevel::Debug,
+                        format_args!("Turning a connection away with {} open", connections.open()),
+                    );
+                    let _ = connection::refuse(stream, current.config.retry_after);
+                    continue;
+                };
+                if let Err(error) = current.config.stream_options.apply(&stream) {
+                    current.log.write(
+                        LogLevel::Warn,
+                        format_args!("Failed to set socket options: {error}"),
+                    );
+                }
+                let streams = streams.cloned();
+                let address = stream.peer_addr();
+                let peer = match address {
+                    Ok(Some(address)) => address.to_string(),
+                    Ok(None) => String::from("a local client"),
+                    Err(_) => String::from("an unknown client"),
+                };
+                let ip = address.ok().flatten().map(|address| address.ip());
+
+                if let Err(error) = pool.execute(move || {
+                    let _admitted = admitted;
+                    let span = trace::span("connection");
+                    span.record("peer", &peer);
+                    let config = &current.config;
+                    let (keep_alive, timeouts, limits) =
+                        (config.keep_alive, config.timeouts, config.limits);
+                    let router = &current.router;
+                    let access = |exchange: Exchange| {
+                        let Some(access_log) = &current.access_log else {
+                            return;
+                        };
+                        if let Err(error) = access_log.write(ip, &exchange) {
+                            current.log.write(
+                                LogLevel::Warn,
+                                format_args!("Failed to write to the access log: {error}"),
+                            );
+                        }
+                    };
+                    let log = current.access_log.as_ref().map(|_| &access as Sink);
+                    let served = match &streams {
+                        Some(streams) => connection::serve_with_http2(
+                            stream, router, keep_alive, timeouts, limits, log, streams,
+                        ),
+                        None => {
+                            connection::serve(stream, router, keep_alive, timeouts, limits, log)
+                        }
+                    };
+                    if let Err(error) = served {
+                        current.log.write(
+                            LogLevel::Warn,
+                            format_args!("Connection from {peer} failed: {error}"),
+                        );
+                    }
+                }) {
+                    site.get().log.write(
+                        LogLevel::Error,
+                        format_args!("Dropping connection: {error}"),
+                    );
+                    break;
+                }
+            }
+            Err(error) => site
+                .get()
+                .log
+                .write(LogLevel::Warn, format_args!("Failed to accept: {error}")),
+        }
+        // The connection that woke the loop is served like any other, since after a
+        // handover it may as well be a client's.
+        if stopping.load(Ordering::SeqCst) {
+            break;
+        }
+    }
+}
+
+/// Take over the sockets handed over by the server that started this one, or those
+/// systemd listens on, or else listen where the settings say. Each listener comes
+/// with whether HTTP/2 is spoken on it. Fails with a message saying what is wrong.
+fn listen(config: &Config) -> Result<Vec<(Listener, bool)>, String> {
+    let configured = config.listeners();
+
+    #[cfg(unix)]
+    {
+        let error = |error| format!("Failed to take over the sockets: {error}");
+        let mut passed = handover::inherit().map_err(error)?.unwrap_or_default();
+        if passed.is_empty() {
+            passed = systemd::listeners().map_err(error)?;
+        }
+        if !passed.is_empty() {
+            // Sockets opened elsewhere get the settings of the configured listener
+            // at the same address, if there is one.
+            let http2 = |listener: &Listener| {
+                let address = listener.local_addr().ok();
+                configured
+                    .iter()
+                    .find(|listen| address.as_ref().is_some_and(|address| at(address, listen)))
+                    .is_none_or(|listen| listen.http2)
+            };
+            return Ok(passed
+                .into_iter()
+                .map(|listener| {
+                    let http2 = http2(&listener);
+                    (listener, http2)
+                })
+                .collect());
+        }
+    }
+
+    let mut listeners = Vec::new();
+    for listen in &configured {
+        let bound = match &listen.endpoint {
+            Endpoint::Tcp(address) => bind_tcp(*address, listen.only_v6, config),
+            #[cfg(unix)]
+            Endpoint::Unix(socket) => {
+                Listener::bind_unix(&socket.path, socket.mode).map(|listener| vec![listener])
+            }
+            #[cfg(not(unix))]
+            Endpoint::Unix(_) => Err(io::ErrorKind::Unsupported.into()),
+        };
+        let bound =
+            bound.map_err(|error| format!("Failed to listen on {}: {error}", listen.endpoint))?;
+        listeners.extend(bound.into_iter().map(|listener| (listener, listen.http2)));
+    }
+    Ok(listeners)
+}
 
-    let (status_line, filename) = match &request_line[..] {
-        "GET / HTTP/1.1" => ("HTTP/1.1 200 OK", "hello.html"),
-        "GET /sleep HTTP/1.1" => {
-            thread::sleep(Duration::from_secs(5));
-            ("HTTP/1.1 200 OK", "hello.html")
+/// Listen on `address` with the configured number of acceptors, which share it
+/// with `SO_REUSEPORT` if there is more than one.
+fn bind_tcp(
+    address: SocketAddr,
+    only_v6: Option<bool>,
+    config: &Config,
+) -> io::Result<Vec<Listener>> {
+    let builder = |address| {
+        let mut builder = TcpListenerBuilder::new(address)
+            .reuse_port(config.acceptors > 1)
+            .backlog(config.backlog);
+        if let Some(only) = only_v6 {
+            builder = builder.only_v6(only);
+        }
+        if let Some(bytes) = config.send_buffer {
+            builder = builder.send_buffer(bytes);
+        }
+        if let Some(bytes) = config.receive_buffer {
+            builder = builder.receive_buffer(bytes);
         }
-        _ => ("HTTP/1.1 404 NOT FOUND", "404.html"),
+        builder
     };
+    let first = builder(address).bind()?;
+    // The rest have to be on the port the first was given, if it was chosen by the
+    // system.
+    let address = first.local_addr()?;
+    let mut listeners = vec![Listener::from(first)];
+    for _ in 1..config.acceptors {
+        listeners.push(builder(address).bind()?.into());
+    }
+    Ok(listeners)
+}
 
-    let contents = fs::read_to_string(filename).unwrap();
-    let length = contents.len();
+/// Whether `address` is where `listen` says to listen.
+#[cfg(unix)]
+fn at(address: &Address, listen: &Listen) -> bool {
+    match (address, &listen.endpoint) {
+        (Address::Tcp(address), Endpoint::Tcp(endpoint)) => address == endpoint,
+        (Address::Unix(path), Endpoint::Unix(socket)) => *path == socket.path,
+        _ => false,
+    }
+}
 
-    let response = format!("{status_line}\r\nContent-Length: {length}\r\n\r\n{contents}");
+/// Wake the accept loops listening on `addresses` by connecting to them, until they
+/// have stopped. After a handover, the new server may take some of the connections.
+#[cfg(unix)]
+fn wake(addresses: &[Address], stopped: &AtomicBool) {
+    use std::{
+        net::{IpAddr, Ipv4Addr, Ipv6Addr, TcpStream},
+        os::unix::net::UnixStream,
+    };
 
-    stream.write_all(response.as_bytes()).unwrap();
-    stream.flush().unwrap();
+    while !stopped.load(Ordering::SeqCst) {
+        for address in addresses {
+            match address {
+                Address::Tcp(address) => {
+                    let ip = match address.ip() {
+                        IpAddr::V4(ip) if ip.is_unspecified() => IpAddr::V4(Ipv4Addr::LOCALHOST),
+                        IpAddr::V6(ip) if ip.is_unspecified() => IpAddr::V6(Ipv6Addr::LOCALHOST),
+                        ip => ip,
+                    };
+                    let _ = TcpStream::connect((ip, address.port()));
+                }
+                Address::Unix(path) => {
+                    let _ = UnixStream::connect(path);
+                }
+            }
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
 }
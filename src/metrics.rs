@@ -0,0 +1,418 @@
+//! Metrics kept while the server runs, and rendered in the text format Prometheus
+//! scrapes.
+//!
+//! A `Registry` holds families of counters, histograms and gauges, each told apart
+//! by its labels. Counters and histograms are looked up once and updated by whoever
+//! holds them, while gauges are read from a function whenever the registry is
+//! rendered, so values kept elsewhere, such as how many connections are open, do not
+//! have to be copied in.
+//! ```
+//! use ch20_web_server::metrics::Registry;
+//!
+//! let registry = Registry::new();
+//! registry
+//!     .counter("jobs_total", "Jobs run.", &[("kind", "backup")])
+//!     .add(2);
+//! registry.gauge("answer", "The answer.", &[], || 42.0);
+//! assert_eq!(
+//!     registry.render(),
+//!     "# HELP jobs_total Jobs run.\n\
+//!      ## TYPE jobs_total counter\n\
+//!      jobs_total{kind=\"backup\"} 2\n\
+//!      ## HELP answer The answer.\n\
+//!      ## TYPE answer gauge\n\
+//!      answer 42\n"
+//! );
+//! ```
+
+use std::{
+    collections::BTreeMap,
+    fmt::Write as _,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::Instant,
+};
+
+use crate::{request::Request, response::Response};
+
+/// The upper bounds, in seconds, of the buckets Prometheus uses by default.
+pub const DEFAULT_BUCKETS: [f64; 11] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+/// A label's name and value, sorted by name within a series.
+type Labels = Vec<(String, String)>;
+type Read = Box<dyn Fn() -> f64 + Send + Sync>;
+
+/// A count that only goes up.
+#[derive(Debug, Default)]
+pub struct Counter {
+    value: AtomicU64,
+}
+
+impl Counter {
+    pub fn inc(&self) {
+        self.add(1);
+    }
+
+    pub fn add(&self, n: u64) {
+        self.value.fetch_add(n, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> u64 {
+        self.value.load(Ordering::Relaxed)
+    }
+}
+
+/// Counts of values, such as how long requests took, by the buckets they fall in.
+#[derive(Debug)]
+pub struct Histogram {
+    bounds: Vec<f64>,
+    /// The values in each bucket, and then those above the last bound.
+    counts: Vec<AtomicU64>,
+    /// The sum of the values, as the bits of an `f64`.
+    sum: AtomicU64,
+}
+
+impl Histogram {
+    fn new(bounds: &[f64]) -> Histogram {
+        Histogram {
+            bounds: bounds.to_vec(),
+            counts: (0..=bounds.len()).map(|_| AtomicU64::new(0)).collect(),
+            sum: AtomicU64::new(0f64.to_bits()),
+        }
+    }
+
+    pub fn observe(&self, value: f64) {
+        let bucket = self.bounds.partition_point(|&bound| bound < value);
+        self.counts[bucket].fetch_add(1, Ordering::Relaxed);
+        let _ = self
+            .sum
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |sum| {
+                Some((f64::from_bits(sum) + value).to_bits())
+            });
+    }
+
+    /// How many values have been observed.
+    pub fn count(&self) -> u64 {
+        self.counts
+            .iter()
+            .map(|count| count.load(Ordering::Relaxed))
+            .sum()
+    }
+}
+
+enum Series {
+    Counter(BTreeMap<Labels, Arc<Counter>>),
+    Histogram(Vec<f64>, BTreeMap<Labels, Arc<Histogram>>),
+    Gauge(Vec<(Labels, Read)>),
+}
+
+struct Family {
+    name: String,
+    help: String,
+    series: Series,
+}
+
+/// Families of metrics, rendered in the order they were first registered.
+#[derive(Default)]
+pub struct Registry {
+    families: Mutex<Vec<Family>>,
+}
+
+impl Registry {
+    pub fn new() -> Registry {
+        Registry::default()
+    }
+
+    /// Run `f` on the family called `name`, adding it with `series` if there is none.
+    ///
+    /// # Panics
+    ///
+    /// If the family has been registered as another kind of metric.
+    fn family<R>(
+        &self,
+        name: &str,
+        help: &str,
+        series: impl FnOnce() -> Series,
+        f: impl FnOnce(&mut Series) -> Option<R>,
+    ) -> R {
+        let mut families = self.families.lock().unwrap();
+        let index = match families.iter().position(|family| family.name == name) {
+            Some(index) => index,
+            None => {
+                families.push(Family {
+                    name: name.to_owned(),
+                    help: help.to_owned(),
+                    series: series(),
+                });
+                families.len() - 1
+            }
+        };
+        match f(&mut families[index].series) {
+            Some(result) => result,
+            None => panic!("metric {name:?} was registered as another kind"),
+        }
+    }
+
+    /// The counter called `name` with `labels`, which is added at zero the first time
+    /// it is asked for.
+    pub fn counter(&self, name: &str, help: &str, labels: &[(&str, &str)]) -> Arc<Counter> {
+        let labels = sorted(labels);
+        self.family(
+            name,
+            help,
+            || Series::Counter(BTreeMap::new()),
+            |series| match series {
+                Series::Counter(counters) => Some(Arc::clone(counters.entry(labels).or_default())),
+                _ => None,
+            },
+        )
+    }
+
+    /// The histogram called `name` with `labels`, which is added empty the first time
+    /// it is asked for. Every histogram in a family has the `bounds` it was added with
+    /// first, which have to be in ascending order.
+    pub fn histogram(
+        &self,
+        name: &str,
+        help: &str,
+        bounds: &[f64],
+        labels: &[(&str, &str)],
+    ) -> Arc<Histogram> {
+        let labels = sorted(labels);
+        self.family(
+            name,
+            help,
+            || Series::Histogram(bounds.to_vec(), BTreeMap::new()),
+            |series| match series {
+                Series::Histogram(bounds, histograms) => Some(Arc::clone(
+                    histograms
+                        .entry(labels)
+                        .or_insert_with(|| Arc::new(Histogram::new(bounds))),
+                )),
+                _ => None,
+            },
+        )
+    }
+
+    /// Add a gauge called `name` with `labels`, whose value is read with `read` each
+    /// time the registry is rendered.
+    pub fn gauge<F>(&self, name: &str, help: &str, labels: &[(&str, &str)], read: F)
+    where
+        F: Fn() -> f64 + Send + Sync + 'static,
+    {
+        let labels = sorted(labels);
+        self.family(
+            name,
+            help,
+            || Series::Gauge(Vec::new()),
+            |series| match series {
+                Series::Gauge(gauges) => {
+                    gauges.push((labels, Box::new(read)));
+                    Some(())
+                }
+                _ => None,
+            },
+        )
+    }
+
+    /// Every metric, in the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        for family in self.families.lock().unwrap().iter() {
+            let kind = match family.series {
+                Series::Counter(_) => "counter",
+                Series::Histogram(..) => "histogram",
+                Series::Gauge(_) => "gauge",
+            };
+            let _ = writeln!(out, "# HELP {} {}", family.name, escape_help(&family.help));
+            let _ = writeln!(out, "# TYPE {} {kind}", family.name);
+
+            let name = &family.name;
+            match &family.series {
+                Series::Counter(counters) => {
+                    for (labels, counter) in counters {
+                        sample(&mut out, name, labels, None, counter.get() as f64);
+                    }
+                }
+                Series::Gauge(gauges) => {
+                    for (labels, read) in gauges {
+                        sample(&mut out, name, labels, None, read());
+                    }
+                }
+                Series::Histogram(bounds, histograms) => {
+                    for (labels, histogram) in histograms {
+                        let mut cumulative = 0;
+                        let bucket = format!("{name}_bucket");
+                        for (i, count) in histogram.counts.iter().enumerate() {
+                            cumulative += count.load(Ordering::Relaxed);
+                            let le = bounds.get(i).map_or(String::from("+Inf"), f64::to_string);
+                            sample(&mut out, &bucket, labels, Some(&le), cumulative as f64);
+                        }
+                        let sum = f64::from_bits(histogram.sum.load(Ordering::Relaxed));
+                        sample(&mut out, &format!("{name}_sum"), labels, None, sum);
+                        let count = format!("{name}_count");
+                        sample(&mut out, &count, labels, None, cumulative as f64);
+                    }
+                }
+            }
+        }
+        out
+    }
+}
+
+fn sorted(labels: &[(&str, &str)]) -> Labels {
+    let mut labels: Labels = labels
+        .iter()
+        .map(|&(name, value)| (name.to_owned(), value.to_owned()))
+        .collect();
+    labels.sort();
+    labels
+}
+
+/// Write one sample line, with an `le` label after the others for histogram buckets.
+fn sample(out: &mut String, name: &str, labels: &Labels, le: Option<&str>, value: f64) {
+    out.push_str(name);
+    let le = le.map(|le| (String::from("le"), String::from(le)));
+    let mut labels = labels.iter().chain(le.as_ref()).peekable();
+    if labels.peek().is_some() {
+        out.push('{');
+        for (i, (name, value)) in labels.enumerate() {
+            if i > 0 {
+                out.push(',');
+            }
+            let _ = write!(out, "{name}=\"{}\"", escape_label(value));
+        }
+        out.push('}');
+    }
+    if value.is_nan() {
+        out.push_str(" NaN\n");
+    } else if value.is_infinite() {
+        out.push_str(if value > 0.0 { " +Inf\n" } else { " -Inf\n" });
+    } else {
+        let _ = writeln!(out, " {value}");
+    }
+}
+
+fn escape_help(help: &str) -> String {
+    help.replace('\\', "\\\\").replace('\n', "\\n")
+}
+
+fn escape_label(value: &str) -> String {
+    escape_help(value).replace('"', "\\\"")
+}
+
+/// Middleware for `Router::wrap` that counts the requests the router answers in
+/// `http_requests_total`, by method, route and status, and times them in
+/// `http_request_duration_seconds`, by method and route. The route is the pattern
+/// that matched, or `none` if no route did, so paths cannot add series without end.
+///
+/// The time is the handler's, until it returns the response: streamed bodies are
+/// written after that.
+pub fn record_requests(
+    registry: &Arc<Registry>,
+) -> impl Fn(&Request, &dyn Fn(&Request) -> Response) -> Response + Send + Sync + 'static {
+    let registry = Arc::clone(registry);
+    move |request, next| {
+        let started = Instant::now();
+        let response = next(request);
+        let method = request.method().as_str();
+        let route = request.route().unwrap_or("none");
+        registry
+            .counter(
+                "http_requests_total",
+                "Requests answered, by method, route and status.",
+                &[
+                    ("method", method),
+                    ("route", route),
+                    ("status", &response.status().code().to_string()),
+                ],
+            )
+            .inc();
+        registry
+            .histogram(
+                "http_request_duration_seconds",
+                "Seconds handlers took to answer requests, by method and route.",
+                &DEFAULT_BUCKETS,
+                &[("method", method), ("route", route)],
+            )
+            .observe(started.elapsed().as_secs_f64());
+        response
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{response::Status, router::Router};
+
+    #[test]
+    fn renders_histograms_cumulatively() {
+        let registry = Registry::new();
+        let histogram = registry.histogram("took", "Time\ntaken.", &[0.5, 1.0], &[]);
+        for value in [0.25, 0.5, 0.75, 3.0] {
+            histogram.observe(value);
+        }
+        assert_eq!(histogram.count(), 4);
+        assert_eq!(
+            registry.render(),
+            "# HELP took Time\\ntaken.\n\
+             # TYPE took histogram\n\
+             took_bucket{le=\"0.5\"} 2\n\
+             took_bucket{le=\"1\"} 3\n\
+             took_bucket{le=\"+Inf\"} 4\n\
+             took_sum 4.5\n\
+             took_count 4\n"
+        );
+    }
+
+    #[test]
+    fn sorts_and_escapes_labels() {
+        let registry = Registry::new();
+        registry
+            .counter("c", "", &[("b", "\"x\"\\"), ("a", "1")])
+            .inc();
+        registry
+            .counter("c", "", &[("a", "1"), ("b", "\"x\"\\")])
+            .inc();
+        assert!(registry
+            .render()
+            .ends_with("c{a=\"1\",b=\"\\\"x\\\"\\\\\"} 2\n"));
+    }
+
+    #[test]
+    #[should_panic(expected = "another kind")]
+    fn keeps_each_name_to_one_kind() {
+        let registry = Registry::new();
+        registry.counter("m", "", &[]);
+        registry.gauge("m", "", &[], || 0.0);
+    }
+
+    #[test]
+    fn counts_requests_by_route() {
+        let registry = Arc::new(Registry::new());
+        let router = Router::new()
+            .get("/users/:id", |_| Response::new(Status::Ok))
+            .wrap(record_requests(&registry));
+        for target in ["/users/1", "/users/2", "/nowhere"] {
+            let head = format!("GET {target} HTTP/1.1\r\n\r\n");
+            router.dispatch(Request::read_from(&mut head.as_bytes()).unwrap());
+        }
+
+        let count =
+            |labels: &[(&str, &str)]| registry.counter("http_requests_total", "", labels).get();
+        let get = ("method", "GET");
+        assert_eq!(count(&[get, ("route", "/users/:id"), ("status", "200")]), 2);
+        assert_eq!(count(&[get, ("route", "none"), ("status", "404")]), 1);
+        let took = registry.histogram(
+            "http_request_duration_seconds",
+            "",
+            &[],
+            &[get, ("route", "none")],
+        );
+        assert_eq!(took.count(), 1);
+    }
+}
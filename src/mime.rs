@@ -0,0 +1,68 @@
+use std::path::Path;
+
+/// The type sent for files whose extension is not known.
+pub const FALLBACK: &str = "application/octet-stream";
+
+/// Return the media type for files with the extension `extension`, compared
+/// case-insensitively, or `None` if it is not one the server knows.
+pub fn from_extension(extension: &str) -> Option<&'static str> {
+    let mime = match extension.to_ascii_lowercase().as_str() {
+        "html" | "htm" => "text/html; charset=utf-8",
+        "css" => "text/css; charset=utf-8",
+        "js" | "mjs" => "text/javascript; charset=utf-8",
+        "txt" => "text/plain; charset=utf-8",
+        "csv" => "text/csv; charset=utf-8",
+        "md" => "text/markdown; charset=utf-8",
+        "xml" => "application/xml",
+        "json" => "application/json",
+        "map" => "application/json",
+        "webmanifest" => "application/manifest+json",
+        "wasm" => "application/wasm",
+        "pdf" => "application/pdf",
+        "zip" => "application/zip",
+        "gz" => "application/gzip",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "avif" => "image/avif",
+        "svg" => "image/svg+xml",
+        "ico" => "image/x-icon",
+        "woff" => "font/woff",
+        "woff2" => "font/woff2",
+        "ttf" => "font/ttf",
+        "otf" => "font/otf",
+        "mp3" => "audio/mpeg",
+        "ogg" => "audio/ogg",
+        "wav" => "audio/wav",
+        "mp4" => "video/mp4",
+        "webm" => "video/webm",
+        _ => return None,
+    };
+    Some(mime)
+}
+
+/// Return the media type for the file at `path` by its extension, or `FALLBACK`.
+pub fn from_path(path: &Path) -> &'static str {
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .and_then(from_extension)
+        .unwrap_or(FALLBACK)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detects_types_by_extension() {
+        assert_eq!(
+            from_path(Path::new("a/index.HTML")),
+            "text/html; charset=utf-8"
+        );
+        assert_eq!(from_path(Path::new("app.wasm")), "application/wasm");
+        assert_eq!(from_path(Path::new("logo.svg")), "image/svg+xml");
+        assert_eq!(from_path(Path::new("archive.tar.unknown")), FALLBACK);
+        assert_eq!(from_path(Path::new("Makefile")), FALLBACK);
+    }
+}
@@ -0,0 +1,115 @@
+//! Password hashes, as kept in credential files: bcrypt (`$2a$`, `$2b$` or `$2y$`),
+//! which `htpasswd -B` writes, and Argon2 (`$argon2id$`, `$argon2i$` or `$argon2d$`)
+//! in the PHC string format.
+//!
+//! The algorithms are written here rather than taken from a library, which the
+//! server does without. That is a much smaller thing than the TLS and ACME it leaves
+//! out for the same reason: hashes are only checked, never made, so there are no
+//! salts to generate, and checking one recomputes a fixed function of the password
+//! and the parameters stored with it. There is no key exchange, signature or
+//! long-lived key, and nothing on the network depends on the code beyond the answer
+//! it gives. A fault shows up as a right password refused or a wrong one accepted,
+//! and the known-answer tests of each module look for both. Those tests, against
+//! hashes made by the reference tools and published vectors, are what keeps the
+//! hashes compatible. Otherwise the work takes as long as the parameters say, as in
+//! the reference implementations, and the final comparison takes constant time.
+//!
+//! ```
+//! use ch20_web_server::password::PasswordHash;
+//!
+//! let hash: PasswordHash = "$2y$05$N9qo8uLOickgx2ZMRZoMyeySTQZHYTvN5Lzja/W0KswX.y7D.SAs6"
+//!     .parse()
+//!     .unwrap();
+//! assert!(hash.verify(b"correct horse"));
+//! assert!(!hash.verify(b"battery staple"));
+//! ```
+
+use std::{error::Error, fmt, str::FromStr};
+
+use crate::{argon2, bcrypt};
+
+/// A password hash in one of the formats that can be checked.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PasswordHash(Kind);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Kind {
+    Bcrypt(bcrypt::Hash),
+    Argon2(argon2::Hash),
+}
+
+impl PasswordHash {
+    /// Whether `password` is the one the hash was made from. This takes as long as
+    /// the hash asks for, on purpose, so it should not be done more than needed.
+    pub fn verify(&self, password: &[u8]) -> bool {
+        match &self.0 {
+            Kind::Bcrypt(hash) => hash.verify(password),
+            Kind::Argon2(hash) => hash.verify(password),
+        }
+    }
+}
+
+/// A hash in a format that is not supported, or not a hash at all.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnsupportedHash;
+
+impl fmt::Display for UnsupportedHash {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("not a bcrypt or Argon2 (version 1.3) password hash")
+    }
+}
+
+impl Error for UnsupportedHash {}
+
+impl FromStr for PasswordHash {
+    type Err = UnsupportedHash;
+
+    fn from_str(hash: &str) -> Result<PasswordHash, UnsupportedHash> {
+        bcrypt::Hash::parse(hash)
+            .map(Kind::Bcrypt)
+            .or_else(|| argon2::Hash::parse(hash).map(Kind::Argon2))
+            .map(PasswordHash)
+            .ok_or(UnsupportedHash)
+    }
+}
+
+/// Compare `a` and `b` in a time that depends only on their lengths, so a secret
+/// cannot be guessed a byte at a time by timing the answers.
+pub(crate) fn same(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_either_format() {
+        let bcrypt: PasswordHash = "$2b$04$abcdefghijklmnopqrstuubyCG3zY1GIXMyxfivm.ClDiInHzxjiq"
+            .parse()
+            .unwrap();
+        assert!(bcrypt.verify(b""));
+        let argon2: PasswordHash =
+            "$argon2id$v=19$m=64,t=2,p=1$c29tZXNhbHQ$FqGkmHNGCd0BRW2kBt6fPZ2pPmyGwwChL8FGUhTOSSI"
+                .parse()
+                .unwrap();
+        assert!(!argon2.verify(b"wrong"));
+
+        for hash in [
+            "",
+            "plain",
+            "{SHA}W6ph5Mm5Pz8GgiULbPgzG37mj9g=",
+            "$apr1$salt$hash",
+        ] {
+            assert_eq!(hash.parse::<PasswordHash>(), Err(UnsupportedHash), "{hash}");
+        }
+    }
+
+    #[test]
+    fn compares_whole_slices() {
+        assert!(same(b"token", b"token"));
+        assert!(!same(b"token", b"tokens"));
+        assert!(!same(b"token", b"tokeN"));
+        assert!(same(b"", b""));
+    }
+}
@@ -0,0 +1,239 @@
+//! Limiting how fast each client can send requests, so that a single one cannot keep
+//! every worker busy.
+//!
+//! Each client address has a bucket of up to `burst` tokens, which refills at `rate`
+//! tokens a second. A request takes a token, and one that finds the bucket empty is
+//! answered with `429 Too Many Requests` and a `Retry-After` of when there will be
+//! one. Only the buckets of the clients seen most recently are kept, so a flood of
+//! addresses cannot use up memory, and a client whose bucket is dropped starts again
+//! with a full one.
+//! ```
+//! use ch20_web_server::{
+//!     rate_limit::{self, RateLimit, RateLimiter},
+//!     router::Router,
+//! };
+//!
+//! let limiter = RateLimiter::new(RateLimit::new(10.0).burst(20));
+//! let router = Router::new().wrap(rate_limit::limit_requests(limiter));
+//! ```
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    net::IpAddr,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    request::Request,
+    response::{Response, Status},
+};
+
+/// How fast each client may send requests.
+/// ```
+/// use ch20_web_server::rate_limit::RateLimit;
+///
+/// // Two requests a second, with up to ten at once after a pause.
+/// let limit = RateLimit::new(2.0).burst(10).clients(50_000);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RateLimit {
+    rate: f64,
+    burst: u32,
+    clients: usize,
+}
+
+impl RateLimit {
+    /// Allow `rate` requests a second from each client, in bursts of as many as are
+    /// allowed in a second, and keep the buckets of 10,000 clients.
+    ///
+    /// # Panics
+    ///
+    /// If `rate` is not a positive number.
+    pub fn new(rate: f64) -> RateLimit {
+        assert!(rate > 0.0, "the rate must be positive");
+        RateLimit {
+            rate,
+            burst: rate.ceil() as u32,
+            clients: 10_000,
+        }
+    }
+
+    /// Allow `requests` to be sent at once by a client that has sent none for a while.
+    pub fn burst(mut self, requests: u32) -> RateLimit {
+        self.burst = requests.max(1);
+        self
+    }
+
+    /// Keep the buckets of the `clients` seen most recently.
+    pub fn clients(mut self, clients: usize) -> RateLimit {
+        self.clients = clients.max(1);
+        self
+    }
+}
+
+/// The buckets of the clients seen recently, to take tokens from.
+pub struct RateLimiter {
+    limit: RateLimit,
+    buckets: Mutex<Buckets>,
+}
+
+struct Buckets {
+    by_client: HashMap<IpAddr, Bucket>,
+    /// The clients by when their buckets were last taken from, oldest first.
+    by_use: BTreeMap<u64, IpAddr>,
+    uses: u64,
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+    /// When it was last taken from, as a key of `Buckets::by_use`.
+    used: u64,
+}
+
+impl RateLimiter {
+    pub fn new(limit: RateLimit) -> RateLimiter {
+        RateLimiter {
+            limit,
+            buckets: Mutex::new(Buckets {
+                by_client: HashMap::new(),
+                by_use: BTreeMap::new(),
+                uses: 0,
+            }),
+        }
+    }
+
+    /// Take a token for a request from `client`, or return how long it has to wait
+    /// for one.
+    pub fn take(&self, client: IpAddr) -> Result<(), Duration> {
+        self.take_at(client, Instant::now())
+    }
+
+    fn take_at(&self, client: IpAddr, now: Instant) -> Result<(), Duration> {
+        let RateLimit {
+            rate,
+            burst,
+            clients,
+        } = self.limit;
+        let burst = f64::from(burst);
+        let mut buckets = self.buckets.lock().unwrap();
+        let buckets = &mut *buckets;
+
+        if let Some(bucket) = buckets.by_client.get(&client) {
+            buckets.by_use.remove(&bucket.used);
+        } else if buckets.by_client.len() >= clients {
+            if let Some((_, oldest)) = buckets.by_use.pop_first() {
+                buckets.by_client.remove(&oldest);
+            }
+        }
+        buckets.uses += 1;
+        let used = buckets.uses;
+        buckets.by_use.insert(used, client);
+        let bucket = buckets.by_client.entry(client).or_insert(Bucket {
+            tokens: burst,
+            updated: now,
+            used,
+        });
+        bucket.used = used;
+
+        let refilled = now.saturating_duration_since(bucket.updated).as_secs_f64() * rate;
+        bucket.tokens = (bucket.tokens + refilled).min(burst);
+        bucket.updated = bucket.updated.max(now);
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - bucket.tokens) / rate))
+        }
+    }
+}
+
+/// Middleware that answers requests from clients over `limiter`'s rate with
+/// `429 Too Many Requests`. Requests without a client address, such as those over a
+/// Unix socket from a proxy on the same host, are not limited.
+pub fn limit_requests(
+    limiter: RateLimiter,
+) -> impl Fn(&Request, &dyn Fn(&Request) -> Response) -> Response + Send + Sync + 'static {
+    move |request, next| {
+        let Some(client) = request.peer() else {
+            return next(request);
+        };
+        match limiter.take(client) {
+            Ok(()) => next(request),
+            Err(wait) => {
+                let seconds = wait.as_secs_f64().ceil().max(1.0) as u64;
+                Response::error(Status::TooManyRequests).header("Retry-After", seconds.to_string())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::router::Router;
+
+    #[test]
+    fn refills_at_the_rate() {
+        let limiter = RateLimiter::new(RateLimit::new(2.0).burst(3));
+        let (client, start) = ("192.0.2.1".parse().unwrap(), Instant::now());
+
+        for _ in 0..3 {
+            assert_eq!(limiter.take_at(client, start), Ok(()));
+        }
+        assert_eq!(
+            limiter.take_at(client, start),
+            Err(Duration::from_millis(500))
+        );
+        // Other clients have buckets of their own.
+        assert_eq!(limiter.take_at("192.0.2.2".parse().unwrap(), start), Ok(()));
+
+        let later = start + Duration::from_millis(500);
+        assert_eq!(limiter.take_at(client, later), Ok(()));
+        assert!(limiter.take_at(client, later).is_err());
+        // The bucket holds no more than the burst, however long the client waits.
+        let much_later = start + Duration::from_secs(60);
+        for _ in 0..3 {
+            assert_eq!(limiter.take_at(client, much_later), Ok(()));
+        }
+        assert!(limiter.take_at(client, much_later).is_err());
+    }
+
+    #[test]
+    fn forgets_the_clients_seen_least_recently() {
+        let limiter = RateLimiter::new(RateLimit::new(1.0).clients(2));
+        let [a, b, c] = ["192.0.2.1", "192.0.2.2", "2001:db8::1"].map(|ip| ip.parse().unwrap());
+        let now = Instant::now();
+
+        assert_eq!(limiter.take_at(a, now), Ok(()));
+        assert_eq!(limiter.take_at(b, now), Ok(()));
+        assert!(limiter.take_at(a, now).is_err());
+        // `b` was used longest ago, so its bucket makes room for `c`'s.
+        assert_eq!(limiter.take_at(c, now), Ok(()));
+        assert_eq!(limiter.buckets.lock().unwrap().by_client.len(), 2);
+        assert!(limiter.take_at(a, now).is_err());
+        assert_eq!(limiter.take_at(b, now), Ok(()));
+    }
+
+    #[test]
+    fn answers_clients_over_the_rate_with_429() {
+        let limiter = RateLimiter::new(RateLimit::new(0.1).burst(1));
+        let router = Router::new()
+            .get("/", |_| Response::new(Status::Ok))
+            .wrap(limit_requests(limiter));
+        let request = || {
+            let mut request = Request::read_from(&mut &b"GET / HTTP/1.1\r\n\r\n"[..]).unwrap();
+            request.set_peer(Some("192.0.2.1".parse().unwrap()));
+            request
+        };
+
+        assert_eq!(router.dispatch(request()).status(), Status::Ok);
+        let limited = router.dispatch(request());
+        assert_eq!(limited.status(), Status::TooManyRequests);
+        assert_eq!(limited.headers().get("Retry-After"), Some("10"));
+        // Without an address, there is no bucket to take from.
+        let local = Request::read_from(&mut &b"GET / HTTP/1.1\r\n\r\n"[..]).unwrap();
+        assert_eq!(router.dispatch(local).status(), Status::Ok);
+    }
+}
@@ -0,0 +1,881 @@
+use std::{
+    fmt,
+    io::{self, BufRead, Read},
+    net::IpAddr,
+    str::FromStr,
+};
+
+use crate::{headers::Headers, response::Status, router::Params};
+
+/// The request methods the server understands.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Method {
+    Get,
+    Head,
+    Post,
+    Put,
+    Delete,
+    Options,
+    Patch,
+    Trace,
+    Connect,
+}
+
+impl Method {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Method::Get => "GET",
+            Method::Head => "HEAD",
+            Method::Post => "POST",
+            Method::Put => "PUT",
+            Method::Delete => "DELETE",
+            Method::Options => "OPTIONS",
+            Method::Patch => "PATCH",
+            Method::Trace => "TRACE",
+            Method::Connect => "CONNECT",
+        }
+    }
+}
+
+impl FromStr for Method {
+    type Err = ParseError;
+
+    /// Methods are case-sensitive, so `get` is not `GET`.
+    fn from_str(method: &str) -> Result<Method, ParseError> {
+        match method {
+            "GET" => Ok(Method::Get),
+            "HEAD" => Ok(Method::Head),
+            "POST" => Ok(Method::Post),
+            "PUT" => Ok(Method::Put),
+            "DELETE" => Ok(Method::Delete),
+            "OPTIONS" => Ok(Method::Options),
+            "PATCH" => Ok(Method::Patch),
+            "TRACE" => Ok(Method::Trace),
+            "CONNECT" => Ok(Method::Connect),
+            _ if is_token(method) => Err(ParseError::UnknownMethod),
+            _ => Err(ParseError::BadRequestLine),
+        }
+    }
+}
+
+impl fmt::Display for Method {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// The HTTP versions the server speaks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Version {
+    Http10,
+    Http11,
+    Http2,
+}
+
+impl Version {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Version::Http10 => "HTTP/1.0",
+            Version::Http11 => "HTTP/1.1",
+            Version::Http2 => "HTTP/2",
+        }
+    }
+}
+
+impl FromStr for Version {
+    type Err = ParseError;
+
+    fn from_str(version: &str) -> Result<Version, ParseError> {
+        match version {
+            "HTTP/1.0" => Ok(Version::Http10),
+            "HTTP/1.1" => Ok(Version::Http11),
+            _ => {
+                let number = version
+                    .strip_prefix("HTTP/")
+                    .ok_or(ParseError::BadRequestLine)?;
+                match number.as_bytes() {
+                    [major, b'.', minor] if major.is_ascii_digit() && minor.is_ascii_digit() => {
+                        Err(ParseError::UnsupportedVersion)
+                    }
+                    _ => Err(ParseError::BadRequestLine),
+                }
+            }
+        }
+    }
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// How large the parts of a request may be, so a client cannot make the server hold
+/// as much of one as it likes.
+/// ```
+/// use ch20_web_server::request::Limits;
+///
+/// let limits = Limits::default()
+///     .request_line(4096)
+///     .headers(50)
+///     .body(1024 * 1024);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Limits {
+    pub(crate) request_line: usize,
+    pub(crate) headers: usize,
+    pub(crate) header_size: usize,
+    pub(crate) body: usize,
+}
+
+impl Default for Limits {
+    /// Allow request lines of 8 KiB, 100 headers taking up to 64 KiB together, and
+    /// bodies of 16 MiB.
+    fn default() -> Limits {
+        Limits {
+            request_line: 8 * 1024,
+            headers: 100,
+            header_size: 64 * 1024,
+            body: 16 * 1024 * 1024,
+        }
+    }
+}
+
+impl Limits {
+    /// Reject request lines longer than `bytes` as `UriTooLong`.
+    pub fn request_line(mut self, bytes: usize) -> Limits {
+        self.request_line = bytes;
+        self
+    }
+
+    /// Reject requests with more than `count` headers as `HeadersTooLarge`, as with
+    /// trailers.
+    pub fn headers(mut self, count: usize) -> Limits {
+        self.headers = count;
+        self
+    }
+
+    /// Reject requests whose header lines take up more than `bytes` together as
+    /// `HeadersTooLarge`, as with trailers.
+    pub fn header_size(mut self, bytes: usize) -> Limits {
+        self.header_size = bytes;
+        self
+    }
+
+    /// Reject bodies longer than `bytes` as `BodyTooLarge`, before reading them if
+    /// they have a `Content-Length`.
+    pub fn body(mut self, bytes: usize) -> Limits {
+        self.body = bytes;
+        self
+    }
+}
+
+/// A parsed HTTP request.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Request {
+    method: Method,
+    target: String,
+    version: Version,
+    headers: Headers,
+    body: Vec<u8>,
+    trailers: Headers,
+    params: Params,
+    route: Option<String>,
+    id: Option<String>,
+    peer: Option<IpAddr>,
+    principal: Option<String>,
+}
+
+impl Request {
+    /// Read one request from `reader`.
+    ///
+    /// Returns `ConnectionClosed` if the reader is at its end before the request
+    /// starts, and another `ParseError` if what was sent is not a valid request.
+    ///
+    /// The body is framed by `Content-Length` or by `Transfer-Encoding: chunked`, and
+    /// read in full. A request with both is rejected as `ConflictingFraming`, since
+    /// servers that pick different ones can be made to disagree where it ends.
+    ///
+    /// The request is held to the default `Limits`.
+    pub fn read_from(reader: &mut impl BufRead) -> Result<Request, ParseError> {
+        Request::read_with_limits(reader, Limits::default())
+    }
+
+    /// Like `read_from`, but holding the request to `limits`.
+    pub fn read_with_limits(
+        reader: &mut impl BufRead,
+        limits: Limits,
+    ) -> Result<Request, ParseError> {
+        let mut request = Request::read_head(reader, limits)?;
+        request.read_body(reader, limits)?;
+        Ok(request)
+    }
+
+    /// Read the request line and headers of a request, leaving its body to be read
+    /// by `read_body`.
+    pub(crate) fn read_head(
+        reader: &mut impl BufRead,
+        limits: Limits,
+    ) -> Result<Request, ParseError> {
+        let line = match read_line(reader, limits.request_line, ParseError::UriTooLong)? {
+            Some(line) => line,
+            None => return Err(ParseError::ConnectionClosed),
+        };
+        let (method, target, version) = parse_request_line(&line)?;
+        let headers = read_fields(reader, limits)?;
+
+        Ok(Request {
+            method,
+            target: target.to_owned(),
+            version,
+            headers,
+            body: Vec::new(),
+            trailers: Headers::new(),
+            params: Params::default(),
+            route: None,
+            id: None,
+            peer: None,
+            principal: None,
+        })
+    }
+
+    /// Read the body of a request whose head was read by `read_head`.
+    pub(crate) fn read_body(
+        &mut self,
+        reader: &mut impl BufRead,
+        limits: Limits,
+    ) -> Result<(), ParseError> {
+        self.body = if self.headers.contains("Transfer-Encoding") {
+            if self.headers.contains("Content-Length") {
+                return Err(ParseError::ConflictingFraming);
+            }
+            check_chunked(&self.headers)?;
+            let (body, trailers) = read_chunked(reader, limits)?;
+            self.trailers = trailers;
+            body
+        } else {
+            let length = content_length(&self.headers)?;
+            if length > limits.body {
+                return Err(ParseError::BodyTooLarge);
+            }
+            read_exact(reader, length)?
+        };
+        Ok(())
+    }
+
+    /// Assemble a request that was read in another framing than HTTP/1, such as from
+    /// the frames of an HTTP/2 stream.
+    pub(crate) fn from_parts(
+        method: Method,
+        target: String,
+        version: Version,
+        headers: Headers,
+        body: Vec<u8>,
+        trailers: Headers,
+    ) -> Request {
+        Request {
+            method,
+            target,
+            version,
+            headers,
+            body,
+            trailers,
+            params: Params::default(),
+            route: None,
+            id: None,
+            peer: None,
+            principal: None,
+        }
+    }
+
+    /// A copy of the request line and headers, without the body.
+    pub(crate) fn head(&self) -> Request {
+        Request {
+            method: self.method,
+            target: self.target.clone(),
+            version: self.version,
+            headers: self.headers.clone(),
+            body: Vec::new(),
+            trailers: Headers::new(),
+            params: Params::default(),
+            route: None,
+            id: self.id.clone(),
+            peer: self.peer,
+            principal: self.principal.clone(),
+        }
+    }
+
+    pub fn method(&self) -> Method {
+        self.method
+    }
+
+    /// The request target as sent, including any query string.
+    pub fn target(&self) -> &str {
+        &self.target
+    }
+
+    /// The request target without its query string.
+    pub fn path(&self) -> &str {
+        self.target
+            .split_once('?')
+            .map_or(&self.target[..], |(path, _)| path)
+    }
+
+    /// The query string after the `?`, if there is one.
+    pub fn query(&self) -> Option<&str> {
+        self.target.split_once('?').map(|(_, query)| query)
+    }
+
+    pub fn version(&self) -> Version {
+        self.version
+    }
+
+    pub fn headers(&self) -> &Headers {
+        &self.headers
+    }
+
+    /// Shorthand for `headers().get(name)`.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name)
+    }
+
+    /// Whether the client asked to keep the connection open after this request. That
+    /// is the default in HTTP/1.1 unless it sent `Connection: close`, while HTTP/1.0
+    /// clients have to send `Connection: keep-alive`. HTTP/2 connections are always
+    /// kept open.
+    pub fn keep_alive(&self) -> bool {
+        let has_option = |option: &str| {
+            self.headers
+                .get_all("Connection")
+                .flat_map(|value| value.split(','))
+                .any(|token| token.trim().eq_ignore_ascii_case(option))
+        };
+
+        match self.version {
+            Version::Http10 => has_option("keep-alive"),
+            Version::Http11 => !has_option("close"),
+            Version::Http2 => true,
+        }
+    }
+
+    pub fn body(&self) -> &[u8] {
+        &self.body
+    }
+
+    /// The trailer fields sent after a chunked body, which are empty for other bodies.
+    pub fn trailers(&self) -> &Headers {
+        &self.trailers
+    }
+
+    /// Return a reader over the body, with any chunked framing removed.
+    pub fn body_reader(&self) -> impl Read + '_ {
+        &self.body[..]
+    }
+
+    /// The path parameters captured by the route that matched the request.
+    pub fn params(&self) -> &Params {
+        &self.params
+    }
+
+    /// Shorthand for `params().get(name)`.
+    pub fn param(&self, name: &str) -> Option<&str> {
+        self.params.get(name)
+    }
+
+    pub(crate) fn set_params(&mut self, params: Params) {
+        self.params = params;
+    }
+
+    /// The pattern of the route that matched the request, such as `/users/:id`, or
+    /// `None` if no route has.
+    pub fn route(&self) -> Option<&str> {
+        self.route.as_deref()
+    }
+
+    pub(crate) fn set_route(&mut self, pattern: &str) {
+        self.route = Some(pattern.to_owned());
+    }
+
+    /// The id the connection gave the request, which is logged with it and sent back
+    /// in `X-Request-Id`, or `None` if it was not read from a connection.
+    pub fn id(&self) -> Option<&str> {
+        self.id.as_deref()
+    }
+
+    pub(crate) fn set_id(&mut self, id: String) {
+        self.id = Some(id);
+    }
+
+    /// The address of the client that sent the request, or `None` if it came over a
+    /// Unix socket or was not read from a connection.
+    pub fn peer(&self) -> Option<IpAddr> {
+        self.peer
+    }
+
+    pub(crate) fn set_peer(&mut self, peer: Option<IpAddr>) {
+        self.peer = peer;
+    }
+
+    /// Who the request was authenticated as, such as the user of its Basic
+    /// credentials or the owner of its API key, or `None` if it was not.
+    pub fn principal(&self) -> Option<&str> {
+        self.principal.as_deref()
+    }
+
+    pub(crate) fn set_principal(&mut self, principal: String) {
+        self.principal = Some(principal);
+    }
+}
+
+/// Why a request could not be read.
+#[derive(Debug)]
+pub enum ParseError {
+    /// The connection closed cleanly before a request started.
+    ConnectionClosed,
+    /// The connection closed in the middle of a request.
+    Incomplete,
+    /// The request line is not `METHOD target HTTP/x.y`.
+    BadRequestLine,
+    /// The method is well formed but not one the server knows.
+    UnknownMethod,
+    /// The version is well formed but not HTTP/1.0 or HTTP/1.1.
+    UnsupportedVersion,
+    /// A header line is not `name: value`.
+    BadHeader,
+    /// The `Content-Length` is not a number, or is given twice with different values.
+    BadContentLength,
+    /// The request has both a `Transfer-Encoding` and a `Content-Length`.
+    ConflictingFraming,
+    /// The request uses a `Transfer-Encoding` other than `chunked`.
+    UnsupportedTransferEncoding,
+    /// A chunk of a chunked body is malformed.
+    BadChunk,
+    /// The request contains bytes that are not valid UTF-8 outside of the body.
+    NotUtf8,
+    /// The request line is longer than its limit.
+    UriTooLong,
+    /// There are more headers or trailers than allowed, or they are too long.
+    HeadersTooLarge,
+    /// The body is longer than its limit.
+    BodyTooLarge,
+    /// Reading from the connection failed.
+    Io(io::Error),
+}
+
+impl ParseError {
+    /// The status to answer the request with, or `None` if there is no one left to
+    /// answer.
+    pub fn status(&self) -> Option<Status> {
+        match self {
+            ParseError::Io(error)
+                if matches!(
+                    error.kind(),
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                ) =>
+            {
+                Some(Status::RequestTimeout)
+            }
+            ParseError::ConnectionClosed | ParseError::Io(_) => None,
+            ParseError::UnknownMethod | ParseError::UnsupportedTransferEncoding => {
+                Some(Status::NotImplemented)
+            }
+            ParseError::UnsupportedVersion => Some(Status::HttpVersionNotSupported),
+            ParseError::UriTooLong => Some(Status::UriTooLong),
+            ParseError::HeadersTooLarge => Some(Status::RequestHeaderFieldsTooLarge),
+            ParseError::BodyTooLarge => Some(Status::PayloadTooLarge),
+            ParseError::Incomplete
+            | ParseError::BadRequestLine
+            | ParseError::BadHeader
+            | ParseError::BadContentLength
+            | ParseError::ConflictingFraming
+            | ParseError::BadChunk
+            | ParseError::NotUtf8 => Some(Status::BadRequest),
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::ConnectionClosed => write!(f, "Connection closed"),
+            ParseError::Incomplete => write!(f, "Connection closed mid-request"),
+            ParseError::BadRequestLine => write!(f, "Malformed request line"),
+            ParseError::UnknownMethod => write!(f, "Unknown request method"),
+            ParseError::UnsupportedVersion => write!(f, "Unsupported HTTP version"),
+            ParseError::BadHeader => write!(f, "Malformed header"),
+            ParseError::BadContentLength => write!(f, "Invalid Content-Length"),
+            ParseError::ConflictingFraming => {
+                write!(f, "Both Transfer-Encoding and Content-Length are set")
+            }
+            ParseError::UnsupportedTransferEncoding => {
+                write!(f, "Unsupported Transfer-Encoding")
+            }
+            ParseError::BadChunk => write!(f, "Malformed chunk"),
+            ParseError::NotUtf8 => write!(f, "Request head is not valid UTF-8"),
+            ParseError::UriTooLong => write!(f, "Request line is too long"),
+            ParseError::HeadersTooLarge => write!(f, "Too many or too large headers"),
+            ParseError::BodyTooLarge => write!(f, "Request body is too large"),
+            ParseError::Io(error) => write!(f, "Failed to read request: {error}"),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// The longest line giving the size of a chunk, with any extensions.
+const MAX_CHUNK_LINE: usize = 4096;
+
+/// Read a line without its line ending, or `None` at the end of the reader. A bare
+/// `\n` is accepted as a line ending as well as `\r\n`. Lines longer than `max`
+/// fail with `too_long`, without more of them being read.
+fn read_line(
+    reader: &mut impl BufRead,
+    max: usize,
+    too_long: ParseError,
+) -> Result<Option<String>, ParseError> {
+    let mut line = Vec::new();
+    // Room for the line ending, and a byte to tell it has gone over.
+    let read = reader
+        .take(max as u64 + 3)
+        .read_until(b'\n', &mut line)
+        .map_err(ParseError::Io)?;
+    if read == 0 {
+        return Ok(None);
+    }
+
+    if line.pop() != Some(b'\n') {
+        return Err(if read > max {
+            too_long
+        } else {
+            ParseError::Incomplete
+        });
+    }
+    if line.last() == Some(&b'\r') {
+        line.pop();
+    }
+    if line.len() > max {
+        return Err(too_long);
+    }
+    String::from_utf8(line)
+        .map(Some)
+        .map_err(|_| ParseError::NotUtf8)
+}
+
+/// Read header fields up to the empty line after them, as in the head of a request
+/// or the trailers of a chunked body.
+fn read_fields(reader: &mut impl BufRead, limits: Limits) -> Result<Headers, ParseError> {
+    let mut fields = Headers::new();
+    let (mut count, mut left) = (0, limits.header_size);
+    loop {
+        let line =
+            read_line(reader, left, ParseError::HeadersTooLarge)?.ok_or(ParseError::Incomplete)?;
+        if line.is_empty() {
+            return Ok(fields);
+        }
+        count += 1;
+        if count > limits.headers {
+            return Err(ParseError::HeadersTooLarge);
+        }
+        left -= line.len();
+        let (name, value) = parse_header(&line)?;
+        fields.append(name, value);
+    }
+}
+
+fn parse_request_line(line: &str) -> Result<(Method, &str, Version), ParseError> {
+    let mut parts = line.split(' ');
+    let (Some(method), Some(target), Some(version), None) =
+        (parts.next(), parts.next(), parts.next(), parts.next())
+    else {
+        return Err(ParseError::BadRequestLine);
+    };
+
+    let valid_target = target == "*" || target.starts_with('/') || target.contains("://");
+    if !valid_target || target.bytes().any(|byte| byte.is_ascii_control()) {
+        return Err(ParseError::BadRequestLine);
+    }
+
+    Ok((method.parse()?, target, version.parse()?))
+}
+
+fn parse_header(line: &str) -> Result<(&str, &str), ParseError> {
+    let (name, value) = line.split_once(':').ok_or(ParseError::BadHeader)?;
+
+    // Whitespace between the name and the colon is forbidden, which also rules out
+    // obsolete folded continuation lines.
+    if !is_token(name) {
+        return Err(ParseError::BadHeader);
+    }
+    let value = value.trim_matches([' ', '\t']);
+    if value.chars().any(|c| c.is_ascii_control() && c != '\t') {
+        return Err(ParseError::BadHeader);
+    }
+    Ok((name, value))
+}
+
+fn content_length(headers: &Headers) -> Result<usize, ParseError> {
+    let mut length = None;
+
+    for value in headers.get_all("Content-Length") {
+        if value.is_empty() || !value.bytes().all(|byte| byte.is_ascii_digit()) {
+            return Err(ParseError::BadContentLength);
+        }
+        let value = value.parse().map_err(|_| ParseError::BadContentLength)?;
+        if length.is_some_and(|length| length != value) {
+            return Err(ParseError::BadContentLength);
+        }
+        length = Some(value);
+    }
+    Ok(length.unwrap_or(0))
+}
+
+/// Check that the `Transfer-Encoding` is just `chunked`.
+fn check_chunked(headers: &Headers) -> Result<(), ParseError> {
+    let codings: Vec<_> = headers
+        .get_all("Transfer-Encoding")
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .filter(|coding| !coding.is_empty())
+        .collect();
+
+    match codings[..] {
+        [coding] if coding.eq_ignore_ascii_case("chunked") => Ok(()),
+        // Without `chunked` last the end of the body cannot be found, and twice is
+        // meaningless.
+        _ if codings
+            .iter()
+            .all(|coding| coding.eq_ignore_ascii_case("chunked")) =>
+        {
+            Err(ParseError::BadChunk)
+        }
+        _ => Err(ParseError::UnsupportedTransferEncoding),
+    }
+}
+
+/// Read exactly `length` bytes of body.
+fn read_exact(reader: &mut impl BufRead, length: usize) -> Result<Vec<u8>, ParseError> {
+    let mut body = Vec::new();
+    reader
+        .take(length as u64)
+        .read_to_end(&mut body)
+        .map_err(ParseError::Io)?;
+
+    if body.len() < length {
+        return Err(ParseError::Incomplete);
+    }
+    Ok(body)
+}
+
+/// Read a chunked body, and the trailer fields after it.
+fn read_chunked(
+    reader: &mut impl BufRead,
+    limits: Limits,
+) -> Result<(Vec<u8>, Headers), ParseError> {
+    let mut body = Vec::new();
+
+    loop {
+        let line = read_line(reader, MAX_CHUNK_LINE, ParseError::BadChunk)?
+            .ok_or(ParseError::Incomplete)?;
+        // Chunk extensions after a `;` carry nothing the server uses.
+        let size = line
+            .split_once(';')
+            .map_or(&line[..], |(size, _)| size)
+            .trim_end();
+        if size.is_empty() || !size.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+            return Err(ParseError::BadChunk);
+        }
+        let size = usize::from_str_radix(size, 16).map_err(|_| ParseError::BadChunk)?;
+        if size == 0 {
+            break;
+        }
+        if size > limits.body - body.len() {
+            return Err(ParseError::BodyTooLarge);
+        }
+
+        body.extend(read_exact(reader, size)?);
+        let end = read_line(reader, 0, ParseError::BadChunk)?.ok_or(ParseError::Incomplete)?;
+        if !end.is_empty() {
+            return Err(ParseError::BadChunk);
+        }
+    }
+
+    Ok((body, read_fields(reader, limits)?))
+}
+
+/// Whether `s` is a non-empty HTTP token, as used for methods and header names.
+fn is_token(s: &str) -> bool {
+    !s.is_empty()
+        && s.bytes()
+            .all(|byte| byte.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&byte))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(raw: &str) -> Result<Request, ParseError> {
+        Request::read_from(&mut raw.as_bytes())
+    }
+
+    #[test]
+    fn parses_a_request_with_headers_and_body() {
+        let request = parse(
+            "POST /upload?name=a.txt HTTP/1.1\r\n\
+             Host: localhost\r\n\
+             content-length: 5\r\n\
+             X-Tag:  one \r\n\
+             X-Tag: two\r\n\
+             \r\n\
+             helloextra",
+        )
+        .unwrap();
+
+        assert_eq!(request.method(), Method::Post);
+        assert_eq!(request.target(), "/upload?name=a.txt");
+        assert_eq!(request.path(), "/upload");
+        assert_eq!(request.query(), Some("name=a.txt"));
+        assert_eq!(request.version(), Version::Http11);
+        assert_eq!(request.header("HOST"), Some("localhost"));
+        assert_eq!(
+            request.headers().get_all("x-tag").collect::<Vec<_>>(),
+            ["one", "two"]
+        );
+        assert_eq!(request.body(), b"hello");
+    }
+
+    #[test]
+    fn decodes_chunked_bodies() {
+        let request = parse(
+            "POST /upload HTTP/1.1\r\n\
+             Transfer-Encoding: Chunked\r\n\
+             \r\n\
+             5;name=value\r\nhello\r\n\
+             7\r\n, world\r\n\
+             0\r\n\
+             X-Checksum: abc\r\n\
+             \r\n",
+        )
+        .unwrap();
+
+        let mut body = String::new();
+        request.body_reader().read_to_string(&mut body).unwrap();
+        assert_eq!(body, "hello, world");
+        assert_eq!(request.trailers().get("x-checksum"), Some("abc"));
+    }
+
+    #[test]
+    fn accepts_bare_newlines() {
+        let request = parse("GET / HTTP/1.0\nHost: localhost\n\n").unwrap();
+
+        assert_eq!(request.version(), Version::Http10);
+        assert!(request.body().is_empty());
+    }
+
+    #[test]
+    fn keep_alive_depends_on_version_and_connection_header() {
+        let cases = [
+            ("GET / HTTP/1.1\r\n\r\n", true),
+            (
+                "GET / HTTP/1.1\r\nConnection: Upgrade, Close\r\n\r\n",
+                false,
+            ),
+            ("GET / HTTP/1.0\r\n\r\n", false),
+            ("GET / HTTP/1.0\r\nConnection: keep-alive\r\n\r\n", true),
+        ];
+
+        for (raw, keep_alive) in cases {
+            assert_eq!(parse(raw).unwrap().keep_alive(), keep_alive, "{raw:?}");
+        }
+    }
+
+    #[test]
+    fn rejects_malformed_requests() {
+        let cases = [
+            ("GET /\r\n\r\n", 400),
+            ("GET  / HTTP/1.1\r\n\r\n", 400),
+            ("GET index.html HTTP/1.1\r\n\r\n", 400),
+            ("get / HTTP/1.1\r\n\r\n", 501),
+            ("BREW / HTTP/1.1\r\n\r\n", 501),
+            ("GET / HTTP/2.0\r\n\r\n", 505),
+            ("GET / HTPT/1.1\r\n\r\n", 400),
+            ("GET / HTTP/1.1\r\nHost localhost\r\n\r\n", 400),
+            ("GET / HTTP/1.1\r\nHost : localhost\r\n\r\n", 400),
+            ("GET / HTTP/1.1\r\n folded\r\n\r\n", 400),
+            ("POST / HTTP/1.1\r\nContent-Length: -1\r\n\r\n", 400),
+            (
+                "POST / HTTP/1.1\r\nContent-Length: 1\r\nContent-Length: 2\r\n\r\n",
+                400,
+            ),
+            ("POST / HTTP/1.1\r\nContent-Length: 10\r\n\r\nshort", 400),
+            ("POST / HTTP/1.1\r\nTransfer-Encoding: gzip, chunked\r\n\r\n", 501),
+            (
+                "POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\nContent-Length: 0\r\n\r\n",
+                400,
+            ),
+            (
+                "POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\nTransfer-Encoding: chunked\r\n\r\n",
+                400,
+            ),
+            ("POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\nz\r\n", 400),
+            ("POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n2\r\nabc\r\n", 400),
+            ("POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nab", 400),
+            ("GET / HTTP/1.1\r\nHost: local", 400),
+        ];
+
+        for (raw, status) in cases {
+            let error = parse(raw).unwrap_err();
+            assert_eq!(
+                error.status().map(Status::code),
+                Some(status),
+                "{raw:?} gave {error}"
+            );
+        }
+        assert!(matches!(parse(""), Err(ParseError::ConnectionClosed)));
+        assert_eq!(
+            Request::read_from(&mut &b"GET /\xff HTTP/1.1\r\n\r\n"[..])
+                .unwrap_err()
+                .status(),
+            Some(Status::BadRequest)
+        );
+    }
+
+    #[test]
+    fn holds_requests_to_their_limits() {
+        let limits = Limits::default()
+            .request_line(20)
+            .headers(2)
+            .header_size(30)
+            .body(5);
+        let cases = [
+            ("GET /a-long-path HTTP/1.1\r\n\r\n", 414),
+            ("GET / HTTP/1.1\r\nA: 1\r\nB: 2\r\nC: 3\r\n\r\n", 431),
+            (
+                "GET / HTTP/1.1\r\nA: 1\r\nLong: 0123456789abcdefghijklmnop\r\n\r\n",
+                431,
+            ),
+            ("POST / HTTP/1.1\r\nContent-Length: 6\r\n\r\n", 413),
+            (
+                "POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n3\r\nabc\r\n3\r\ndef\r\n",
+                413,
+            ),
+        ];
+
+        for (raw, status) in cases {
+            let error = Request::read_with_limits(&mut raw.as_bytes(), limits).unwrap_err();
+            assert_eq!(
+                error.status().map(Status::code),
+                Some(status),
+                "{raw:?} gave {error}"
+            );
+        }
+
+        // Up to the limits is fine.
+        let request = Request::read_with_limits(
+            &mut &b"POST /path HTTP/1.1\r\nA: 1\r\nB: 0123456789\r\nContent-Length: 5\r\n\r\nhello"
+                [..],
+            limits.headers(3).header_size(40),
+        )
+        .unwrap();
+        assert_eq!(request.body(), b"hello");
+    }
+}
@@ -0,0 +1,131 @@
+//! Request ids, which tie what a client saw of a request to the lines logged for it.
+//!
+//! Each request is given the id its client sent in `X-Request-Id`, if that is a
+//! sensible one, or else a new one. The id is sent back in the same header, logged
+//! with every line written while the request is answered, and recorded on its span.
+
+use std::{
+    cell::RefCell,
+    process,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        OnceLock,
+    },
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::request::Request;
+
+/// The header ids are read from and sent back in.
+pub const HEADER: &str = "X-Request-Id";
+/// The longest id taken from a client.
+const MAX_LENGTH: usize = 128;
+
+thread_local! {
+    static CURRENT: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+/// A new id, of 16 hexadecimal digits, that no other request served by this process
+/// has, and that is unlikely to come up in another.
+/// ```
+/// use ch20_web_server::request_id;
+///
+/// let (a, b) = (request_id::generate(), request_id::generate());
+/// assert_eq!(a.len(), 16);
+/// assert_ne!(a, b);
+/// ```
+pub fn generate() -> String {
+    static SEED: OnceLock<u64> = OnceLock::new();
+    static COUNT: AtomicU64 = AtomicU64::new(0);
+
+    let seed = *SEED.get_or_init(|| {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since| since.as_nanos() as u64);
+        nanos ^ u64::from(process::id()).rotate_left(32)
+    });
+    let count = COUNT.fetch_add(1, Ordering::Relaxed);
+    format!("{:016x}", mix(seed.wrapping_add(count)))
+}
+
+/// Scramble `x` with the finaliser of SplitMix64, which gives each input its own
+/// output, so ids counted from the seed cannot repeat.
+fn mix(x: u64) -> u64 {
+    let x = x.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    let x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    let x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    x ^ (x >> 31)
+}
+
+/// The id to give `request`: the one it was sent with, if that is up to 128 letters,
+/// digits, `-`, `_`, `.` or `:`, or else a new one. Anything else could forge a log
+/// line or a header.
+pub fn choose(request: &Request) -> String {
+    match request.header(HEADER) {
+        Some(id) if is_valid(id) => id.to_owned(),
+        _ => generate(),
+    }
+}
+
+fn is_valid(id: &str) -> bool {
+    (1..=MAX_LENGTH).contains(&id.len())
+        && id
+            .bytes()
+            .all(|byte| byte.is_ascii_alphanumeric() || b"-_.:".contains(&byte))
+}
+
+/// The id of the request being answered on this thread, if there is one.
+pub fn current() -> Option<String> {
+    CURRENT.with_borrow(Clone::clone)
+}
+
+/// Make `id` the current one on this thread until the returned guard is dropped.
+pub(crate) fn enter(id: &str) -> Entered {
+    let previous = CURRENT.replace(Some(id.to_owned()));
+    Entered { previous }
+}
+
+/// Restores the id that was current before `enter` when it is dropped.
+pub(crate) struct Entered {
+    previous: Option<String>,
+}
+
+impl Drop for Entered {
+    fn drop(&mut self) {
+        CURRENT.set(self.previous.take());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(id: &str) -> Request {
+        let head = format!("GET / HTTP/1.1\r\nX-Request-Id: {id}\r\n\r\n");
+        Request::read_from(&mut head.as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn honours_sensible_ids_only() {
+        assert_eq!(choose(&request("abc-123_x.y:z")), "abc-123_x.y:z");
+        for id in ["a b", "a\"b", &"a".repeat(129)] {
+            let chosen = choose(&request(id));
+            assert_ne!(chosen, id);
+            assert_eq!(chosen.len(), 16);
+        }
+    }
+
+    #[test]
+    fn keeps_the_current_id_while_entered() {
+        assert_eq!(current(), None);
+        {
+            let _outer = enter("outer");
+            {
+                let _inner = enter("inner");
+                assert_eq!(current().as_deref(), Some("inner"));
+            }
+            assert_eq!(current().as_deref(), Some("outer"));
+        }
+        assert_eq!(current(), None);
+    }
+}
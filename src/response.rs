@@ -0,0 +1,565 @@
+use std::{
+    fmt,
+    io::{self, Read, Write},
+};
+
+use crate::headers::Headers;
+
+/// The response status codes the server sends.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Status {
+    SwitchingProtocols,
+    Ok,
+    Created,
+    Accepted,
+    NoContent,
+    PartialContent,
+    MovedPermanently,
+    Found,
+    NotModified,
+    TemporaryRedirect,
+    PermanentRedirect,
+    BadRequest,
+    Unauthorized,
+    Forbidden,
+    NotFound,
+    MethodNotAllowed,
+    RequestTimeout,
+    Conflict,
+    LengthRequired,
+    PayloadTooLarge,
+    UriTooLong,
+    UnsupportedMediaType,
+    TooManyRequests,
+    RequestHeaderFieldsTooLarge,
+    InternalServerError,
+    NotImplemented,
+    ServiceUnavailable,
+    HttpVersionNotSupported,
+}
+
+impl Status {
+    pub fn code(self) -> u16 {
+        match self {
+            Status::SwitchingProtocols => 101,
+            Status::Ok => 200,
+            Status::Created => 201,
+            Status::Accepted => 202,
+            Status::NoContent => 204,
+            Status::PartialContent => 206,
+            Status::MovedPermanently => 301,
+            Status::Found => 302,
+            Status::NotModified => 304,
+            Status::TemporaryRedirect => 307,
+            Status::PermanentRedirect => 308,
+            Status::BadRequest => 400,
+            Status::Unauthorized => 401,
+            Status::Forbidden => 403,
+            Status::NotFound => 404,
+            Status::MethodNotAllowed => 405,
+            Status::RequestTimeout => 408,
+            Status::Conflict => 409,
+            Status::LengthRequired => 411,
+            Status::PayloadTooLarge => 413,
+            Status::UriTooLong => 414,
+            Status::UnsupportedMediaType => 415,
+            Status::TooManyRequests => 429,
+            Status::RequestHeaderFieldsTooLarge => 431,
+            Status::InternalServerError => 500,
+            Status::NotImplemented => 501,
+            Status::ServiceUnavailable => 503,
+            Status::HttpVersionNotSupported => 505,
+        }
+    }
+
+    /// The standard reason phrase sent after the code.
+    pub fn reason(self) -> &'static str {
+        match self {
+            Status::SwitchingProtocols => "Switching Protocols",
+            Status::Ok => "OK",
+            Status::Created => "Created",
+            Status::Accepted => "Accepted",
+            Status::NoContent => "No Content",
+            Status::PartialContent => "Partial Content",
+            Status::MovedPermanently => "Moved Permanently",
+            Status::Found => "Found",
+            Status::NotModified => "Not Modified",
+            Status::TemporaryRedirect => "Temporary Redirect",
+            Status::PermanentRedirect => "Permanent Redirect",
+            Status::BadRequest => "Bad Request",
+            Status::Unauthorized => "Unauthorized",
+            Status::Forbidden => "Forbidden",
+            Status::NotFound => "Not Found",
+            Status::MethodNotAllowed => "Method Not Allowed",
+            Status::RequestTimeout => "Request Timeout",
+            Status::Conflict => "Conflict",
+            Status::LengthRequired => "Length Required",
+            Status::PayloadTooLarge => "Content Too Large",
+            Status::UriTooLong => "URI Too Long",
+            Status::UnsupportedMediaType => "Unsupported Media Type",
+            Status::TooManyRequests => "Too Many Requests",
+            Status::RequestHeaderFieldsTooLarge => "Request Header Fields Too Large",
+            Status::InternalServerError => "Internal Server Error",
+            Status::NotImplemented => "Not Implemented",
+            Status::ServiceUnavailable => "Service Unavailable",
+            Status::HttpVersionNotSupported => "HTTP Version Not Supported",
+        }
+    }
+
+    /// Whether responses with this status never carry a body.
+    pub(crate) fn forbids_body(self) -> bool {
+        matches!(
+            self,
+            Status::SwitchingProtocols | Status::NoContent | Status::NotModified
+        )
+    }
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.code(), self.reason())
+    }
+}
+
+/// An HTTP response, built up with chained calls and then written out with
+/// `write_to`.
+/// ```
+/// use ch20_web_server::response::{Response, Status};
+///
+/// let response = Response::new(Status::Ok)
+///     .header("Content-Type", "text/html")
+///     .body("<h1>Hello!</h1>");
+///
+/// let mut bytes = Vec::new();
+/// response.write_to(&mut bytes).unwrap();
+/// assert!(bytes.starts_with(b"HTTP/1.1 200 OK\r\n"));
+/// ```
+#[derive(Debug)]
+pub struct Response {
+    status: Status,
+    headers: Headers,
+    body: Body,
+}
+
+enum Body {
+    Bytes(Vec<u8>),
+    /// Copied from the reader to the connection as the response is written.
+    Stream {
+        reader: Box<dyn Read + Send>,
+        length: u64,
+    },
+    /// Copied from the reader until it ends, as chunks of whatever each read returns.
+    Chunked {
+        reader: Box<dyn Read + Send>,
+        trailers: Option<TrailerFn>,
+    },
+    /// Copied from the reader until it ends, with the end of the body marked by closing
+    /// the connection. Used instead of `Chunked` for HTTP/1.0 clients.
+    UntilClose(Box<dyn Read + Send>),
+}
+
+/// Produces the trailer fields sent after the last chunk of a chunked body.
+pub type TrailerFn = Box<dyn FnOnce() -> Headers + Send>;
+
+impl fmt::Debug for Body {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Body::Bytes(bytes) => f.debug_tuple("Bytes").field(&bytes.len()).finish(),
+            Body::Stream { length, .. } => {
+                f.debug_struct("Stream").field("length", length).finish()
+            }
+            Body::Chunked { trailers, .. } => f
+                .debug_struct("Chunked")
+                .field("trailers", &trailers.is_some())
+                .finish(),
+            Body::UntilClose(_) => f.write_str("UntilClose"),
+        }
+    }
+}
+
+/// A response taken apart, for writing it out in another framing than HTTP/1.1.
+pub(crate) struct Parts {
+    pub(crate) status: Status,
+    pub(crate) headers: Headers,
+    pub(crate) body: Box<dyn Read + Send>,
+    /// The length of the body, if it is known before it is written.
+    pub(crate) length: Option<u64>,
+    pub(crate) trailers: Option<TrailerFn>,
+}
+
+/// Size of the buffer each chunk of a chunked body is read into.
+const CHUNK_SIZE: usize = 8 * 1024;
+
+impl Response {
+    /// Create a response with no headers and an empty body.
+    pub fn new(status: Status) -> Response {
+        Response {
+            status,
+            headers: Headers::new(),
+            body: Body::Bytes(Vec::new()),
+        }
+    }
+
+    /// Create a plain text response whose body is the status itself, such as
+    /// `404 Not Found`, for errors without a page of their own.
+    pub fn error(status: Status) -> Response {
+        Response::new(status)
+            .header("Content-Type", "text/plain; charset=utf-8")
+            .body(status.to_string())
+    }
+
+    /// Set the header `name`, replacing any earlier value.
+    ///
+    /// `Content-Length` and `Transfer-Encoding` are always set from the body, so
+    /// setting them here has no effect.
+    pub fn header(mut self, name: impl Into<String>, value: impl Into<String>) -> Response {
+        self.headers.set(name, value);
+        self
+    }
+
+    /// Set the body.
+    pub fn body(mut self, body: impl Into<Vec<u8>>) -> Response {
+        self.body = Body::Bytes(body.into());
+        self
+    }
+
+    /// Set the body to the first `length` bytes of `reader`, which are read as the
+    /// response is written instead of being held in memory, as for a large file.
+    pub fn stream_body(mut self, reader: impl Read + Send + 'static, length: u64) -> Response {
+        self.body = Body::Stream {
+            reader: Box::new(reader),
+            length,
+        };
+        self
+    }
+
+    /// Set the body to everything `reader` returns until it ends, sent with
+    /// `Transfer-Encoding: chunked` so the length does not have to be known up front.
+    ///
+    /// Each read becomes one chunk, so a reader that returns data as it is produced,
+    /// such as a pipe, is streamed to the client as it goes.
+    /// ```
+    /// use std::io::Read;
+    /// use ch20_web_server::response::{Response, Status};
+    ///
+    /// let report = b"line 1\n".chain(&b"line 2\n"[..]);
+    /// let response = Response::new(Status::Ok).chunked_body(report);
+    /// ```
+    pub fn chunked_body(mut self, reader: impl Read + Send + 'static) -> Response {
+        self.body = Body::Chunked {
+            reader: Box::new(reader),
+            trailers: None,
+        };
+        self
+    }
+
+    /// Send the fields returned by `trailers` after the last chunk of a chunked body.
+    /// It is called once the whole body has been written, so it can report on it, for
+    /// example with a checksum.
+    ///
+    /// Has no effect unless the body is set with `chunked_body`, or for HTTP/1.0
+    /// clients, which do not understand chunks.
+    pub fn trailers<F>(mut self, trailers: F) -> Response
+    where
+        F: FnOnce() -> Headers + Send + 'static,
+    {
+        if let Body::Chunked { trailers: slot, .. } = &mut self.body {
+            *slot = Some(Box::new(trailers));
+        }
+        self
+    }
+
+    /// Whether the body is sent in chunks.
+    pub fn is_chunked(&self) -> bool {
+        matches!(self.body, Body::Chunked { .. })
+    }
+
+    /// Send a chunked body as is, ending it by closing the connection, for clients
+    /// that do not understand chunks.
+    pub(crate) fn until_close(mut self) -> Response {
+        if let Body::Chunked { reader, .. } = self.body {
+            self.body = Body::UntilClose(reader);
+        }
+        self
+    }
+
+    /// Replace the body with what `encode` reads from it, as for compression.
+    ///
+    /// A body held in memory is encoded straight away, so it keeps its
+    /// `Content-Length`. A streamed body is encoded as it is written, so it is sent in
+    /// chunks.
+    pub(crate) fn encode_body<F>(mut self, encode: F) -> Response
+    where
+        F: FnOnce(Box<dyn Read + Send>) -> Box<dyn Read + Send>,
+    {
+        self.body = match self.body {
+            Body::Bytes(bytes) => {
+                let mut encoded = Vec::new();
+                encode(Box::new(io::Cursor::new(bytes)))
+                    .read_to_end(&mut encoded)
+                    .expect("encoding a body in memory cannot fail");
+                Body::Bytes(encoded)
+            }
+            Body::Stream { reader, length } => Body::Chunked {
+                reader: encode(Box::new(reader.take(length))),
+                trailers: None,
+            },
+            Body::Chunked { reader, trailers } => Body::Chunked {
+                reader: encode(reader),
+                trailers,
+            },
+            Body::UntilClose(reader) => Body::UntilClose(encode(reader)),
+        };
+        self
+    }
+
+    /// The length of the body, if it is known before it is written.
+    pub fn body_len(&self) -> Option<u64> {
+        match &self.body {
+            Body::Bytes(bytes) => Some(bytes.len() as u64),
+            Body::Stream { length, .. } => Some(*length),
+            Body::Chunked { .. } | Body::UntilClose(_) => None,
+        }
+    }
+
+    pub fn status(&self) -> Status {
+        self.status
+    }
+
+    pub fn headers(&self) -> &Headers {
+        &self.headers
+    }
+
+    /// The body set with `body`. Bodies set from a reader are not buffered, so they
+    /// give an empty slice.
+    pub fn body_bytes(&self) -> &[u8] {
+        match &self.body {
+            Body::Bytes(bytes) => bytes,
+            Body::Stream { .. } | Body::Chunked { .. } | Body::UntilClose(_) => &[],
+        }
+    }
+
+    /// Take the response apart. A status that forbids a body gets an empty one, and a
+    /// streamed body is limited to its length.
+    pub(crate) fn into_parts(self) -> Parts {
+        let (body, length, trailers): (Box<dyn Read + Send>, _, _) = if self.status.forbids_body() {
+            (Box::new(io::empty()), None, None)
+        } else {
+            match self.body {
+                Body::Bytes(bytes) => {
+                    let length = bytes.len() as u64;
+                    (Box::new(io::Cursor::new(bytes)), Some(length), None)
+                }
+                Body::Stream { reader, length } => {
+                    (Box::new(reader.take(length)), Some(length), None)
+                }
+                Body::Chunked { reader, trailers } => (reader, None, trailers),
+                Body::UntilClose(reader) => (reader, None, None),
+            }
+        };
+
+        Parts {
+            status: self.status,
+            headers: self.headers,
+            body,
+            length,
+            trailers,
+        }
+    }
+
+    /// Serialize the response as HTTP/1.1 into `writer`.
+    ///
+    /// Fails with `UnexpectedEof` if a streamed body ends before its length, in which
+    /// case the connection cannot be reused.
+    pub fn write_to(self, writer: &mut impl Write) -> io::Result<()> {
+        self.write_counted(writer, true, &mut 0)
+    }
+
+    /// Like `write_to`, adding the bytes of the body to `written` as they are written,
+    /// so a response that is cut short counts what was sent of it. Without
+    /// `with_body`, as for an answer to `HEAD`, only the head is written, with the
+    /// `Content-Length` or `Transfer-Encoding` the body would have had.
+    pub(crate) fn write_counted(
+        self,
+        writer: &mut impl Write,
+        with_body: bool,
+        written: &mut u64,
+    ) -> io::Result<()> {
+        let mut head = format!("HTTP/1.1 {}\r\n", self.status);
+
+        for (name, value) in self.headers.iter() {
+            if !name.eq_ignore_ascii_case("Content-Length")
+                && !name.eq_ignore_ascii_case("Transfer-Encoding")
+            {
+                head.push_str(&format!("{name}: {value}\r\n"));
+            }
+        }
+        let body = if self.status.forbids_body() {
+            Body::Bytes(Vec::new())
+        } else {
+            match &self.body {
+                Body::Bytes(bytes) => {
+                    head.push_str(&format!("Content-Length: {}\r\n", bytes.len()))
+                }
+                Body::Stream { length, .. } => {
+                    head.push_str(&format!("Content-Length: {length}\r\n"))
+                }
+                Body::Chunked { .. } => head.push_str("Transfer-Encoding: chunked\r\n"),
+                Body::UntilClose(_) => {}
+            }
+            self.body
+        };
+        head.push_str("\r\n");
+        writer.write_all(head.as_bytes())?;
+        if !with_body {
+            return writer.flush();
+        }
+
+        match body {
+            Body::Bytes(bytes) => {
+                writer.write_all(&bytes)?;
+                *written += bytes.len() as u64;
+            }
+            Body::Stream { reader, length } => {
+                let mut counted = Counted { writer, written };
+                if io::copy(&mut reader.take(length), &mut counted)? < length {
+                    return Err(io::ErrorKind::UnexpectedEof.into());
+                }
+            }
+            Body::Chunked { reader, trailers } => write_chunks(reader, trailers, writer, written)?,
+            Body::UntilClose(mut reader) => {
+                io::copy(&mut reader, &mut Counted { writer, written })?;
+            }
+        }
+        writer.flush()
+    }
+}
+
+/// Adds the bytes written through it to a count.
+struct Counted<'a, W> {
+    writer: &'a mut W,
+    written: &'a mut u64,
+}
+
+impl<W: Write> Write for Counted<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let count = self.writer.write(buf)?;
+        *self.written += count as u64;
+        Ok(count)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+fn write_chunks(
+    mut reader: Box<dyn Read + Send>,
+    trailers: Option<TrailerFn>,
+    writer: &mut impl Write,
+    written: &mut u64,
+) -> io::Result<()> {
+    let mut buffer = vec![0; CHUNK_SIZE];
+
+    loop {
+        let read = match reader.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(read) => read,
+            Err(error) if error.kind() == io::ErrorKind::Interrupted => continue,
+            Err(error) => return Err(error),
+        };
+        write!(writer, "{read:x}\r\n")?;
+        writer.write_all(&buffer[..read])?;
+        *written += read as u64;
+        writer.write_all(b"\r\n")?;
+        // Send each chunk as soon as it is read, rather than when the buffer fills.
+        writer.flush()?;
+    }
+
+    writer.write_all(b"0\r\n")?;
+    for (name, value) in trailers
+        .map(|trailers| trailers())
+        .unwrap_or_default()
+        .iter()
+    {
+        write!(writer, "{name}: {value}\r\n")?;
+    }
+    writer.write_all(b"\r\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn serialize(response: Response) -> String {
+        let mut bytes = Vec::new();
+        response.write_to(&mut bytes).unwrap();
+        String::from_utf8(bytes).unwrap()
+    }
+
+    #[test]
+    fn computes_content_length() {
+        let response = Response::new(Status::NotFound)
+            .header("Content-Type", "text/html")
+            .header("Content-Length", "999")
+            .body("missing");
+
+        assert_eq!(
+            serialize(response),
+            "HTTP/1.1 404 Not Found\r\n\
+             Content-Type: text/html\r\n\
+             Content-Length: 7\r\n\
+             \r\n\
+             missing"
+        );
+    }
+
+    #[test]
+    fn streams_bodies_up_to_their_length() {
+        let response = Response::new(Status::Ok).stream_body(&b"streamed and more"[..], 8);
+        assert_eq!(
+            serialize(response),
+            "HTTP/1.1 200 OK\r\nContent-Length: 8\r\n\r\nstreamed"
+        );
+
+        let short = Response::new(Status::Ok).stream_body(&b"short"[..], 8);
+        let error = short.write_to(&mut Vec::new()).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn chunks_bodies_of_unknown_length() {
+        let response = Response::new(Status::Ok)
+            .header("Content-Length", "3")
+            .chunked_body(b"hello ".chain(&b"chunked world"[..]))
+            .trailers(|| {
+                let mut trailers = Headers::new();
+                trailers.append("X-Checksum", "abc");
+                trailers
+            });
+
+        assert_eq!(
+            serialize(response),
+            "HTTP/1.1 200 OK\r\n\
+             Transfer-Encoding: chunked\r\n\
+             \r\n\
+             6\r\nhello \r\n\
+             d\r\nchunked world\r\n\
+             0\r\n\
+             X-Checksum: abc\r\n\
+             \r\n"
+        );
+
+        let response = Response::new(Status::Ok)
+            .chunked_body(&b"plain"[..])
+            .until_close();
+        assert_eq!(serialize(response), "HTTP/1.1 200 OK\r\n\r\nplain");
+    }
+
+    #[test]
+    fn bodiless_statuses_send_no_body() {
+        let response = Response::new(Status::NoContent).body("ignored");
+
+        assert_eq!(serialize(response), "HTTP/1.1 204 No Content\r\n\r\n");
+    }
+}
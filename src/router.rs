@@ -0,0 +1,545 @@
+use crate::{
+    request::{Method, Request},
+    response::{Response, Status},
+};
+
+/// Answers a request matched by a `Router`.
+pub type Handler = Box<dyn Fn(&Request) -> Response + Send + Sync>;
+
+/// Runs around every request a `Router` answers, added with `Router::wrap`.
+pub type Middleware =
+    Box<dyn Fn(&Request, &dyn Fn(&Request) -> Response) -> Response + Send + Sync>;
+
+/// Values taken from the path by a route's `:name` and `*name` segments, available
+/// from `Request::param`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Params {
+    values: Vec<(String, String)>,
+}
+
+impl Params {
+    /// Return the value of the parameter called `name`, percent-decoded.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.values
+            .iter()
+            .find(|(param, _)| param == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Iterate over every parameter as name and value, in path order.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.values
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.as_str()))
+    }
+}
+
+#[derive(Debug)]
+enum Segment {
+    Literal(String),
+    /// `:name` matches exactly one non-empty segment.
+    Param(String),
+    /// `*name` matches the rest of the path, possibly empty. Only valid last.
+    Rest(String),
+}
+
+struct Route {
+    method: Method,
+    pattern: String,
+    segments: Vec<Segment>,
+    handler: Handler,
+    /// Whether the route is answered without the middleware.
+    bare: bool,
+}
+
+impl Route {
+    /// Match the decoded segments of a path against the route, returning the
+    /// parameters it captures.
+    fn matches(&self, parts: &[String]) -> Option<Params> {
+        let mut parts = parts.iter();
+        let mut params = Params::default();
+
+        for segment in &self.segments {
+            match segment {
+                Segment::Literal(literal) => {
+                    if parts.next()? != literal {
+                        return None;
+                    }
+                }
+                Segment::Param(name) => {
+                    let part = parts.next().filter(|part| !part.is_empty())?;
+                    params.values.push((name.clone(), part.clone()));
+                }
+                Segment::Rest(name) => {
+                    let rest: Vec<_> = parts.by_ref().map(String::as_str).collect();
+                    // An encoded `/` could not be told apart from a real one once the
+                    // segments are joined.
+                    if rest.iter().any(|part| part.contains('/')) {
+                        return None;
+                    }
+                    params.values.push((name.clone(), rest.join("/")));
+                }
+            }
+        }
+
+        match parts.next() {
+            None => Some(params),
+            // A trailing slash only matches routes that end in one.
+            Some(_) => None,
+        }
+    }
+}
+
+/// Picks the handler for a request by its method and path.
+///
+/// Paths are made of `/`-separated segments. A segment `:name` matches any single
+/// segment and a final `*name` matches the rest of the path, and both make what they
+/// matched available from `Request::param`. Each segment is percent-decoded before
+/// it is matched, and a `*name` does not match segments that decode to contain a `/`.
+/// Routes are tried in the order they were added. A request whose path matches a
+/// route but not its method is answered with `405 Method Not Allowed`, and one that
+/// matches no route with the not-found handler.
+/// ```
+/// use ch20_web_server::{
+///     request::Method,
+///     response::{Response, Status},
+///     router::Router,
+/// };
+///
+/// let router = Router::new()
+///     .get("/users/:id", |request| {
+///         let id = request.param("id").unwrap();
+///         Response::new(Status::Ok).body(format!("user {id}"))
+///     })
+///     .route(Method::Post, "/upload", |_| Response::new(Status::Created));
+/// ```
+pub struct Router {
+    routes: Vec<Route>,
+    not_found: Handler,
+    /// Innermost first.
+    middleware: Vec<Middleware>,
+}
+
+/// What a `Router` found to answer a request with.
+enum Endpoint<'a> {
+    Route(&'a Handler),
+    Bare(&'a Handler),
+    BadRequest,
+    MethodNotAllowed(String),
+    NotFound,
+}
+
+impl Default for Router {
+    fn default() -> Router {
+        Router::new()
+    }
+}
+
+impl Router {
+    /// Create a router without routes, which answers everything with `404 Not Found`.
+    pub fn new() -> Router {
+        Router {
+            routes: Vec::new(),
+            not_found: Box::new(|_| Response::error(Status::NotFound)),
+            middleware: Vec::new(),
+        }
+    }
+
+    /// Add a route answering `method` requests for paths matching `pattern`.
+    ///
+    /// Panics if `pattern` does not start with `/` or has a `*name` segment that is
+    /// not the last one.
+    pub fn route<F>(self, method: Method, pattern: &str, handler: F) -> Router
+    where
+        F: Fn(&Request) -> Response + Send + Sync + 'static,
+    {
+        self.add(method, pattern, Box::new(handler), false)
+    }
+
+    /// Add a route like `route`, but answer it without running the middleware, for
+    /// endpoints such as health checks that have to stay cheap whatever the
+    /// middleware does.
+    pub fn bare_route<F>(self, method: Method, pattern: &str, handler: F) -> Router
+    where
+        F: Fn(&Request) -> Response + Send + Sync + 'static,
+    {
+        self.add(method, pattern, Box::new(handler), true)
+    }
+
+    fn add(mut self, method: Method, pattern: &str, handler: Handler, bare: bool) -> Router {
+        let segments = parse_pattern(pattern);
+
+        self.routes.push(Route {
+            method,
+            pattern: pattern.to_owned(),
+            segments,
+            handler,
+            bare,
+        });
+        self
+    }
+
+    /// Add a route for `GET` requests.
+    pub fn get<F>(self, pattern: &str, handler: F) -> Router
+    where
+        F: Fn(&Request) -> Response + Send + Sync + 'static,
+    {
+        self.route(Method::Get, pattern, handler)
+    }
+
+    /// Add a route for `POST` requests.
+    pub fn post<F>(self, pattern: &str, handler: F) -> Router
+    where
+        F: Fn(&Request) -> Response + Send + Sync + 'static,
+    {
+        self.route(Method::Post, pattern, handler)
+    }
+
+    /// Answer requests that match no route with `handler` instead of a plain `404`.
+    pub fn not_found<F>(mut self, handler: F) -> Router
+    where
+        F: Fn(&Request) -> Response + Send + Sync + 'static,
+    {
+        self.not_found = Box::new(handler);
+        self
+    }
+
+    /// Run `middleware` around every request the router answers, including those
+    /// answered with an error because no route matched.
+    ///
+    /// The middleware is given the request and a `next` function that produces the
+    /// response, which it can then inspect or change, or it can answer the request
+    /// itself without calling `next`. Each call adds a layer outside the existing
+    /// ones, so the most recently added middleware runs first.
+    /// ```
+    /// use std::time::Instant;
+    /// use ch20_web_server::router::Router;
+    ///
+    /// let router = Router::new().wrap(|request, next| {
+    ///     let started = Instant::now();
+    ///     let response = next(request);
+    ///     println!("{} took {:?}", request.path(), started.elapsed());
+    ///     response
+    /// });
+    /// ```
+    pub fn wrap<F>(mut self, middleware: F) -> Router
+    where
+        F: Fn(&Request, &dyn Fn(&Request) -> Response) -> Response + Send + Sync + 'static,
+    {
+        self.middleware.push(Box::new(middleware));
+        self
+    }
+
+    /// Answer `request` with the first matching route.
+    ///
+    /// The request is taken by value so the route's parameters can be stored in it.
+    ///
+    /// Paths with malformed percent escapes are answered with `400 Bad Request`.
+    pub fn dispatch(&self, mut request: Request) -> Response {
+        let endpoint = self.find(&mut request);
+        if let Endpoint::Bare(handler) = endpoint {
+            return handler(&request);
+        }
+        let answer = |request: &Request| match &endpoint {
+            Endpoint::Route(handler) | Endpoint::Bare(handler) => handler(request),
+            Endpoint::BadRequest => Response::error(Status::BadRequest),
+            Endpoint::MethodNotAllowed(allowed) => {
+                Response::error(Status::MethodNotAllowed).header("Allow", allowed)
+            }
+            Endpoint::NotFound => (self.not_found)(request),
+        };
+
+        Router::run_from(&self.middleware, &request, &answer)
+    }
+
+    /// Find the route for `request`, storing the parameters it captures in it. A
+    /// `HEAD` request with no route of its own gets the `GET` route, as HTTP requires
+    /// of every resource that can be got; the connection leaves out the body.
+    fn find(&self, request: &mut Request) -> Endpoint<'_> {
+        let Some(parts) = decode_path(request.path()) else {
+            return Endpoint::BadRequest;
+        };
+        let mut allowed = Vec::new();
+        let mut get = None;
+
+        for route in &self.routes {
+            let Some(params) = route.matches(&parts) else {
+                continue;
+            };
+            if route.method == request.method() {
+                return Router::found(request, route, params);
+            }
+            if route.method == Method::Get && request.method() == Method::Head && get.is_none() {
+                get = Some((route, params));
+            }
+            let methods: &[Method] = if route.method == Method::Get {
+                &[Method::Get, Method::Head]
+            } else {
+                &[route.method]
+            };
+            for method in methods {
+                if !allowed.contains(&method.as_str()) {
+                    allowed.push(method.as_str());
+                }
+            }
+        }
+
+        if let Some((route, params)) = get {
+            return Router::found(request, route, params);
+        }
+        if allowed.is_empty() {
+            return Endpoint::NotFound;
+        }
+        Endpoint::MethodNotAllowed(allowed.join(", "))
+    }
+
+    fn found<'a>(request: &mut Request, route: &'a Route, params: Params) -> Endpoint<'a> {
+        request.set_params(params);
+        request.set_route(&route.pattern);
+        if route.bare {
+            Endpoint::Bare(&route.handler)
+        } else {
+            Endpoint::Route(&route.handler)
+        }
+    }
+
+    fn run_from(
+        layers: &[Middleware],
+        request: &Request,
+        endpoint: &dyn Fn(&Request) -> Response,
+    ) -> Response {
+        match layers.split_last() {
+            Some((outer, inner)) => outer(request, &|request| {
+                Router::run_from(inner, request, endpoint)
+            }),
+            None => endpoint(request),
+        }
+    }
+}
+
+fn parse_pattern(pattern: &str) -> Vec<Segment> {
+    let Some(path) = pattern.strip_prefix('/') else {
+        panic!("route pattern {pattern:?} must start with '/'");
+    };
+    if path.is_empty() {
+        return Vec::new();
+    }
+
+    let parts: Vec<_> = path.split('/').collect();
+    parts
+        .iter()
+        .enumerate()
+        .map(|(i, part)| {
+            if let Some(name) = part.strip_prefix(':') {
+                Segment::Param(name.to_owned())
+            } else if let Some(name) = part.strip_prefix('*') {
+                assert!(
+                    i == parts.len() - 1,
+                    "'*{name}' must be the last segment of {pattern:?}"
+                );
+                Segment::Rest(name.to_owned())
+            } else {
+                Segment::Literal((*part).to_owned())
+            }
+        })
+        .collect()
+}
+
+/// Split a path into its percent-decoded segments, or `None` if it does not start
+/// with `/` or has a malformed escape.
+pub(crate) fn decode_path(path: &str) -> Option<Vec<String>> {
+    let path = path.strip_prefix('/')?;
+
+    // `/` itself has no segments rather than one empty one.
+    if path.is_empty() {
+        return Some(Vec::new());
+    }
+    path.split('/').map(percent_decode).collect()
+}
+
+/// Decode `%XX` escapes in a path segment. Returns `None` for malformed escapes and
+/// for escapes that do not decode to UTF-8.
+pub(crate) fn percent_decode(s: &str) -> Option<String> {
+    let bytes = s.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = s.get(i + 1..i + 3)?;
+            decoded.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(decoded).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(method: &str, target: &str) -> Request {
+        let raw = format!("{method} {target} HTTP/1.1\r\n\r\n");
+        Request::read_from(&mut raw.as_bytes()).unwrap()
+    }
+
+    fn body(response: &Response) -> &str {
+        std::str::from_utf8(response.body_bytes()).unwrap()
+    }
+
+    fn router() -> Router {
+        Router::new()
+            .get("/", |_| Response::new(Status::Ok).body("index"))
+            .get("/users/:id", |request| {
+                let id = request.param("id").unwrap();
+                Response::new(Status::Ok).body(format!("user {id}"))
+            })
+            .get("/files/*path", |request| {
+                let path = request.param("path").unwrap();
+                Response::new(Status::Ok).body(format!("file {path}"))
+            })
+            .post("/upload", |_| Response::new(Status::Created))
+    }
+
+    #[test]
+    fn routes_by_path_and_extracts_params() {
+        let router = router();
+
+        assert_eq!(body(&router.dispatch(request("GET", "/"))), "index");
+        assert_eq!(
+            body(&router.dispatch(request("GET", "/users/42?full=1"))),
+            "user 42"
+        );
+        assert_eq!(
+            body(&router.dispatch(request("GET", "/users/ada%20l"))),
+            "user ada l"
+        );
+        assert_eq!(
+            body(&router.dispatch(request("GET", "/files/css/site.css"))),
+            "file css/site.css"
+        );
+        assert_eq!(
+            body(&router.dispatch(request("GET", "/%75sers/a%2Fb"))),
+            "user a/b"
+        );
+        assert_eq!(
+            router.dispatch(request("POST", "/upload")).status(),
+            Status::Created
+        );
+    }
+
+    #[test]
+    fn unmatched_requests_get_an_error() {
+        let router = router();
+
+        for target in [
+            "/users",
+            "/users/",
+            "/users/42/posts",
+            "/nope",
+            "/files/..%2F..%2Fetc%2Fpasswd",
+        ] {
+            let response = router.dispatch(request("GET", target));
+            assert_eq!(response.status(), Status::NotFound, "{target}");
+        }
+
+        let response = router.dispatch(request("GET", "/users/%zz"));
+        assert_eq!(response.status(), Status::BadRequest);
+
+        let response = router.dispatch(request("DELETE", "/upload"));
+        assert_eq!(response.status(), Status::MethodNotAllowed);
+        assert_eq!(response.headers().get("Allow"), Some("POST"));
+    }
+
+    #[test]
+    fn head_requests_get_the_get_route() {
+        let router = router().bare_route(Method::Get, "/healthz", |_| {
+            Response::new(Status::Ok).body("ok")
+        });
+
+        assert_eq!(
+            body(&router.dispatch(request("HEAD", "/users/7"))),
+            "user 7"
+        );
+        assert_eq!(body(&router.dispatch(request("HEAD", "/healthz"))), "ok");
+        let response = router.dispatch(request("PUT", "/"));
+        assert_eq!(response.headers().get("Allow"), Some("GET, HEAD"));
+
+        // A route of its own comes first, wherever it was added.
+        let router = router.route(Method::Head, "/", |_| Response::new(Status::NoContent));
+        assert_eq!(
+            router.dispatch(request("HEAD", "/")).status(),
+            Status::NoContent
+        );
+    }
+
+    #[test]
+    fn allow_names_each_method_once() {
+        let router = Router::new()
+            .get("/a", |_| Response::new(Status::Ok))
+            .post("/a", |_| Response::new(Status::Ok))
+            .get("/a", |_| Response::new(Status::Ok));
+
+        let response = router.dispatch(request("DELETE", "/a"));
+        assert_eq!(response.headers().get("Allow"), Some("GET, HEAD, POST"));
+    }
+
+    #[test]
+    fn not_found_handler_can_be_replaced() {
+        let router = Router::new().not_found(|_| Response::new(Status::NotFound).body("gone"));
+
+        assert_eq!(body(&router.dispatch(request("GET", "/x"))), "gone");
+    }
+
+    #[test]
+    fn middleware_runs_outermost_last_added() {
+        let router = router()
+            .wrap(|request, next| {
+                let response = next(request);
+                let body = format!("inner({})", body(&response));
+                response.body(body)
+            })
+            .wrap(|request, next| {
+                if request.path() == "/blocked" {
+                    return Response::new(Status::Forbidden).body("blocked");
+                }
+                let response = next(request);
+                let body = format!("outer({})", body(&response));
+                response.body(body)
+            });
+
+        assert_eq!(
+            body(&router.dispatch(request("GET", "/users/7"))),
+            "outer(inner(user 7))"
+        );
+        let response = router.dispatch(request("PUT", "/"));
+        assert_eq!(response.status(), Status::MethodNotAllowed);
+        assert_eq!(body(&response), "outer(inner(405 Method Not Allowed))");
+        assert_eq!(
+            body(&router.dispatch(request("GET", "/blocked"))),
+            "blocked"
+        );
+    }
+
+    #[test]
+    fn bare_routes_skip_the_middleware() {
+        let router = router()
+            .bare_route(Method::Get, "/healthz", |_| {
+                Response::new(Status::Ok).body("ok")
+            })
+            .wrap(|_, _| Response::new(Status::Forbidden));
+
+        assert_eq!(body(&router.dispatch(request("GET", "/healthz"))), "ok");
+        let response = router.dispatch(request("GET", "/"));
+        assert_eq!(response.status(), Status::Forbidden);
+    }
+
+    #[test]
+    #[should_panic(expected = "must be the last segment")]
+    fn rest_segment_must_be_last() {
+        Router::new().get("/*path/more", |_| Response::new(Status::Ok));
+    }
+}
@@ -0,0 +1,188 @@
+//! Headers that ask browsers to hold pages to stricter rules, such as not to guess
+//! their types or show them in frames on other sites, added to every response.
+//!
+//! By default responses get `X-Content-Type-Options: nosniff`,
+//! `X-Frame-Options: SAMEORIGIN` and `Referrer-Policy: strict-origin-when-cross-origin`.
+//! `Strict-Transport-Security` and `Content-Security-Policy` depend too much on the
+//! site to have defaults, and are only sent once set. The paths under a prefix can
+//! set or leave out headers of their own, and a header a handler sets is kept.
+//! ```
+//! use ch20_web_server::{router::Router, security_headers::{self, SecurityHeaders}};
+//!
+//! let headers = SecurityHeaders::default()
+//!     .set("Content-Security-Policy", "default-src 'self'")
+//!     .route("/embed", [("X-Frame-Options", None)]);
+//! assert_eq!(
+//!     headers.for_path("/embed/player").get("X-Frame-Options"),
+//!     None
+//! );
+//! let router = Router::new().wrap(security_headers::add(headers));
+//! ```
+
+use crate::{headers::Headers, request::Request, response::Response, router};
+
+/// The headers to add to responses, for every path and for those under prefixes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SecurityHeaders {
+    headers: Headers,
+    /// Each prefix's segments and changes, shortest first.
+    routes: Vec<(Vec<String>, Changes)>,
+}
+
+/// The headers a prefix sets, or leaves out with `None`.
+type Changes = Vec<(String, Option<String>)>;
+
+impl Default for SecurityHeaders {
+    fn default() -> SecurityHeaders {
+        SecurityHeaders::none()
+            .set("X-Content-Type-Options", "nosniff")
+            .set("X-Frame-Options", "SAMEORIGIN")
+            .set("Referrer-Policy", "strict-origin-when-cross-origin")
+    }
+}
+
+impl SecurityHeaders {
+    /// No headers, to set only the ones wanted.
+    pub fn none() -> SecurityHeaders {
+        SecurityHeaders {
+            headers: Headers::new(),
+            routes: Vec::new(),
+        }
+    }
+
+    /// Send `name` with `value` on every path, in place of any earlier value.
+    pub fn set(mut self, name: &str, value: &str) -> SecurityHeaders {
+        self.headers.set(name, value);
+        self
+    }
+
+    /// Send no `name`, unless a prefix sets it.
+    pub fn remove(mut self, name: &str) -> SecurityHeaders {
+        self.headers.remove(name);
+        self
+    }
+
+    /// For `prefix` and the paths under it, send each header with its value, or
+    /// leave it out if that is `None`. Where prefixes are under one another, the
+    /// longer one has the last word.
+    pub fn route<'a>(
+        mut self,
+        prefix: &str,
+        headers: impl IntoIterator<Item = (&'a str, Option<&'a str>)>,
+    ) -> SecurityHeaders {
+        let segments = router::decode_path(prefix.trim_end_matches('/')).unwrap_or_default();
+        let headers = headers
+            .into_iter()
+            .map(|(name, value)| (name.to_owned(), value.map(str::to_owned)));
+        match self
+            .routes
+            .iter_mut()
+            .find(|(existing, _)| *existing == segments)
+        {
+            Some((_, existing)) => existing.extend(headers),
+            None => {
+                self.routes.push((segments, headers.collect()));
+                self.routes.sort_by_key(|(segments, _)| segments.len());
+            }
+        }
+        self
+    }
+
+    /// The headers for the responses to requests for `path`.
+    pub fn for_path(&self, path: &str) -> Headers {
+        let mut headers = self.headers.clone();
+        // A path that cannot be decoded gets only the headers for every path.
+        let segments = router::decode_path(path).unwrap_or_default();
+        let routes = self
+            .routes
+            .iter()
+            .filter(|(prefix, _)| segments.starts_with(prefix));
+        for (_, changes) in routes {
+            for (name, value) in changes {
+                match value {
+                    Some(value) => headers.set(name.as_str(), value.as_str()),
+                    None => headers.remove(name),
+                }
+            }
+        }
+        headers
+    }
+}
+
+/// Middleware that adds the headers of `headers` for each request's path to its
+/// response, but for those the response already has.
+pub fn add(
+    headers: SecurityHeaders,
+) -> impl Fn(&Request, &dyn Fn(&Request) -> Response) -> Response + Send + Sync + 'static {
+    move |request, next| {
+        let mut response = next(request);
+        for (name, value) in headers.for_path(request.path()).iter() {
+            if !response.headers().contains(name) {
+                response = response.header(name, value);
+            }
+        }
+        response
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{response::Status, router::Router};
+
+    #[test]
+    fn sets_and_leaves_out_headers_by_prefix() {
+        let headers = SecurityHeaders::default()
+            .set("Strict-Transport-Security", "max-age=31536000")
+            .remove("Referrer-Policy")
+            .route("/embed/", [("X-Frame-Options", None)])
+            .route(
+                "/embed/admin",
+                [
+                    ("X-Frame-Options", Some("DENY")),
+                    ("Content-Security-Policy", Some("default-src 'none'")),
+                ],
+            );
+
+        let root = headers.for_path("/");
+        assert_eq!(root.get("X-Content-Type-Options"), Some("nosniff"));
+        assert_eq!(root.get("X-Frame-Options"), Some("SAMEORIGIN"));
+        assert_eq!(
+            root.get("Strict-Transport-Security"),
+            Some("max-age=31536000")
+        );
+        assert_eq!(root.get("Referrer-Policy"), None);
+
+        assert_eq!(
+            headers.for_path("/%65mbed/video").get("X-Frame-Options"),
+            None
+        );
+        assert_eq!(
+            headers.for_path("/embedded").get("X-Frame-Options"),
+            Some("SAMEORIGIN")
+        );
+        let admin = headers.for_path("/embed/admin/users");
+        assert_eq!(admin.get("X-Frame-Options"), Some("DENY"));
+        assert_eq!(
+            admin.get("Content-Security-Policy"),
+            Some("default-src 'none'")
+        );
+        assert_eq!(admin.get("X-Content-Type-Options"), Some("nosniff"));
+    }
+
+    #[test]
+    fn keeps_headers_the_handler_set() {
+        let router = Router::new()
+            .get("/", |_| {
+                Response::new(Status::Ok).header("X-Frame-Options", "DENY")
+            })
+            .wrap(add(SecurityHeaders::default()));
+        let request = Request::read_from(&mut &b"GET / HTTP/1.1\r\n\r\n"[..]).unwrap();
+        let response = router.dispatch(request);
+        assert_eq!(response.headers().get("X-Frame-Options"), Some("DENY"));
+        assert_eq!(
+            response.headers().get("Referrer-Policy"),
+            Some("strict-origin-when-cross-origin")
+        );
+    }
+}
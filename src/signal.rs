@@ -0,0 +1,147 @@
+//! Waiting for Unix signals, such as the SIGHUP that asks a server to reload its
+//! configuration.
+//!
+//! The handler only writes the signal's number to a socket, which is all a signal
+//! handler can safely do, and `Signals::wait` reads it on an ordinary thread.
+
+use std::{
+    ffi::{c_int, c_void},
+    io::{self, Read},
+    os::unix::{io::AsRawFd, net::UnixStream},
+    sync::atomic::{AtomicI32, Ordering},
+};
+
+extern "C" {
+    /// Takes and returns a handler, as a function pointer or one of the `SIG_`
+    /// constants.
+    fn signal(signum: c_int, handler: usize) -> usize;
+    fn write(fd: c_int, buf: *const c_void, count: usize) -> isize;
+}
+
+/// The handler that gives a signal its default effect.
+const SIG_DFL: usize = 0;
+/// What `signal` returns if it fails.
+const SIG_ERR: usize = usize::MAX;
+
+/// The socket the handler writes to, or -1 before anything listens.
+static WRITE_FD: AtomicI32 = AtomicI32::new(-1);
+
+extern "C" fn handle(signum: c_int) {
+    let fd = WRITE_FD.load(Ordering::Relaxed);
+    if fd >= 0 {
+        let byte = signum as u8;
+        // SAFETY: `write` is async-signal-safe, and `byte` outlives the call. If the
+        // socket is full, the signal is dropped, which is fine since one is already
+        // waiting to be read.
+        unsafe { write(fd, &byte as *const u8 as *const c_void, 1) };
+    }
+}
+
+/// The signals that can be waited for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Signal {
+    /// SIGHUP, sent to daemons to reload their configuration.
+    Hangup,
+    /// SIGINT, sent by Ctrl-C.
+    Interrupt,
+    /// SIGTERM, sent to stop a process.
+    Terminate,
+    /// SIGUSR1, which has no meaning of its own for programs to give it.
+    User1,
+    /// SIGUSR2, likewise.
+    User2,
+}
+
+impl Signal {
+    fn number(self) -> c_int {
+        match self {
+            Signal::Hangup => 1,
+            Signal::Interrupt => 2,
+            Signal::Terminate => 15,
+            #[cfg(target_os = "linux")]
+            Signal::User1 => 10,
+            #[cfg(not(target_os = "linux"))]
+            Signal::User1 => 30,
+            #[cfg(target_os = "linux")]
+            Signal::User2 => 12,
+            #[cfg(not(target_os = "linux"))]
+            Signal::User2 => 31,
+        }
+    }
+}
+
+/// Receives the signals it listens for, instead of them having their default
+/// effect, which for all of these is to end the process.
+///
+/// Only one `Signals` may listen at a time in a process. Once it is dropped, the
+/// signals have their default effect again.
+pub struct Signals {
+    reader: UnixStream,
+    /// Kept open for the handler to write to.
+    _writer: UnixStream,
+    signals: Vec<Signal>,
+}
+
+impl Signals {
+    /// Start catching `signals`.
+    pub fn listen(signals: &[Signal]) -> io::Result<Signals> {
+        let (reader, writer) = UnixStream::pair()?;
+        writer.set_nonblocking(true)?;
+        WRITE_FD.store(writer.as_raw_fd(), Ordering::Relaxed);
+
+        for &caught in signals {
+            let handler = handle as extern "C" fn(c_int) as usize;
+            // SAFETY: `handle` only does what a signal handler may.
+            if unsafe { signal(caught.number(), handler) } == SIG_ERR {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        Ok(Signals {
+            reader,
+            _writer: writer,
+            signals: signals.to_vec(),
+        })
+    }
+
+    /// Block until one of the signals arrives.
+    pub fn wait(&mut self) -> io::Result<Signal> {
+        loop {
+            let mut number = [0];
+            self.reader.read_exact(&mut number)?;
+            if let Some(&caught) = self
+                .signals
+                .iter()
+                .find(|caught| caught.number() == number[0] as c_int)
+            {
+                return Ok(caught);
+            }
+        }
+    }
+}
+
+impl Drop for Signals {
+    fn drop(&mut self) {
+        for caught in &self.signals {
+            // SAFETY: Restoring the default handler is always sound.
+            unsafe { signal(caught.number(), SIG_DFL) };
+        }
+        WRITE_FD.store(-1, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    extern "C" {
+        fn raise(signum: c_int) -> c_int;
+    }
+
+    #[test]
+    fn receives_signals_instead_of_exiting() {
+        let mut signals = Signals::listen(&[Signal::Hangup]).unwrap();
+        // SAFETY: SIGHUP is caught, so raising it only runs the handler.
+        assert_eq!(unsafe { raise(Signal::Hangup.number()) }, 0);
+        assert_eq!(signals.wait().unwrap(), Signal::Hangup);
+    }
+}
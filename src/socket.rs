@@ -0,0 +1,169 @@
+//! Opening TCP listeners by hand, for the socket options std has no way to set
+//! before a socket binds.
+
+use std::{
+    ffi::{c_int, c_void},
+    io, mem,
+    net::{SocketAddr, TcpListener},
+    os::unix::io::{AsRawFd, FromRawFd, OwnedFd, RawFd},
+};
+
+extern "C" {
+    fn socket(domain: c_int, kind: c_int, protocol: c_int) -> c_int;
+    fn setsockopt(fd: c_int, level: c_int, name: c_int, value: *const c_void, length: u32)
+        -> c_int;
+    #[cfg(test)]
+    fn getsockopt(
+        fd: c_int,
+        level: c_int,
+        name: c_int,
+        value: *mut c_void,
+        length: *mut u32,
+    ) -> c_int;
+    fn bind(fd: c_int, address: *const c_void, length: u32) -> c_int;
+    fn listen(fd: c_int, backlog: c_int) -> c_int;
+}
+
+const AF_INET: c_int = 2;
+const AF_INET6: c_int = 10;
+const SOCK_STREAM: c_int = 1;
+const SOCK_CLOEXEC: c_int = 0o2000000;
+
+pub(crate) const SOL_SOCKET: c_int = 1;
+pub(crate) const SO_REUSEADDR: c_int = 2;
+pub(crate) const SO_SNDBUF: c_int = 7;
+pub(crate) const SO_RCVBUF: c_int = 8;
+pub(crate) const SO_KEEPALIVE: c_int = 9;
+pub(crate) const SO_REUSEPORT: c_int = 15;
+pub(crate) const IPPROTO_TCP: c_int = 6;
+pub(crate) const TCP_KEEPIDLE: c_int = 4;
+pub(crate) const TCP_KEEPINTVL: c_int = 5;
+pub(crate) const IPPROTO_IPV6: c_int = 41;
+pub(crate) const IPV6_V6ONLY: c_int = 26;
+
+#[repr(C)]
+struct SockaddrIn {
+    family: u16,
+    /// In network byte order, as is the address.
+    port: u16,
+    address: [u8; 4],
+    zero: [u8; 8],
+}
+
+#[repr(C)]
+struct SockaddrIn6 {
+    family: u16,
+    port: u16,
+    flow_info: u32,
+    address: [u8; 16],
+    scope_id: u32,
+}
+
+/// Set an option whose value is an integer, or a boolean as 0 or 1.
+pub(crate) fn set_option(fd: RawFd, level: c_int, name: c_int, value: c_int) -> io::Result<()> {
+    // SAFETY: `value` outlives the call, and is as long as the length given.
+    let result = unsafe {
+        setsockopt(
+            fd,
+            level,
+            name,
+            &value as *const c_int as *const c_void,
+            mem::size_of::<c_int>() as u32,
+        )
+    };
+    if result < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Read an option whose value is an integer.
+#[cfg(test)]
+pub(crate) fn get_option(fd: RawFd, level: c_int, name: c_int) -> io::Result<c_int> {
+    let mut value: c_int = 0;
+    let mut length = mem::size_of::<c_int>() as u32;
+    // SAFETY: `value` and `length` outlive the call, and `length` is its size.
+    let result = unsafe {
+        getsockopt(
+            fd,
+            level,
+            name,
+            &mut value as *mut c_int as *mut c_void,
+            &mut length,
+        )
+    };
+    if result < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(value)
+}
+
+/// Open a socket, set `options` on it as `(level, name, value)`, then bind it to
+/// `address` and listen with room for `backlog` connections waiting to be accepted.
+pub(crate) fn bind_with(
+    address: SocketAddr,
+    options: &[(c_int, c_int, c_int)],
+    backlog: c_int,
+) -> io::Result<TcpListener> {
+    let domain = match address {
+        SocketAddr::V4(_) => AF_INET,
+        SocketAddr::V6(_) => AF_INET6,
+    };
+    // SAFETY: `socket` takes no pointers.
+    let fd = unsafe { socket(domain, SOCK_STREAM | SOCK_CLOEXEC, 0) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // SAFETY: The socket was just opened, and nothing else owns it.
+    let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+
+    // As std does, so a restarted server can bind while old connections linger.
+    set_option(fd.as_raw_fd(), SOL_SOCKET, SO_REUSEADDR, 1)?;
+    for &(level, name, value) in options {
+        set_option(fd.as_raw_fd(), level, name, value)?;
+    }
+
+    let bound = match address {
+        SocketAddr::V4(address) => {
+            let address = SockaddrIn {
+                family: AF_INET as u16,
+                port: address.port().to_be(),
+                address: address.ip().octets(),
+                zero: [0; 8],
+            };
+            // SAFETY: The address outlives the call, and is as long as the length.
+            unsafe {
+                bind(
+                    fd.as_raw_fd(),
+                    &address as *const SockaddrIn as *const c_void,
+                    mem::size_of::<SockaddrIn>() as u32,
+                )
+            }
+        }
+        SocketAddr::V6(address) => {
+            let address = SockaddrIn6 {
+                family: AF_INET6 as u16,
+                port: address.port().to_be(),
+                flow_info: address.flowinfo().to_be(),
+                address: address.ip().octets(),
+                scope_id: address.scope_id(),
+            };
+            // SAFETY: As above.
+            unsafe {
+                bind(
+                    fd.as_raw_fd(),
+                    &address as *const SockaddrIn6 as *const c_void,
+                    mem::size_of::<SockaddrIn6>() as u32,
+                )
+            }
+        }
+    };
+    if bound < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // SAFETY: `listen` takes no pointers.
+    if unsafe { listen(fd.as_raw_fd(), backlog) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(TcpListener::from(fd))
+}
@@ -0,0 +1,285 @@
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    io,
+    path::{Component, Path, PathBuf},
+};
+
+use crate::{
+    mime,
+    response::{Response, Status},
+};
+
+/// Serves the files under a document root.
+/// ```no_run
+/// use ch20_web_server::{router::Router, static_files::StaticFiles};
+///
+/// let files = StaticFiles::new("public").not_found_page("404.html");
+/// let router = Router::new().get("/*path", move |request| {
+///     files.serve(request.param("path").unwrap())
+/// });
+/// ```
+#[derive(Debug, Clone)]
+pub struct StaticFiles {
+    root: PathBuf,
+    index: String,
+    not_found_page: Option<String>,
+    /// Media types by lowercase extension, overriding the built-in ones.
+    mime_types: HashMap<String, String>,
+}
+
+impl StaticFiles {
+    /// Serve the files under `root`, answering requests for a directory with its
+    /// `index.html`.
+    pub fn new(root: impl Into<PathBuf>) -> StaticFiles {
+        StaticFiles {
+            root: root.into(),
+            index: String::from("index.html"),
+            not_found_page: None,
+            mime_types: HashMap::new(),
+        }
+    }
+
+    /// Answer requests for a directory with its file called `name` instead of
+    /// `index.html`.
+    pub fn index(mut self, name: impl Into<String>) -> StaticFiles {
+        self.index = name.into();
+        self
+    }
+
+    /// Answer requests for missing files with the file at `path` under the root,
+    /// instead of a plain `404 Not Found`.
+    pub fn not_found_page(mut self, path: impl Into<String>) -> StaticFiles {
+        self.not_found_page = Some(path.into());
+        self
+    }
+
+    /// Send files with the extension `extension` as `mime`, overriding the built-in
+    /// type for it. Files with no known type are sent as `application/octet-stream`.
+    /// ```
+    /// use ch20_web_server::static_files::StaticFiles;
+    ///
+    /// let files = StaticFiles::new("public")
+    ///     .mime_type("md", "text/plain; charset=utf-8")
+    ///     .mime_type("glb", "model/gltf-binary");
+    /// ```
+    pub fn mime_type(mut self, extension: &str, mime: impl Into<String>) -> StaticFiles {
+        self.mime_types
+            .insert(extension.to_ascii_lowercase(), mime.into());
+        self
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Answer a request for the file at `path`, relative to the root.
+    ///
+    /// The file is streamed into the response rather than read up front. Missing files
+    /// get `404 Not Found`, and files or directories without an index the server is
+    /// not allowed to read get `403 Forbidden`.
+    ///
+    /// `path` should already be percent-decoded, as router parameters are. Paths with
+    /// `..` segments, backslashes or NUL bytes get `400 Bad Request`, and files that
+    /// are only reachable through a symlink leading out of the root get
+    /// `403 Forbidden`.
+    pub fn serve(&self, path: &str) -> Response {
+        let mut file_path = match self.resolve(path) {
+            Ok(file_path) => file_path,
+            Err(status) => return Response::error(status),
+        };
+
+        match self.open(&mut file_path) {
+            Ok((file, length)) => Response::new(Status::Ok)
+                .header("Content-Type", self.content_type(&file_path))
+                .stream_body(file, length),
+            Err(error) => match error.kind() {
+                io::ErrorKind::NotFound | io::ErrorKind::NotADirectory => self.not_found(),
+                io::ErrorKind::PermissionDenied => Response::error(Status::Forbidden),
+                _ => Response::error(Status::InternalServerError),
+            },
+        }
+    }
+
+    /// Answer with the not-found page, or a plain `404 Not Found` if there is none or
+    /// it cannot be read.
+    pub fn not_found(&self) -> Response {
+        let page = self
+            .not_found_page
+            .as_ref()
+            .and_then(|page| self.resolve(page).ok())
+            .and_then(|path| Some((fs::read(&path).ok()?, path)));
+
+        match page {
+            Some((contents, path)) => Response::new(Status::NotFound)
+                .header("Content-Type", self.content_type(&path))
+                .body(contents),
+            None => Response::error(Status::NotFound),
+        }
+    }
+
+    fn content_type(&self, path: &Path) -> &str {
+        let overridden = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .and_then(|extension| self.mime_types.get(&extension.to_ascii_lowercase()));
+
+        match overridden {
+            Some(mime) => mime,
+            None => mime::from_path(path),
+        }
+    }
+
+    /// Map `path` to a file under the root, without touching the filesystem.
+    ///
+    /// Fails with `400 Bad Request` for paths that could name something outside the
+    /// root.
+    fn resolve(&self, path: &str) -> Result<PathBuf, Status> {
+        // A backslash is a separator on Windows, and a NUL would cut the path short.
+        if path.contains(['\\', '\0']) {
+            return Err(Status::BadRequest);
+        }
+        let mut resolved = self.root.clone();
+
+        for component in Path::new(path).components() {
+            match component {
+                Component::Normal(part) => resolved.push(part),
+                Component::CurDir | Component::RootDir => {}
+                Component::ParentDir | Component::Prefix(_) => return Err(Status::BadRequest),
+            }
+        }
+        Ok(resolved)
+    }
+
+    /// Open the regular file at `path`, or the index of the directory at `path`, and
+    /// return it with its length. `path` is updated to the file that was opened.
+    ///
+    /// Fails with `PermissionDenied` if the file is outside the root once symlinks are
+    /// followed, or if `path` is a directory without an index.
+    fn open(&self, path: &mut PathBuf) -> io::Result<(File, u64)> {
+        if fs::metadata(&*path)?.is_dir() {
+            path.push(&self.index);
+            if !path.exists() {
+                return Err(io::ErrorKind::PermissionDenied.into());
+            }
+        }
+        if !fs::canonicalize(&*path)?.starts_with(fs::canonicalize(&self.root)?) {
+            return Err(io::ErrorKind::PermissionDenied.into());
+        }
+
+        let file = File::open(&*path)?;
+        let metadata = file.metadata()?;
+        if !metadata.is_file() {
+            return Err(io::ErrorKind::NotFound.into());
+        }
+        Ok((file, metadata.len()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn body(response: Response) -> String {
+        let mut bytes = Vec::new();
+        response.write_to(&mut bytes).unwrap();
+        let bytes = String::from_utf8(bytes).unwrap();
+        bytes.split_once("\r\n\r\n").unwrap().1.to_owned()
+    }
+
+    fn root(name: &str) -> PathBuf {
+        let root = std::env::temp_dir().join(format!("static-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("docs/empty")).unwrap();
+        fs::write(root.join("index.html"), "home").unwrap();
+        fs::write(root.join("docs/guide.txt"), "guide").unwrap();
+        fs::write(root.join("docs/index.html"), "docs").unwrap();
+        fs::write(root.join("404.html"), "missing").unwrap();
+        root
+    }
+
+    #[test]
+    fn serves_files_and_directory_indexes() {
+        let root = root("serves");
+        let files = StaticFiles::new(&root);
+
+        assert_eq!(body(files.serve("docs/guide.txt")), "guide");
+        assert_eq!(body(files.serve("")), "home");
+        assert_eq!(body(files.serve("docs/")), "docs");
+        assert_eq!(body(files.serve("./docs/./guide.txt")), "guide");
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn sends_content_types() {
+        let root = root("types");
+        fs::write(root.join("data.bin"), "").unwrap();
+        fs::write(root.join("notes.MD"), "").unwrap();
+        let files = StaticFiles::new(&root).mime_type("md", "text/plain");
+
+        let content_type = |path| {
+            files
+                .serve(path)
+                .headers()
+                .get("Content-Type")
+                .map(String::from)
+        };
+        assert_eq!(
+            content_type("").as_deref(),
+            Some("text/html; charset=utf-8")
+        );
+        assert_eq!(
+            content_type("docs/guide.txt").as_deref(),
+            Some("text/plain; charset=utf-8")
+        );
+        assert_eq!(content_type("notes.MD").as_deref(), Some("text/plain"));
+        assert_eq!(content_type("data.bin").as_deref(), Some(mime::FALLBACK));
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn answers_missing_and_unlisted_paths() {
+        let root = root("missing");
+        let files = StaticFiles::new(&root);
+
+        assert_eq!(files.serve("nope.html").status(), Status::NotFound);
+        assert_eq!(files.serve("index.html/x").status(), Status::NotFound);
+        assert_eq!(files.serve("docs/empty").status(), Status::Forbidden);
+
+        let files = files.not_found_page("404.html");
+        let response = files.serve("nope.html");
+        assert_eq!(response.status(), Status::NotFound);
+        assert_eq!(body(response), "missing");
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn rejects_paths_leaving_the_root() {
+        let root = root("traversal");
+        let files = StaticFiles::new(root.join("docs"));
+
+        for path in [
+            "../index.html",
+            "a/../../index.html",
+            "..",
+            "guide.txt\0",
+            "..\\index.html",
+        ] {
+            assert_eq!(files.serve(path).status(), Status::BadRequest, "{path:?}");
+        }
+
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink(root.join("index.html"), root.join("docs/escape")).unwrap();
+            std::os::unix::fs::symlink(root.join("docs/guide.txt"), root.join("docs/inside"))
+                .unwrap();
+            assert_eq!(files.serve("escape").status(), Status::Forbidden);
+            assert_eq!(body(files.serve("inside")), "guide");
+        }
+
+        fs::remove_dir_all(root).unwrap();
+    }
+}
@@ -0,0 +1,104 @@
+//! Running as a systemd service: taking the sockets systemd listens on for a
+//! socket-activated unit, and telling it when the server is ready, reloading or
+//! stopping, for a unit of `Type=notify`.
+//!
+//! Both are skipped when the server was not started by systemd, so they can be
+//! used unconditionally.
+
+use std::{
+    env, io,
+    ops::Range,
+    os::unix::{
+        io::{FromRawFd, OwnedFd},
+        net::UnixDatagram,
+    },
+    process,
+};
+
+use crate::{handover::set_cloexec, listener::Listener};
+
+/// The descriptor systemd passes the first socket as.
+const LISTEN_FDS_START: i32 = 3;
+
+/// The descriptors passed by systemd, given the values of `LISTEN_PID` and
+/// `LISTEN_FDS`. They are only for this process if `LISTEN_PID` is its ID.
+fn passed(pid: Option<&str>, count: Option<&str>) -> io::Result<Range<i32>> {
+    let (Some(pid), Some(count)) = (pid, count) else {
+        return Ok(0..0);
+    };
+    if pid.parse() != Ok(process::id()) {
+        return Ok(0..0);
+    }
+    let count: i32 = count
+        .parse()
+        .map_err(|_| io::Error::other("LISTEN_FDS is not a number"))?;
+    Ok(LISTEN_FDS_START..LISTEN_FDS_START + count)
+}
+
+/// Take the sockets systemd passed to this process, TCP or Unix, in the order the
+/// unit's `.socket` file lists them. There are none if it did not pass any.
+pub fn listeners() -> io::Result<Vec<Listener>> {
+    let pid = env::var("LISTEN_PID").ok();
+    let count = env::var("LISTEN_FDS").ok();
+    let fds = passed(pid.as_deref(), count.as_deref())?;
+    // Processes this one starts should not take them too.
+    env::remove_var("LISTEN_PID");
+    env::remove_var("LISTEN_FDS");
+    env::remove_var("LISTEN_FDNAMES");
+
+    fds.map(|fd| {
+        set_cloexec(fd, true)?;
+        // SAFETY: systemd left the socket open for this process, which now owns it.
+        Ok(Listener::from(unsafe { OwnedFd::from_raw_fd(fd) }))
+    })
+    .collect()
+}
+
+/// Send systemd a state change, such as `READY=1`, if it asked to be told of them.
+pub fn notify(state: &str) -> io::Result<()> {
+    let Some(path) = env::var_os("NOTIFY_SOCKET") else {
+        return Ok(());
+    };
+    let socket = UnixDatagram::unbound()?;
+    let path = path.to_string_lossy();
+    match path.strip_prefix('@') {
+        #[cfg(target_os = "linux")]
+        Some(name) => {
+            use std::os::{linux::net::SocketAddrExt, unix::net::SocketAddr};
+            let address = SocketAddr::from_abstract_name(name)?;
+            socket.send_to_addr(state.as_bytes(), &address)?;
+        }
+        _ => {
+            socket.send_to(state.as_bytes(), &*path)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_takes_sockets_passed_to_this_process() {
+        let pid = process::id().to_string();
+        assert_eq!(passed(Some(&pid), Some("2")).unwrap(), 3..5);
+        assert_eq!(passed(Some("1"), Some("2")).unwrap(), 0..0);
+        assert_eq!(passed(None, None).unwrap(), 0..0);
+        assert!(passed(Some(&pid), Some("two")).is_err());
+    }
+
+    #[test]
+    fn notifies_the_socket_systemd_gave() {
+        let path = env::temp_dir().join(format!("notify-{}.sock", process::id()));
+        let _ = std::fs::remove_file(&path);
+        let systemd = UnixDatagram::bind(&path).unwrap();
+        env::set_var("NOTIFY_SOCKET", &path);
+
+        notify("READY=1").unwrap();
+        let mut state = [0; 16];
+        let read = systemd.recv(&mut state).unwrap();
+        assert_eq!(&state[..read], b"READY=1");
+        std::fs::remove_file(&path).unwrap();
+    }
+}
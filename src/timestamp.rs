@@ -0,0 +1,99 @@
+//! Writing points in time as logs lay them out, in UTC.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+/// A point in time broken down into its date and time of day.
+struct Civil {
+    year: u64,
+    month: u64,
+    day: u64,
+    hour: u64,
+    minute: u64,
+    second: u64,
+    millisecond: u32,
+}
+
+impl Civil {
+    fn of(time: SystemTime) -> Civil {
+        let since = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+        let seconds = since.as_secs();
+        let (year, month, day) = civil_date(seconds / 86400);
+        let seconds = seconds % 86400;
+        Civil {
+            year,
+            month,
+            day,
+            hour: seconds / 3600,
+            minute: seconds / 60 % 60,
+            second: seconds % 60,
+            millisecond: since.subsec_millis(),
+        }
+    }
+}
+
+/// `time` as the Common Log Format writes it, such as `10/Oct/2000:13:55:36 +0000`.
+pub fn common_log(time: SystemTime) -> String {
+    let t = Civil::of(time);
+    format!(
+        "{:02}/{}/{}:{:02}:{:02}:{:02} +0000",
+        t.day,
+        MONTHS[t.month as usize - 1],
+        t.year,
+        t.hour,
+        t.minute,
+        t.second
+    )
+}
+
+/// `time` as RFC 3339 writes it, to the millisecond, such as
+/// `2000-10-10T13:55:36.000Z`.
+pub fn rfc3339(time: SystemTime) -> String {
+    let t = Civil::of(time);
+    format!(
+        "{}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        t.year, t.month, t.day, t.hour, t.minute, t.second, t.millisecond
+    )
+}
+
+/// The year, month and day `days` after 1970-01-01, in the proleptic Gregorian
+/// calendar.
+fn civil_date(days: u64) -> (u64, u64, u64) {
+    // Counted from 0000-03-01, so leap days fall at the end of each year, in eras of
+    // 400 years that each have the same number of days.
+    let days = days + 719_468;
+    let era = days / 146_097;
+    let day_of_era = days % 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month + 2) / 5 + 1;
+    let month = if month < 10 { month + 3 } else { month - 9 };
+    let year = era * 400 + year_of_era + u64::from(month <= 2);
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn finds_the_date_of_a_day() {
+        assert_eq!(civil_date(0), (1970, 1, 1));
+        assert_eq!(civil_date(11_016), (2000, 2, 29));
+        assert_eq!(civil_date(11_017), (2000, 3, 1));
+        assert_eq!(civil_date(20_740), (2026, 10, 14));
+    }
+
+    #[test]
+    fn lays_out_times() {
+        let time = UNIX_EPOCH + Duration::from_millis(1_792_000_000_042);
+        assert_eq!(common_log(time), "14/Oct/2026:17:46:40 +0000");
+        assert_eq!(rfc3339(time), "2026-10-14T17:46:40.042Z");
+    }
+}
@@ -0,0 +1,354 @@
+//! The subset of TOML that configuration files are written in: tables, arrays of
+//! tables, and keys set to strings, integers, floats, booleans, or arrays of them on
+//! a single line.
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Value {
+    String(String),
+    Integer(i64),
+    Float(f64),
+    Boolean(bool),
+    Array(Vec<Value>),
+}
+
+impl Value {
+    /// What sort of value this is, as an error message would name it.
+    pub(crate) fn kind(&self) -> &'static str {
+        match self {
+            Value::String(_) => "a string",
+            Value::Integer(_) => "an integer",
+            Value::Float(_) => "a float",
+            Value::Boolean(_) => "a boolean",
+            Value::Array(_) => "an array",
+        }
+    }
+}
+
+/// A key set in a file, with the names of the tables it is in joined by dots. Each
+/// table in an array of tables is named by its index, so the key in the second
+/// `[[listen]]` table is `listen.1.key`.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Entry {
+    pub(crate) key: String,
+    /// The line the key is set on, from 1.
+    pub(crate) line: usize,
+    pub(crate) value: Value,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct SyntaxError {
+    pub(crate) line: usize,
+    pub(crate) message: &'static str,
+}
+
+/// Parse `text` into the keys it sets, in the order they appear.
+pub(crate) fn parse(text: &str) -> Result<Vec<Entry>, SyntaxError> {
+    let mut entries: Vec<Entry> = Vec::new();
+    let mut tables = Vec::new();
+    // The names of the arrays of tables, once for each table in them.
+    let mut arrays = Vec::new();
+    let mut table = String::new();
+
+    for (index, line) in text.lines().enumerate() {
+        let error = |message| SyntaxError {
+            line: index + 1,
+            message,
+        };
+        let mut rest = line.trim_start();
+        if rest.is_empty() || rest.starts_with('#') {
+            continue;
+        }
+
+        if let Some(header) = rest.strip_prefix("[[") {
+            let (name, after) = header
+                .split_once("]]")
+                .ok_or(error("unclosed table header"))?;
+            if !trailing(after) {
+                return Err(error("unexpected text after table header"));
+            }
+            let name = dotted_key(name).ok_or(error("invalid table name"))?;
+            if tables.contains(&name) {
+                return Err(error("table is defined twice"));
+            }
+            let index = arrays.iter().filter(|array| **array == name).count();
+            table = format!("{name}.{index}");
+            arrays.push(name);
+            continue;
+        }
+        if let Some(header) = rest.strip_prefix('[') {
+            let (name, after) = header
+                .split_once(']')
+                .ok_or(error("unclosed table header"))?;
+            if !trailing(after) {
+                return Err(error("unexpected text after table header"));
+            }
+            table = dotted_key(name).ok_or(error("invalid table name"))?;
+            if arrays.contains(&table) {
+                return Err(error("table is defined twice"));
+            }
+            if tables.contains(&table) {
+                return Err(error("table is defined twice"));
+            }
+            tables.push(table.clone());
+            continue;
+        }
+
+        let (key, value) = rest
+            .split_once('=')
+            .ok_or(error("expected `key = value`"))?;
+        let key = dotted_key(key).ok_or(error("invalid key"))?;
+        let key = match &table[..] {
+            "" => key,
+            table => format!("{table}.{key}"),
+        };
+        if entries.iter().any(|entry| entry.key == key) {
+            return Err(error("key is set twice"));
+        }
+
+        rest = value;
+        let value = parse_value(&mut rest).map_err(error)?;
+        if !trailing(rest) {
+            return Err(error("unexpected text after value"));
+        }
+        entries.push(Entry {
+            key,
+            line: index + 1,
+            value,
+        });
+    }
+    Ok(entries)
+}
+
+/// Whether `rest` of a line holds nothing but whitespace and perhaps a comment.
+fn trailing(rest: &str) -> bool {
+    let rest = rest.trim_start();
+    rest.is_empty() || rest.starts_with('#')
+}
+
+/// Normalise a key of bare names joined by dots, such as `log.level`.
+fn dotted_key(key: &str) -> Option<String> {
+    let parts: Vec<_> = key.split('.').map(str::trim).collect();
+    let bare = |part: &&str| {
+        !part.is_empty()
+            && part
+                .bytes()
+                .all(|byte| byte.is_ascii_alphanumeric() || byte == b'_' || byte == b'-')
+    };
+    parts.iter().all(bare).then(|| parts.join("."))
+}
+
+/// Parse the value at the start of `rest`, leaving `rest` just past it.
+fn parse_value(rest: &mut &str) -> Result<Value, &'static str> {
+    *rest = rest.trim_start();
+
+    if let Some(after) = rest.strip_prefix('"') {
+        *rest = after;
+        return basic_string(rest).map(Value::String);
+    }
+    if let Some(after) = rest.strip_prefix('\'') {
+        let (string, after) = after.split_once('\'').ok_or("unclosed string")?;
+        *rest = after;
+        return Ok(Value::String(string.to_owned()));
+    }
+    if let Some(after) = rest.strip_prefix('[') {
+        *rest = after;
+        return array(rest).map(Value::Array);
+    }
+
+    let end = rest
+        .find(|c: char| !(c.is_ascii_alphanumeric() || "+-._".contains(c)))
+        .unwrap_or(rest.len());
+    let (word, after) = rest.split_at(end);
+    *rest = after;
+
+    match word {
+        "true" => Ok(Value::Boolean(true)),
+        "false" => Ok(Value::Boolean(false)),
+        "" => Err("expected a value"),
+        word => number(word),
+    }
+}
+
+/// Parse a string after its opening quote, with its escapes.
+fn basic_string(rest: &mut &str) -> Result<String, &'static str> {
+    let mut string = String::new();
+    let mut chars = rest.char_indices();
+
+    while let Some((index, c)) = chars.next() {
+        match c {
+            '"' => {
+                *rest = &rest[index + 1..];
+                return Ok(string);
+            }
+            '\\' => {
+                let escaped = match chars.next().ok_or("unclosed string")?.1 {
+                    'b' => '\u{8}',
+                    't' => '\t',
+                    'n' => '\n',
+                    'f' => '\u{c}',
+                    'r' => '\r',
+                    '"' => '"',
+                    '\\' => '\\',
+                    escape @ ('u' | 'U') => {
+                        let digits = if escape == 'u' { 4 } else { 8 };
+                        let hex: String = chars.by_ref().take(digits).map(|(_, c)| c).collect();
+                        u32::from_str_radix(&hex, 16)
+                            .ok()
+                            .filter(|_| hex.len() == digits)
+                            .and_then(char::from_u32)
+                            .ok_or("invalid unicode escape")?
+                    }
+                    _ => return Err("invalid escape"),
+                };
+                string.push(escaped);
+            }
+            c => string.push(c),
+        }
+    }
+    Err("unclosed string")
+}
+
+/// Parse an array after its opening bracket.
+fn array(rest: &mut &str) -> Result<Vec<Value>, &'static str> {
+    let mut values = Vec::new();
+
+    loop {
+        *rest = rest.trim_start();
+        if let Some(after) = rest.strip_prefix(']') {
+            *rest = after;
+            return Ok(values);
+        }
+        if rest.is_empty() || rest.starts_with('#') {
+            return Err("unclosed array");
+        }
+        values.push(parse_value(rest)?);
+
+        *rest = rest.trim_start();
+        match rest.strip_prefix(',') {
+            Some(after) => *rest = after,
+            None if rest.starts_with(']') => {}
+            None => return Err("expected `,` or `]` in array"),
+        }
+    }
+}
+
+fn number(word: &str) -> Result<Value, &'static str> {
+    // Underscores may only separate digits.
+    let digits = word.trim_start_matches(['+', '-']);
+    if digits.starts_with('_') || digits.ends_with('_') || digits.contains("__") {
+        return Err("invalid number");
+    }
+    let word = word.replace('_', "");
+
+    // Integers may also be written in hex, octal or binary, such as `0o755`.
+    for (prefix, radix) in [("0x", 16), ("0o", 8), ("0b", 2)] {
+        if let Some(digits) = word.strip_prefix(prefix) {
+            return i64::from_str_radix(digits, radix)
+                .ok()
+                .filter(|_| !digits.starts_with(['+', '-']))
+                .map(Value::Integer)
+                .ok_or("invalid number");
+        }
+    }
+    if let Ok(integer) = word.parse() {
+        return Ok(Value::Integer(integer));
+    }
+    match word.parse() {
+        Ok(float) if word.bytes().any(|byte| byte.is_ascii_digit()) => Ok(Value::Float(float)),
+        _ => Err("unsupported value"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn values(text: &str) -> Vec<(String, Value)> {
+        parse(text)
+            .unwrap()
+            .into_iter()
+            .map(|entry| (entry.key, entry.value))
+            .collect()
+    }
+
+    #[test]
+    fn parses_keys_in_tables() {
+        let text = "\
+# The server.
+port = 8080 # A comment.
+name = \"web\"
+
+[log]
+level = 'info'
+colours.enabled = false
+";
+        assert_eq!(
+            values(text),
+            [
+                (String::from("port"), Value::Integer(8080)),
+                (String::from("name"), Value::String(String::from("web"))),
+                (
+                    String::from("log.level"),
+                    Value::String(String::from("info"))
+                ),
+                (String::from("log.colours.enabled"), Value::Boolean(false)),
+            ]
+        );
+        assert_eq!(parse(text).unwrap()[2].line, 6);
+    }
+
+    #[test]
+    fn numbers_the_tables_in_an_array() {
+        assert_eq!(
+            values("[[listen]]\nport = 80\n[[listen]]\nport = 8080\n[log]\nlevel = 'info'"),
+            [
+                (String::from("listen.0.port"), Value::Integer(80)),
+                (String::from("listen.1.port"), Value::Integer(8080)),
+                (
+                    String::from("log.level"),
+                    Value::String(String::from("info"))
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn parses_values() {
+        assert_eq!(
+            values(r#"a = "tab\t\"quoted\" é # not a comment""#)[0].1,
+            Value::String(String::from("tab\t\"quoted\" é # not a comment"))
+        );
+        assert_eq!(values("a = 1_000")[0].1, Value::Integer(1000));
+        assert_eq!(values("a = 0o755")[0].1, Value::Integer(0o755));
+        assert_eq!(values("a = 0xff")[0].1, Value::Integer(255));
+        assert_eq!(values("a = -2.5e1")[0].1, Value::Float(-25.0));
+        assert_eq!(
+            values("a = [ 1, 'two', [true], ]")[0].1,
+            Value::Array(vec![
+                Value::Integer(1),
+                Value::String(String::from("two")),
+                Value::Array(vec![Value::Boolean(true)]),
+            ])
+        );
+    }
+
+    #[test]
+    fn reports_the_line_of_syntax_errors() {
+        let error = |text| parse(text).unwrap_err();
+        assert_eq!(
+            error("a = 1\nb = \"open\n"),
+            SyntaxError {
+                line: 2,
+                message: "unclosed string",
+            }
+        );
+        assert_eq!(error("a = 1\na = 2").message, "key is set twice");
+        assert_eq!(error("[t]\n[t]").message, "table is defined twice");
+        assert_eq!(error("[t]\n[[t]]").message, "table is defined twice");
+        assert_eq!(error("a = 1 2").message, "unexpected text after value");
+        assert_eq!(error("a = 1979-05-27").message, "unsupported value");
+        assert_eq!(error("a = 1__0").message, "invalid number");
+        assert_eq!(error("a = 0o8").message, "invalid number");
+        assert_eq!(error("just words").message, "expected `key = value`");
+    }
+}
@@ -0,0 +1,368 @@
+//! Spans: named stretches of work, such as a connection or a request, with fields
+//! that describe them. A span opened while another is open on the same thread is
+//! nested in it, and `bind` carries the open span over to a job that runs on
+//! another thread, such as a threadpool worker.
+//!
+//! Finished spans are given to the exporter set with `set_exporter`, and nothing is
+//! recorded while there is none. `SpanLog` exports them as lines of JSON that a
+//! collector can be fed from.
+//! ```
+//! use std::sync::{Arc, Mutex};
+//! use ch20_web_server::trace::{self, Record};
+//!
+//! let finished = Arc::new(Mutex::new(Vec::new()));
+//! let sink = Arc::clone(&finished);
+//! trace::set_exporter(Some(Arc::new(move |record: &Record| {
+//!     sink.lock().unwrap().push((record.name, record.parent.is_some()));
+//! })));
+//!
+//! {
+//!     let request = trace::span("request");
+//!     request.record("method", "GET");
+//!     let _query = trace::span("query");
+//! }
+//! trace::set_exporter(None);
+//! assert_eq!(*finished.lock().unwrap(), [("query", true), ("request", false)]);
+//! ```
+
+use std::{
+    cell::RefCell,
+    fmt::Display,
+    io::{self, Write},
+    marker::PhantomData,
+    path::Path,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex, RwLock,
+    },
+    thread,
+    time::{Duration, Instant, SystemTime},
+};
+
+use crate::{
+    json::Object,
+    log_file::{LogFile, Output, Rotation},
+    timestamp,
+};
+
+/// Where a span is in its trace, to nest other spans in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Context {
+    /// The id of the outermost span.
+    pub trace: u64,
+    pub span: u64,
+}
+
+/// A span that has finished.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Record {
+    /// The id of the outermost span it is nested in, or its own if there is none.
+    pub trace: u64,
+    /// Its id, which no other span in the process has.
+    pub id: u64,
+    /// The span it is nested in.
+    pub parent: Option<u64>,
+    pub name: &'static str,
+    /// The fields recorded on it, in the order they were.
+    pub fields: Vec<(&'static str, String)>,
+    /// The name of the thread it was opened on, such as `connection-3`.
+    pub worker: Option<String>,
+    pub started: SystemTime,
+    pub duration: Duration,
+}
+
+/// What finished spans are given to.
+pub type Exporter = Arc<dyn Fn(&Record) + Send + Sync>;
+
+static EXPORTER: RwLock<Option<Exporter>> = RwLock::new(None);
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+/// A span on the current thread's stack, or the context of one on another thread that
+/// the spans opened here are nested in.
+struct Frame {
+    context: Context,
+    open: Option<(Record, Instant)>,
+}
+
+thread_local! {
+    static FRAMES: RefCell<Vec<Frame>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Give every span that finishes from now on to `exporter`, or stop recording them if
+/// it is `None`.
+pub fn set_exporter(exporter: Option<Exporter>) {
+    *EXPORTER.write().unwrap() = exporter;
+}
+
+/// Open a span named `name`, nested in the current one, that is open until the
+/// returned guard is dropped.
+pub fn span(name: &'static str) -> Span {
+    if EXPORTER.read().unwrap().is_none() {
+        return Span {
+            depth: None,
+            _thread: PhantomData,
+        };
+    }
+
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    let parent = current();
+    let record = Record {
+        trace: parent.map_or(id, |parent| parent.trace),
+        id,
+        parent: parent.map(|parent| parent.span),
+        name,
+        fields: Vec::new(),
+        worker: thread::current().name().map(String::from),
+        started: SystemTime::now(),
+        duration: Duration::ZERO,
+    };
+    let depth = FRAMES.with_borrow_mut(|frames| {
+        frames.push(Frame {
+            context: Context {
+                trace: record.trace,
+                span: id,
+            },
+            open: Some((record, Instant::now())),
+        });
+        frames.len() - 1
+    });
+    Span {
+        depth: Some(depth),
+        _thread: PhantomData,
+    }
+}
+
+/// Record `name` as `value` on the innermost span open on this thread, if there is
+/// one, for code that is not given the span itself.
+pub fn record(name: &'static str, value: impl Display) {
+    FRAMES.with_borrow_mut(|frames| {
+        if let Some((record, _)) = frames
+            .iter_mut()
+            .rev()
+            .find_map(|frame| frame.open.as_mut())
+        {
+            record.fields.push((name, value.to_string()));
+        }
+    });
+}
+
+/// Give the exporter a span that was timed elsewhere, as one of a trace of its own.
+pub fn export(
+    name: &'static str,
+    fields: Vec<(&'static str, String)>,
+    started: SystemTime,
+    duration: Duration,
+) {
+    let Some(exporter) = EXPORTER.read().unwrap().clone() else {
+        return;
+    };
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    exporter(&Record {
+        trace: id,
+        id,
+        parent: None,
+        name,
+        fields,
+        worker: thread::current().name().map(String::from),
+        started,
+        duration,
+    });
+}
+
+/// An observer for a pool that exports each job's time in the queue and running as
+/// spans named `queue_wait` and `execute`, with the `worker` that took it and the
+/// `queue_depth` behind it, so slow requests can be told apart from a busy pool.
+#[cfg(feature = "pool-spans")]
+pub fn pool_spans() -> threadpool::SpanObserver<impl Fn(&threadpool::JobSpan) + Send + Sync> {
+    threadpool::SpanObserver::new(|span: &threadpool::JobSpan| {
+        let mut fields = vec![
+            ("worker", span.worker.to_string()),
+            ("queue_depth", span.queue_depth.to_string()),
+        ];
+        if span.panicked {
+            fields.push(("panicked", String::from("true")));
+        }
+        let started = SystemTime::now()
+            .checked_sub(span.started.elapsed())
+            .unwrap_or(SystemTime::UNIX_EPOCH);
+        export(span.stage.name(), fields, started, span.duration);
+    })
+}
+
+/// The span that spans opened now would be nested in.
+pub fn current() -> Option<Context> {
+    FRAMES.with_borrow(|frames| frames.last().map(|frame| frame.context))
+}
+
+/// Wrap `job` so the spans it opens are nested in the current span, wherever it runs.
+/// ```
+/// use std::sync::Arc;
+/// use ch20_web_server::trace::{self, Record};
+///
+/// trace::set_exporter(Some(Arc::new(|_: &Record| {})));
+/// let _connection = trace::span("connection");
+/// let context = trace::current();
+/// let job = trace::bind(move || trace::current());
+/// assert!(context.is_some());
+/// assert_eq!(std::thread::spawn(job).join().unwrap(), context);
+/// ```
+pub fn bind<F, R>(job: F) -> impl FnOnce() -> R + Send
+where
+    F: FnOnce() -> R + Send,
+{
+    let context = current();
+    move || {
+        let Some(context) = context else {
+            return job();
+        };
+        let depth = FRAMES.with_borrow_mut(|frames| {
+            frames.push(Frame {
+                context,
+                open: None,
+            });
+            frames.len() - 1
+        });
+        // Taken off again even if the job panics.
+        let _adopted = Span {
+            depth: Some(depth),
+            _thread: PhantomData,
+        };
+        job()
+    }
+}
+
+/// An open span, which finishes when it is dropped.
+///
+/// It has to be dropped on the thread it was opened on, and before the spans it was
+/// opened in.
+#[must_use = "the span finishes as soon as it is dropped"]
+pub struct Span {
+    /// Where its frame is on the thread's stack, or `None` if spans are not being
+    /// recorded.
+    depth: Option<usize>,
+    _thread: PhantomData<*const ()>,
+}
+
+impl Span {
+    /// Record `name` as `value` on the span.
+    pub fn record(&self, name: &'static str, value: impl Display) {
+        let Some(depth) = self.depth else {
+            return;
+        };
+        FRAMES.with_borrow_mut(|frames| {
+            if let Some((record, _)) = frames.get_mut(depth).and_then(|frame| frame.open.as_mut()) {
+                record.fields.push((name, value.to_string()));
+            }
+        });
+    }
+}
+
+impl Drop for Span {
+    fn drop(&mut self) {
+        let Some(depth) = self.depth else {
+            return;
+        };
+        // Spans left open inside this one finish with it.
+        let finished: Vec<_> = FRAMES.with_borrow_mut(|frames| {
+            frames
+                .drain(depth.min(frames.len())..)
+                .rev()
+                .filter_map(|frame| frame.open)
+                .collect()
+        });
+        if finished.is_empty() {
+            return;
+        }
+        let exporter = EXPORTER.read().unwrap().clone();
+        for (mut record, opened) in finished {
+            record.duration = opened.elapsed();
+            if let Some(exporter) = &exporter {
+                exporter(&record);
+            }
+        }
+    }
+}
+
+/// `record` as an object with `ts`, `trace_id`, `span_id`, `parent_id`, `name`,
+/// `worker`, `duration_ms` and `fields`, with the ids in hexadecimal.
+pub fn json(record: &Record) -> String {
+    let fields = record
+        .fields
+        .iter()
+        .fold(Object::new(), |fields, (name, value)| {
+            fields.string(name, value)
+        })
+        .finish();
+    let parent = record.parent.map(|parent| format!("{parent:016x}"));
+    Object::new()
+        .string("ts", &timestamp::rfc3339(record.started))
+        .string("trace_id", &format!("{:016x}", record.trace))
+        .string("span_id", &format!("{:016x}", record.id))
+        .optional_string("parent_id", parent.as_deref())
+        .string("name", record.name)
+        .optional_string("worker", record.worker.as_deref())
+        .number("duration_ms", record.duration.as_micros() as f64 / 1000.0)
+        .raw("fields", &fields)
+        .finish()
+}
+
+/// Writes each finished span as a line of JSON to a file, or to standard output.
+pub struct SpanLog {
+    out: Mutex<Output>,
+}
+
+impl SpanLog {
+    /// Append spans to the file at `path`, which is created if it does not exist and
+    /// rotated as `rotation` says. A `path` of `-` writes them to standard output.
+    pub fn open(path: &Path, rotation: Rotation) -> io::Result<SpanLog> {
+        let out = if path == Path::new("-") {
+            Output::Stream(Box::new(io::stdout()))
+        } else {
+            Output::File(LogFile::open(path, rotation)?)
+        };
+        Ok(SpanLog {
+            out: Mutex::new(out),
+        })
+    }
+
+    /// Open the file again, for when it has been moved to be rotated by another
+    /// program.
+    pub fn reopen(&self) -> io::Result<()> {
+        self.out.lock().unwrap().reopen()
+    }
+
+    pub fn write(&self, record: &Record) -> io::Result<()> {
+        let mut line = json(record);
+        line.push('\n');
+        self.out.lock().unwrap().write_all(line.as_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn writes_spans_as_json() {
+        let record = Record {
+            trace: 1,
+            id: 2,
+            parent: Some(1),
+            name: "request",
+            fields: vec![
+                ("method", String::from("GET")),
+                ("status", String::from("200")),
+            ],
+            worker: Some(String::from("connection-0")),
+            started: SystemTime::UNIX_EPOCH,
+            duration: Duration::from_micros(2500),
+        };
+        assert_eq!(
+            json(&record),
+            "{\"ts\":\"1970-01-01T00:00:00.000Z\",\"trace_id\":\"0000000000000001\",\
+             \"span_id\":\"0000000000000002\",\"parent_id\":\"0000000000000001\",\
+             \"name\":\"request\",\"worker\":\"connection-0\",\"duration_ms\":2.5,\
+             \"fields\":{\"method\":\"GET\",\"status\":\"200\"}}"
+        );
+    }
+}
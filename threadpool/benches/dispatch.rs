@@ -0,0 +1,160 @@
+//! Measures how fast the pool dispatches short jobs under contention, for each of the
+//! ways to take the shared queue lock off the hot path, and for each lock it can be
+//! built with.
+//!
+//! Run with `cargo bench -p threadpool` for the pool with `std::sync::Mutex`, and with
+//! `cargo bench -p threadpool --features spin-lock` for the pool with `SpinMutex`,
+//! which also compares the two locks on their own. Every producer thread submits
+//! `JOBS` empty jobs, so the numbers are dominated by the queue and its lock rather
+//! than the jobs.
+
+use std::{
+    hint::black_box,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use threadpool::ThreadpoolBuilder;
+
+const WORKERS: usize = 8;
+const PRODUCERS: usize = 4;
+const JOBS: usize = 50_000;
+const RUNS: usize = 5;
+
+/// A named way of configuring the pool under test.
+type Config = (&'static str, fn() -> ThreadpoolBuilder);
+
+fn main() {
+    let configs: [Config; 4] = [
+        ("locked queue", || ThreadpoolBuilder::new(WORKERS)),
+        ("locked queue, batch 16", || {
+            ThreadpoolBuilder::new(WORKERS).dequeue_batch(16)
+        }),
+        ("locked queue, no spin", || {
+            ThreadpoolBuilder::new(WORKERS).idle_spin(Duration::ZERO)
+        }),
+        ("lock-free queue", || {
+            ThreadpoolBuilder::new(WORKERS).lock_free_queue(true)
+        }),
+    ];
+
+    let lock = if cfg!(feature = "spin-lock") {
+        "SpinMutex"
+    } else {
+        "std::sync::Mutex"
+    };
+    println!(
+        "{PRODUCERS} producers x {JOBS} jobs on {WORKERS} workers, best of {RUNS}, \
+         queues locked with {lock}"
+    );
+    for (name, builder) in configs {
+        let best = (0..RUNS).map(|_| run(builder())).min().unwrap();
+        let rate = (PRODUCERS * JOBS) as f64 / best.as_secs_f64();
+        println!("{name:>24}: {best:>10.2?}  {:>6.2} M jobs/s", rate / 1e6);
+    }
+
+    #[cfg(feature = "spin-lock")]
+    locks::compare();
+}
+
+/// Submit every job from the producer threads and return the time until the last one
+/// has run.
+fn run(builder: ThreadpoolBuilder) -> Duration {
+    let pool = Arc::new(builder.build().unwrap());
+    let counter = Arc::new(AtomicUsize::new(0));
+    let started = Instant::now();
+
+    let producers: Vec<_> = (0..PRODUCERS)
+        .map(|_| {
+            let pool = Arc::clone(&pool);
+            let counter = Arc::clone(&counter);
+            thread::spawn(move || {
+                for _ in 0..JOBS {
+                    let counter = Arc::clone(&counter);
+                    pool.execute(move || {
+                        black_box(counter.fetch_add(1, Ordering::Relaxed));
+                    })
+                    .unwrap();
+                }
+            })
+        })
+        .collect();
+    for producer in producers {
+        producer.join().unwrap();
+    }
+    pool.join();
+
+    let elapsed = started.elapsed();
+    assert_eq!(counter.load(Ordering::Relaxed), PRODUCERS * JOBS);
+    elapsed
+}
+
+/// The two locks on their own, each taken by every producer and worker thread in turn
+/// to push and pop a queue, as the pool's threads take the shared queue's.
+#[cfg(feature = "spin-lock")]
+mod locks {
+    use std::{collections::VecDeque, sync::Mutex};
+
+    use threadpool::SpinMutex;
+
+    use super::*;
+
+    const TAKES: usize = 200_000;
+
+    /// The lock under test, behind the one method both have.
+    trait Lock: Send + Sync + 'static {
+        fn with(&self, f: impl FnOnce(&mut VecDeque<usize>));
+    }
+
+    impl Lock for Mutex<VecDeque<usize>> {
+        fn with(&self, f: impl FnOnce(&mut VecDeque<usize>)) {
+            f(&mut self.lock().unwrap());
+        }
+    }
+
+    impl Lock for SpinMutex<VecDeque<usize>> {
+        fn with(&self, f: impl FnOnce(&mut VecDeque<usize>)) {
+            f(&mut self.lock().unwrap());
+        }
+    }
+
+    pub(super) fn compare() {
+        let threads = PRODUCERS + WORKERS;
+        println!("{threads} threads x {TAKES} takes of one lock, best of {RUNS}");
+        let std = (0..RUNS).map(|_| run(Mutex::default())).min().unwrap();
+        let spin = (0..RUNS).map(|_| run(SpinMutex::default())).min().unwrap();
+        for (name, best) in [("std::sync::Mutex", std), ("SpinMutex", spin)] {
+            let rate = (threads * TAKES) as f64 / best.as_secs_f64();
+            println!("{name:>24}: {best:>10.2?}  {:>6.2} M takes/s", rate / 1e6);
+        }
+    }
+
+    fn run(lock: impl Lock) -> Duration {
+        let lock = Arc::new(lock);
+        let started = Instant::now();
+        let threads: Vec<_> = (0..PRODUCERS + WORKERS)
+            .map(|thread| {
+                let lock = Arc::clone(&lock);
+                thread::spawn(move || {
+                    for take in 0..TAKES {
+                        lock.with(|queue| {
+                            if thread < PRODUCERS || queue.is_empty() {
+                                queue.push_back(black_box(take));
+                            } else {
+                                black_box(queue.pop_front());
+                            }
+                        });
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        started.elapsed()
+    }
+}
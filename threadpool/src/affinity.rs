@@ -0,0 +1,144 @@
+use std::{fs, io};
+
+use crate::Shared;
+
+/// Pin the calling thread of worker `id` as configured on `shared`, and return the
+/// NUMA node it was placed on.
+///
+/// Explicit cores from `pin_to_cores` take precedence over NUMA placement. Failures
+/// are reported on stderr and leave the worker unpinned.
+pub(crate) fn place_worker(shared: &Shared, id: usize) -> usize {
+    if let Some(core) = shared.cores.get(id % shared.cores.len().max(1)) {
+        if let Err(error) = pin_current_thread(&[*core]) {
+            eprintln!("Worker {id} could not be pinned to core {core}: {error}");
+        }
+        return 0;
+    }
+
+    let nodes = &shared.numa_nodes;
+    if nodes.is_empty() {
+        return 0;
+    }
+    let node = id % nodes.len();
+    if let Err(error) = pin_current_thread(&nodes[node]) {
+        eprintln!("Worker {id} could not be pinned to NUMA node {node}: {error}");
+    }
+    node
+}
+
+/// Return the CPU cores of each online NUMA node, or nothing if the topology cannot be
+/// read, as on non-Linux systems.
+pub(crate) fn numa_nodes() -> Vec<Vec<usize>> {
+    let read = |path: &str| fs::read_to_string(format!("/sys/devices/system/node/{path}"));
+    let Some(online) = read("online").ok().and_then(|list| parse_cpu_list(&list)) else {
+        return Vec::new();
+    };
+
+    online
+        .into_iter()
+        .filter_map(|node| parse_cpu_list(&read(&format!("node{node}/cpulist")).ok()?))
+        .filter(|cores| !cores.is_empty())
+        .collect()
+}
+
+/// Parse a kernel list of indices such as `0-3,8-11`.
+fn parse_cpu_list(list: &str) -> Option<Vec<usize>> {
+    let mut indices = Vec::new();
+
+    for range in list.trim().split(',').filter(|range| !range.is_empty()) {
+        let (start, end) = range.split_once('-').unwrap_or((range, range));
+        indices.extend(start.parse::<usize>().ok()?..=end.parse().ok()?);
+    }
+    Some(indices)
+}
+
+/// Pin the calling thread to the CPU cores with the given indices.
+#[cfg(target_os = "linux")]
+pub(crate) fn pin_current_thread(cores: &[usize]) -> io::Result<()> {
+    use std::mem;
+
+    /// Mirrors glibc's `cpu_set_t`, a bitmask of 1024 cores.
+    #[repr(C)]
+    struct CpuSet([u64; 16]);
+
+    extern "C" {
+        fn sched_setaffinity(pid: i32, cpusetsize: usize, mask: *const CpuSet) -> i32;
+    }
+
+    let mut set = CpuSet([0; 16]);
+    let bits = u64::BITS as usize;
+    for &core in cores {
+        if core >= set.0.len() * bits {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("core {core} is out of range"),
+            ));
+        }
+        set.0[core / bits] |= 1 << (core % bits);
+    }
+
+    // SAFETY: `set` is a valid `cpu_set_t` of the size passed, and pid 0 refers to the
+    // calling thread.
+    let result = unsafe { sched_setaffinity(0, mem::size_of::<CpuSet>(), &set) };
+    if result != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn pin_current_thread(_cores: &[usize]) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "pinning threads to cores is only supported on Linux",
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ThreadpoolBuilder;
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn rejects_out_of_range_core() {
+        let error = pin_current_thread(&[usize::MAX]).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn pinned_pool_runs_jobs() {
+        // Core 4096 cannot exist, so worker 1 runs unpinned instead.
+        let pool = ThreadpoolBuilder::new(2)
+            .pin_to_cores(&[0, 4096])
+            .build()
+            .unwrap();
+
+        let handles: Vec<_> = (0..4)
+            .map(|i| pool.submit(move || i * 2).unwrap())
+            .collect();
+        let results: Vec<_> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+        assert_eq!(results, [0, 2, 4, 6]);
+    }
+
+    #[test]
+    fn parses_cpu_lists() {
+        assert_eq!(
+            parse_cpu_list("0-3,8,10-11\n"),
+            Some(vec![0, 1, 2, 3, 8, 10, 11])
+        );
+        assert_eq!(parse_cpu_list(""), Some(vec![]));
+        assert_eq!(parse_cpu_list("0-x"), None);
+    }
+
+    #[test]
+    fn numa_aware_pool_runs_jobs() {
+        let pool = ThreadpoolBuilder::new(4).numa_aware(true).build().unwrap();
+
+        let handles: Vec<_> = (0..8)
+            .map(|i| pool.submit(move || i + 1).unwrap())
+            .collect();
+        let total: usize = handles.into_iter().map(|h| h.join().unwrap()).sum();
+        assert_eq!(total, 36);
+    }
+}
@@ -0,0 +1,551 @@
+use std::{
+    any::Any,
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize},
+        Arc, Mutex, RwLock,
+    },
+    time::Duration,
+};
+
+use crate::{
+    category::Category,
+    local::LocalThread,
+    metrics::{Counters, SlowDequeueHook},
+    middleware::Layers,
+    mpmc::ArrayQueue,
+    observer::{PoolObserver, Silent},
+    queue::{JobQueue, RejectionPolicy, SchedulingPolicy, DEFAULT_AGING_INTERVAL},
+    timer::Timer,
+    watchdog::{StuckHook, Watchdog},
+    worker::{PanicHook, WorkerHook},
+    PoolError, QueueCondvar, QueueLock, Shared, State, Threadpool, Worker, NO_WORKER,
+};
+
+/// Configures and creates a `Threadpool`.
+///
+/// ```
+/// use threadpool::ThreadpoolBuilder;
+/// let pool = ThreadpoolBuilder::new(4)
+///     .queue_capacity(64)
+///     .thread_name("worker")
+///     .build()
+///     .unwrap();
+/// ```
+pub struct ThreadpoolBuilder {
+    size: usize,
+    queue_capacity: Option<usize>,
+    rejection_policy: RejectionPolicy,
+    scheduling_policy: SchedulingPolicy,
+    priority_aging: Option<Duration>,
+    queue_weights: Vec<(String, usize)>,
+    category_limits: HashMap<String, usize>,
+    max_size: Option<usize>,
+    keep_alive: Duration,
+    idle_spin: Duration,
+    thread_name: Option<String>,
+    stack_size: Option<usize>,
+    lock_free_queue: bool,
+    dequeue_batch: usize,
+    observer: Arc<dyn PoolObserver>,
+    on_worker_start: Option<WorkerHook>,
+    on_worker_stop: Option<WorkerHook>,
+    on_panic: Option<PanicHook>,
+    on_slow_dequeue: Option<(Duration, SlowDequeueHook)>,
+    watchdog: Option<Duration>,
+    on_stuck_job: Option<StuckHook>,
+    replace_stuck_workers: bool,
+    #[cfg(feature = "affinity")]
+    cores: Vec<usize>,
+    #[cfg(feature = "affinity")]
+    numa_aware: bool,
+}
+
+/// How long an elastic worker may stay idle before it exits, unless configured.
+const DEFAULT_KEEP_ALIVE: Duration = Duration::from_secs(60);
+
+/// How long an idle worker spins looking for work before it parks, unless configured.
+const DEFAULT_IDLE_SPIN: Duration = Duration::from_micros(50);
+
+/// Size of the lock-free queue when the pool has no queue capacity. Jobs beyond this
+/// overflow into the shared queue.
+const DEFAULT_LOCK_FREE_CAPACITY: usize = 1024;
+
+impl ThreadpoolBuilder {
+    /// Start configuring a pool with `size` worker threads.
+    pub fn new(size: usize) -> ThreadpoolBuilder {
+        ThreadpoolBuilder {
+            size,
+            queue_capacity: None,
+            rejection_policy: RejectionPolicy::Block,
+            scheduling_policy: SchedulingPolicy::Fifo,
+            priority_aging: Some(DEFAULT_AGING_INTERVAL),
+            queue_weights: Vec::new(),
+            category_limits: HashMap::new(),
+            max_size: None,
+            keep_alive: DEFAULT_KEEP_ALIVE,
+            idle_spin: DEFAULT_IDLE_SPIN,
+            thread_name: None,
+            stack_size: None,
+            lock_free_queue: false,
+            dequeue_batch: 1,
+            observer: Arc::new(Silent),
+            on_worker_start: None,
+            on_worker_stop: None,
+            watchdog: None,
+            on_panic: None,
+            on_slow_dequeue: None,
+            on_stuck_job: None,
+            replace_stuck_workers: false,
+            #[cfg(feature = "affinity")]
+            cores: Vec::new(),
+            #[cfg(feature = "affinity")]
+            numa_aware: false,
+        }
+    }
+
+    /// Limit the number of jobs that can wait in the queue.
+    ///
+    /// Once the limit is reached `execute` blocks, unless a different
+    /// `rejection_policy` is set, and `try_execute` returns a `QueueFull` error. By
+    /// default the queue is unbounded.
+    pub fn queue_capacity(mut self, capacity: usize) -> ThreadpoolBuilder {
+        self.queue_capacity = Some(capacity);
+        self
+    }
+
+    /// Choose what `execute` and the other blocking submissions do once the queue
+    /// capacity is reached: wait for space, which is the default, drop the new or the
+    /// oldest job, or run the new job on the caller's thread.
+    /// ```
+    /// use threadpool::{RejectionPolicy, ThreadpoolBuilder};
+    /// let pool = ThreadpoolBuilder::new(4)
+    ///     .queue_capacity(64)
+    ///     .rejection_policy(RejectionPolicy::CallerRuns)
+    ///     .build()
+    ///     .unwrap();
+    /// ```
+    pub fn rejection_policy(mut self, policy: RejectionPolicy) -> ThreadpoolBuilder {
+        self.rejection_policy = policy;
+        self
+    }
+
+    /// Choose whether jobs of equal priority leave the shared queue oldest-first (the
+    /// default) or newest-first.
+    ///
+    /// Jobs on the lock-free queue always run oldest-first, and workers always run the
+    /// jobs they spawned themselves newest-first.
+    pub fn scheduling_policy(mut self, policy: SchedulingPolicy) -> ThreadpoolBuilder {
+        self.scheduling_policy = policy;
+        self
+    }
+
+    /// Set how long a queued job waits before it is treated as one priority level
+    /// higher, or pass `None` to always dequeue strictly by priority.
+    ///
+    /// Aging guarantees that `Low` jobs eventually run under a constant stream of
+    /// `High` ones: with an interval of 500ms, the default, a `Low` job competes as
+    /// `High` after waiting one second.
+    /// ```
+    /// use std::time::Duration;
+    /// use threadpool::ThreadpoolBuilder;
+    /// let pool = ThreadpoolBuilder::new(4)
+    ///     .priority_aging(Some(Duration::from_millis(100)))
+    ///     .build()
+    ///     .unwrap();
+    /// ```
+    pub fn priority_aging(mut self, interval: Option<Duration>) -> ThreadpoolBuilder {
+        self.priority_aging = interval;
+        self
+    }
+
+    /// Give the named queue `name` a weight, creating it if needed.
+    ///
+    /// When it is a queue's turn, workers take up to `weight` of its jobs before moving
+    /// on to the next queue, so a queue with weight 3 gets three times the share of one
+    /// with weight 1 while both are busy. The pool's unnamed queue has weight 1.
+    /// ```
+    /// use threadpool::ThreadpoolBuilder;
+    /// let pool = ThreadpoolBuilder::new(4)
+    ///     .queue_weight("premium", 3)
+    ///     .build()
+    ///     .unwrap();
+    ///
+    /// pool.queue("premium").execute(|| println!("serving...")).unwrap();
+    /// ```
+    pub fn queue_weight(mut self, name: impl Into<String>, weight: usize) -> ThreadpoolBuilder {
+        self.queue_weights.push((name.into(), weight));
+        self
+    }
+
+    /// Allow at most `limit` jobs of `category` to be queued or running at once.
+    ///
+    /// Jobs are tagged with a category by `Threadpool::execute_in_category`, so one
+    /// heavy class of jobs cannot take up every worker. Limits below one are raised to
+    /// one.
+    pub fn category_limit(
+        mut self,
+        category: impl Into<String>,
+        limit: usize,
+    ) -> ThreadpoolBuilder {
+        self.category_limits.insert(category.into(), limit);
+        self
+    }
+
+    /// Make the pool elastic, growing up to `max_size` workers under load.
+    ///
+    /// The size passed to `new` becomes the core size, which is always kept running.
+    /// Whenever a job is queued while no worker is idle, another worker is spawned until
+    /// the pool reaches `max_size`. Workers above the core size exit once they have been
+    /// idle for the keep-alive period. A `max_size` below the core size is raised to it.
+    /// ```
+    /// use std::time::Duration;
+    /// use threadpool::ThreadpoolBuilder;
+    /// let pool = ThreadpoolBuilder::new(2)
+    ///     .max_size(16)
+    ///     .keep_alive(Duration::from_secs(30))
+    ///     .build()
+    ///     .unwrap();
+    /// ```
+    pub fn max_size(mut self, max_size: usize) -> ThreadpoolBuilder {
+        self.max_size = Some(max_size);
+        self
+    }
+
+    /// Set how long a worker above the core size may stay idle before it exits.
+    ///
+    /// Only has an effect on elastic pools. Defaults to 60 seconds.
+    pub fn keep_alive(mut self, keep_alive: Duration) -> ThreadpoolBuilder {
+        self.keep_alive = keep_alive;
+        self
+    }
+
+    /// Set how long a worker that runs out of jobs spins, checking for new ones, before
+    /// it parks until one is queued.
+    ///
+    /// Spinning lets a burst of short jobs be picked up without the latency of waking
+    /// parked threads, at the cost of keeping idle cores busy for that long after each
+    /// job. `Duration::ZERO` parks straight away. Defaults to 50 microseconds.
+    /// ```
+    /// use std::time::Duration;
+    /// use threadpool::ThreadpoolBuilder;
+    /// let pool = ThreadpoolBuilder::new(4)
+    ///     .idle_spin(Duration::from_micros(200))
+    ///     .build()
+    ///     .unwrap();
+    /// ```
+    pub fn idle_spin(mut self, idle_spin: Duration) -> ThreadpoolBuilder {
+        self.idle_spin = idle_spin;
+        self
+    }
+
+    /// Name worker threads `{prefix}-{id}` so they can be told apart in debuggers and
+    /// panic messages.
+    pub fn thread_name(mut self, prefix: impl Into<String>) -> ThreadpoolBuilder {
+        self.thread_name = Some(prefix.into());
+        self
+    }
+
+    /// Set the stack size, in bytes, of each worker thread.
+    ///
+    /// By default the platform's default stack size for spawned threads is used.
+    pub fn stack_size(mut self, size: usize) -> ThreadpoolBuilder {
+        self.stack_size = Some(size);
+        self
+    }
+
+    /// Queue jobs submitted from outside the pool on a lock-free queue instead of the
+    /// mutex-protected priority queue.
+    ///
+    /// This removes the shared lock from the submit and dequeue path, which helps when
+    /// many cores hand out short jobs. The trade-off is that jobs on the lock-free queue
+    /// run in arrival order: priorities passed to `execute_with_priority` are ignored.
+    /// With a queue capacity the lock-free queue holds up to that many jobs, rounded up
+    /// to a power of two.
+    pub fn lock_free_queue(mut self, enabled: bool) -> ThreadpoolBuilder {
+        self.lock_free_queue = enabled;
+        self
+    }
+
+    /// Let a worker take up to `batch` jobs from the shared queue each time it locks it.
+    ///
+    /// The worker runs the first job straight away and moves the rest to its own deque,
+    /// where other workers can still steal them. Under high submission rates this cuts
+    /// contention on the queue lock, at the cost of jobs in a worker's deque no longer
+    /// counting against the queue capacity or being reordered by priority. Defaults to
+    /// one, and a batch below one is raised to one.
+    /// ```
+    /// use threadpool::ThreadpoolBuilder;
+    /// let pool = ThreadpoolBuilder::new(8).dequeue_batch(16).build().unwrap();
+    /// ```
+    pub fn dequeue_batch(mut self, batch: usize) -> ThreadpoolBuilder {
+        self.dequeue_batch = batch.max(1);
+        self
+    }
+
+    /// Report worker activity to `observer`.
+    ///
+    /// By default events are discarded. Use `StdoutObserver` to print them, or implement
+    /// `PoolObserver` to forward them to a logger.
+    pub fn observer(mut self, observer: impl PoolObserver + 'static) -> ThreadpoolBuilder {
+        self.observer = Arc::new(observer);
+        self
+    }
+
+    /// Run `hook` on every worker thread, with the worker's id, before it takes its
+    /// first job.
+    ///
+    /// This is the place to set up thread-local state such as a database connection or
+    /// a scratch buffer. Threads spawned to replace a worker whose job panicked, or by
+    /// resizing the pool, run the hook too.
+    /// ```
+    /// use std::cell::RefCell;
+    /// use threadpool::ThreadpoolBuilder;
+    ///
+    /// thread_local! {
+    ///     static BUFFER: RefCell<Vec<u8>> = const { RefCell::new(Vec::new()) };
+    /// }
+    ///
+    /// let pool = ThreadpoolBuilder::new(4)
+    ///     .on_worker_start(|_| BUFFER.with_borrow_mut(|buffer| buffer.reserve(64 * 1024)))
+    ///     .build()
+    ///     .unwrap();
+    /// ```
+    pub fn on_worker_start<F>(mut self, hook: F) -> ThreadpoolBuilder
+    where
+        F: Fn(usize) + Send + Sync + 'static,
+    {
+        self.on_worker_start = Some(Arc::new(hook));
+        self
+    }
+
+    /// Run `hook` on every worker thread, with the worker's id, after it has taken its
+    /// last job and just before the thread exits.
+    pub fn on_worker_stop<F>(mut self, hook: F) -> ThreadpoolBuilder
+    where
+        F: Fn(usize) + Send + Sync + 'static,
+    {
+        self.on_worker_stop = Some(Arc::new(hook));
+        self
+    }
+
+    /// Call `hook` on the worker whenever one of its jobs panics, with the worker's id,
+    /// the panic payload and the job's label from `Threadpool::execute_labelled`.
+    ///
+    /// Unlike `std::panic::set_hook` the hook only sees this pool's jobs, and it runs
+    /// after the panic has been caught, so it is the place to log structured details
+    /// or answer a request with an error. The process-wide panic hook still runs when
+    /// the job panics. Jobs run by `spawn_local`, and jobs whose panic is delivered
+    /// through a `JobHandle`, are not reported here.
+    /// ```
+    /// use threadpool::ThreadpoolBuilder;
+    /// let pool = ThreadpoolBuilder::new(4)
+    ///     .on_panic(|worker, payload, label| {
+    ///         let message = payload.downcast_ref::<&str>().copied().unwrap_or("unknown");
+    ///         eprintln!("worker {worker} panicked in {label:?}: {message}");
+    ///     })
+    ///     .build()
+    ///     .unwrap();
+    /// ```
+    pub fn on_panic<F>(mut self, hook: F) -> ThreadpoolBuilder
+    where
+        F: Fn(usize, &(dyn Any + Send), Option<&str>) + Send + Sync + 'static,
+    {
+        self.on_panic = Some(Arc::new(hook));
+        self
+    }
+
+    /// Call `hook` with the queue wait of every job that waited for longer than
+    /// `threshold` before a worker picked it up.
+    ///
+    /// The hook runs on the worker just before the job, so it should be quick. A steady
+    /// stream of calls means jobs arrive faster than the workers keep up with, and is a
+    /// sign the pool should grow. The full distribution of queue waits is available
+    /// from `Threadpool::latency` either way.
+    /// ```
+    /// use std::time::Duration;
+    /// use threadpool::ThreadpoolBuilder;
+    /// let pool = ThreadpoolBuilder::new(4)
+    ///     .on_slow_dequeue(Duration::from_millis(100), |waited| {
+    ///         eprintln!("job waited {waited:?} in the queue")
+    ///     })
+    ///     .build()
+    ///     .unwrap();
+    /// ```
+    pub fn on_slow_dequeue<F>(mut self, threshold: Duration, hook: F) -> ThreadpoolBuilder
+    where
+        F: Fn(Duration) + Send + Sync + 'static,
+    {
+        self.on_slow_dequeue = Some((threshold, Arc::new(hook)));
+        self
+    }
+
+    /// Start a watchdog thread that reports jobs running for longer than `threshold`.
+    ///
+    /// Each overrunning job is reported once, to the `on_stuck_job` callback if one is
+    /// set and to stderr otherwise.
+    /// ```
+    /// use std::time::Duration;
+    /// use threadpool::ThreadpoolBuilder;
+    /// let pool = ThreadpoolBuilder::new(4)
+    ///     .watchdog(Duration::from_secs(30))
+    ///     .on_stuck_job(|worker, elapsed| eprintln!("worker {worker} stuck for {elapsed:?}"))
+    ///     .replace_stuck_workers(true)
+    ///     .build()
+    ///     .unwrap();
+    /// ```
+    pub fn watchdog(mut self, threshold: Duration) -> ThreadpoolBuilder {
+        self.watchdog = Some(threshold);
+        self
+    }
+
+    /// Call `hook` with the worker's id and the job's running time when the watchdog
+    /// finds an overrunning job.
+    pub fn on_stuck_job<F>(mut self, hook: F) -> ThreadpoolBuilder
+    where
+        F: Fn(usize, Duration) + Send + Sync + 'static,
+    {
+        self.on_stuck_job = Some(Arc::new(hook));
+        self
+    }
+
+    /// Spawn a replacement worker whenever the watchdog finds an overrunning job, so
+    /// throughput holds up while it is stuck. The stuck worker exits once its job
+    /// finishes.
+    pub fn replace_stuck_workers(mut self, enabled: bool) -> ThreadpoolBuilder {
+        self.replace_stuck_workers = enabled;
+        self
+    }
+
+    /// Pin each worker thread to one of `cores`, given as zero-based CPU indices.
+    ///
+    /// Worker `id` is pinned to `cores[id % cores.len()]` when its thread starts, so
+    /// with more workers than cores they share them round-robin. Workers that cannot be
+    /// pinned, for example because the core does not exist, keep running unpinned and
+    /// report the failure on stderr. Pinning is only supported on Linux.
+    /// ```
+    /// use threadpool::ThreadpoolBuilder;
+    /// let pool = ThreadpoolBuilder::new(2)
+    ///     .pin_to_cores(&[0])
+    ///     .build()
+    ///     .unwrap();
+    /// ```
+    #[cfg(feature = "affinity")]
+    pub fn pin_to_cores(mut self, cores: &[usize]) -> ThreadpoolBuilder {
+        self.cores = cores.to_vec();
+        self
+    }
+
+    /// Spread workers across the machine's NUMA nodes, pinning worker `id` to every
+    /// core of node `id % nodes`.
+    ///
+    /// Idle workers then steal jobs from workers on their own node before crossing to
+    /// another, so work spawned from inside a job tends to stay close to its data.
+    /// Cores given to `pin_to_cores` take precedence. On machines whose topology cannot
+    /// be read, such as non-Linux systems, workers run unpinned.
+    /// ```
+    /// use threadpool::ThreadpoolBuilder;
+    /// let pool = ThreadpoolBuilder::new(8).numa_aware(true).build().unwrap();
+    /// ```
+    #[cfg(feature = "affinity")]
+    pub fn numa_aware(mut self, enabled: bool) -> ThreadpoolBuilder {
+        self.numa_aware = enabled;
+        self
+    }
+
+    /// Create the pool and start its workers.
+    ///
+    /// Returns `ZeroSize` if the size or the queue capacity is zero, and `SpawnFailed`
+    /// if a worker thread could not be spawned.
+    pub fn build(self) -> Result<Threadpool, PoolError> {
+        if self.size == 0 || self.queue_capacity == Some(0) {
+            return Err(PoolError::ZeroSize);
+        }
+
+        let mut jobs = JobQueue::new(self.scheduling_policy);
+        jobs.set_aging(self.priority_aging);
+        for (name, weight) in &self.queue_weights {
+            jobs.set_weight(name, *weight);
+        }
+
+        let shared = Arc::new(Shared {
+            state: QueueLock::new(State {
+                jobs,
+                size: self.size,
+                core: self.size,
+                live: 0,
+                next_id: self.size,
+            }),
+            job_available: QueueCondvar::new(),
+            space_available: QueueCondvar::new(),
+            worker_exited: QueueCondvar::new(),
+            idle: QueueCondvar::new(),
+            counters: Counters::default(),
+            statuses: Arc::default(),
+            observer: self.observer,
+            middleware: Layers::default(),
+            std_scopes: Arc::default(),
+            activity: Arc::default(),
+            on_worker_start: self.on_worker_start,
+            on_worker_stop: self.on_worker_stop,
+            on_panic: self.on_panic,
+            on_slow_dequeue: self.on_slow_dequeue,
+            timer: Timer::default(),
+            local: LocalThread::default(),
+            watchdog: self.watchdog.map(|threshold| {
+                Watchdog::new(threshold, self.on_stuck_job, self.replace_stuck_workers)
+            }),
+            categories: self
+                .category_limits
+                .into_iter()
+                .map(|(category, limit)| (category, Arc::new(Category::new(limit))))
+                .collect(),
+            children: Mutex::default(),
+            lanes: (0..self.size).map(|_| Arc::new(Category::new(1))).collect(),
+            capacity: self.queue_capacity,
+            rejection_policy: self.rejection_policy,
+            max_size: self.max_size.map(|max_size| max_size.max(self.size)),
+            keep_alive: self.keep_alive,
+            idle_spin: self.idle_spin,
+            dequeue_batch: self.dequeue_batch,
+            thread_name: self.thread_name,
+            stack_size: self.stack_size,
+            #[cfg(feature = "affinity")]
+            cores: self.cores,
+            #[cfg(feature = "affinity")]
+            numa_nodes: if self.numa_aware {
+                crate::affinity::numa_nodes()
+            } else {
+                Vec::new()
+            },
+            workers: Mutex::new(Vec::with_capacity(self.size)),
+            locals: RwLock::new(Vec::with_capacity(self.size)),
+            injector: self.lock_free_queue.then(|| {
+                ArrayQueue::new(self.queue_capacity.unwrap_or(DEFAULT_LOCK_FREE_CAPACITY))
+            }),
+            unlocked_jobs: AtomicUsize::new(0),
+            waiting_producers: AtomicUsize::new(0),
+            sleepers: AtomicUsize::new(0),
+            spinners: AtomicUsize::new(0),
+            in_flight: AtomicUsize::new(0),
+            retiring: AtomicUsize::new(0),
+            recycle: AtomicUsize::new(NO_WORKER),
+            recycled: AtomicUsize::new(0),
+            recycling: Mutex::new(()),
+            paused: AtomicBool::new(false),
+            closed: AtomicBool::new(false),
+        });
+
+        // Dropping the pool on failure shuts down the workers spawned so far.
+        let pool = Threadpool { shared };
+
+        for id in 0..self.size {
+            let worker =
+                Worker::new(id, Arc::clone(&pool.shared)).map_err(PoolError::SpawnFailed)?;
+            pool.shared.workers.lock().unwrap().push(worker);
+        }
+        Watchdog::start(&pool.shared);
+
+        Ok(pool)
+    }
+}
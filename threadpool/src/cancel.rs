@@ -0,0 +1,165 @@
+use std::sync::{
+    atomic::{AtomicBool, AtomicU8, Ordering},
+    Arc, Weak,
+};
+
+use crate::{queue::DEFAULT_QUEUE, ExecuteError, Priority, Shared, Threadpool};
+
+const PENDING: u8 = 0;
+const RUNNING: u8 = 1;
+const CANCELLED: u8 = 2;
+
+struct CancelState {
+    state: AtomicU8,
+    cancelled: AtomicBool,
+    shared: Weak<Shared>,
+}
+
+/// A handle for cancelling a job submitted with `Threadpool::execute_cancellable`.
+///
+/// Clones refer to the same job. The job itself receives a clone so it can check
+/// `is_cancelled` while it runs.
+#[derive(Clone)]
+pub struct CancelToken {
+    inner: Arc<CancelState>,
+}
+
+impl CancelToken {
+    /// Cancel the job.
+    ///
+    /// If the job has not started yet it is removed from the queue and never runs, and
+    /// this returns `true`. If it is already running it keeps going; it is up to the job
+    /// to notice `is_cancelled` and stop early.
+    pub fn cancel(&self) -> bool {
+        self.inner.cancelled.store(true, Ordering::SeqCst);
+
+        let revoked = self
+            .inner
+            .state
+            .compare_exchange(PENDING, CANCELLED, Ordering::SeqCst, Ordering::SeqCst)
+            .is_ok();
+
+        // A worker may already have dequeued the job; it then skips it when it sees the
+        // cancelled state.
+        if revoked {
+            if let Some(shared) = self.inner.shared.upgrade() {
+                shared.remove_queued(self.tag());
+            }
+        }
+        revoked
+    }
+
+    /// Whether `cancel` has been called.
+    pub fn is_cancelled(&self) -> bool {
+        self.inner.cancelled.load(Ordering::SeqCst)
+    }
+
+    /// Identifies the job in the shared queue. The job holds a clone of the token, so the
+    /// address stays unique for as long as the job is queued.
+    fn tag(&self) -> usize {
+        Arc::as_ptr(&self.inner) as usize
+    }
+}
+
+impl Threadpool {
+    /// Execute a closure that can be cancelled through the returned token.
+    ///
+    /// The closure is given a clone of the token to poll while it runs.
+    ///
+    /// Fails under the same conditions as `execute`.
+    /// ```
+    /// use threadpool::Threadpool;
+    /// let pool = Threadpool::build(1).unwrap();
+    ///
+    /// let token = pool.execute_cancellable(|token| {
+    ///     while !token.is_cancelled() {
+    ///         // serve the client...
+    ///         # break;
+    ///     }
+    /// })
+    /// .unwrap();
+    /// token.cancel();
+    /// ```
+    pub fn execute_cancellable<F>(&self, f: F) -> Result<CancelToken, ExecuteError>
+    where
+        F: FnOnce(CancelToken) + Send + 'static,
+    {
+        let token = CancelToken {
+            inner: Arc::new(CancelState {
+                state: AtomicU8::new(PENDING),
+                cancelled: AtomicBool::new(false),
+                shared: Arc::downgrade(&self.shared),
+            }),
+        };
+
+        let job_token = token.clone();
+        let job = Box::new(move || {
+            let started = job_token
+                .inner
+                .state
+                .compare_exchange(PENDING, RUNNING, Ordering::SeqCst, Ordering::SeqCst)
+                .is_ok();
+
+            if started {
+                f(job_token);
+            }
+        });
+
+        // Cancellable jobs always go through the shared queue so that cancelling them
+        // frees their slot straight away.
+        self.shared
+            .push_blocking(DEFAULT_QUEUE, Priority::Normal, job, Some(token.tag()))?;
+        Ok(token)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{sync::mpsc, time::Duration};
+
+    #[test]
+    fn cancelled_job_never_runs() {
+        let pool = Threadpool::build(1).unwrap();
+        let (release_tx, release_rx) = mpsc::channel::<()>();
+        let (started_tx, started_rx) = mpsc::channel();
+        let (ran_tx, ran_rx) = mpsc::channel();
+
+        pool.execute(move || {
+            started_tx.send(()).unwrap();
+            release_rx.recv().unwrap();
+        })
+        .unwrap();
+        started_rx.recv().unwrap();
+        let token = pool
+            .execute_cancellable(move |_| ran_tx.send(()).unwrap())
+            .unwrap();
+
+        assert!(token.cancel());
+        assert_eq!(pool.metrics().queued, 0);
+
+        release_tx.send(()).unwrap();
+        pool.join();
+        assert!(ran_rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn running_job_observes_cancellation() {
+        let pool = Threadpool::build(1).unwrap();
+        let (started_tx, started_rx) = mpsc::channel();
+
+        let token = pool
+            .execute_cancellable(move |token| {
+                started_tx.send(()).unwrap();
+                while !token.is_cancelled() {
+                    std::thread::sleep(Duration::from_millis(1));
+                }
+            })
+            .unwrap();
+
+        started_rx.recv().unwrap();
+        assert!(!token.cancel());
+        pool.join();
+        assert!(token.is_cancelled());
+    }
+}
@@ -0,0 +1,229 @@
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Condvar, Mutex, Weak},
+};
+
+use crate::{ExecuteError, Job, JobId, PoolError, Priority, Shared, Threadpool};
+
+/// A class of jobs of which only `limit` may be queued or running at once, configured
+/// with `ThreadpoolBuilder::category_limit` or created by `Threadpool::child`.
+pub(crate) struct Category {
+    limit: usize,
+    slots: Mutex<Slots>,
+    /// Notified when the last admitted job finishes.
+    idle: Condvar,
+}
+
+#[derive(Default)]
+struct Slots {
+    /// Jobs admitted to the pool and not yet finished.
+    admitted: usize,
+    /// Jobs waiting for an admitted job of the category to finish.
+    waiting: VecDeque<Job>,
+}
+
+impl Category {
+    /// Limits below one are raised to one.
+    pub(crate) fn new(limit: usize) -> Category {
+        Category {
+            limit: limit.max(1),
+            slots: Mutex::default(),
+            idle: Condvar::new(),
+        }
+    }
+
+    pub(crate) fn limit(&self) -> usize {
+        self.limit
+    }
+
+    /// Return the number of jobs admitted or waiting for a slot.
+    pub(crate) fn pending(&self) -> usize {
+        let slots = self.slots.lock().unwrap();
+        slots.admitted + slots.waiting.len()
+    }
+
+    /// Queue `job` on `shared` if the category has a free slot, or keep it until one of
+    /// the admitted jobs finishes.
+    pub(crate) fn admit(
+        self: &Arc<Self>,
+        shared: &Arc<Shared>,
+        job: Job,
+    ) -> Result<(), ExecuteError> {
+        if shared.is_closed() {
+            return Err(PoolError::ShuttingDown);
+        }
+
+        let mut slots = self.slots.lock().unwrap();
+        if slots.admitted >= self.limit {
+            slots.waiting.push_back(job);
+            return Ok(());
+        }
+        slots.admitted += 1;
+        drop(slots);
+
+        let permit = Permit {
+            category: Arc::clone(self),
+            shared: Arc::downgrade(shared),
+        };
+        // On failure the job is dropped, and its permit with it.
+        shared.execute_job(Priority::Normal, permit.wrap(job))
+    }
+
+    /// Block until no job of the category is admitted or waiting.
+    pub(crate) fn wait_idle(&self) {
+        let mut slots = self.slots.lock().unwrap();
+
+        while slots.admitted > 0 {
+            slots = self.idle.wait(slots).unwrap();
+        }
+    }
+
+    /// Remove every job waiting for a slot.
+    pub(crate) fn take_waiting(&self) -> Vec<Job> {
+        self.slots.lock().unwrap().waiting.drain(..).collect()
+    }
+}
+
+/// Moves with an admitted job and frees its slot when dropped, whether the job ran,
+/// panicked or was discarded. The slot goes straight to the next waiting job, if any.
+struct Permit {
+    category: Arc<Category>,
+    shared: Weak<Shared>,
+}
+
+impl Permit {
+    fn wrap(self, job: Job) -> Job {
+        Box::new(move || {
+            let _permit = self;
+            job();
+        })
+    }
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        // Without a pool the waiting jobs can never run, so the slot is just freed.
+        let shared = self.shared.upgrade();
+        let next = {
+            let mut slots = self.category.slots.lock().unwrap();
+            let next = shared.as_ref().and_then(|_| slots.waiting.pop_front());
+            if next.is_none() {
+                slots.admitted -= 1;
+                if slots.admitted == 0 {
+                    self.category.idle.notify_all();
+                }
+            }
+            next
+        };
+
+        if let (Some(job), Some(shared)) = (next, shared) {
+            let permit = Permit {
+                category: Arc::clone(&self.category),
+                shared: Weak::clone(&self.shared),
+            };
+            // Queued even while the pool shuts down, so `shutdown` still runs every
+            // job of the category.
+            shared.push_nonblocking(permit.wrap(job));
+        }
+    }
+}
+
+impl Threadpool {
+    /// Execute a closure using a thread from the pool, counting it against the
+    /// concurrency limit of `category`.
+    ///
+    /// Once the category's limit of jobs are queued or running, further jobs of the
+    /// category wait outside the pool without taking up a worker, and are queued one at
+    /// a time as earlier ones finish. Waiting jobs are not counted in `metrics` until
+    /// they are queued. Categories without a limit run like `execute`.
+    /// ```
+    /// use threadpool::ThreadpoolBuilder;
+    /// let pool = ThreadpoolBuilder::new(8)
+    ///     .category_limit("thumbnailing", 2)
+    ///     .build()
+    ///     .unwrap();
+    ///
+    /// for _ in 0..10 {
+    ///     pool.execute_in_category("thumbnailing", || println!("resizing..."))
+    ///         .unwrap();
+    /// }
+    /// ```
+    pub fn execute_in_category<F>(&self, category: &str, f: F) -> Result<JobId, ExecuteError>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        let Some(limited) = self.shared.categories.get(category) else {
+            return self.execute(f);
+        };
+
+        let (id, job) = self.shared.statuses.track(Box::new(f));
+        limited.admit(&self.shared, job)?;
+        Ok(id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ThreadpoolBuilder;
+    use std::{
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            mpsc, Arc,
+        },
+        thread,
+        time::Duration,
+    };
+
+    #[test]
+    fn category_limit_caps_concurrent_jobs() {
+        let pool = ThreadpoolBuilder::new(4)
+            .category_limit("heavy", 2)
+            .build()
+            .unwrap();
+        let running = Arc::new(AtomicUsize::new(0));
+        let peak = Arc::new(AtomicUsize::new(0));
+
+        for _ in 0..8 {
+            let running = Arc::clone(&running);
+            let peak = Arc::clone(&peak);
+            pool.execute_in_category("heavy", move || {
+                let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+                peak.fetch_max(now, Ordering::SeqCst);
+                thread::sleep(Duration::from_millis(10));
+                running.fetch_sub(1, Ordering::SeqCst);
+            })
+            .unwrap();
+        }
+        pool.join();
+
+        assert_eq!(peak.load(Ordering::SeqCst), 2);
+        assert_eq!(pool.metrics().completed, 8);
+    }
+
+    #[test]
+    fn full_category_leaves_workers_for_other_jobs() {
+        let pool = ThreadpoolBuilder::new(2)
+            .category_limit("heavy", 1)
+            .build()
+            .unwrap();
+        let (started_tx, started_rx) = mpsc::channel();
+        let (release_tx, release_rx) = mpsc::channel::<()>();
+        let (done_tx, done_rx) = mpsc::channel();
+
+        pool.execute_in_category("heavy", move || {
+            started_tx.send(()).unwrap();
+            release_rx.recv().unwrap();
+        })
+        .unwrap();
+        started_rx.recv().unwrap();
+        let heavy_done = done_tx.clone();
+        pool.execute_in_category("heavy", move || heavy_done.send("heavy").unwrap())
+            .unwrap();
+        pool.execute(move || done_tx.send("light").unwrap())
+            .unwrap();
+
+        assert_eq!(done_rx.recv().unwrap(), "light");
+        release_tx.send(()).unwrap();
+        assert_eq!(done_rx.recv().unwrap(), "heavy");
+    }
+}
@@ -0,0 +1,160 @@
+use std::sync::{Arc, Weak};
+
+use crate::{category::Category, ExecuteError, JobId, PoolError, Shared, Threadpool};
+
+/// A bounded slice of a pool, returned by `Threadpool::child`.
+///
+/// A child has no threads of its own: its jobs run on the parent's workers, but no more
+/// than its limit of them are queued or running at once. Further jobs wait in the child
+/// without taking up a worker, so a busy child cannot crowd out its siblings. Like a
+/// `PoolHandle`, a child does not keep the parent alive.
+/// ```
+/// use threadpool::Threadpool;
+/// let pool = Threadpool::build(8).unwrap();
+/// let blog = pool.child(2);
+/// let shop = pool.child(6);
+///
+/// blog.execute(|| println!("serving blog.example.com...")).unwrap();
+/// shop.execute(|| println!("serving shop.example.com...")).unwrap();
+/// blog.join();
+/// shop.join();
+/// ```
+#[derive(Clone)]
+pub struct ChildPool {
+    shared: Weak<Shared>,
+    category: Arc<Category>,
+}
+
+impl ChildPool {
+    /// Execute a closure on one of the parent's workers, or keep it in the child until
+    /// fewer than the limit of the child's jobs are in flight.
+    ///
+    /// Never blocks for a slot in the child, but may block for space in a bounded parent
+    /// queue like `Threadpool::execute`.
+    pub fn execute<F>(&self, f: F) -> Result<JobId, ExecuteError>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        let shared = self.shared.upgrade().ok_or(PoolError::ShuttingDown)?;
+
+        let (id, job) = shared.statuses.track(Box::new(f));
+        self.category.admit(&shared, job)?;
+        Ok(id)
+    }
+
+    /// Return the most jobs of this child that may be queued or running at once.
+    pub fn max_concurrency(&self) -> usize {
+        self.category.limit()
+    }
+
+    /// Return the number of this child's jobs that are queued, running or waiting for
+    /// a slot.
+    pub fn pending(&self) -> usize {
+        self.category.pending()
+    }
+
+    /// Block until every job submitted to this child has finished. Jobs submitted to
+    /// the parent or to other children are not waited for.
+    pub fn join(&self) {
+        self.category.wait_idle();
+    }
+}
+
+impl Threadpool {
+    /// Return a child pool that runs at most `max_concurrency` jobs at once on this
+    /// pool's workers. A limit below one is raised to one.
+    ///
+    /// Limits of different children may add up to more than the parent's size, in
+    /// which case they share the workers as they would any other jobs.
+    pub fn child(&self, max_concurrency: usize) -> ChildPool {
+        let category = Arc::new(Category::new(max_concurrency));
+
+        let mut children = self.shared.children.lock().unwrap();
+        children.retain(|child| child.strong_count() > 0);
+        children.push(Arc::downgrade(&category));
+
+        ChildPool {
+            shared: Arc::downgrade(&self.shared),
+            category,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Threadpool;
+    use std::{
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            mpsc, Arc,
+        },
+        thread,
+        time::Duration,
+    };
+
+    #[test]
+    fn child_limits_its_own_concurrency() {
+        let pool = Threadpool::build(4).unwrap();
+        let child = pool.child(2);
+        let running = Arc::new(AtomicUsize::new(0));
+        let peak = Arc::new(AtomicUsize::new(0));
+
+        for _ in 0..8 {
+            let running = Arc::clone(&running);
+            let peak = Arc::clone(&peak);
+            child
+                .execute(move || {
+                    let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+                    peak.fetch_max(now, Ordering::SeqCst);
+                    thread::sleep(Duration::from_millis(10));
+                    running.fetch_sub(1, Ordering::SeqCst);
+                })
+                .unwrap();
+        }
+        child.join();
+
+        assert_eq!(child.pending(), 0);
+        assert_eq!(peak.load(Ordering::SeqCst), 2);
+        assert_eq!(pool.metrics().completed, 8);
+    }
+
+    #[test]
+    fn full_child_leaves_workers_for_siblings() {
+        let pool = Threadpool::build(2).unwrap();
+        let busy = pool.child(1);
+        let other = pool.child(1);
+        let (release_tx, release_rx) = mpsc::channel::<()>();
+        let (done_tx, done_rx) = mpsc::channel();
+
+        busy.execute(move || release_rx.recv().unwrap()).unwrap();
+        busy.execute(|| {}).unwrap();
+        other.execute(move || done_tx.send(()).unwrap()).unwrap();
+
+        done_rx.recv().unwrap();
+        assert_eq!(busy.pending(), 2);
+        release_tx.send(()).unwrap();
+        busy.join();
+    }
+
+    #[test]
+    fn drain_takes_waiting_child_jobs() {
+        let pool = Threadpool::build(1).unwrap();
+        let child = pool.child(1);
+        let (started_tx, started_rx) = mpsc::channel();
+        let (release_tx, release_rx) = mpsc::channel::<()>();
+
+        child
+            .execute(move || {
+                started_tx.send(()).unwrap();
+                release_rx.recv().unwrap();
+            })
+            .unwrap();
+        started_rx.recv().unwrap();
+        child.execute(|| {}).unwrap();
+
+        assert_eq!(pool.drain().len(), 1);
+        release_tx.send(()).unwrap();
+        child.join();
+        assert_eq!(pool.metrics().completed, 1);
+    }
+}
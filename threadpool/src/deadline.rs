@@ -0,0 +1,105 @@
+use std::time::{Duration, Instant};
+
+use crate::{ExecuteError, JobId, Priority, Threadpool};
+
+/// What a job submitted with `Threadpool::execute_with_deadline` knows about its
+/// deadline.
+#[derive(Debug, Clone, Copy)]
+pub struct JobContext {
+    deadline: Instant,
+}
+
+impl JobContext {
+    /// The instant by which the job should have finished.
+    pub fn deadline(&self) -> Instant {
+        self.deadline
+    }
+
+    /// Whether the deadline has passed.
+    pub fn deadline_exceeded(&self) -> bool {
+        Instant::now() >= self.deadline
+    }
+
+    /// How long is left until the deadline, or zero once it has passed.
+    pub fn remaining(&self) -> Duration {
+        self.deadline.saturating_duration_since(Instant::now())
+    }
+}
+
+impl Threadpool {
+    /// Execute a closure that should be done by `deadline`.
+    ///
+    /// A job whose deadline passes while it is still queued is discarded when a worker
+    /// reaches it, without running. Once running, the job is given a `JobContext` to
+    /// check, and it is up to the job to stop early when the deadline is exceeded.
+    ///
+    /// Fails under the same conditions as `execute`.
+    /// ```
+    /// use std::time::{Duration, Instant};
+    /// use threadpool::Threadpool;
+    /// let pool = Threadpool::build(4).unwrap();
+    ///
+    /// let deadline = Instant::now() + Duration::from_secs(30);
+    /// pool.execute_with_deadline(deadline, |ctx| {
+    ///     for chunk in 0..10 {
+    ///         if ctx.deadline_exceeded() {
+    ///             return;
+    ///         }
+    ///         println!("sending chunk {chunk}...");
+    ///     }
+    /// })
+    /// .unwrap();
+    /// ```
+    pub fn execute_with_deadline<F>(&self, deadline: Instant, f: F) -> Result<JobId, ExecuteError>
+    where
+        F: FnOnce(&JobContext) + Send + 'static,
+    {
+        let ctx = JobContext { deadline };
+
+        self.shared.execute_tracked(
+            Priority::Normal,
+            Box::new(move || {
+                if !ctx.deadline_exceeded() {
+                    f(&ctx);
+                }
+            }),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+
+    #[test]
+    fn expired_jobs_are_skipped() {
+        let pool = Threadpool::build(1).unwrap();
+        let (started_tx, started_rx) = mpsc::channel();
+        let (release_tx, release_rx) = mpsc::channel::<()>();
+        let (ran_tx, ran_rx) = mpsc::channel();
+
+        pool.execute(move || {
+            started_tx.send(()).unwrap();
+            release_rx.recv().unwrap();
+        })
+        .unwrap();
+        started_rx.recv().unwrap();
+
+        let soon = Instant::now() + Duration::from_millis(10);
+        let expired_tx = ran_tx.clone();
+        pool.execute_with_deadline(soon, move |_| expired_tx.send("expired").unwrap())
+            .unwrap();
+        let later = Instant::now() + Duration::from_secs(30);
+        pool.execute_with_deadline(later, move |ctx| {
+            assert!(!ctx.deadline_exceeded());
+            ran_tx.send("on time").unwrap();
+        })
+        .unwrap();
+
+        std::thread::sleep(Duration::from_millis(20));
+        release_tx.send(()).unwrap();
+        pool.join();
+        assert_eq!(ran_rx.try_iter().collect::<Vec<_>>(), ["on time"]);
+    }
+}
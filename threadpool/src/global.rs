@@ -0,0 +1,34 @@
+use std::{env, sync::OnceLock};
+
+use crate::Threadpool;
+
+/// Environment variable that overrides the size of the global pool.
+const SIZE_VAR: &str = "THREADPOOL_SIZE";
+
+static GLOBAL: OnceLock<Threadpool> = OnceLock::new();
+
+/// Return the process-wide pool, creating it on first use.
+///
+/// The pool has `THREADPOOL_SIZE` workers if that environment variable is set to a
+/// positive number, and one worker per available CPU otherwise. It is never shut down,
+/// so jobs still queued when the process exits do not run.
+///
+/// Panics if the pool's worker threads cannot be spawned.
+/// ```
+/// let handle = threadpool::global().submit(|| 2 + 2).unwrap();
+/// assert_eq!(handle.join().unwrap(), 4);
+/// ```
+pub fn global() -> &'static Threadpool {
+    GLOBAL.get_or_init(|| {
+        let size = env::var(SIZE_VAR)
+            .ok()
+            .and_then(|size| size.parse().ok())
+            .filter(|&size| size > 0);
+
+        let pool = match size {
+            Some(size) => Threadpool::build(size),
+            None => Threadpool::new_auto(),
+        };
+        pool.expect("failed to create the global Threadpool")
+    })
+}
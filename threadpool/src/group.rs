@@ -0,0 +1,163 @@
+use std::{
+    sync::{Arc, Condvar, Mutex},
+    time::{Duration, Instant},
+};
+
+/// A set of jobs that can be waited for together, without waiting for the rest of the
+/// pool like `Threadpool::join` does.
+///
+/// Jobs join the group by being wrapped with `add` before they are submitted, so any
+/// submission method works, including those of a `PoolHandle` or a named queue. A
+/// member counts as complete once it has run, panicked or been discarded unrun.
+/// ```
+/// use std::sync::mpsc;
+/// use threadpool::{JobGroup, Threadpool};
+/// let pool = Threadpool::build(4).unwrap();
+/// let group = JobGroup::new();
+/// let (sender, reciever) = mpsc::channel();
+///
+/// for file in ["a.html", "b.html", "c.html"] {
+///     let sender = sender.clone();
+///     pool.execute(group.add(move || sender.send(file.len()).unwrap()))
+///         .unwrap();
+/// }
+/// group.wait();
+/// assert_eq!(reciever.try_iter().sum::<usize>(), 18);
+/// ```
+#[derive(Clone, Default)]
+pub struct JobGroup {
+    latch: Arc<Latch>,
+}
+
+#[derive(Default)]
+struct Latch {
+    pending: Mutex<usize>,
+    finished: Condvar,
+}
+
+/// Moves with a member job and counts it as complete when dropped, whether it ran,
+/// panicked or was discarded.
+struct Member {
+    latch: Arc<Latch>,
+}
+
+impl Drop for Member {
+    fn drop(&mut self) {
+        let mut pending = self.latch.pending.lock().unwrap();
+        *pending -= 1;
+        if *pending == 0 {
+            self.latch.finished.notify_all();
+        }
+    }
+}
+
+impl JobGroup {
+    /// Create a group with no members.
+    pub fn new() -> JobGroup {
+        JobGroup::default()
+    }
+
+    /// Wrap `f` so it counts as a member of the group, ready to be submitted.
+    ///
+    /// The job counts as pending from this call on, so a wrapped closure that is never
+    /// submitted must be dropped for `wait` to return.
+    pub fn add<F, T>(&self, f: F) -> impl FnOnce() -> T + Send + 'static
+    where
+        F: FnOnce() -> T + Send + 'static,
+    {
+        *self.latch.pending.lock().unwrap() += 1;
+        let member = Member {
+            latch: Arc::clone(&self.latch),
+        };
+
+        move || {
+            let _member = member;
+            f()
+        }
+    }
+
+    /// Return the number of members that have not completed yet.
+    pub fn pending(&self) -> usize {
+        *self.latch.pending.lock().unwrap()
+    }
+
+    /// Block until every member added so far has completed.
+    ///
+    /// Waiting from inside one of the pool's own jobs blocks that worker, so the pool
+    /// needs at least one other worker to make progress.
+    pub fn wait(&self) {
+        let mut pending = self.latch.pending.lock().unwrap();
+
+        while *pending > 0 {
+            pending = self.latch.finished.wait(pending).unwrap();
+        }
+    }
+
+    /// Like `wait`, but give up once `timeout` has elapsed.
+    ///
+    /// Returns `true` if every member completed in time.
+    pub fn wait_timeout(&self, timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+        let mut pending = self.latch.pending.lock().unwrap();
+
+        while *pending > 0 {
+            let now = Instant::now();
+            if now >= deadline {
+                return false;
+            }
+
+            pending = self
+                .latch
+                .finished
+                .wait_timeout(pending, deadline - now)
+                .unwrap()
+                .0;
+        }
+
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Threadpool;
+    use std::sync::mpsc;
+
+    #[test]
+    fn wait_ignores_jobs_outside_the_group() {
+        let pool = Threadpool::build(2).unwrap();
+        let group = JobGroup::new();
+        let (release_tx, release_rx) = mpsc::channel::<()>();
+
+        pool.execute(move || release_rx.recv().unwrap()).unwrap();
+        let handle = pool.submit(group.add(|| 7)).unwrap();
+
+        group.wait();
+        assert_eq!(group.pending(), 0);
+        assert_eq!(handle.join().unwrap(), 7);
+        release_tx.send(()).unwrap();
+    }
+
+    #[test]
+    fn panicked_and_discarded_members_complete() {
+        let pool = Threadpool::build(1).unwrap();
+        let group = JobGroup::new();
+        let (started_tx, started_rx) = mpsc::channel();
+        let (release_tx, release_rx) = mpsc::channel::<()>();
+
+        pool.execute(group.add(move || {
+            started_tx.send(()).unwrap();
+            release_rx.recv().unwrap();
+            panic!("boom");
+        }))
+        .unwrap();
+        started_rx.recv().unwrap();
+        pool.execute(group.add(|| {})).unwrap();
+
+        assert!(!group.wait_timeout(Duration::from_millis(10)));
+        drop(pool.drain());
+        release_tx.send(()).unwrap();
+        assert!(group.wait_timeout(Duration::from_secs(5)));
+    }
+}
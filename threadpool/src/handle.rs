@@ -0,0 +1,114 @@
+use std::sync::{Arc, Weak};
+
+use crate::{
+    ExecuteError, JobHandle, JobId, PoolError, Priority, Shared, Threadpool, TryExecuteError,
+};
+
+/// A cheap, cloneable handle for submitting jobs to a pool, returned by
+/// `Threadpool::handle`.
+///
+/// A handle does not keep the pool alive: once the pool has been shut down or dropped,
+/// every submission through it returns a `ShuttingDown` error.
+/// ```
+/// use std::thread;
+/// use threadpool::Threadpool;
+/// let pool = Threadpool::build(2).unwrap();
+///
+/// let handle = pool.handle();
+/// thread::spawn(move || handle.execute(|| println!("executing...")).unwrap())
+///     .join()
+///     .unwrap();
+/// ```
+#[derive(Clone)]
+pub struct PoolHandle {
+    shared: Weak<Shared>,
+}
+
+impl PoolHandle {
+    /// Execute a closure using a thread from the pool, like `Threadpool::execute`.
+    pub fn execute<F>(&self, f: F) -> Result<JobId, ExecuteError>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        self.execute_with_priority(Priority::Normal, f)
+    }
+
+    /// Execute a closure at the given priority, like `Threadpool::execute_with_priority`.
+    pub fn execute_with_priority<F>(&self, priority: Priority, f: F) -> Result<JobId, ExecuteError>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        self.shared()?.execute_tracked(priority, Box::new(f))
+    }
+
+    /// Execute a closure without blocking, like `Threadpool::try_execute`.
+    pub fn try_execute<F>(&self, f: F) -> Result<JobId, TryExecuteError<F>>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        match self.shared() {
+            Ok(shared) => shared.try_execute(f),
+            Err(error) => Err(TryExecuteError::new(error, f)),
+        }
+    }
+
+    /// Execute a closure and return a handle to its result, like `Threadpool::submit`.
+    pub fn submit<F, T>(&self, f: F) -> Result<JobHandle<T>, ExecuteError>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        self.shared()?.submit(f)
+    }
+
+    fn shared(&self) -> Result<Arc<Shared>, ExecuteError> {
+        self.shared
+            .upgrade()
+            .filter(|shared| !shared.is_closed())
+            .ok_or(PoolError::ShuttingDown)
+    }
+}
+
+impl Threadpool {
+    /// Return a handle that can submit jobs to this pool from anywhere, without sharing
+    /// the pool itself.
+    pub fn handle(&self) -> PoolHandle {
+        PoolHandle {
+            shared: Arc::downgrade(&self.shared),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+
+    fn assert_shareable<T: Clone + Send + Sync>() {}
+
+    #[test]
+    fn handle_submits_to_the_pool() {
+        assert_shareable::<PoolHandle>();
+
+        let pool = Threadpool::build(2).unwrap();
+        let handle = pool.handle();
+
+        let result = handle.submit(|| 6 * 7).unwrap();
+
+        assert_eq!(result.join().unwrap(), 42);
+    }
+
+    #[test]
+    fn handle_returns_err_after_pool_is_dropped() {
+        let pool = Threadpool::build(1).unwrap();
+        let handle = pool.handle();
+        let (sender, reciever) = mpsc::channel::<()>();
+        drop(pool);
+
+        assert!(matches!(
+            handle.execute(move || sender.send(()).unwrap()),
+            Err(PoolError::ShuttingDown)
+        ));
+        assert!(reciever.recv().is_err());
+    }
+}
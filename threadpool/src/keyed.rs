@@ -0,0 +1,82 @@
+use std::{
+    collections::hash_map::DefaultHasher,
+    hash::{Hash, Hasher},
+};
+
+use crate::{ExecuteError, JobId, Threadpool};
+
+impl Threadpool {
+    /// Execute a closure after every job previously submitted with the same `key`, and
+    /// before any submitted with it later.
+    ///
+    /// Keys are hashed onto one lane per worker the pool was built with, and each lane
+    /// runs one job at a time in submission order, so jobs for one key never overlap
+    /// and need no locking between them. Jobs for different keys usually run in
+    /// parallel, but keys that share a lane take turns. Jobs waiting behind an earlier
+    /// one of their lane do not take up a worker. The lane's jobs may run on any worker,
+    /// so a worker that panics or is recycled does not break the ordering.
+    ///
+    /// Fails under the same conditions as `execute`.
+    /// ```
+    /// use threadpool::Threadpool;
+    /// let pool = Threadpool::build(4).unwrap();
+    ///
+    /// for request in 0..3 {
+    ///     pool.execute_keyed("session-42", move || println!("request {request}..."))
+    ///         .unwrap();
+    /// }
+    /// ```
+    pub fn execute_keyed<K, F>(&self, key: K, f: F) -> Result<JobId, ExecuteError>
+    where
+        K: Hash,
+        F: FnOnce() + Send + 'static,
+    {
+        let lanes = &self.shared.lanes;
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        let lane = &lanes[hasher.finish() as usize % lanes.len()];
+
+        let (id, job) = self.shared.statuses.track(Box::new(f));
+        lane.admit(&self.shared, job)?;
+        Ok(id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Threadpool;
+    use std::{
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            mpsc, Arc,
+        },
+        thread,
+        time::Duration,
+    };
+
+    #[test]
+    fn jobs_with_the_same_key_run_serially_in_order() {
+        let pool = Threadpool::build(4).unwrap();
+        let (sender, reciever) = mpsc::channel();
+        let running = Arc::new(AtomicBool::new(false));
+
+        for i in 0..20 {
+            let sender = sender.clone();
+            let running = Arc::clone(&running);
+            pool.execute_keyed("session", move || {
+                assert!(!running.swap(true, Ordering::SeqCst));
+                thread::sleep(Duration::from_millis(1));
+                running.store(false, Ordering::SeqCst);
+                sender.send(i).unwrap();
+            })
+            .unwrap();
+        }
+        pool.join();
+
+        assert_eq!(
+            reciever.try_iter().collect::<Vec<_>>(),
+            (0..20).collect::<Vec<_>>()
+        );
+        assert_eq!(pool.metrics().panicked, 0);
+    }
+}
@@ -1,12 +1,98 @@
+use category::Category;
 use core::fmt;
+use local::LocalThread;
+use metrics::{Counters, SlowDequeueHook};
+use middleware::Layers;
+use mpmc::ArrayQueue;
+use queue::{JobQueue, DEFAULT_QUEUE};
+use scope::StdScopes;
+use snapshot::Activity;
+use status::JobTable;
 use std::{
-    sync::{mpsc, Arc, Mutex},
+    any::Any,
+    collections::HashMap,
+    error::Error,
+    hint, io,
+    panic::{self, AssertUnwindSafe},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        mpsc, Arc, Mutex, RwLock, Weak,
+    },
     thread,
+    time::{Duration, Instant},
 };
+use timer::Timer;
+use watchdog::Watchdog;
+// The lock on the dispatch path, around the shared queue and the local deques, and the
+// condition variables that wait on the shared one.
+#[cfg(feature = "spin-lock")]
+use spin::{SpinCondvar as QueueCondvar, SpinMutex as QueueLock};
+#[cfg(not(feature = "spin-lock"))]
+use std::sync::{Condvar as QueueCondvar, Mutex as QueueLock};
+use worker::{LocalQueue, PanicHook, Worker, WorkerHook};
+
+#[cfg(feature = "affinity")]
+mod affinity;
+mod builder;
+mod cancel;
+mod category;
+mod child;
+mod deadline;
+#[cfg(feature = "global")]
+mod global;
+mod group;
+mod handle;
+mod keyed;
+mod local;
+mod metrics;
+mod middleware;
+mod mpmc;
+mod named;
+mod observer;
+mod queue;
+mod runnable;
+mod schedule;
+mod scope;
+mod segmented;
+mod snapshot;
+#[cfg(feature = "spans")]
+mod spans;
+#[cfg(feature = "spin-lock")]
+mod spin;
+mod status;
+mod task;
+mod throttle;
+mod timer;
+mod watchdog;
+mod worker;
+
+pub use builder::ThreadpoolBuilder;
+pub use cancel::CancelToken;
+pub use child::ChildPool;
+pub use deadline::JobContext;
+#[cfg(feature = "global")]
+pub use global::global;
+pub use group::JobGroup;
+pub use handle::PoolHandle;
+pub use metrics::{Histogram, Latency, Metrics};
+pub use named::NamedQueue;
+pub use observer::{JobInfo, PoolObserver, StdoutObserver, WorkerExit};
+pub use queue::{Priority, RejectionPolicy, SchedulingPolicy};
+pub use runnable::{Recycled, Recycler, Runnable};
+pub use schedule::{RepeatMode, ScheduleHandle};
+pub use scope::Scope;
+pub use segmented::SegmentedPool;
+pub use snapshot::{PoolSnapshot, RunningJob, WorkerSnapshot};
+#[cfg(feature = "spans")]
+pub use spans::{JobSpan, SpanObserver, Stage};
+#[cfg(feature = "spin-lock")]
+pub use spin::{SpinCondvar, SpinMutex, SpinMutexGuard, WaitTimeoutResult};
+pub use status::{JobId, JobStatus};
+pub use task::{block_on, TaskHandle};
+pub use throttle::ThrottledHandle;
 
 pub struct Threadpool {
-    workers: Vec<Worker>,
-    sender: Option<mpsc::Sender<Job>>,
+    shared: Arc<Shared>,
 }
 
 impl Threadpool {
@@ -14,114 +100,2178 @@ impl Threadpool {
     ///
     /// The size is the number of threads in the pool.
     ///
-    /// The 'build' function will return an PoolCreationError if the size is zero.
+    /// The 'build' function will return a `ZeroSize` error if the size is zero.
     /// ```
     /// use threadpool::Threadpool;
     /// let pool = Threadpool::build(4);
     /// ```
-    pub fn build(size: usize) -> Result<Threadpool, PoolCreationError> {
-        if size == 0 {
-            return Err(PoolCreationError);
-        } else {
-            let (sender, reciever) = mpsc::channel();
+    pub fn build(size: usize) -> Result<Threadpool, PoolError> {
+        ThreadpoolBuilder::new(size).build()
+    }
 
-            let reciever = Arc::new(Mutex::new(reciever));
+    /// Create a Threadpool with one thread per CPU available to this process.
+    ///
+    /// Falls back to a single thread if the available parallelism cannot be determined.
+    /// ```
+    /// use threadpool::Threadpool;
+    /// let pool = Threadpool::new_auto().unwrap();
+    /// assert!(pool.size() >= 1);
+    /// ```
+    pub fn new_auto() -> Result<Threadpool, PoolError> {
+        Threadpool::new_auto_with_multiplier(1)
+    }
 
-            let mut workers = Vec::with_capacity(size);
+    /// Create a Threadpool with `multiplier` threads per available CPU.
+    ///
+    /// Jobs that spend most of their time waiting on IO leave CPUs idle, so such
+    /// workloads usually want a multiplier above one.
+    ///
+    /// Returns `ZeroSize` if the multiplier is zero.
+    /// ```
+    /// use threadpool::Threadpool;
+    /// let pool = Threadpool::new_auto_with_multiplier(2).unwrap();
+    /// assert!(pool.size() >= 2);
+    /// ```
+    pub fn new_auto_with_multiplier(multiplier: usize) -> Result<Threadpool, PoolError> {
+        let cpus = thread::available_parallelism().map_or(1, |cpus| cpus.get());
 
-            for id in 0..size {
-                workers.push(Worker::new(id, Arc::clone(&reciever)));
-            }
-            return Ok(Threadpool {
-                workers,
-                sender: Some(sender),
-            });
-        }
+        Threadpool::build(cpus.saturating_mul(multiplier))
     }
 
     /// Execute a closure using a thread from the pool.
     ///
+    /// If the pool was built with a queue capacity and the queue is full, this blocks
+    /// until a worker takes a job off the queue.
+    ///
+    /// Returns an id that can be passed to `status` to follow the job. Returns a
+    /// `ShuttingDown` error, dropping the closure, if the pool has been shut down.
     /// ```
     /// use threadpool::Threadpool;
     /// let pool = Threadpool::build(1).unwrap();
     ///
-    /// pool.execute(|| {println!("executing...")})
+    /// pool.execute(|| {println!("executing...")}).unwrap();
     /// ```
-    pub fn execute<F>(&self, f: F)
+    pub fn execute<F>(&self, f: F) -> Result<JobId, ExecuteError>
     where
         F: FnOnce() + Send + 'static,
     {
-        let job = Box::new(f);
+        self.execute_with_priority(Priority::Normal, f)
+    }
 
-        self.sender.as_ref().unwrap().send(job).unwrap();
+    /// Execute a closure using a thread from the pool, ahead of any queued jobs with a
+    /// lower priority.
+    ///
+    /// Blocks and fails under the same conditions as `execute`.
+    ///
+    /// `Normal` priority jobs submitted from one of the pool's own workers skip the
+    /// shared queue and go onto that worker's local deque, where idle workers can steal
+    /// them. Such jobs are not counted against the queue capacity.
+    /// ```
+    /// use threadpool::{Priority, Threadpool};
+    /// let pool = Threadpool::build(1).unwrap();
+    ///
+    /// pool.execute_with_priority(Priority::High, || println!("executing first...")).unwrap();
+    /// ```
+    pub fn execute_with_priority<F>(&self, priority: Priority, f: F) -> Result<JobId, ExecuteError>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        self.shared.execute_tracked(priority, Box::new(f))
     }
-}
 
-impl Drop for Threadpool {
-    fn drop(&mut self) {
-        drop(self.sender.take());
+    /// Execute a closure using a thread from the pool once `delay` has elapsed.
+    ///
+    /// The job waits in a separate delay queue and only enters the job queue when it is
+    /// due, so no worker is blocked in the meantime. Delayed jobs do not count towards
+    /// `join` or the queue capacity until they are due, and jobs that are not yet due
+    /// when the pool shuts down are discarded.
+    ///
+    /// Returns a `ShuttingDown` error if the pool has been shut down.
+    /// ```
+    /// use std::time::Duration;
+    /// use threadpool::Threadpool;
+    /// let pool = Threadpool::build(1).unwrap();
+    ///
+    /// pool.execute_after(Duration::from_secs(30), || println!("expiring sessions..."))
+    ///     .unwrap();
+    /// ```
+    pub fn execute_after<F>(&self, delay: Duration, f: F) -> Result<(), ExecuteError>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        if !Timer::schedule(&self.shared, Instant::now() + delay, Box::new(f)) {
+            return Err(PoolError::ShuttingDown);
+        }
+        Ok(())
+    }
+
+    /// Queue a batch of jobs while taking the queue lock only once.
+    ///
+    /// This is cheaper than calling `execute` in a loop when submitting many small jobs.
+    /// Batched jobs always go onto the shared queue at `Normal` priority. If the pool has
+    /// a queue capacity this blocks whenever the queue fills up. If the pool shuts down
+    /// part way through, the jobs that were not queued yet are dropped and a
+    /// `ShuttingDown` error is returned.
+    /// ```
+    /// use threadpool::{Job, Threadpool};
+    /// let pool = Threadpool::build(4).unwrap();
+    ///
+    /// pool.execute_batch((0..100).map(|i| Box::new(move || println!("job {i}")) as Job))
+    ///     .unwrap();
+    /// pool.join();
+    /// ```
+    pub fn execute_batch<I>(&self, jobs: I) -> Result<(), ExecuteError>
+    where
+        I: IntoIterator<Item = Job>,
+    {
+        if let Some(injector) = &self.shared.injector {
+            // Pushing to the lock-free queue takes no lock to amortise.
+            for job in jobs {
+                self.shared.inject_blocking(injector, job)?;
+            }
+            return Ok(());
+        }
+
+        // Overflowing jobs need the lock released to be run or dropped.
+        if self.shared.rejection_policy != RejectionPolicy::Block {
+            for job in jobs {
+                self.shared
+                    .push_blocking(DEFAULT_QUEUE, Priority::Normal, job, None)?;
+            }
+            return Ok(());
+        }
+
+        let mut state = self.shared.state.lock().unwrap();
+
+        for job in jobs {
+            while self.shared.is_full(&state) && !self.shared.is_closed() {
+                state = self.shared.space_available.wait(state).unwrap();
+            }
+
+            if self.shared.is_closed() {
+                return Err(PoolError::ShuttingDown);
+            }
+
+            self.shared.push(&mut state, Priority::Normal, job);
+        }
+        drop(state);
+
+        self.shared.spawn_if_busy();
+        Ok(())
+    }
+
+    /// Apply `f` to every item on the pool and collect the results in input order.
+    ///
+    /// Items are split into a few chunks per worker rather than submitted one job per
+    /// item. If `f` panics for any item, `map` panics once the other chunks finish.
+    /// ```
+    /// use threadpool::Threadpool;
+    /// let pool = Threadpool::build(4).unwrap();
+    ///
+    /// let squares = pool.map(1..=5, |n| n * n);
+    /// assert_eq!(squares, [1, 4, 9, 16, 25]);
+    /// ```
+    pub fn map<T, R, I, F>(&self, items: I, f: F) -> Vec<R>
+    where
+        I: IntoIterator<Item = T>,
+        T: Send,
+        R: Send,
+        F: Fn(T) -> R + Sync,
+    {
+        let items: Vec<T> = items.into_iter().collect();
+        let chunk_len = items.len().div_ceil(self.size() * 4).max(1);
+        let mut results: Vec<Option<R>> = items.iter().map(|_| None).collect();
+        let mut items = items.into_iter();
+        let f = &f;
+
+        self.scope(|s| {
+            for outputs in results.chunks_mut(chunk_len) {
+                let inputs: Vec<T> = items.by_ref().take(outputs.len()).collect();
+
+                s.execute(move || {
+                    for (output, input) in outputs.iter_mut().zip(inputs) {
+                        *output = Some(f(input));
+                    }
+                });
+            }
+        });
+
+        results.into_iter().map(Option::unwrap).collect()
+    }
+
+    /// Execute a closure using a thread from the pool without blocking.
+    ///
+    /// If the queue is full, or the pool has been shut down, the closure is handed back
+    /// inside a `TryExecuteError` so the caller can decide whether to retry, run it
+    /// inline or drop it.
+    /// ```
+    /// use threadpool::ThreadpoolBuilder;
+    /// let pool = ThreadpoolBuilder::new(1).queue_capacity(8).build().unwrap();
+    ///
+    /// if let Err(rejected) = pool.try_execute(|| println!("executing...")) {
+    ///     (rejected.into_job())();
+    /// }
+    /// ```
+    pub fn try_execute<F>(&self, f: F) -> Result<JobId, TryExecuteError<F>>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        self.shared.try_execute(f)
+    }
+
+    /// Execute a closure using a thread from the pool and return a handle to its result.
+    ///
+    /// Calling `join` on the returned handle blocks until the closure has run. If the
+    /// closure panics, `join` returns `JoinError::JobPanicked` with the panic payload
+    /// instead of the value.
+    ///
+    /// Fails under the same conditions as `execute`.
+    /// ```
+    /// use threadpool::Threadpool;
+    /// let pool = Threadpool::build(1).unwrap();
+    ///
+    /// let handle = pool.submit(|| 2 + 2).unwrap();
+    /// assert_eq!(handle.join().unwrap(), 4);
+    /// ```
+    pub fn submit<F, T>(&self, f: F) -> Result<JobHandle<T>, ExecuteError>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        self.shared.submit(f)
+    }
+
+    /// Return the number of workers the pool is currently sized for.
+    ///
+    /// Workers asked to exit by `shrink` are no longer counted, even if they are still
+    /// finishing their current job.
+    pub fn size(&self) -> usize {
+        self.shared.state.lock().unwrap().size
+    }
+
+    /// Spawn `additional` new workers.
+    ///
+    /// Returns a `SpawnFailed` error if a thread could not be spawned. Workers spawned
+    /// before the failure keep running.
+    /// ```
+    /// use threadpool::Threadpool;
+    /// let pool = Threadpool::build(2).unwrap();
+    ///
+    /// pool.grow(2).unwrap();
+    /// assert_eq!(pool.size(), 4);
+    /// ```
+    pub fn grow(&self, additional: usize) -> Result<(), PoolError> {
+        let ids = {
+            let mut state = self.shared.state.lock().unwrap();
+            let first = state.next_id;
+
+            state.next_id += additional;
+            state.size += additional;
+            state.core += additional;
+            first..state.next_id
+        };
+        let end = ids.end;
+
+        for id in ids {
+            match Worker::new(id, Arc::clone(&self.shared)) {
+                Ok(worker) => self.shared.workers.lock().unwrap().push(worker),
+                Err(error) => {
+                    let mut state = self.shared.state.lock().unwrap();
+                    state.size -= end - id;
+                    state.core -= end - id;
+                    return Err(PoolError::SpawnFailed(error));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Ask up to `count` workers to exit once they have finished their current job.
+    ///
+    /// The pool always keeps at least one worker, so shrinking by the full size leaves a
+    /// single worker running.
+    pub fn shrink(&self, count: usize) {
+        let mut state = self.shared.state.lock().unwrap();
+        let count = count.min(state.size - 1);
+
+        state.size -= count;
+        state.core = state.core.min(state.size);
+        self.shared.retiring.fetch_add(count, Ordering::SeqCst);
+        self.shared.job_available.notify_all();
+    }
+
+    /// Grow or shrink the pool so that it has `size` workers.
+    pub fn set_size(&self, size: usize) -> Result<(), PoolError> {
+        let current = self.size();
+
+        if size > current {
+            self.grow(size - current)
+        } else {
+            self.shrink(current - size);
+            Ok(())
+        }
+    }
+
+    /// Replace every worker with a freshly spawned thread, one worker at a time.
+    ///
+    /// Each worker finishes its current job, exits and is replaced by a new thread with
+    /// the same id before the next worker is asked, so all but one worker keep taking
+    /// jobs throughout. This drops whatever the old threads built up in thread-locals,
+    /// and the worker hooks run for the old and the new threads as they would for any
+    /// other. Blocks until every worker has been replaced, which includes waiting for
+    /// long-running jobs to finish. Concurrent calls recycle the workers in turn.
+    /// ```
+    /// use threadpool::Threadpool;
+    /// let pool = Threadpool::build(4).unwrap();
+    ///
+    /// pool.execute(|| println!("executing...")).unwrap();
+    /// pool.recycle_workers();
+    /// assert_eq!(pool.size(), 4);
+    /// ```
+    pub fn recycle_workers(&self) {
+        let _recycling = self.shared.recycling.lock().unwrap();
+        let ids: Vec<_> = self
+            .shared
+            .workers
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|worker| worker.id)
+            .collect();
 
-        for worker in &mut self.workers {
-            println!("Shutting down worker {}", worker.id);
+        for id in ids {
+            self.shared.recycle_worker(id);
+        }
+    }
+
+    /// Stop workers from starting new jobs until `resume` is called.
+    ///
+    /// Jobs that are already running finish normally, and new jobs are still accepted
+    /// and queued. While the pool is paused `join` only returns once the queue is empty,
+    /// so it blocks until the pool is resumed. Shutting the pool down overrides a pause
+    /// so queued jobs are drained.
+    /// ```
+    /// use threadpool::Threadpool;
+    /// let pool = Threadpool::build(2).unwrap();
+    ///
+    /// pool.pause();
+    /// pool.execute(|| println!("runs after resume")).unwrap();
+    /// assert_eq!(pool.metrics().queued, 1);
+    ///
+    /// pool.resume();
+    /// pool.join();
+    /// ```
+    pub fn pause(&self) {
+        let _state = self.shared.state.lock().unwrap();
+        self.shared.paused.store(true, Ordering::SeqCst);
+    }
+
+    /// Let workers pick up jobs again after `pause`.
+    pub fn resume(&self) {
+        let _state = self.shared.state.lock().unwrap();
+        self.shared.paused.store(false, Ordering::SeqCst);
+        self.shared.job_available.notify_all();
+    }
+
+    /// Whether the pool is currently paused.
+    pub fn is_paused(&self) -> bool {
+        self.shared.paused.load(Ordering::SeqCst)
+    }
+
+    /// Return a snapshot of the pool's queue depth, busy workers and job counts.
+    /// ```
+    /// use threadpool::Threadpool;
+    /// let pool = Threadpool::build(2).unwrap();
+    ///
+    /// pool.execute(|| println!("executing...")).unwrap();
+    /// pool.join();
+    /// assert_eq!(pool.metrics().completed, 1);
+    /// ```
+    pub fn metrics(&self) -> Metrics {
+        self.shared.counters.snapshot()
+    }
+
+    /// Return histograms of how long jobs waited in the queue and how long they ran.
+    pub fn latency(&self) -> Latency {
+        self.shared.counters.latency()
+    }
+
+    /// Block until the queue is empty and no worker is running a job.
+    ///
+    /// Unlike `shutdown` the pool stays usable afterwards, so it can be reused for the
+    /// next batch of jobs.
+    /// ```
+    /// use std::sync::{atomic::{AtomicUsize, Ordering}, Arc};
+    /// use threadpool::Threadpool;
+    /// let pool = Threadpool::build(4).unwrap();
+    /// let counter = Arc::new(AtomicUsize::new(0));
+    ///
+    /// for _ in 0..8 {
+    ///     let counter = Arc::clone(&counter);
+    ///     pool.execute(move || { counter.fetch_add(1, Ordering::SeqCst); }).unwrap();
+    /// }
+    /// pool.join();
+    /// assert_eq!(counter.load(Ordering::SeqCst), 8);
+    /// ```
+    pub fn join(&self) {
+        let mut state = self.shared.state.lock().unwrap();
+
+        while !self.shared.is_idle() {
+            state = self.shared.idle.wait(state).unwrap();
+        }
+    }
+
+    /// Like `join`, but give up waiting once `timeout` has elapsed.
+    ///
+    /// Returns `true` if the pool became idle in time.
+    pub fn wait_idle_timeout(&self, timeout: Duration) -> bool {
+        let state = self.shared.state.lock().unwrap();
+        let (state, _) = self
+            .shared
+            .idle
+            .wait_timeout_while(state, timeout, |_| !self.shared.is_idle())
+            .unwrap();
+        drop(state);
+
+        self.shared.is_idle()
+    }
+
+    /// Stop accepting jobs, run everything already queued and wait for every worker to
+    /// exit.
+    /// ```
+    /// use threadpool::Threadpool;
+    /// let pool = Threadpool::build(2).unwrap();
+    ///
+    /// pool.execute(|| println!("executing...")).unwrap();
+    /// pool.shutdown();
+    /// ```
+    pub fn shutdown(&self) {
+        self.shared.close();
+        self.join_workers();
+    }
+
+    /// Like `shutdown`, but give up waiting once `timeout` has elapsed.
+    ///
+    /// Returns `true` if every worker exited in time. Otherwise the remaining workers are
+    /// detached: they keep draining the queue in the background but are no longer
+    /// joined, and `false` is returned.
+    pub fn shutdown_timeout(&self, timeout: Duration) -> bool {
+        self.shared.close();
+
+        let deadline = Instant::now() + timeout;
+        let mut state = self.shared.state.lock().unwrap();
+
+        while state.live > 0 {
+            let now = Instant::now();
+            if now >= deadline {
+                break;
+            }
+
+            state = self
+                .shared
+                .worker_exited
+                .wait_timeout(state, deadline - now)
+                .unwrap()
+                .0;
+        }
+
+        let finished = state.live == 0;
+        drop(state);
+
+        if finished {
+            self.join_workers();
+        } else {
+            for worker in self.shared.workers.lock().unwrap().iter_mut() {
+                drop(worker.thread.take());
+            }
+            drop(self.shared.local.take_thread());
+        }
+
+        finished
+    }
+
+    /// Stop accepting jobs, discard everything still queued and wait for the running jobs
+    /// to finish.
+    ///
+    /// The discarded jobs, including delayed jobs that were not yet due, are returned so
+    /// the caller can run, persist or drop them.
+    pub fn shutdown_now(&self) -> Vec<Job> {
+        let mut jobs = self.shared.timer.close();
+        jobs.extend(self.shared.take_queued());
+
+        self.shared.close();
+        self.join_workers();
+
+        jobs
+    }
+
+    /// Remove every job that has not started yet and hand it back, leaving the pool
+    /// running.
+    ///
+    /// Jobs already running are unaffected, and delayed jobs stay scheduled until they
+    /// are due. Producers blocked on a full queue are woken, so jobs submitted while
+    /// draining may end up either in the returned list or in the pool.
+    /// ```
+    /// use threadpool::Threadpool;
+    /// let pool = Threadpool::build(2).unwrap();
+    /// pool.pause();
+    /// pool.execute(|| println!("never started")).unwrap();
+    ///
+    /// let pending = pool.drain();
+    /// assert_eq!(pending.len(), 1);
+    /// ```
+    pub fn drain(&self) -> Vec<Job> {
+        let jobs = self.shared.take_queued();
+
+        let _state = self.shared.state.lock().unwrap();
+        self.shared.space_available.notify_all();
+        jobs
+    }
 
-            if let Some(thread) = worker.thread.take() {
+    fn join_workers(&self) {
+        // A worker that panics while the queue drains installs a replacement in its
+        // slot before exiting, so keep joining until no running thread is left.
+        loop {
+            let threads: Vec<_> = self
+                .shared
+                .workers
+                .lock()
+                .unwrap()
+                .iter_mut()
+                .filter_map(|worker| worker.thread.take())
+                .collect();
+
+            if threads.is_empty() {
+                break;
+            }
+
+            for thread in threads {
                 thread.join().unwrap();
-            };
+            }
         }
+
+        if let Some(thread) = self.shared.local.take_thread() {
+            thread.join().unwrap();
+        }
+    }
+}
+
+/// A handle to the result of a job started with `Threadpool::submit`.
+pub struct JobHandle<T> {
+    reciever: mpsc::Receiver<Result<T, JoinError>>,
+}
+
+impl<T> JobHandle<T> {
+    /// Block until the job has finished and return the value it produced.
+    ///
+    /// Returns `JobPanicked` with the panic payload if the job panicked, and `Dropped`
+    /// if it was discarded without running.
+    pub fn join(self) -> Result<T, JoinError> {
+        self.reciever.recv().unwrap_or(Err(JoinError::Dropped))
     }
 }
 
-struct Worker {
-    id: usize,
-    thread: Option<thread::JoinHandle<()>>,
+impl Drop for Threadpool {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+/// A boxed closure waiting to be run by the pool.
+pub type Job = Box<dyn FnOnce() + Send + 'static>;
+
+/// A queued job together with the moment it was queued, to measure how long it waited.
+type Stamped = (Job, Instant);
+
+/// State shared between the pool and its workers.
+struct Shared {
+    state: QueueLock<State>,
+    job_available: QueueCondvar,
+    space_available: QueueCondvar,
+    worker_exited: QueueCondvar,
+    idle: QueueCondvar,
+    counters: Counters,
+    statuses: Arc<JobTable>,
+    observer: Arc<dyn PoolObserver>,
+    /// What each worker is doing, for `Threadpool::snapshot`.
+    activity: Arc<Activity>,
+    /// Jobs tied to `std::thread::Scope`s, see `Threadpool::execute_scoped`.
+    std_scopes: Arc<StdScopes>,
+    /// Middleware added with `Threadpool::wrap`, run around every job.
+    middleware: Layers,
+    on_worker_start: Option<WorkerHook>,
+    on_worker_stop: Option<WorkerHook>,
+    on_panic: Option<PanicHook>,
+    /// Called for jobs that waited in the queue for longer than the threshold.
+    on_slow_dequeue: Option<(Duration, SlowDequeueHook)>,
+    timer: Timer,
+    local: LocalThread,
+    watchdog: Option<Watchdog>,
+    /// Concurrency limits for `Threadpool::execute_in_category`, by category name.
+    categories: HashMap<String, Arc<Category>>,
+    /// Limits of the child pools created with `Threadpool::child`.
+    children: Mutex<Vec<Weak<Category>>>,
+    /// Serial lanes for `Threadpool::execute_keyed`, one per initial worker.
+    lanes: Vec<Arc<Category>>,
+    capacity: Option<usize>,
+    /// What blocking submissions do when the queue is at capacity.
+    rejection_policy: RejectionPolicy,
+    /// Upper bound on elastic growth, if the pool is elastic.
+    max_size: Option<usize>,
+    /// How long an elastic worker may sit idle before it exits.
+    keep_alive: Duration,
+    /// How long a worker spins for work before it parks.
+    idle_spin: Duration,
+    /// Most jobs a worker takes from `state` per lock.
+    dequeue_batch: usize,
+    thread_name: Option<String>,
+    stack_size: Option<usize>,
+    /// Cores to pin workers to, assigned round-robin by worker id.
+    #[cfg(feature = "affinity")]
+    cores: Vec<usize>,
+    /// Cores of each NUMA node to spread workers across, empty unless NUMA-aware.
+    #[cfg(feature = "affinity")]
+    numa_nodes: Vec<Vec<usize>>,
+    workers: Mutex<Vec<Worker>>,
+    /// The local deque of every running worker, used for stealing.
+    locals: RwLock<Vec<Arc<LocalQueue>>>,
+    /// Optional lock-free queue for jobs submitted from outside the pool.
+    injector: Option<ArrayQueue<Stamped>>,
+    /// Jobs queued outside of `state`: in local deques or in the lock-free queue.
+    unlocked_jobs: AtomicUsize,
+    /// Producers blocked waiting for space in the lock-free queue.
+    waiting_producers: AtomicUsize,
+    /// Workers blocked waiting for `job_available`.
+    sleepers: AtomicUsize,
+    /// Idle workers spinning for work before they park.
+    spinners: AtomicUsize,
+    /// Jobs that have been submitted but have not finished yet, wherever they are queued.
+    in_flight: AtomicUsize,
+    /// Number of workers that have been asked to exit but have not yet done so.
+    retiring: AtomicUsize,
+    /// The id of the worker asked to recycle itself, or `NO_WORKER`.
+    recycle: AtomicUsize,
+    /// Number of workers that have been recycled, to tell when a request is done.
+    recycled: AtomicUsize,
+    /// Held by `Threadpool::recycle_workers` so only one worker recycles at a time.
+    recycling: Mutex<()>,
+    /// Set by `pause`; workers stop taking jobs until it is cleared.
+    paused: AtomicBool,
+    closed: AtomicBool,
+}
+
+struct State {
+    jobs: JobQueue,
+    /// Number of workers the pool is sized for, including extra elastic workers.
+    size: usize,
+    /// Number of workers kept alive even when idle. Equal to `size` unless the pool is
+    /// elastic.
+    core: usize,
+    /// Number of worker threads that are currently running, plus the local thread once
+    /// it has been spawned.
+    live: usize,
+    next_id: usize,
+}
+
+/// Stands in for a worker id when no worker is meant.
+const NO_WORKER: usize = usize::MAX;
+
+enum Message {
+    /// A job to run, with how long it waited and how many jobs were still waiting when
+    /// it was taken.
+    Job {
+        job: Job,
+        waited: Duration,
+        queue_depth: usize,
+    },
+    Retire,
+    /// The worker has been asked to make way for a fresh thread.
+    Recycle,
+    /// An elastic worker has been idle for longer than the keep-alive.
+    IdleExpired,
+    Shutdown,
 }
 
-impl Worker {
-    fn new(id: usize, reciever: Arc<Mutex<mpsc::Receiver<Job>>>) -> Worker {
-        let thread = thread::spawn(move || loop {
-            let message = reciever.lock().unwrap().recv();
+impl Shared {
+    fn execute_job(self: &Arc<Self>, priority: Priority, mut job: Job) -> Result<(), ExecuteError> {
+        if priority == Priority::Normal && !self.is_closed() {
+            match worker::push_local(self, job) {
+                Ok(()) => return Ok(()),
+                Err(rejected) => job = rejected,
+            }
+        }
+
+        if let Some(injector) = &self.injector {
+            return self.inject_blocking(injector, job);
+        }
+
+        self.push_blocking(DEFAULT_QUEUE, priority, job, None)
+    }
 
-            match message {
-                Ok(job) => {
-                    println!("Worker {id} got a job; executing.");
+    /// Push onto the shared queue, waiting for space if it is full.
+    fn push_blocking(
+        self: &Arc<Self>,
+        queue: usize,
+        priority: Priority,
+        job: Job,
+        tag: Option<usize>,
+    ) -> Result<(), ExecuteError> {
+        let mut state = self.state.lock().unwrap();
+        let mut evicted = None;
 
-                    job();
+        while self.is_full(&state) && !self.is_closed() {
+            match self.rejection_policy {
+                RejectionPolicy::Block => state = self.space_available.wait(state).unwrap(),
+                RejectionPolicy::DropNewest => {
+                    drop(state);
+                    drop(job);
+                    return Ok(());
                 }
-                Err(_) => {
-                    println!("Worker {id} disconnected; shutting down.");
-                    break;
+                RejectionPolicy::DropOldest => evicted = state.jobs.pop_oldest(),
+                RejectionPolicy::CallerRuns => {
+                    drop(state);
+                    self.middleware.run(job);
+                    return Ok(());
                 }
             }
-        });
+        }
 
-        Worker {
-            id,
-            thread: Some(thread),
+        if self.is_closed() {
+            return Err(PoolError::ShuttingDown);
         }
+
+        self.push_to(&mut state, queue, priority, job, tag);
+        drop(state);
+
+        // Dropped outside the lock, since dropping a job can run arbitrary code.
+        if let Some(evicted) = evicted {
+            self.evicted(evicted);
+        }
+        self.spawn_if_busy();
+        Ok(())
     }
-}
 
-type Job = Box<dyn FnOnce() + Send + 'static>;
+    /// Spawn an extra worker if the pool is elastic, every worker is busy and the
+    /// maximum size has not been reached. Called after a job has been queued.
+    ///
+    /// Failing to spawn is not an error: the job is already queued and the existing
+    /// workers will get to it.
+    fn spawn_if_busy(self: &Arc<Self>) {
+        let Some(max_size) = self.max_size else {
+            return;
+        };
 
-#[derive(Debug, Clone, PartialEq)]
-pub struct PoolCreationError;
+        if self.sleepers.load(Ordering::SeqCst) > 0
+            || self.spinners.load(Ordering::SeqCst) > 0
+            || self.paused.load(Ordering::SeqCst)
+            || self.is_closed()
+        {
+            return;
+        }
 
-impl fmt::Display for PoolCreationError {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "Invalid size value provided to Threadpool::build")
+        let id = {
+            let mut state = self.state.lock().unwrap();
+            if state.size >= max_size {
+                return;
+            }
+
+            state.size += 1;
+            state.next_id += 1;
+            state.next_id - 1
+        };
+
+        match Worker::new(id, Arc::clone(self)) {
+            Ok(worker) => self.workers.lock().unwrap().push(worker),
+            Err(_) => self.state.lock().unwrap().size -= 1,
+        }
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    /// Push onto the lock-free queue, waiting for space if the pool is bounded.
+    ///
+    /// An unbounded pool overflows into the shared queue instead of waiting.
+    fn inject_blocking(
+        self: &Arc<Self>,
+        injector: &ArrayQueue<Stamped>,
+        mut job: Job,
+    ) -> Result<(), ExecuteError> {
+        loop {
+            if self.is_closed() {
+                return Err(PoolError::ShuttingDown);
+            }
 
-    #[test]
-    fn zero_size_returns_err() {
-        let result = Threadpool::build(0);
+            match self.inject(injector, job) {
+                Ok(()) => {
+                    self.spawn_if_busy();
+                    return Ok(());
+                }
+                Err(rejected) => job = rejected,
+            }
 
-        // let expected = Err(PoolCreationError);
+            let mut state = self.state.lock().unwrap();
 
-        assert!(result.is_err())
+            if self.capacity.is_none() {
+                self.push(&mut state, Priority::Normal, job);
+                drop(state);
+
+                self.spawn_if_busy();
+                return Ok(());
+            }
+
+            match self.rejection_policy {
+                RejectionPolicy::Block => {}
+                RejectionPolicy::DropNewest => {
+                    drop(state);
+                    drop(job);
+                    return Ok(());
+                }
+                RejectionPolicy::DropOldest => {
+                    drop(state);
+
+                    let oldest = injector.pop();
+                    let pushed = self.inject(injector, job);
+                    if let Some((oldest, _)) = oldest {
+                        self.unlocked_jobs.fetch_sub(1, Ordering::SeqCst);
+                        self.evicted(oldest);
+                    }
+                    match pushed {
+                        Ok(()) => {
+                            self.spawn_if_busy();
+                            return Ok(());
+                        }
+                        // Another producer took the slot first.
+                        Err(rejected) => job = rejected,
+                    }
+                    continue;
+                }
+                RejectionPolicy::CallerRuns => {
+                    drop(state);
+                    self.middleware.run(job);
+                    return Ok(());
+                }
+            }
+
+            // Announce ourselves before retrying, so a worker that frees a slot after
+            // the retry fails knows to wake us.
+            self.waiting_producers.fetch_add(1, Ordering::SeqCst);
+            match self.inject(injector, job) {
+                Ok(()) => {
+                    self.waiting_producers.fetch_sub(1, Ordering::SeqCst);
+                    drop(state);
+
+                    self.spawn_if_busy();
+                    return Ok(());
+                }
+                Err(rejected) => job = rejected,
+            }
+            if !self.is_closed() {
+                state = self.space_available.wait(state).unwrap();
+            }
+            self.waiting_producers.fetch_sub(1, Ordering::SeqCst);
+            drop(state);
+        }
+    }
+
+    fn try_execute<F>(self: &Arc<Self>, f: F) -> Result<JobId, TryExecuteError<F>>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        if let Some(injector) = &self.injector {
+            if self.is_closed() {
+                return Err(TryExecuteError::new(PoolError::ShuttingDown, f));
+            }
+
+            if injector.try_reserve() {
+                let (id, job) = self.statuses.track(Box::new(f));
+                injector.push_reserved((job, Instant::now()));
+                self.queued_unlocked();
+                self.spawn_if_busy();
+                return Ok(id);
+            }
+
+            if self.capacity.is_some() {
+                return Err(TryExecuteError::new(PoolError::QueueFull, f));
+            }
+        }
+
+        let mut state = self.state.lock().unwrap();
+
+        if self.is_closed() {
+            return Err(TryExecuteError::new(PoolError::ShuttingDown, f));
+        }
+
+        if self.is_full(&state) {
+            return Err(TryExecuteError::new(PoolError::QueueFull, f));
+        }
+
+        let (id, job) = self.statuses.track(Box::new(f));
+        self.push(&mut state, Priority::Normal, job);
+        drop(state);
+
+        self.spawn_if_busy();
+        Ok(id)
+    }
+
+    /// Queue a job like `execute_job`, recording its progress for `Threadpool::status`.
+    fn execute_tracked(
+        self: &Arc<Self>,
+        priority: Priority,
+        job: Job,
+    ) -> Result<JobId, ExecuteError> {
+        let (id, job) = self.statuses.track(job);
+        self.execute_job(priority, job)?;
+        Ok(id)
+    }
+
+    fn submit<F, T>(self: &Arc<Self>, f: F) -> Result<JobHandle<T>, ExecuteError>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let (sender, reciever) = mpsc::channel();
+
+        let job = Box::new(move || {
+            // A panic is caught here so it reaches the handle instead of taking
+            // the worker down with it.
+            let result = panic::catch_unwind(AssertUnwindSafe(f)).map_err(JoinError::JobPanicked);
+            let _ = sender.send(result);
+        });
+        self.execute_job(Priority::Normal, job)?;
+
+        Ok(JobHandle { reciever })
+    }
+
+    fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::SeqCst)
+    }
+
+    fn is_idle(&self) -> bool {
+        self.in_flight.load(Ordering::SeqCst) == 0
+    }
+
+    /// Stop accepting jobs and wake everyone waiting on the queue.
+    ///
+    /// Delayed jobs that are not yet due are discarded.
+    fn close(&self) {
+        self.timer.close();
+        self.local.close();
+        if let Some(watchdog) = &self.watchdog {
+            watchdog.close();
+        }
+        self.closed.store(true, Ordering::SeqCst);
+
+        let _state = self.state.lock().unwrap();
+        self.job_available.notify_all();
+        self.space_available.notify_all();
+    }
+
+    fn push(&self, state: &mut State, priority: Priority, job: Job) {
+        self.push_to(state, DEFAULT_QUEUE, priority, job, None);
+    }
+
+    fn push_to(
+        &self,
+        state: &mut State,
+        queue: usize,
+        priority: Priority,
+        job: Job,
+        tag: Option<usize>,
+    ) {
+        state.jobs.push_to(queue, priority, job, tag);
+        self.in_flight.fetch_add(1, Ordering::SeqCst);
+        self.counters.job_queued();
+        self.job_available.notify_one();
+    }
+
+    /// Push onto the lock-free queue, handing the job back if it is full.
+    fn inject(&self, injector: &ArrayQueue<Stamped>, job: Job) -> Result<(), Job> {
+        injector
+            .push((job, Instant::now()))
+            .map_err(|(job, _)| job)?;
+        self.queued_unlocked();
+        Ok(())
+    }
+
+    /// Account for a job that was queued without holding the `state` lock, waking a
+    /// sleeping worker if there is one.
+    fn queued_unlocked(&self) {
+        self.in_flight.fetch_add(1, Ordering::SeqCst);
+        self.counters.job_queued();
+        self.unlocked_jobs.fetch_add(1, Ordering::SeqCst);
+
+        if self.sleepers.load(Ordering::SeqCst) > 0 {
+            let _state = self.state.lock().unwrap();
+            self.job_available.notify_one();
+        }
+    }
+
+    /// Queue a job from inside the pool, such as a timer firing, without blocking.
+    fn push_nonblocking(&self, job: Job) {
+        let job = match &self.injector {
+            Some(injector) => match self.inject(injector, job) {
+                Ok(()) => return,
+                Err(rejected) => rejected,
+            },
+            None => job,
+        };
+
+        let mut state = self.state.lock().unwrap();
+        self.push(&mut state, Priority::Normal, job);
+    }
+
+    /// Remove every job that has not started yet, from the shared queue, the lock-free
+    /// queue, every worker's local deque, the local thread and the categories' waiting
+    /// lists.
+    fn take_queued(&self) -> Vec<Job> {
+        let mut jobs = self.state.lock().unwrap().jobs.take_all();
+
+        if let Some(injector) = &self.injector {
+            while let Some((job, _)) = injector.pop() {
+                self.unlocked_jobs.fetch_sub(1, Ordering::SeqCst);
+                jobs.push(job);
+            }
+        }
+
+        for local in self.locals.read().unwrap().iter() {
+            let taken = local.take_all();
+
+            self.unlocked_jobs.fetch_sub(taken.len(), Ordering::SeqCst);
+            jobs.extend(taken);
+        }
+        jobs.extend(self.local.take_queued());
+
+        self.counters.jobs_discarded(jobs.len());
+        self.finished(jobs.len());
+
+        // Jobs waiting for a category slot were never counted as queued.
+        for category in self.categories.values().chain(&self.lanes) {
+            jobs.extend(category.take_waiting());
+        }
+        for child in self.children.lock().unwrap().iter() {
+            if let Some(child) = child.upgrade() {
+                jobs.extend(child.take_waiting());
+            }
+        }
+        jobs
+    }
+
+    /// Take the job queued with `tag` back out of the shared queue, returning whether it
+    /// was still there.
+    fn remove_queued(&self, tag: usize) -> bool {
+        let Some(job) = self.state.lock().unwrap().jobs.remove(tag) else {
+            return false;
+        };
+
+        self.space_available.notify_one();
+        self.counters.jobs_discarded(1);
+        self.finished(1);
+        drop(job);
+        true
+    }
+
+    /// Account for a queued job thrown away to make room for a newer one.
+    fn evicted(&self, job: Job) {
+        self.counters.jobs_discarded(1);
+        self.finished(1);
+        drop(job);
+    }
+
+    fn job_finished(&self, panicked: bool, ran: Duration) {
+        self.counters.job_finished(panicked, ran);
+        self.finished(1);
+    }
+
+    /// Record that `count` jobs have left the pool, waking `join` callers if it is now
+    /// idle.
+    fn finished(&self, count: usize) {
+        if count > 0 && self.in_flight.fetch_sub(count, Ordering::SeqCst) == count {
+            let _state = self.state.lock().unwrap();
+            self.idle.notify_all();
+        }
+    }
+
+    fn worker_exited(&self) {
+        self.state.lock().unwrap().live -= 1;
+        self.worker_exited.notify_all();
+    }
+
+    fn is_full(&self, state: &State) -> bool {
+        self.capacity
+            .is_some_and(|capacity| state.jobs.len() >= capacity)
+    }
+
+    fn claim_retirement(&self) -> bool {
+        self.retiring
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |retiring| {
+                retiring.checked_sub(1)
+            })
+            .is_ok()
+    }
+
+    /// Whether the worker on the current thread has been asked to recycle itself.
+    fn recycle_requested(&self) -> bool {
+        let target = self.recycle.load(Ordering::SeqCst);
+        target != NO_WORKER && worker::current_id() == Some(target)
+    }
+
+    /// Take up a recycle request meant for the worker on the current thread.
+    fn claim_recycle(&self) -> bool {
+        let target = self.recycle.load(Ordering::SeqCst);
+
+        target != NO_WORKER
+            && worker::current_id() == Some(target)
+            && self
+                .recycle
+                .compare_exchange(target, NO_WORKER, Ordering::SeqCst, Ordering::SeqCst)
+                .is_ok()
+    }
+
+    /// Ask the worker with the given id to recycle itself, and block until its
+    /// replacement has been spawned or the worker has gone for another reason.
+    fn recycle_worker(&self, id: usize) {
+        let mut state = self.state.lock().unwrap();
+        let recycled = self.recycled.load(Ordering::SeqCst);
+
+        self.recycle.store(id, Ordering::SeqCst);
+        self.job_available.notify_all();
+
+        while self.recycled.load(Ordering::SeqCst) == recycled {
+            let present = self.workers.lock().unwrap().iter().any(|w| w.id == id);
+            if !present || self.is_closed() {
+                self.recycle.store(NO_WORKER, Ordering::SeqCst);
+                return;
+            }
+            state = self.worker_exited.wait(state).unwrap();
+        }
+    }
+
+    /// Record that a worker has been recycled, waking `recycle_worker`.
+    fn worker_recycled(&self) {
+        let _state = self.state.lock().unwrap();
+        self.recycled.fetch_add(1, Ordering::SeqCst);
+        self.worker_exited.notify_all();
+    }
+
+    /// Block until there is something for the worker owning `local` to do.
+    ///
+    /// Jobs are taken from the worker's own deque first, then from the lock-free queue
+    /// and the shared queue, and finally stolen from other workers. Retirement requests
+    /// are honoured before picking up new jobs, even while the pool is paused, and
+    /// `Shutdown` is only returned once the pool is closed and every queue has been
+    /// drained.
+    fn next_job(&self, local: &Arc<LocalQueue>) -> Message {
+        let mut spun = self.idle_spin.is_zero();
+
+        loop {
+            if self.claim_retirement() {
+                return Message::Retire;
+            }
+            if self.claim_recycle() {
+                return Message::Recycle;
+            }
+
+            if self.paused.load(Ordering::SeqCst) && !self.is_closed() {
+                let state = self.state.lock().unwrap();
+                if self.paused.load(Ordering::SeqCst)
+                    && !self.is_closed()
+                    && self.retiring.load(Ordering::SeqCst) == 0
+                    && !self.recycle_requested()
+                {
+                    drop(self.job_available.wait(state).unwrap());
+                }
+                continue;
+            }
+
+            if let Some(job) = local.pop() {
+                return self.start_unlocked(job);
+            }
+
+            if let Some(job) = self.injector.as_ref().and_then(ArrayQueue::pop) {
+                if self.waiting_producers.load(Ordering::SeqCst) > 0 {
+                    let _state = self.state.lock().unwrap();
+                    self.space_available.notify_all();
+                }
+                return self.start_unlocked(job);
+            }
+
+            {
+                let mut state = self.state.lock().unwrap();
+
+                if let Some((job, enqueued)) = state.jobs.pop() {
+                    let batched = self.batch_to_local(&mut state, local);
+
+                    let message = self.hand_over(job, enqueued);
+                    if batched == 0 {
+                        self.space_available.notify_one();
+                    } else {
+                        self.space_available.notify_all();
+                    }
+                    return message;
+                }
+
+                if self.unlocked_jobs.load(Ordering::SeqCst) == 0 {
+                    if self.is_closed() {
+                        return Message::Shutdown;
+                    }
+
+                    if !spun {
+                        spun = true;
+                        drop(state);
+                        self.spin_for_work();
+                        continue;
+                    }
+
+                    // Announce ourselves before re-checking the unlocked queues, so a
+                    // concurrent `queued_unlocked` either sees a sleeper to wake or its
+                    // job is seen here.
+                    self.sleepers.fetch_add(1, Ordering::SeqCst);
+                    if self.unlocked_jobs.load(Ordering::SeqCst) == 0
+                        && self.retiring.load(Ordering::SeqCst) == 0
+                        && !self.recycle_requested()
+                    {
+                        if state.size > state.core {
+                            let timeout;
+                            (state, timeout) = self
+                                .job_available
+                                .wait_timeout(state, self.keep_alive)
+                                .unwrap();
+
+                            if timeout.timed_out() && self.idle_expired(&mut state) {
+                                self.sleepers.fetch_sub(1, Ordering::SeqCst);
+                                return Message::IdleExpired;
+                            }
+                        } else {
+                            state = self.job_available.wait(state).unwrap();
+                        }
+                    }
+                    self.sleepers.fetch_sub(1, Ordering::SeqCst);
+                    drop(state);
+                    continue;
+                }
+            }
+
+            match worker::steal(self, local) {
+                Some(job) => return self.start_unlocked(job),
+                // Another worker took the job we were counting on.
+                None => thread::yield_now(),
+            }
+        }
+    }
+
+    /// Move up to `dequeue_batch - 1` more jobs from the shared queue to the worker's
+    /// deque, keeping their order, and return how many were moved.
+    fn batch_to_local(&self, state: &mut State, local: &LocalQueue) -> usize {
+        let batch: Vec<_> = (1..self.dequeue_batch)
+            .map_while(|_| state.jobs.pop())
+            .collect();
+        if batch.is_empty() {
+            return 0;
+        }
+
+        let moved = batch.len();
+        self.unlocked_jobs.fetch_add(moved, Ordering::SeqCst);
+        local.push_batch(batch);
+        if self.sleepers.load(Ordering::SeqCst) > 0 {
+            // Let a sleeping worker steal some of the batch.
+            self.job_available.notify_one();
+        }
+        moved
+    }
+
+    /// Busy-wait for up to `idle_spin` until there may be a job to take, or something
+    /// else for the worker to act on.
+    fn spin_for_work(&self) {
+        let started = Instant::now();
+        self.spinners.fetch_add(1, Ordering::SeqCst);
+
+        while started.elapsed() < self.idle_spin {
+            if self.counters.queued() > 0
+                || self.unlocked_jobs.load(Ordering::SeqCst) > 0
+                || self.retiring.load(Ordering::SeqCst) > 0
+                || self.recycle.load(Ordering::SeqCst) != NO_WORKER
+                || self.paused.load(Ordering::SeqCst)
+                || self.is_closed()
+            {
+                break;
+            }
+            hint::spin_loop();
+        }
+        self.spinners.fetch_sub(1, Ordering::SeqCst);
+    }
+
+    /// Decide, after an idle wait timed out, whether this worker should exit because the
+    /// pool has more workers than its core size. Gives up the worker's slot if so.
+    fn idle_expired(&self, state: &mut State) -> bool {
+        let idle = state.jobs.len() == 0
+            && self.unlocked_jobs.load(Ordering::SeqCst) == 0
+            && !self.is_closed();
+
+        if idle && state.size > state.core {
+            state.size -= 1;
+            return true;
+        }
+        false
+    }
+
+    fn start_unlocked(&self, (job, enqueued): Stamped) -> Message {
+        self.unlocked_jobs.fetch_sub(1, Ordering::SeqCst);
+        self.hand_over(job, enqueued)
+    }
+
+    /// Record that a job queued at `enqueued` is about to run, reporting it if it waited
+    /// for longer than the slow dequeue threshold.
+    /// Account for a job queued at `enqueued` being taken, returning how long it waited.
+    fn job_dequeued(&self, enqueued: Instant) -> Duration {
+        let waited = self.counters.job_started(enqueued);
+
+        if let Some((threshold, hook)) = &self.on_slow_dequeue {
+            if waited > *threshold {
+                hook(waited);
+            }
+        }
+        waited
+    }
+
+    /// Account for `job` being taken by a worker, and return the message handing it
+    /// over.
+    fn hand_over(&self, job: Job, enqueued: Instant) -> Message {
+        Message::Job {
+            job,
+            waited: self.job_dequeued(enqueued),
+            queue_depth: self.counters.queued(),
+        }
+    }
+}
+
+/// The ways creating, resizing or submitting work to a pool can fail.
+#[derive(Debug)]
+pub enum PoolError {
+    /// The pool size or queue capacity was zero.
+    ZeroSize,
+    /// The operating system refused to spawn a worker thread.
+    SpawnFailed(io::Error),
+    /// The pool has been shut down and no longer accepts jobs.
+    ShuttingDown,
+    /// The queue is at capacity.
+    QueueFull,
+    /// A `ThrottledHandle` already has its limit of jobs in flight.
+    Throttled,
+}
+
+/// The error returned when submitting a job fails.
+pub type ExecuteError = PoolError;
+
+impl fmt::Display for PoolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PoolError::ZeroSize => {
+                write!(f, "Invalid size value provided to Threadpool::build")
+            }
+            PoolError::SpawnFailed(error) => {
+                write!(f, "Failed to spawn a worker thread: {error}")
+            }
+            PoolError::ShuttingDown => write!(f, "Threadpool is shutting down"),
+            PoolError::QueueFull => write!(f, "Threadpool job queue is full"),
+            PoolError::Throttled => write!(f, "Throttle limit of jobs in flight reached"),
+        }
+    }
+}
+
+impl Error for PoolError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            PoolError::SpawnFailed(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+/// Returned by `Threadpool::try_execute` when a job is rejected, carrying the closure
+/// back to the caller.
+pub struct TryExecuteError<F> {
+    error: PoolError,
+    job: F,
+}
+
+impl<F> TryExecuteError<F> {
+    fn new(error: PoolError, job: F) -> TryExecuteError<F> {
+        TryExecuteError { error, job }
+    }
+
+    /// Why the job was rejected: `QueueFull`, `Throttled` or `ShuttingDown`.
+    pub fn error(&self) -> &PoolError {
+        &self.error
+    }
+
+    /// Take back the rejected closure.
+    pub fn into_job(self) -> F {
+        self.job
+    }
+}
+
+impl<F> fmt::Debug for TryExecuteError<F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TryExecuteError")
+            .field("error", &self.error)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<F> fmt::Display for TryExecuteError<F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.error.fmt(f)
+    }
+}
+
+impl<F> Error for TryExecuteError<F> {}
+
+/// Why a job handle did not produce a value.
+pub enum JoinError {
+    /// The job panicked. Carries the panic payload, like `std::thread::JoinHandle::join`.
+    JobPanicked(Box<dyn Any + Send + 'static>),
+    /// The job was dropped without running, for example by `shutdown_now`.
+    Dropped,
+}
+
+impl JoinError {
+    /// The panic message, if the job panicked with a string payload.
+    pub fn panic_message(&self) -> Option<&str> {
+        match self {
+            JoinError::JobPanicked(payload) => payload
+                .downcast_ref::<&str>()
+                .copied()
+                .or_else(|| payload.downcast_ref::<String>().map(String::as_str)),
+            JoinError::Dropped => None,
+        }
+    }
+}
+
+impl fmt::Debug for JoinError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JoinError::JobPanicked(_) => f
+                .debug_tuple("JobPanicked")
+                .field(&self.panic_message().unwrap_or("Any { .. }"))
+                .finish(),
+            JoinError::Dropped => write!(f, "Dropped"),
+        }
+    }
+}
+
+impl fmt::Display for JoinError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self, self.panic_message()) {
+            (JoinError::JobPanicked(_), Some(message)) => {
+                write!(f, "Job panicked before producing a result: {message}")
+            }
+            (JoinError::JobPanicked(_), None) => {
+                write!(f, "Job panicked before producing a result")
+            }
+            (JoinError::Dropped, _) => write!(f, "Job was dropped before it ran"),
+        }
+    }
+}
+
+impl Error for JoinError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+
+    #[test]
+    fn zero_size_returns_err() {
+        let result = Threadpool::build(0);
+
+        // let expected = Err(PoolError);
+
+        assert!(result.is_err())
+    }
+
+    #[test]
+    fn new_auto_rejects_zero_multiplier() {
+        assert!(Threadpool::new_auto_with_multiplier(0).is_err());
+    }
+
+    #[test]
+    fn paused_pool_queues_jobs_until_resumed() {
+        let pool = Threadpool::build(2).unwrap();
+        let (tx, rx) = mpsc::channel();
+
+        pool.pause();
+        pool.execute(move || tx.send(()).unwrap()).unwrap();
+
+        assert!(rx.recv_timeout(Duration::from_millis(50)).is_err());
+        assert!(pool.is_paused());
+
+        pool.resume();
+        rx.recv_timeout(Duration::from_secs(5)).unwrap();
+    }
+
+    #[test]
+    fn shutdown_drains_a_paused_pool() {
+        let pool = Threadpool::build(1).unwrap();
+        let counter = Arc::new(AtomicUsize::new(0));
+
+        pool.pause();
+        for _ in 0..3 {
+            let counter = Arc::clone(&counter);
+            pool.execute(move || {
+                counter.fetch_add(1, Ordering::SeqCst);
+            })
+            .unwrap();
+        }
+        pool.shutdown();
+
+        assert_eq!(counter.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn execute_after_shutdown_returns_err() {
+        let pool = Threadpool::build(1).unwrap();
+        pool.shutdown();
+
+        assert!(matches!(pool.execute(|| {}), Err(PoolError::ShuttingDown)));
+        let rejected = pool.try_execute(|| {}).unwrap_err();
+        assert!(matches!(rejected.error(), PoolError::ShuttingDown));
+    }
+
+    #[test]
+    fn workers_run_jobs_with_and_without_idle_spin() {
+        for idle_spin in [Duration::ZERO, Duration::from_secs(10)] {
+            let pool = ThreadpoolBuilder::new(2)
+                .idle_spin(idle_spin)
+                .build()
+                .unwrap();
+
+            for _ in 0..3 {
+                // Time for the workers to run dry and start spinning or park.
+                thread::sleep(Duration::from_millis(5));
+                assert_eq!(pool.submit(|| 7).unwrap().join().unwrap(), 7);
+            }
+
+            // Spinning workers notice the shutdown rather than running out the spin.
+            let started = Instant::now();
+            pool.shutdown();
+            assert!(started.elapsed() < Duration::from_secs(5));
+        }
+    }
+
+    #[test]
+    fn recycle_workers_replaces_every_thread() {
+        let (started_tx, started_rx) = mpsc::channel();
+        let pool = ThreadpoolBuilder::new(2)
+            .on_worker_start(move |id| started_tx.send((id, thread::current().id())).unwrap())
+            .build()
+            .unwrap();
+        let (release_tx, release_rx) = mpsc::channel::<()>();
+        let (running_tx, running_rx) = mpsc::channel();
+
+        let before: Vec<_> = started_rx.iter().take(2).collect();
+        // A busy worker is recycled once its job has finished.
+        pool.execute(move || {
+            running_tx.send(()).unwrap();
+            release_rx.recv().unwrap();
+        })
+        .unwrap();
+        running_rx.recv().unwrap();
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(20));
+            release_tx.send(()).unwrap();
+        });
+        pool.recycle_workers();
+
+        let mut after: Vec<_> = started_rx.iter().take(2).collect();
+        after.sort_unstable_by_key(|(id, _)| *id);
+        assert_eq!(after.iter().map(|(id, _)| *id).collect::<Vec<_>>(), [0, 1]);
+        assert!(after.iter().all(|started| !before.contains(started)));
+        assert_eq!(pool.size(), 2);
+        assert_eq!(pool.submit(|| 3).unwrap().join().unwrap(), 3);
+    }
+
+    #[test]
+    fn elastic_pool_grows_under_load_and_reclaims_idle_workers() {
+        let pool = ThreadpoolBuilder::new(1)
+            .max_size(3)
+            .keep_alive(Duration::from_millis(50))
+            .build()
+            .unwrap();
+        let (started_tx, started_rx) = mpsc::channel();
+        let (release_tx, release_rx) = mpsc::channel::<()>();
+        let release_rx = Arc::new(Mutex::new(release_rx));
+
+        // Wait for each job to start so every worker is busy when the next one arrives.
+        for _ in 0..3 {
+            let started_tx = started_tx.clone();
+            let release_rx = Arc::clone(&release_rx);
+            pool.execute(move || {
+                started_tx.send(()).unwrap();
+                let _ = release_rx.lock().unwrap().recv();
+            })
+            .unwrap();
+            started_rx.recv().unwrap();
+        }
+        assert_eq!(pool.size(), 3);
+
+        drop(release_tx);
+        pool.join();
+        thread::sleep(Duration::from_millis(300));
+        assert_eq!(pool.size(), 1);
+    }
+
+    #[test]
+    fn worker_hooks_run_once_per_worker_thread() {
+        thread_local! {
+            static SCRATCH: Cell<Option<usize>> = const { Cell::new(None) };
+        }
+
+        let stopped = Arc::new(Mutex::new(Vec::new()));
+        let pool = ThreadpoolBuilder::new(2)
+            .on_worker_start(|id| SCRATCH.set(Some(id)))
+            .on_worker_stop({
+                let stopped = Arc::clone(&stopped);
+                move |id| stopped.lock().unwrap().push(id)
+            })
+            .build()
+            .unwrap();
+
+        let handle = pool.submit(|| SCRATCH.get()).unwrap();
+        assert!(handle.join().unwrap().is_some());
+
+        pool.shutdown();
+        let mut stopped = stopped.lock().unwrap().clone();
+        stopped.sort_unstable();
+        assert_eq!(stopped, [0, 1]);
+    }
+
+    #[test]
+    fn submit_returns_value() {
+        let pool = Threadpool::build(2).unwrap();
+
+        let handle = pool.submit(|| 6 * 7).unwrap();
+
+        assert_eq!(handle.join().unwrap(), 42);
+    }
+
+    #[test]
+    fn submit_reports_panic() {
+        let pool = Threadpool::build(2).unwrap();
+
+        let handle = pool.submit(|| -> u32 { panic!("boom") }).unwrap();
+
+        let error = handle.join().unwrap_err();
+        assert_eq!(error.panic_message(), Some("boom"));
+    }
+
+    #[test]
+    fn submit_reports_dropped_jobs() {
+        let pool = Threadpool::build(1).unwrap();
+        let (started_tx, started_rx) = mpsc::channel();
+        let (release_tx, release_rx) = mpsc::channel::<()>();
+
+        pool.execute(move || {
+            started_tx.send(()).unwrap();
+            release_rx.recv().unwrap();
+        })
+        .unwrap();
+        started_rx.recv().unwrap();
+        let handle = pool.submit(|| 1).unwrap();
+
+        let discarded = pool.shared.take_queued();
+        drop(discarded);
+        release_tx.send(()).unwrap();
+
+        assert!(matches!(handle.join(), Err(JoinError::Dropped)));
+    }
+
+    #[test]
+    fn drain_returns_queued_jobs_and_keeps_running() {
+        let pool = Threadpool::build(1).unwrap();
+        let (started_tx, started_rx) = mpsc::channel();
+        let (release_tx, release_rx) = mpsc::channel::<()>();
+        let (done_tx, done_rx) = mpsc::channel();
+
+        pool.execute(move || {
+            started_tx.send(()).unwrap();
+            release_rx.recv().unwrap();
+        })
+        .unwrap();
+        started_rx.recv().unwrap();
+        for i in 0..3 {
+            let done_tx = done_tx.clone();
+            pool.execute(move || done_tx.send(i).unwrap()).unwrap();
+        }
+
+        let drained = pool.drain();
+        assert_eq!(drained.len(), 3);
+        assert_eq!(pool.metrics().queued, 0);
+        drained.into_iter().for_each(|job| job());
+        assert_eq!(done_rx.try_iter().collect::<Vec<_>>(), [0, 1, 2]);
+
+        release_tx.send(()).unwrap();
+        let done_tx = done_tx.clone();
+        pool.execute(move || done_tx.send(3).unwrap()).unwrap();
+        assert_eq!(done_rx.recv().unwrap(), 3);
+    }
+
+    #[test]
+    fn zero_queue_capacity_returns_err() {
+        let result = ThreadpoolBuilder::new(1).queue_capacity(0).build();
+
+        assert!(result.is_err())
+    }
+
+    /// Build a pool with one busy worker and a queue of one holding a job labelled
+    /// "queued", then submit a job labelled "new" to it.
+    fn overflow_with(policy: RejectionPolicy, lock_free: bool) -> Vec<&'static str> {
+        let pool = ThreadpoolBuilder::new(1)
+            .queue_capacity(1)
+            .lock_free_queue(lock_free)
+            .rejection_policy(policy)
+            .build()
+            .unwrap();
+        let (started_tx, started_rx) = mpsc::channel();
+        let (release_tx, release_rx) = mpsc::channel::<()>();
+        let (ran_tx, ran_rx) = mpsc::channel();
+
+        pool.execute(move || {
+            started_tx.send(()).unwrap();
+            release_rx.recv().unwrap();
+        })
+        .unwrap();
+        started_rx.recv().unwrap();
+        for label in ["queued", "new"] {
+            let ran_tx = ran_tx.clone();
+            pool.execute(move || ran_tx.send(label).unwrap()).unwrap();
+        }
+
+        release_tx.send(()).unwrap();
+        pool.join();
+        ran_rx.try_iter().collect()
+    }
+
+    #[test]
+    fn rejection_policies_decide_which_job_runs() {
+        for lock_free in [false, true] {
+            assert_eq!(
+                overflow_with(RejectionPolicy::DropNewest, lock_free),
+                ["queued"]
+            );
+            assert_eq!(
+                overflow_with(RejectionPolicy::DropOldest, lock_free),
+                ["new"]
+            );
+            assert_eq!(
+                overflow_with(RejectionPolicy::CallerRuns, lock_free),
+                ["new", "queued"]
+            );
+        }
+    }
+
+    #[test]
+    fn try_execute_rejects_when_queue_full() {
+        let pool = ThreadpoolBuilder::new(1).queue_capacity(1).build().unwrap();
+        let (started_tx, started_rx) = mpsc::channel();
+        let (release_tx, release_rx) = mpsc::channel::<()>();
+
+        pool.execute(move || {
+            started_tx.send(()).unwrap();
+            release_rx.recv().unwrap();
+        })
+        .unwrap();
+        started_rx.recv().unwrap();
+
+        assert!(pool.try_execute(|| {}).is_ok());
+        assert!(pool.try_execute(|| {}).is_err());
+
+        release_tx.send(()).unwrap();
+    }
+
+    #[test]
+    fn panicking_job_does_not_shrink_pool() {
+        let pool = Threadpool::build(1).unwrap();
+
+        pool.execute(|| panic!("boom")).unwrap();
+        let handle = pool.submit(|| "still running").unwrap();
+
+        assert_eq!(handle.join().unwrap(), "still running");
+    }
+
+    #[test]
+    fn jobs_spawned_by_workers_are_stolen_by_idle_workers() {
+        let pool = Arc::new(Threadpool::build(4).unwrap());
+        let (sender, reciever) = mpsc::channel();
+
+        let inner = Arc::clone(&pool);
+        pool.execute(move || {
+            for _ in 0..8 {
+                let sender = sender.clone();
+                inner
+                    .execute(move || {
+                        thread::sleep(Duration::from_millis(20));
+                        sender.send(thread::current().id()).unwrap();
+                    })
+                    .unwrap();
+            }
+        })
+        .unwrap();
+
+        let mut threads: Vec<_> = reciever.iter().take(8).collect();
+        threads.sort_unstable_by_key(|id| format!("{id:?}"));
+        threads.dedup();
+
+        assert!(threads.len() > 1);
+        pool.join();
+    }
+
+    #[test]
+    fn execute_batch_runs_every_job() {
+        let pool = ThreadpoolBuilder::new(2).queue_capacity(4).build().unwrap();
+        let (sender, reciever) = mpsc::channel();
+
+        pool.execute_batch((0..32).map(|i| {
+            let sender = sender.clone();
+            Box::new(move || sender.send(i).unwrap()) as Job
+        }))
+        .unwrap();
+        pool.join();
+
+        let mut received: Vec<_> = reciever.try_iter().collect();
+        received.sort_unstable();
+        assert_eq!(received, (0..32).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn map_preserves_input_order() {
+        let pool = Threadpool::build(3).unwrap();
+
+        let lengths = pool.map(vec!["a", "bb", "ccc", "dddd"], str::len);
+
+        assert_eq!(lengths, [1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn execute_after_waits_for_delay() {
+        let pool = Threadpool::build(1).unwrap();
+        let (sender, reciever) = mpsc::channel();
+        let start = Instant::now();
+
+        let later = sender.clone();
+        pool.execute_after(Duration::from_millis(100), move || {
+            later.send("later").unwrap()
+        })
+        .unwrap();
+        pool.execute_after(Duration::from_millis(20), move || {
+            sender.send("sooner").unwrap()
+        })
+        .unwrap();
+
+        assert_eq!(reciever.recv().unwrap(), "sooner");
+        assert_eq!(reciever.recv().unwrap(), "later");
+        assert!(start.elapsed() >= Duration::from_millis(100));
+    }
+
+    #[test]
+    fn shutdown_now_returns_delayed_jobs() {
+        let pool = Threadpool::build(1).unwrap();
+
+        pool.execute_after(Duration::from_secs(60), || {}).unwrap();
+
+        assert_eq!(pool.shutdown_now().len(), 1);
+    }
+
+    #[test]
+    fn lock_free_queue_runs_jobs_and_applies_capacity() {
+        let pool = ThreadpoolBuilder::new(1)
+            .queue_capacity(2)
+            .lock_free_queue(true)
+            .build()
+            .unwrap();
+        let (started_tx, started_rx) = mpsc::channel();
+        let (release_tx, release_rx) = mpsc::channel::<()>();
+
+        pool.execute(move || {
+            started_tx.send(()).unwrap();
+            release_rx.recv().unwrap();
+        })
+        .unwrap();
+        started_rx.recv().unwrap();
+
+        assert!(pool.try_execute(|| {}).is_ok());
+        assert!(pool.try_execute(|| {}).is_ok());
+        assert!(pool.try_execute(|| {}).is_err());
+
+        release_tx.send(()).unwrap();
+        pool.execute_batch((0..8).map(|_| Box::new(|| {}) as Job))
+            .unwrap();
+        pool.join();
+        assert_eq!(pool.metrics().completed, 11);
+    }
+
+    #[test]
+    fn resize_changes_worker_count() {
+        let pool = Threadpool::build(2).unwrap();
+
+        pool.set_size(5).unwrap();
+        assert_eq!(pool.size(), 5);
+
+        pool.shrink(3);
+        assert_eq!(pool.size(), 2);
+
+        pool.shrink(10);
+        assert_eq!(pool.size(), 1);
+
+        let handle = pool.submit(|| "done").unwrap();
+        assert_eq!(handle.join().unwrap(), "done");
+    }
+
+    #[test]
+    fn workers_use_thread_name_prefix() {
+        let pool = ThreadpoolBuilder::new(1)
+            .thread_name("web")
+            .stack_size(256 * 1024)
+            .build()
+            .unwrap();
+
+        let handle = pool
+            .submit(|| thread::current().name().map(String::from))
+            .unwrap();
+
+        assert_eq!(handle.join().unwrap(), Some(String::from("web-0")));
+    }
+
+    #[test]
+    fn join_waits_for_jobs_and_keeps_pool_usable() {
+        let pool = Threadpool::build(2).unwrap();
+        let (sender, reciever) = mpsc::channel();
+
+        for round in 0..2 {
+            for _ in 0..4 {
+                let sender = sender.clone();
+                pool.execute(move || {
+                    thread::sleep(Duration::from_millis(10));
+                    sender.send(round).unwrap();
+                })
+                .unwrap();
+            }
+            pool.join();
+
+            assert_eq!(reciever.try_iter().count(), 4);
+        }
+    }
+
+    #[test]
+    fn wait_idle_timeout_expires_while_busy() {
+        let pool = Threadpool::build(1).unwrap();
+        let (release_tx, release_rx) = mpsc::channel::<()>();
+
+        pool.execute(move || release_rx.recv().unwrap()).unwrap();
+
+        assert!(!pool.wait_idle_timeout(Duration::from_millis(20)));
+        release_tx.send(()).unwrap();
+        assert!(pool.wait_idle_timeout(Duration::from_secs(5)));
+    }
+
+    #[test]
+    fn metrics_count_completed_and_panicked_jobs() {
+        let pool = Threadpool::build(2).unwrap();
+
+        pool.execute(|| {}).unwrap();
+        pool.execute(|| {}).unwrap();
+        pool.execute(|| panic!("boom")).unwrap();
+        pool.join();
+
+        let metrics = pool.metrics();
+        assert_eq!(metrics.queued, 0);
+        assert_eq!(metrics.active, 0);
+        assert_eq!(metrics.completed, 2);
+        assert_eq!(metrics.panicked, 1);
+    }
+
+    #[test]
+    fn batched_dequeue_keeps_queue_order() {
+        let pool = ThreadpoolBuilder::new(1).dequeue_batch(4).build().unwrap();
+        let (started_tx, started_rx) = mpsc::channel();
+        let (release_tx, release_rx) = mpsc::channel::<()>();
+        let (sender, reciever) = mpsc::channel();
+
+        pool.execute(move || {
+            started_tx.send(()).unwrap();
+            release_rx.recv().unwrap();
+        })
+        .unwrap();
+        started_rx.recv().unwrap();
+        for i in 0..10 {
+            let sender = sender.clone();
+            pool.execute(move || sender.send(i).unwrap()).unwrap();
+        }
+        release_tx.send(()).unwrap();
+        pool.join();
+
+        assert_eq!(
+            reciever.try_iter().collect::<Vec<_>>(),
+            (0..10).collect::<Vec<_>>()
+        );
+        assert_eq!(pool.metrics().queued, 0);
+    }
+
+    #[test]
+    fn panic_hook_sees_payload_and_label() {
+        let (sender, reciever) = mpsc::channel();
+        let pool = ThreadpoolBuilder::new(1)
+            .on_panic(move |worker, payload, label| {
+                let message = payload.downcast_ref::<&str>().copied();
+                sender
+                    .send((worker, message.map(str::to_owned), label.map(str::to_owned)))
+                    .unwrap();
+            })
+            .build()
+            .unwrap();
+
+        pool.execute_labelled("GET /", || panic!("boom")).unwrap();
+        pool.execute(|| {}).unwrap();
+        pool.join();
+
+        assert_eq!(
+            reciever.try_iter().collect::<Vec<_>>(),
+            [(0, Some("boom".to_owned()), Some("GET /".to_owned()))]
+        );
+    }
+
+    #[test]
+    fn slow_dequeues_are_reported() {
+        let (slow_tx, slow_rx) = mpsc::channel();
+        let pool = ThreadpoolBuilder::new(1)
+            .on_slow_dequeue(Duration::from_millis(20), move |waited| {
+                slow_tx.send(waited).unwrap()
+            })
+            .build()
+            .unwrap();
+        let (started_tx, started_rx) = mpsc::channel();
+        let (release_tx, release_rx) = mpsc::channel::<()>();
+
+        pool.execute(move || {
+            started_tx.send(()).unwrap();
+            release_rx.recv().unwrap();
+        })
+        .unwrap();
+        started_rx.recv().unwrap();
+        pool.execute(|| {}).unwrap();
+
+        thread::sleep(Duration::from_millis(40));
+        release_tx.send(()).unwrap();
+        pool.join();
+
+        let slow: Vec<_> = slow_rx.try_iter().collect();
+        assert_eq!(slow.len(), 1);
+        assert!(slow[0] >= Duration::from_millis(40));
+    }
+
+    #[test]
+    fn shutdown_runs_queued_jobs() {
+        let pool = Threadpool::build(1).unwrap();
+        let (sender, reciever) = mpsc::channel();
+
+        for i in 0..4 {
+            let sender = sender.clone();
+            pool.execute(move || sender.send(i).unwrap()).unwrap();
+        }
+        pool.shutdown();
+
+        assert_eq!(reciever.try_iter().count(), 4);
+    }
+
+    #[test]
+    fn shutdown_now_returns_queued_jobs() {
+        let pool = Threadpool::build(1).unwrap();
+        let (started_tx, started_rx) = mpsc::channel();
+        let (release_tx, release_rx) = mpsc::channel::<()>();
+
+        pool.execute(move || {
+            started_tx.send(()).unwrap();
+            release_rx.recv().unwrap();
+        })
+        .unwrap();
+        pool.execute(|| {}).unwrap();
+        pool.execute(|| {}).unwrap();
+        started_rx.recv().unwrap();
+
+        let release = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            release_tx.send(()).unwrap();
+        });
+        let jobs = pool.shutdown_now();
+        release.join().unwrap();
+
+        assert_eq!(jobs.len(), 2);
+    }
+
+    #[test]
+    fn shutdown_timeout_detaches_stuck_workers() {
+        let pool = Threadpool::build(1).unwrap();
+        let (release_tx, release_rx) = mpsc::channel::<()>();
+
+        pool.execute(move || release_rx.recv().unwrap()).unwrap();
+
+        assert!(!pool.shutdown_timeout(Duration::from_millis(50)));
+        release_tx.send(()).unwrap();
+    }
+
+    #[test]
+    fn observer_sees_job_and_exit_events() {
+        #[derive(Default)]
+        struct Recorder {
+            started: Mutex<Vec<JobInfo>>,
+            ended: Mutex<Vec<(JobInfo, Duration)>>,
+            exits: Mutex<Vec<WorkerExit>>,
+        }
+
+        impl PoolObserver for Arc<Recorder> {
+            fn on_job_start(&self, job: &JobInfo) {
+                self.started.lock().unwrap().push(*job);
+            }
+
+            fn on_job_end(&self, job: &JobInfo, ran: Duration, _panicked: bool) {
+                self.ended.lock().unwrap().push((*job, ran));
+            }
+
+            fn on_worker_exit(&self, _worker: usize, reason: WorkerExit) {
+                self.exits.lock().unwrap().push(reason);
+            }
+        }
+
+        let recorder = Arc::new(Recorder::default());
+        let pool = ThreadpoolBuilder::new(1)
+            .observer(Arc::clone(&recorder))
+            .build()
+            .unwrap();
+        let (running_tx, running_rx) = mpsc::channel();
+        let (release_tx, release_rx) = mpsc::channel::<()>();
+
+        pool.execute(move || {
+            running_tx.send(()).unwrap();
+            release_rx.recv().unwrap();
+        })
+        .unwrap();
+        running_rx.recv().unwrap();
+        // Both wait behind the first job, and the second behind the first of them.
+        pool.execute(|| {}).unwrap();
+        pool.execute(|| {}).unwrap();
+        thread::sleep(Duration::from_millis(50));
+        release_tx.send(()).unwrap();
+        pool.shutdown();
+
+        let started = recorder.started.lock().unwrap();
+        let depths: Vec<_> = started.iter().map(|job| job.queue_depth).collect();
+        assert_eq!(depths, [0, 1, 0]);
+        assert!(started.iter().all(|job| job.worker == 0));
+        assert!(started[1..]
+            .iter()
+            .all(|job| job.waited >= Duration::from_millis(50)));
+        let ended = recorder.ended.lock().unwrap();
+        assert_eq!(ended.len(), 3);
+        assert_eq!(ended[0].0, started[0]);
+        assert!(ended[0].1 >= Duration::from_millis(50));
+        assert_eq!(*recorder.exits.lock().unwrap(), [WorkerExit::Shutdown]);
     }
 }
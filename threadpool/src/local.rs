@@ -0,0 +1,206 @@
+use std::{
+    collections::VecDeque,
+    panic::{self, AssertUnwindSafe},
+    sync::{atomic::Ordering, Arc, Condvar, Mutex},
+    thread,
+    time::Instant,
+};
+
+use crate::{ExecuteError, Job, PoolError, Shared, Stamped, Threadpool};
+
+/// Runs jobs passed to `Threadpool::spawn_local` on a single dedicated thread.
+///
+/// The thread is spawned the first time a job is passed in and runs jobs one at a time
+/// in the order they were submitted, so state kept in its thread-locals is only ever
+/// touched by one job at once.
+#[derive(Default)]
+pub(crate) struct LocalThread {
+    state: Mutex<LocalState>,
+    changed: Condvar,
+    thread: Mutex<Option<thread::JoinHandle<()>>>,
+}
+
+#[derive(Default)]
+struct LocalState {
+    jobs: VecDeque<Stamped>,
+    closed: bool,
+}
+
+impl LocalThread {
+    fn push(shared: &Arc<Shared>, job: Job) -> Result<(), ExecuteError> {
+        let local = &shared.local;
+        let mut thread = local.thread.lock().unwrap();
+
+        {
+            let mut state = local.state.lock().unwrap();
+            if state.closed {
+                return Err(PoolError::ShuttingDown);
+            }
+
+            state.jobs.push_back((job, Instant::now()));
+            shared.in_flight.fetch_add(1, Ordering::SeqCst);
+            shared.counters.job_queued();
+            local.changed.notify_one();
+        }
+
+        if thread.is_none() {
+            let mut builder = thread::Builder::new();
+            if let Some(prefix) = &shared.thread_name {
+                builder = builder.name(format!("{prefix}-local"));
+            }
+
+            // Counted like a worker so `shutdown_timeout` waits for it too.
+            shared.state.lock().unwrap().live += 1;
+            let spawned = builder.spawn({
+                let shared = Arc::clone(shared);
+                move || {
+                    LocalThread::run(&shared);
+                    shared.worker_exited();
+                }
+            });
+
+            match spawned {
+                Ok(handle) => *thread = Some(handle),
+                Err(error) => {
+                    shared.worker_exited();
+                    let jobs = local.take_queued();
+                    shared.counters.jobs_discarded(jobs.len());
+                    shared.finished(jobs.len());
+                    return Err(PoolError::SpawnFailed(error));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn run(shared: &Shared) {
+        let local = &shared.local;
+
+        loop {
+            let (job, enqueued) = {
+                let mut state = local.state.lock().unwrap();
+
+                loop {
+                    if let Some(job) = state.jobs.pop_front() {
+                        break job;
+                    }
+                    if state.closed {
+                        return;
+                    }
+                    state = local.changed.wait(state).unwrap();
+                }
+            };
+
+            shared.job_dequeued(enqueued);
+            // A panicking job must not take the thread, and its thread-locals, with it.
+            let started = Instant::now();
+            let result = panic::catch_unwind(AssertUnwindSafe(|| shared.middleware.run(job)));
+            shared.job_finished(result.is_err(), started.elapsed());
+        }
+    }
+
+    /// Remove every job that has not started yet.
+    pub(crate) fn take_queued(&self) -> Vec<Job> {
+        let mut state = self.state.lock().unwrap();
+        state.jobs.drain(..).map(|(job, _)| job).collect()
+    }
+
+    /// Stop accepting jobs. The thread exits once it has run the jobs already queued.
+    pub(crate) fn close(&self) {
+        let mut state = self.state.lock().unwrap();
+
+        state.closed = true;
+        self.changed.notify_all();
+    }
+
+    pub(crate) fn take_thread(&self) -> Option<thread::JoinHandle<()>> {
+        self.thread.lock().unwrap().take()
+    }
+}
+
+impl Threadpool {
+    /// Execute a closure on the pool's dedicated local thread.
+    ///
+    /// Every job passed here runs on the same thread, one at a time and in submission
+    /// order, so it can keep `!Send` state such as `Rc`-based library handles in
+    /// thread-locals and reuse it across jobs. The closure itself still has to be
+    /// `Send` to reach that thread, but it may create and use `!Send` values freely.
+    /// The thread is spawned on first use and counts towards `join` and `shutdown`
+    /// like the workers do.
+    /// ```
+    /// use std::{cell::RefCell, rc::Rc};
+    /// use threadpool::Threadpool;
+    ///
+    /// thread_local! {
+    ///     static CACHE: RefCell<Option<Rc<String>>> = const { RefCell::new(None) };
+    /// }
+    ///
+    /// let pool = Threadpool::build(4).unwrap();
+    /// for _ in 0..3 {
+    ///     pool.spawn_local(|| {
+    ///         let cache = CACHE.with_borrow_mut(|cache| {
+    ///             Rc::clone(cache.get_or_insert_with(|| Rc::new("parsed".to_owned())))
+    ///         });
+    ///         println!("using {cache}");
+    ///     })
+    ///     .unwrap();
+    /// }
+    /// pool.join();
+    /// ```
+    pub fn spawn_local<F>(&self, f: F) -> Result<(), ExecuteError>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        if self.shared.is_closed() {
+            return Err(PoolError::ShuttingDown);
+        }
+
+        LocalThread::push(&self.shared, Box::new(f))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Threadpool;
+    use std::{cell::Cell, sync::mpsc, thread};
+
+    #[test]
+    fn local_jobs_share_one_thread_in_order() {
+        thread_local! {
+            static COUNT: Cell<usize> = const { Cell::new(0) };
+        }
+
+        let pool = Threadpool::build(2).unwrap();
+        let (sender, reciever) = mpsc::channel();
+
+        for _ in 0..4 {
+            let sender = sender.clone();
+            pool.spawn_local(move || {
+                COUNT.set(COUNT.get() + 1);
+                sender.send((thread::current().id(), COUNT.get())).unwrap();
+            })
+            .unwrap();
+        }
+        pool.join();
+
+        let runs: Vec<_> = reciever.try_iter().collect();
+        assert!(runs.iter().all(|(id, _)| *id == runs[0].0));
+        let counts: Vec<_> = runs.into_iter().map(|(_, count)| count).collect();
+        assert_eq!(counts, [1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn local_thread_survives_panics_and_drains_on_shutdown() {
+        let pool = Threadpool::build(1).unwrap();
+        let (sender, reciever) = mpsc::channel();
+
+        pool.spawn_local(|| panic!("boom")).unwrap();
+        pool.spawn_local(move || sender.send(()).unwrap()).unwrap();
+        pool.shutdown();
+
+        assert!(reciever.try_recv().is_ok());
+        assert_eq!(pool.metrics().panicked, 1);
+        assert!(pool.spawn_local(|| {}).is_err());
+    }
+}
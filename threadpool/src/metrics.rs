@@ -0,0 +1,228 @@
+use std::{
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+/// Upper bounds of the latency histogram buckets. A final bucket counts everything
+/// slower than the last bound.
+const BOUNDS: [Duration; 17] = [
+    Duration::from_micros(50),
+    Duration::from_micros(100),
+    Duration::from_micros(250),
+    Duration::from_micros(500),
+    Duration::from_millis(1),
+    Duration::from_micros(2500),
+    Duration::from_millis(5),
+    Duration::from_millis(10),
+    Duration::from_millis(25),
+    Duration::from_millis(50),
+    Duration::from_millis(100),
+    Duration::from_millis(250),
+    Duration::from_millis(500),
+    Duration::from_secs(1),
+    Duration::from_millis(2500),
+    Duration::from_secs(5),
+    Duration::from_secs(10),
+];
+
+const BUCKETS: usize = BOUNDS.len() + 1;
+
+/// Called with how long a job waited in the queue, see
+/// `ThreadpoolBuilder::on_slow_dequeue`.
+pub(crate) type SlowDequeueHook = Arc<dyn Fn(Duration) + Send + Sync>;
+
+/// Counters updated by the pool and its workers as jobs move through the queue.
+#[derive(Default)]
+pub(crate) struct Counters {
+    queued: AtomicUsize,
+    active: AtomicUsize,
+    completed: AtomicU64,
+    panicked: AtomicU64,
+    queue_wait: Buckets,
+    run_time: Buckets,
+}
+
+impl Counters {
+    pub(crate) fn job_queued(&self) {
+        self.queued.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Return the number of jobs waiting in any queue.
+    pub(crate) fn queued(&self) -> usize {
+        self.queued.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn jobs_discarded(&self, count: usize) {
+        self.queued.fetch_sub(count, Ordering::Relaxed);
+    }
+
+    /// Record that a job queued at `enqueued` has been picked up by a worker, returning
+    /// how long it waited.
+    pub(crate) fn job_started(&self, enqueued: Instant) -> Duration {
+        let waited = enqueued.elapsed();
+
+        self.queued.fetch_sub(1, Ordering::Relaxed);
+        self.active.fetch_add(1, Ordering::Relaxed);
+        self.queue_wait.record(waited);
+        waited
+    }
+
+    pub(crate) fn job_finished(&self, panicked: bool, ran: Duration) {
+        self.active.fetch_sub(1, Ordering::Relaxed);
+        self.run_time.record(ran);
+
+        if panicked {
+            self.panicked.fetch_add(1, Ordering::Relaxed);
+        } else {
+            self.completed.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub(crate) fn snapshot(&self) -> Metrics {
+        Metrics {
+            queued: self.queued.load(Ordering::Relaxed),
+            active: self.active.load(Ordering::Relaxed),
+            completed: self.completed.load(Ordering::Relaxed),
+            panicked: self.panicked.load(Ordering::Relaxed),
+        }
+    }
+
+    pub(crate) fn latency(&self) -> Latency {
+        Latency {
+            queue_wait: self.queue_wait.snapshot(),
+            run_time: self.run_time.snapshot(),
+        }
+    }
+}
+
+/// Live histogram counts, one per bucket of `BOUNDS`.
+#[derive(Default)]
+struct Buckets {
+    counts: [AtomicU64; BUCKETS],
+}
+
+impl Buckets {
+    fn record(&self, duration: Duration) {
+        let bucket = BOUNDS.partition_point(|bound| *bound < duration);
+        self.counts[bucket].fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn snapshot(&self) -> Histogram {
+        Histogram {
+            counts: self
+                .counts
+                .each_ref()
+                .map(|count| count.load(Ordering::Relaxed)),
+        }
+    }
+}
+
+/// A point-in-time view of a pool's activity, returned by `Threadpool::metrics`.
+///
+/// The counters are read independently, so a snapshot taken while jobs are moving
+/// through the pool may be off by a job or two between fields.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Metrics {
+    /// Jobs waiting in the queue.
+    pub queued: usize,
+    /// Workers currently running a job.
+    pub active: usize,
+    /// Jobs that ran to completion.
+    pub completed: u64,
+    /// Jobs that panicked.
+    pub panicked: u64,
+}
+
+/// Job latency histograms, returned by `Threadpool::latency`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Latency {
+    /// How long jobs waited in the queue before a worker picked them up.
+    pub queue_wait: Histogram,
+    /// How long jobs ran for, including those that panicked.
+    pub run_time: Histogram,
+}
+
+/// A histogram of durations over fixed buckets, from 50µs up to 10s.
+/// ```
+/// use threadpool::Threadpool;
+/// let pool = Threadpool::build(2).unwrap();
+///
+/// pool.execute(|| println!("executing...")).unwrap();
+/// pool.join();
+///
+/// let run_time = pool.latency().run_time;
+/// assert_eq!(run_time.count(), 1);
+/// for (bound, count) in run_time.buckets() {
+///     match bound {
+///         Some(bound) => println!("<= {bound:?}: {count}"),
+///         None => println!("slower: {count}"),
+///     }
+/// }
+/// println!("p99 <= {:?}", run_time.quantile(0.99));
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Histogram {
+    counts: [u64; BUCKETS],
+}
+
+impl Histogram {
+    /// Return the number of recorded durations.
+    pub fn count(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+
+    /// Iterate over the buckets as pairs of inclusive upper bound and count, fastest
+    /// first. The last bucket, for durations above 10s, has no bound.
+    pub fn buckets(&self) -> impl Iterator<Item = (Option<Duration>, u64)> + '_ {
+        BOUNDS
+            .iter()
+            .copied()
+            .map(Some)
+            .chain([None])
+            .zip(self.counts.iter().copied())
+    }
+
+    /// Return the upper bound of the bucket holding the `q` quantile, so
+    /// `quantile(0.99)` is a bound on the p99 latency.
+    ///
+    /// `q` is clamped to `0.0..=1.0`. Returns `None` if the histogram is empty, and
+    /// `Duration::MAX` if the quantile falls in the last, unbounded bucket.
+    pub fn quantile(&self, q: f64) -> Option<Duration> {
+        let count = self.count();
+        if count == 0 {
+            return None;
+        }
+
+        let rank = ((q.clamp(0.0, 1.0) * count as f64).ceil() as u64).max(1);
+        let mut seen = 0;
+        self.buckets().find_map(|(bound, bucket)| {
+            seen += bucket;
+            (seen >= rank).then(|| bound.unwrap_or(Duration::MAX))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quantiles_follow_bucket_bounds() {
+        let buckets = Buckets::default();
+        for _ in 0..98 {
+            buckets.record(Duration::from_micros(30));
+        }
+        buckets.record(Duration::from_millis(7));
+        buckets.record(Duration::from_secs(60));
+
+        let histogram = buckets.snapshot();
+        assert_eq!(histogram.count(), 100);
+        assert_eq!(histogram.quantile(0.5), Some(Duration::from_micros(50)));
+        assert_eq!(histogram.quantile(0.99), Some(Duration::from_millis(10)));
+        assert_eq!(histogram.quantile(1.0), Some(Duration::MAX));
+        assert_eq!(Histogram::default().quantile(0.5), None);
+    }
+}
@@ -0,0 +1,107 @@
+use std::sync::{Arc, RwLock};
+
+use crate::{Job, Threadpool};
+
+/// A layer run around every job, added with `Threadpool::wrap`.
+type Middleware = Arc<dyn Fn(Job, &dyn Fn(Job)) + Send + Sync>;
+
+/// The middleware of a pool, innermost first.
+#[derive(Default)]
+pub(crate) struct Layers {
+    layers: RwLock<Arc<[Middleware]>>,
+}
+
+impl Layers {
+    /// Run `job` through every layer.
+    pub(crate) fn run(&self, job: Job) {
+        let layers = Arc::clone(&self.layers.read().unwrap());
+        Layers::run_from(&layers, job);
+    }
+
+    fn run_from(layers: &[Middleware], job: Job) {
+        match layers.split_last() {
+            Some((outer, inner)) => outer(job, &|job| Layers::run_from(inner, job)),
+            None => job(),
+        }
+    }
+
+    fn push(&self, middleware: Middleware) {
+        let mut layers = self.layers.write().unwrap();
+        *layers = layers.iter().cloned().chain([middleware]).collect();
+    }
+}
+
+impl Threadpool {
+    /// Run `middleware` around every job the pool runs from now on.
+    ///
+    /// The middleware is given the job and a `next` function, and must pass the job to
+    /// `next` for it to run, which makes it the place for timing, panic reporting or
+    /// setting thread-locals such as request ids. Each call adds a layer outside the
+    /// existing ones, so the most recently added middleware runs first. A middleware
+    /// that drops the job instead of calling `next` skips it.
+    /// ```
+    /// use std::time::Instant;
+    /// use threadpool::Threadpool;
+    /// let pool = Threadpool::build(4).unwrap();
+    ///
+    /// pool.wrap(|job, next| {
+    ///     let started = Instant::now();
+    ///     next(job);
+    ///     println!("job took {:?}", started.elapsed());
+    /// });
+    /// pool.execute(|| println!("executing...")).unwrap();
+    /// ```
+    pub fn wrap<F>(&self, middleware: F)
+    where
+        F: Fn(Job, &dyn Fn(Job)) + Send + Sync + 'static,
+    {
+        self.shared.middleware.push(Arc::new(middleware));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Threadpool;
+    use std::sync::mpsc;
+
+    #[test]
+    fn middleware_runs_outermost_last_added() {
+        let pool = Threadpool::build(1).unwrap();
+        let (sender, reciever) = mpsc::channel();
+
+        for name in ["inner", "outer"] {
+            let sender = sender.clone();
+            pool.wrap(move |job, next| {
+                sender.send(format!("{name} before")).unwrap();
+                next(job);
+                sender.send(format!("{name} after")).unwrap();
+            });
+        }
+        pool.execute(move || sender.send("job".to_owned()).unwrap())
+            .unwrap();
+        pool.join();
+
+        assert_eq!(
+            reciever.try_iter().collect::<Vec<_>>(),
+            [
+                "outer before",
+                "inner before",
+                "job",
+                "inner after",
+                "outer after"
+            ]
+        );
+    }
+
+    #[test]
+    fn middleware_can_skip_jobs() {
+        let pool = Threadpool::build(1).unwrap();
+        let (sender, reciever) = mpsc::channel::<()>();
+
+        pool.wrap(|job, _next| drop(job));
+        pool.execute(move || sender.send(()).unwrap()).unwrap();
+        pool.join();
+
+        assert!(reciever.try_recv().is_err());
+    }
+}
@@ -0,0 +1,195 @@
+use std::{
+    cell::UnsafeCell,
+    mem::MaybeUninit,
+    sync::atomic::{AtomicUsize, Ordering},
+    thread,
+};
+
+struct Slot<T> {
+    seq: AtomicUsize,
+    value: UnsafeCell<MaybeUninit<T>>,
+}
+
+/// A bounded lock-free multi-producer multi-consumer queue.
+///
+/// This is Dmitry Vyukov's array-based queue: every slot carries a sequence number that
+/// tells producers and consumers whether it is free for the current lap, so both ends
+/// only ever contend on a single compare-and-swap.
+pub(crate) struct ArrayQueue<T> {
+    slots: Box<[Slot<T>]>,
+    mask: usize,
+    head: AtomicUsize,
+    tail: AtomicUsize,
+    /// Slots that are either occupied or reserved by a producer.
+    len: AtomicUsize,
+}
+
+// SAFETY: values are only accessed by the single producer or consumer that won the
+// slot's sequence number, so sharing the queue is as safe as sending its values.
+unsafe impl<T: Send> Send for ArrayQueue<T> {}
+unsafe impl<T: Send> Sync for ArrayQueue<T> {}
+
+impl<T> ArrayQueue<T> {
+    /// Create a queue holding at least `capacity` values, rounded up to a power of two.
+    pub(crate) fn new(capacity: usize) -> ArrayQueue<T> {
+        let capacity = capacity.max(1).next_power_of_two();
+        let slots = (0..capacity)
+            .map(|i| Slot {
+                seq: AtomicUsize::new(i),
+                value: UnsafeCell::new(MaybeUninit::uninit()),
+            })
+            .collect();
+
+        ArrayQueue {
+            slots,
+            mask: capacity - 1,
+            head: AtomicUsize::new(0),
+            tail: AtomicUsize::new(0),
+            len: AtomicUsize::new(0),
+        }
+    }
+
+    /// Push `value`, or hand it back if the queue is full.
+    pub(crate) fn push(&self, value: T) -> Result<(), T> {
+        if !self.try_reserve() {
+            return Err(value);
+        }
+
+        self.push_reserved(value);
+        Ok(())
+    }
+
+    /// Claim space for one value without blocking. A successful reservation must be
+    /// followed by `push_reserved`.
+    pub(crate) fn try_reserve(&self) -> bool {
+        self.len
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |len| {
+                (len < self.slots.len()).then_some(len + 1)
+            })
+            .is_ok()
+    }
+
+    /// Push into space claimed by `try_reserve`.
+    pub(crate) fn push_reserved(&self, value: T) {
+        loop {
+            let tail = self.tail.load(Ordering::Relaxed);
+            let slot = &self.slots[tail & self.mask];
+            let seq = slot.seq.load(Ordering::Acquire);
+
+            if seq == tail {
+                if self
+                    .tail
+                    .compare_exchange_weak(tail, tail + 1, Ordering::Relaxed, Ordering::Relaxed)
+                    .is_ok()
+                {
+                    // SAFETY: winning the exchange gives this producer exclusive access to
+                    // the slot until its sequence number is published below.
+                    unsafe { (*slot.value.get()).write(value) };
+                    slot.seq.store(tail + 1, Ordering::Release);
+                    return;
+                }
+            } else if seq < tail {
+                // The reservation guarantees space, but the consumer of the previous lap
+                // has not released this slot yet.
+                thread::yield_now();
+            }
+        }
+    }
+
+    pub(crate) fn pop(&self) -> Option<T> {
+        loop {
+            let head = self.head.load(Ordering::Relaxed);
+            let slot = &self.slots[head & self.mask];
+            let seq = slot.seq.load(Ordering::Acquire);
+
+            if seq == head + 1 {
+                if self
+                    .head
+                    .compare_exchange_weak(head, head + 1, Ordering::Relaxed, Ordering::Relaxed)
+                    .is_ok()
+                {
+                    // SAFETY: the sequence number shows the slot holds a value written for
+                    // this lap, and winning the exchange makes this the only reader.
+                    let value = unsafe { (*slot.value.get()).assume_init_read() };
+                    slot.seq.store(head + self.mask + 1, Ordering::Release);
+                    self.len.fetch_sub(1, Ordering::SeqCst);
+                    return Some(value);
+                }
+            } else if seq <= head {
+                return None;
+            }
+        }
+    }
+}
+
+impl<T> Drop for ArrayQueue<T> {
+    fn drop(&mut self) {
+        while self.pop().is_some() {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[test]
+    fn rejects_pushes_when_full() {
+        let queue = ArrayQueue::new(2);
+
+        assert!(queue.push(1).is_ok());
+        assert!(queue.push(2).is_ok());
+        assert_eq!(queue.push(3), Err(3));
+        assert_eq!(queue.pop(), Some(1));
+        assert!(queue.push(3).is_ok());
+        assert_eq!(queue.pop(), Some(2));
+        assert_eq!(queue.pop(), Some(3));
+        assert_eq!(queue.pop(), None);
+    }
+
+    #[test]
+    fn concurrent_producers_and_consumers_see_every_value() {
+        let queue = Arc::new(ArrayQueue::new(16));
+
+        let producers: Vec<_> = (0..4)
+            .map(|p| {
+                let queue = Arc::clone(&queue);
+                thread::spawn(move || {
+                    for i in 0..1000 {
+                        let mut value = p * 1000 + i;
+                        while let Err(rejected) = queue.push(value) {
+                            value = rejected;
+                            thread::yield_now();
+                        }
+                    }
+                })
+            })
+            .collect();
+        let consumers: Vec<_> = (0..4)
+            .map(|_| {
+                let queue = Arc::clone(&queue);
+                thread::spawn(move || {
+                    let mut seen = Vec::new();
+                    while seen.len() < 1000 {
+                        match queue.pop() {
+                            Some(value) => seen.push(value),
+                            None => thread::yield_now(),
+                        }
+                    }
+                    seen
+                })
+            })
+            .collect();
+
+        for producer in producers {
+            producer.join().unwrap();
+        }
+        let mut seen: Vec<_> = consumers
+            .into_iter()
+            .flat_map(|consumer| consumer.join().unwrap())
+            .collect();
+        seen.sort_unstable();
+
+        assert_eq!(seen, (0..4000).collect::<Vec<_>>());
+    }
+}
@@ -0,0 +1,79 @@
+use crate::{ExecuteError, JobId, Priority, Threadpool};
+
+/// A named queue on a pool, returned by `Threadpool::queue`.
+///
+/// Jobs from every named queue share the pool's workers. When several queues have work
+/// waiting, workers take jobs from them in turn, so a burst on one queue only delays
+/// the others by its weight's worth of jobs at a time.
+pub struct NamedQueue<'pool> {
+    pool: &'pool Threadpool,
+    index: usize,
+}
+
+impl NamedQueue<'_> {
+    /// Execute a closure using a thread from the pool, queued on this queue.
+    ///
+    /// Blocks and fails under the same conditions as `Threadpool::execute`.
+    pub fn execute<F>(&self, f: F) -> Result<JobId, ExecuteError>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        self.execute_with_priority(Priority::Normal, f)
+    }
+
+    /// Execute a closure on this queue, ahead of this queue's lower priority jobs.
+    pub fn execute_with_priority<F>(&self, priority: Priority, f: F) -> Result<JobId, ExecuteError>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        let shared = &self.pool.shared;
+        let (id, job) = shared.statuses.track(Box::new(f));
+
+        shared.push_blocking(self.index, priority, job, None)?;
+        Ok(id)
+    }
+}
+
+impl Threadpool {
+    /// Return the queue called `name`, creating it if it does not exist yet.
+    ///
+    /// Queues created here have a weight of one unless configured otherwise with
+    /// `ThreadpoolBuilder::queue_weight`. Named queues always go through the pool's
+    /// shared queue, even when it was built with a lock-free queue.
+    /// ```
+    /// use threadpool::Threadpool;
+    /// let pool = Threadpool::build(4).unwrap();
+    ///
+    /// pool.queue("tenant-a").execute(|| println!("serving a...")).unwrap();
+    /// pool.queue("tenant-b").execute(|| println!("serving b...")).unwrap();
+    /// ```
+    pub fn queue(&self, name: &str) -> NamedQueue<'_> {
+        let index = self.shared.state.lock().unwrap().jobs.queue_index(name);
+
+        NamedQueue { pool: self, index }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+
+    #[test]
+    fn named_queues_run_their_jobs() {
+        let pool = Threadpool::build(2).unwrap();
+        let (sender, reciever) = mpsc::channel();
+
+        for name in ["a", "b", "a"] {
+            let sender = sender.clone();
+            pool.queue(name)
+                .execute(move || sender.send(name).unwrap())
+                .unwrap();
+        }
+        drop(sender);
+
+        let mut names: Vec<_> = reciever.iter().collect();
+        names.sort_unstable();
+        assert_eq!(names, ["a", "a", "b"]);
+    }
+}
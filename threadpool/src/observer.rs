@@ -0,0 +1,93 @@
+use std::time::Duration;
+
+/// What a worker knows about the job it is running, given to `PoolObserver`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct JobInfo {
+    /// The id of the worker running the job.
+    pub worker: usize,
+    /// How long the job waited to be picked up after it was queued.
+    pub waited: Duration,
+    /// The number of jobs still waiting in any queue when this one was picked up.
+    pub queue_depth: usize,
+}
+
+/// Why a worker thread stopped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WorkerExit {
+    /// A job panicked; a replacement worker is spawned with the same id.
+    Panicked,
+    /// The pool was shrunk and this worker was chosen to exit.
+    Retired,
+    /// An elastic worker above the core size was idle for longer than the keep-alive.
+    Idle,
+    /// The watchdog spawned a replacement while this worker's job was overrunning, and
+    /// the job has now finished.
+    Replaced,
+    /// `Threadpool::recycle_workers` asked the worker to make way for a fresh thread
+    /// with the same id.
+    Recycled,
+    /// The pool is shutting down.
+    Shutdown,
+}
+
+/// Receives events from the pool's workers.
+///
+/// Every method has an empty default, so implementors only override the events they
+/// care about. Methods are called on the worker thread itself and should return quickly.
+/// Apart from errors on stderr the pool prints nothing itself, so an observer that
+/// forwards events to `log` or `tracing` sees all of its activity.
+///
+/// ```
+/// use std::time::Duration;
+/// use threadpool::{JobInfo, PoolObserver, ThreadpoolBuilder};
+///
+/// struct Log;
+///
+/// impl PoolObserver for Log {
+///     fn on_job_end(&self, job: &JobInfo, ran: Duration, panicked: bool) {
+///         if panicked {
+///             eprintln!("job on worker {} panicked after {ran:?}", job.worker);
+///         }
+///     }
+/// }
+///
+/// let pool = ThreadpoolBuilder::new(2).observer(Log).build().unwrap();
+/// ```
+pub trait PoolObserver: Send + Sync {
+    /// A worker is about to run a job.
+    fn on_job_start(&self, _job: &JobInfo) {}
+
+    /// A worker finished running a job, which took `ran`.
+    fn on_job_end(&self, _job: &JobInfo, _ran: Duration, _panicked: bool) {}
+
+    /// A worker is exiting.
+    fn on_worker_exit(&self, _worker: usize, _reason: WorkerExit) {}
+}
+
+/// The default observer, which ignores every event.
+pub(crate) struct Silent;
+
+impl PoolObserver for Silent {}
+
+/// An observer that prints worker activity to stdout, as the pool did before observers
+/// were configurable.
+pub struct StdoutObserver;
+
+impl PoolObserver for StdoutObserver {
+    fn on_job_start(&self, job: &JobInfo) {
+        println!("Worker {} got a job; executing.", job.worker);
+    }
+
+    fn on_worker_exit(&self, worker: usize, reason: WorkerExit) {
+        match reason {
+            WorkerExit::Panicked => {
+                println!("Worker {worker} panicked while executing a job; respawning.")
+            }
+            WorkerExit::Retired => println!("Worker {worker} retiring; shutting down."),
+            WorkerExit::Idle => println!("Worker {worker} idle; shutting down."),
+            WorkerExit::Replaced => println!("Worker {worker} replaced; shutting down."),
+            WorkerExit::Shutdown => println!("Worker {worker} disconnected; shutting down."),
+            WorkerExit::Recycled => println!("Worker {worker} recycled; respawning."),
+        }
+    }
+}
@@ -0,0 +1,387 @@
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+use crate::Job;
+
+/// How long a job has to wait before it is treated as one priority level higher,
+/// unless configured with `ThreadpoolBuilder::priority_aging`.
+pub(crate) const DEFAULT_AGING_INTERVAL: Duration = Duration::from_millis(500);
+
+/// The priority of a job, used by `Threadpool::execute_with_priority`.
+///
+/// Higher priority jobs are dequeued first, but a queued job is promoted one level for
+/// every 500ms it has been waiting, so a steady stream of `High` jobs cannot starve
+/// `Normal` and `Low` work indefinitely. The interval can be changed or aging turned
+/// off with `ThreadpoolBuilder::priority_aging`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Priority {
+    High,
+    #[default]
+    Normal,
+    Low,
+}
+
+impl Priority {
+    const LEVELS: usize = 3;
+
+    fn level(self) -> usize {
+        self as usize
+    }
+}
+
+/// The order in which jobs of the same priority leave the shared queue, set with
+/// `ThreadpoolBuilder::scheduling_policy`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SchedulingPolicy {
+    /// Oldest job first.
+    #[default]
+    Fifo,
+    /// Newest job first. Recently queued jobs tend to touch data that is still in
+    /// cache, at the cost of fairness: the oldest job of a level can wait until the
+    /// level drains.
+    Lifo,
+}
+
+/// What a blocking submission such as `Threadpool::execute` does when the queue is at
+/// capacity, set with `ThreadpoolBuilder::rejection_policy`.
+///
+/// `try_execute` always returns a `QueueFull` error instead.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RejectionPolicy {
+    /// Wait until there is space.
+    #[default]
+    Block,
+    /// Discard the new job. The submission still succeeds, so the job's `JobHandle`
+    /// reports `Dropped` and its status is forgotten.
+    DropNewest,
+    /// Discard the job that has been queued longest, whatever its priority, to make
+    /// room for the new one.
+    DropOldest,
+    /// Run the new job on the submitting thread before returning. A panic in the job
+    /// propagates to the caller.
+    CallerRuns,
+}
+
+struct QueuedJob {
+    job: Job,
+    enqueued: Instant,
+    /// Identifies the job for `remove`.
+    tag: Option<usize>,
+}
+
+/// The index of the pool's unnamed queue, used by `Threadpool::execute`.
+pub(crate) const DEFAULT_QUEUE: usize = 0;
+
+/// The pending jobs of one named queue, one FIFO per priority level.
+struct SubQueue {
+    name: Option<String>,
+    /// Number of jobs taken from this queue in a row before moving to the next one.
+    weight: usize,
+    levels: [VecDeque<QueuedJob>; Priority::LEVELS],
+}
+
+impl SubQueue {
+    fn new(name: Option<String>, weight: usize) -> SubQueue {
+        SubQueue {
+            name,
+            weight: weight.max(1),
+            levels: Default::default(),
+        }
+    }
+
+    /// Remove the job with the highest effective priority.
+    ///
+    /// Only the oldest job of each level needs to be considered, since it is also the
+    /// one that has aged the most. Ties go to the job that has waited longest. Within
+    /// the chosen level the scheduling policy decides whether the oldest or the newest
+    /// job is taken.
+    fn pop(
+        &mut self,
+        now: Instant,
+        policy: SchedulingPolicy,
+        aging: Option<Duration>,
+    ) -> Option<(Job, Instant)> {
+        let level = self
+            .levels
+            .iter()
+            .enumerate()
+            .filter_map(|(level, jobs)| {
+                let oldest = jobs.front()?;
+                let waited = now.duration_since(oldest.enqueued);
+                let promotion = aging.map_or(0, |interval| {
+                    (waited.as_nanos() / interval.as_nanos().max(1)) as usize
+                });
+
+                Some((level.saturating_sub(promotion), oldest.enqueued, level))
+            })
+            .min()
+            .map(|(_, _, level)| level)?;
+
+        let jobs = &mut self.levels[level];
+        let queued = match policy {
+            SchedulingPolicy::Fifo => jobs.pop_front(),
+            SchedulingPolicy::Lifo => jobs.pop_back(),
+        };
+        queued.map(|queued| (queued.job, queued.enqueued))
+    }
+
+    fn len(&self) -> usize {
+        self.levels.iter().map(VecDeque::len).sum()
+    }
+}
+
+/// The pool's pending jobs.
+///
+/// Jobs are spread over named queues, see `Threadpool::queue`, which are served in
+/// weighted round-robin order so a burst on one queue cannot starve the others.
+pub(crate) struct JobQueue {
+    queues: Vec<SubQueue>,
+    policy: SchedulingPolicy,
+    /// How long a job waits per level of promotion, or `None` if jobs never age.
+    aging: Option<Duration>,
+    /// The queue currently being served, and how many jobs it has had this turn.
+    current: usize,
+    served: usize,
+}
+
+impl Default for JobQueue {
+    fn default() -> JobQueue {
+        JobQueue::new(SchedulingPolicy::Fifo)
+    }
+}
+
+impl JobQueue {
+    pub(crate) fn new(policy: SchedulingPolicy) -> JobQueue {
+        JobQueue {
+            queues: vec![SubQueue::new(None, 1)],
+            policy,
+            aging: Some(DEFAULT_AGING_INTERVAL),
+            current: DEFAULT_QUEUE,
+            served: 0,
+        }
+    }
+
+    /// Return the index of the queue called `name`, creating it with a weight of one if
+    /// it does not exist yet.
+    pub(crate) fn queue_index(&mut self, name: &str) -> usize {
+        match self
+            .queues
+            .iter()
+            .position(|queue| queue.name.as_deref() == Some(name))
+        {
+            Some(index) => index,
+            None => {
+                self.queues.push(SubQueue::new(Some(name.to_owned()), 1));
+                self.queues.len() - 1
+            }
+        }
+    }
+
+    pub(crate) fn set_aging(&mut self, aging: Option<Duration>) {
+        self.aging = aging;
+    }
+
+    /// Set how many jobs in a row the queue called `name` gets when it is its turn.
+    /// Weights below one are raised to one.
+    pub(crate) fn set_weight(&mut self, name: &str, weight: usize) {
+        let index = self.queue_index(name);
+        self.queues[index].weight = weight.max(1);
+    }
+
+    pub(crate) fn push(&mut self, priority: Priority, job: Job) {
+        self.push_to(DEFAULT_QUEUE, priority, job, None);
+    }
+
+    /// Queue a job on the queue at `queue`. A job pushed with a tag can later be taken
+    /// back out with `remove(tag)`.
+    pub(crate) fn push_to(
+        &mut self,
+        queue: usize,
+        priority: Priority,
+        job: Job,
+        tag: Option<usize>,
+    ) {
+        self.queues[queue].levels[priority.level()].push_back(QueuedJob {
+            job,
+            enqueued: Instant::now(),
+            tag,
+        });
+    }
+
+    /// Take the job queued with `tag` out of the queue, if it is still waiting.
+    pub(crate) fn remove(&mut self, tag: usize) -> Option<Job> {
+        self.queues
+            .iter_mut()
+            .flat_map(|queue| queue.levels.iter_mut())
+            .find_map(|jobs| {
+                let index = jobs.iter().position(|queued| queued.tag == Some(tag))?;
+                jobs.remove(index).map(|queued| queued.job)
+            })
+    }
+
+    /// Remove the next job, taking up to `weight` jobs from each non-empty queue in
+    /// turn. Returns the job together with the moment it was queued.
+    pub(crate) fn pop(&mut self) -> Option<(Job, Instant)> {
+        if self.len() == 0 {
+            return None;
+        }
+        let now = Instant::now();
+
+        // Terminates because some queue is non-empty and every weight is at least one.
+        loop {
+            let queue = &mut self.queues[self.current];
+
+            if self.served < queue.weight {
+                if let Some(popped) = queue.pop(now, self.policy, self.aging) {
+                    self.served += 1;
+                    return Some(popped);
+                }
+            }
+
+            self.current = (self.current + 1) % self.queues.len();
+            self.served = 0;
+        }
+    }
+
+    /// Remove the job that has been queued longest, regardless of its queue or priority.
+    pub(crate) fn pop_oldest(&mut self) -> Option<Job> {
+        self.queues
+            .iter_mut()
+            .flat_map(|queue| queue.levels.iter_mut())
+            .filter(|jobs| !jobs.is_empty())
+            .min_by_key(|jobs| jobs[0].enqueued)?
+            .pop_front()
+            .map(|queued| queued.job)
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.queues.iter().map(SubQueue::len).sum()
+    }
+
+    /// Remove every queued job, highest priority first within each queue.
+    pub(crate) fn take_all(&mut self) -> Vec<Job> {
+        self.queues
+            .iter_mut()
+            .flat_map(|queue| queue.levels.iter_mut())
+            .flat_map(|jobs| jobs.drain(..))
+            .map(|queued| queued.job)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+
+    fn drain_labels(
+        queue: &mut JobQueue,
+        reciever: &mpsc::Receiver<&'static str>,
+    ) -> Vec<&'static str> {
+        while let Some((job, _)) = queue.pop() {
+            job();
+        }
+        reciever.try_iter().collect()
+    }
+
+    #[test]
+    fn pops_highest_priority_first() {
+        let (sender, reciever) = mpsc::channel();
+        let mut queue = JobQueue::default();
+
+        for (priority, label) in [
+            (Priority::Low, "low"),
+            (Priority::High, "high"),
+            (Priority::Normal, "normal"),
+        ] {
+            let sender = sender.clone();
+            queue.push(priority, Box::new(move || sender.send(label).unwrap()));
+        }
+
+        assert_eq!(
+            drain_labels(&mut queue, &reciever),
+            ["high", "normal", "low"]
+        );
+    }
+
+    #[test]
+    fn lifo_pops_newest_first_within_a_level() {
+        let (sender, reciever) = mpsc::channel();
+        let mut queue = JobQueue::new(SchedulingPolicy::Lifo);
+
+        for label in ["first", "second", "third"] {
+            let sender = sender.clone();
+            queue.push(
+                Priority::Normal,
+                Box::new(move || sender.send(label).unwrap()),
+            );
+        }
+
+        assert_eq!(
+            drain_labels(&mut queue, &reciever),
+            ["third", "second", "first"]
+        );
+    }
+
+    #[test]
+    fn named_queues_are_served_by_weight() {
+        let (sender, reciever) = mpsc::channel();
+        let mut queue = JobQueue::default();
+        queue.set_weight("busy", 2);
+        let busy = queue.queue_index("busy");
+        let quiet = queue.queue_index("quiet");
+
+        for label in ["b1", "b2", "b3", "b4"] {
+            let sender = sender.clone();
+            let job = Box::new(move || sender.send(label).unwrap());
+            queue.push_to(busy, Priority::Normal, job, None);
+        }
+        for label in ["q1", "q2"] {
+            let sender = sender.clone();
+            let job = Box::new(move || sender.send(label).unwrap());
+            queue.push_to(quiet, Priority::Normal, job, None);
+        }
+
+        assert_eq!(
+            drain_labels(&mut queue, &reciever),
+            ["b1", "b2", "q1", "b3", "b4", "q2"]
+        );
+    }
+
+    #[test]
+    fn aged_jobs_overtake_newer_high_priority_jobs() {
+        let (sender, reciever) = mpsc::channel();
+        let mut queue = JobQueue::default();
+
+        let low = sender.clone();
+        queue.push(Priority::Low, Box::new(move || low.send("low").unwrap()));
+        queue.queues[DEFAULT_QUEUE].levels[Priority::Low.level()][0].enqueued -=
+            DEFAULT_AGING_INTERVAL * 2;
+        queue.push(
+            Priority::High,
+            Box::new(move || sender.send("high").unwrap()),
+        );
+
+        assert_eq!(drain_labels(&mut queue, &reciever), ["low", "high"]);
+    }
+
+    #[test]
+    fn jobs_keep_their_priority_without_aging() {
+        let (sender, reciever) = mpsc::channel();
+        let mut queue = JobQueue::default();
+        queue.set_aging(None);
+
+        let low = sender.clone();
+        queue.push(Priority::Low, Box::new(move || low.send("low").unwrap()));
+        queue.queues[DEFAULT_QUEUE].levels[Priority::Low.level()][0].enqueued -=
+            Duration::from_secs(60);
+        queue.push(
+            Priority::High,
+            Box::new(move || sender.send("high").unwrap()),
+        );
+
+        assert_eq!(drain_labels(&mut queue, &reciever), ["high", "low"]);
+    }
+}
@@ -0,0 +1,204 @@
+use std::{
+    ops::{Deref, DerefMut},
+    sync::{Arc, Mutex},
+};
+
+use crate::{ExecuteError, JobId, Threadpool};
+
+/// A job object that can be run by `Threadpool::execute_runnable`.
+///
+/// Unlike the closures behind `Job`, a runnable takes `&mut self`, so a stateful job
+/// object, along with any buffers it owns, can be reused for the next job once it has
+/// run. Combine it with a `Recycler` to keep a stock of pre-allocated objects.
+pub trait Runnable: Send {
+    /// Do the job's work.
+    fn run(&mut self);
+}
+
+/// A free list of boxed job objects, so the hot path reuses them instead of allocating
+/// a fresh one per job.
+///
+/// ```
+/// use threadpool::{Recycler, Runnable, Threadpool};
+///
+/// #[derive(Default)]
+/// struct Render {
+///     buffer: Vec<u8>,
+/// }
+///
+/// impl Runnable for Render {
+///     fn run(&mut self) {
+///         self.buffer.clear();
+///         self.buffer.extend_from_slice(b"<h1>Hello!</h1>");
+///     }
+/// }
+///
+/// let pool = Threadpool::build(4).unwrap();
+/// let renders = Recycler::new(8, Render::default);
+///
+/// for _ in 0..100 {
+///     pool.execute_runnable(renders.get()).unwrap();
+/// }
+/// pool.join();
+/// ```
+pub struct Recycler<J> {
+    inner: Arc<RecyclerInner<J>>,
+}
+
+struct RecyclerInner<J> {
+    free: Mutex<Vec<Box<J>>>,
+    capacity: usize,
+    factory: Box<dyn Fn() -> J + Send + Sync>,
+}
+
+impl<J> Clone for Recycler<J> {
+    fn clone(&self) -> Recycler<J> {
+        Recycler {
+            inner: Arc::clone(&self.inner),
+        }
+    }
+}
+
+impl<J> Recycler<J> {
+    /// Create a recycler holding `capacity` objects made by `factory` up front.
+    ///
+    /// When every object is in use `get` makes a new one, and objects returned while
+    /// the free list already holds `capacity` are dropped.
+    pub fn new<F>(capacity: usize, factory: F) -> Recycler<J>
+    where
+        F: Fn() -> J + Send + Sync + 'static,
+    {
+        let free = (0..capacity).map(|_| Box::new(factory())).collect();
+
+        Recycler {
+            inner: Arc::new(RecyclerInner {
+                free: Mutex::new(free),
+                capacity,
+                factory: Box::new(factory),
+            }),
+        }
+    }
+
+    /// Take an object from the free list, or make a new one if it is empty.
+    ///
+    /// The object goes back to the free list when the returned `Recycled` is dropped,
+    /// for example after the pool has run it.
+    pub fn get(&self) -> Recycled<J> {
+        let object = self.inner.free.lock().unwrap().pop();
+
+        Recycled {
+            object: Some(object.unwrap_or_else(|| Box::new((self.inner.factory)()))),
+            recycler: Arc::clone(&self.inner),
+        }
+    }
+
+    /// Return the number of objects waiting on the free list.
+    pub fn available(&self) -> usize {
+        self.inner.free.lock().unwrap().len()
+    }
+}
+
+/// An object borrowed from a `Recycler`, returned to it on drop.
+pub struct Recycled<J> {
+    object: Option<Box<J>>,
+    recycler: Arc<RecyclerInner<J>>,
+}
+
+impl<J> Deref for Recycled<J> {
+    type Target = J;
+
+    fn deref(&self) -> &J {
+        self.object.as_ref().unwrap()
+    }
+}
+
+impl<J> DerefMut for Recycled<J> {
+    fn deref_mut(&mut self) -> &mut J {
+        self.object.as_mut().unwrap()
+    }
+}
+
+impl<J> Drop for Recycled<J> {
+    fn drop(&mut self) {
+        let mut free = self.recycler.free.lock().unwrap();
+
+        if free.len() < self.recycler.capacity {
+            free.extend(self.object.take());
+        }
+    }
+}
+
+impl<J: Runnable> Runnable for Recycled<J> {
+    fn run(&mut self) {
+        J::run(self);
+    }
+}
+
+impl Threadpool {
+    /// Execute a job object using a thread from the pool.
+    ///
+    /// The object is dropped once it has run, which for a `Recycled` object puts it
+    /// back on its recycler's free list. Only a small box holding the object itself is
+    /// allocated per job.
+    ///
+    /// Blocks and fails under the same conditions as `execute`.
+    pub fn execute_runnable<R>(&self, mut job: R) -> Result<JobId, ExecuteError>
+    where
+        R: Runnable + 'static,
+    {
+        self.execute(move || job.run())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+
+    struct Count {
+        runs: usize,
+        sender: mpsc::Sender<usize>,
+    }
+
+    impl Runnable for Count {
+        fn run(&mut self) {
+            self.runs += 1;
+            self.sender.send(self.runs).unwrap();
+        }
+    }
+
+    #[test]
+    fn recycled_objects_keep_their_state() {
+        let pool = Threadpool::build(1).unwrap();
+        let (sender, reciever) = mpsc::channel();
+        let recycler = Recycler::new(1, move || Count {
+            runs: 0,
+            sender: sender.clone(),
+        });
+
+        for _ in 0..3 {
+            pool.execute_runnable(recycler.get()).unwrap();
+            pool.join();
+        }
+
+        assert_eq!(reciever.try_iter().collect::<Vec<_>>(), [1, 2, 3]);
+        assert_eq!(recycler.available(), 1);
+    }
+
+    #[test]
+    fn recycler_drops_objects_beyond_capacity() {
+        let (sender, _reciever) = mpsc::channel();
+        let recycler = Recycler::new(1, move || Count {
+            runs: 0,
+            sender: sender.clone(),
+        });
+
+        let first = recycler.get();
+        let second = recycler.get();
+        assert_eq!(recycler.available(), 0);
+
+        drop(first);
+        drop(second);
+        assert_eq!(recycler.available(), 1);
+    }
+}
@@ -0,0 +1,183 @@
+use std::{
+    panic::{self, AssertUnwindSafe},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Weak,
+    },
+    time::{Duration, Instant},
+};
+
+use crate::{timer::Timer, ExecuteError, PoolError, Shared, Threadpool};
+
+/// How the next run of a repeating job is timed, see `Threadpool::schedule_repeating`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RepeatMode {
+    /// Runs start every `interval` measured from the first scheduled run, regardless of
+    /// how long each run takes. A run that overruns its slot delays the next one, which
+    /// then starts immediately; runs never overlap.
+    FixedRate,
+    /// Each run starts `interval` after the previous run finished.
+    FixedDelay,
+}
+
+/// A handle to a repeating job, returned by `Threadpool::schedule_repeating`.
+///
+/// Dropping the handle does not cancel the job.
+#[derive(Debug, Clone)]
+pub struct ScheduleHandle {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl ScheduleHandle {
+    /// Stop the job from running again. A run that has already started is not
+    /// interrupted.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+}
+
+struct Repeating {
+    f: Arc<dyn Fn() + Send + Sync>,
+    interval: Duration,
+    mode: RepeatMode,
+    cancelled: Arc<AtomicBool>,
+    shared: Weak<Shared>,
+}
+
+impl Repeating {
+    /// Returns `false` if the pool is shutting down, which ends the recurrence.
+    fn schedule(self, due: Instant) -> bool {
+        let Some(shared) = self.shared.upgrade() else {
+            return false;
+        };
+
+        Timer::schedule(&shared, due, Box::new(move || self.run(due)))
+    }
+
+    fn run(self, due: Instant) {
+        if self.cancelled.load(Ordering::SeqCst) {
+            return;
+        }
+
+        let result = panic::catch_unwind(AssertUnwindSafe(|| (self.f)()));
+
+        let next = match self.mode {
+            RepeatMode::FixedRate => due + self.interval,
+            RepeatMode::FixedDelay => Instant::now() + self.interval,
+        };
+        if !self.cancelled.load(Ordering::SeqCst) {
+            self.schedule(next);
+        }
+
+        // The next run is already scheduled, so a panic is only reported to the pool
+        // rather than ending the recurrence.
+        if let Err(payload) = result {
+            panic::resume_unwind(payload);
+        }
+    }
+}
+
+impl Threadpool {
+    /// Run `f` on the pool every `interval`, starting one interval from now, until the
+    /// returned handle is cancelled or the pool shuts down.
+    ///
+    /// A panicking run is reported like any other job panic but does not stop later
+    /// runs.
+    ///
+    /// Returns a `ShuttingDown` error if the pool has been shut down.
+    /// ```
+    /// use std::time::Duration;
+    /// use threadpool::{RepeatMode, Threadpool};
+    /// let pool = Threadpool::build(2).unwrap();
+    ///
+    /// let flush = pool.schedule_repeating(Duration::from_secs(5), RepeatMode::FixedDelay, || {
+    ///     println!("flushing logs...");
+    /// })
+    /// .unwrap();
+    /// flush.cancel();
+    /// ```
+    pub fn schedule_repeating<F>(
+        &self,
+        interval: Duration,
+        mode: RepeatMode,
+        f: F,
+    ) -> Result<ScheduleHandle, ExecuteError>
+    where
+        F: Fn() + Send + Sync + 'static,
+    {
+        let cancelled = Arc::new(AtomicBool::new(false));
+
+        let scheduled = Repeating {
+            f: Arc::new(f),
+            interval,
+            mode,
+            cancelled: Arc::clone(&cancelled),
+            shared: Arc::downgrade(&self.shared),
+        }
+        .schedule(Instant::now() + interval);
+
+        if !scheduled {
+            return Err(PoolError::ShuttingDown);
+        }
+        Ok(ScheduleHandle { cancelled })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{sync::mpsc, thread};
+
+    #[test]
+    fn repeating_job_runs_until_cancelled() {
+        let pool = Threadpool::build(2).unwrap();
+        let (sender, reciever) = mpsc::channel();
+
+        let handle = pool
+            .schedule_repeating(
+                Duration::from_millis(10),
+                RepeatMode::FixedRate,
+                move || {
+                    let _ = sender.send(());
+                },
+            )
+            .unwrap();
+
+        for _ in 0..3 {
+            reciever.recv_timeout(Duration::from_secs(5)).unwrap();
+        }
+        handle.cancel();
+        // A run may already have been queued when the handle was cancelled.
+        thread::sleep(Duration::from_millis(50));
+        let _ = reciever.try_iter().count();
+        thread::sleep(Duration::from_millis(50));
+
+        assert_eq!(reciever.try_iter().count(), 0);
+    }
+
+    #[test]
+    fn fixed_delay_survives_panicking_runs() {
+        let pool = Threadpool::build(1).unwrap();
+        let (sender, reciever) = mpsc::channel();
+
+        let handle = pool
+            .schedule_repeating(
+                Duration::from_millis(10),
+                RepeatMode::FixedDelay,
+                move || {
+                    let _ = sender.send(());
+                    panic!("boom");
+                },
+            )
+            .unwrap();
+
+        for _ in 0..2 {
+            reciever.recv_timeout(Duration::from_secs(5)).unwrap();
+        }
+        handle.cancel();
+    }
+}
@@ -0,0 +1,354 @@
+use std::{
+    collections::HashMap,
+    marker::PhantomData,
+    mem,
+    panic::{self, AssertUnwindSafe},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Condvar, Mutex,
+    },
+    thread,
+};
+
+use crate::{ExecuteError, Job, Priority, Threadpool};
+
+/// A scope for running jobs that borrow from the caller's stack, created by
+/// `Threadpool::scope`.
+pub struct Scope<'scope, 'env: 'scope> {
+    pool: &'scope Threadpool,
+    state: Arc<ScopeState>,
+    // Invariance over both lifetimes, like `std::thread::Scope`.
+    scope: PhantomData<&'scope mut &'scope ()>,
+    env: PhantomData<&'env mut &'env ()>,
+}
+
+struct ScopeState {
+    pending: Mutex<usize>,
+    finished: Condvar,
+    panicked: AtomicBool,
+}
+
+impl ScopeState {
+    fn wait(&self) {
+        let mut pending = self.pending.lock().unwrap();
+
+        while *pending > 0 {
+            pending = self.finished.wait(pending).unwrap();
+        }
+    }
+}
+
+/// A scoped closure together with the bookkeeping that releases the scope.
+///
+/// The closure is always dropped before the scope is told the job is done, whether
+/// it ran or was discarded unrun, so nothing it borrows can outlive the scope.
+struct ScopedJob<F> {
+    f: Option<F>,
+    state: Arc<ScopeState>,
+}
+
+impl<F> Drop for ScopedJob<F> {
+    fn drop(&mut self) {
+        drop(self.f.take());
+
+        let mut pending = self.state.pending.lock().unwrap();
+        *pending -= 1;
+        if *pending == 0 {
+            self.state.finished.notify_all();
+        }
+    }
+}
+
+/// Pool jobs tied to a `std::thread::Scope` with `Threadpool::execute_scoped`, by the
+/// address of their scope.
+#[derive(Default)]
+pub(crate) struct StdScopes {
+    pending: Mutex<HashMap<usize, StdScopePending>>,
+    finished: Condvar,
+}
+
+#[derive(Default)]
+struct StdScopePending {
+    jobs: usize,
+    panicked: bool,
+}
+
+impl StdScopes {
+    /// Count a new job for the scope at `key`, returning whether it needs a waiter: a
+    /// scoped thread that keeps the scope open until its pool jobs are done.
+    fn enter(&self, key: usize) -> bool {
+        let mut pending = self.pending.lock().unwrap();
+        let scope = pending.entry(key).or_default();
+
+        scope.jobs += 1;
+        scope.jobs == 1
+    }
+
+    fn exit(&self, key: usize, panicked: bool) {
+        let mut pending = self.pending.lock().unwrap();
+        let scope = pending.get_mut(&key).unwrap();
+
+        scope.jobs -= 1;
+        scope.panicked |= panicked;
+        if scope.jobs == 0 {
+            self.finished.notify_all();
+        }
+    }
+
+    /// Block until the scope at `key` has no pool jobs left, panicking if one of them
+    /// did.
+    fn wait(&self, key: usize) {
+        let mut pending = self.pending.lock().unwrap();
+
+        while pending.get(&key).is_some_and(|scope| scope.jobs > 0) {
+            pending = self.finished.wait(pending).unwrap();
+        }
+
+        // Another waiter for the same scope may have got here first.
+        let panicked = pending.remove(&key).is_some_and(|scope| scope.panicked);
+        drop(pending);
+        if panicked {
+            panic!("a pool job tied to this scope panicked");
+        }
+    }
+}
+
+/// A closure tied to a `std::thread::Scope`, which releases the scope once the closure
+/// has been dropped, whether it ran, panicked or was discarded unrun.
+struct StdScopedJob<F> {
+    f: Option<F>,
+    scopes: Arc<StdScopes>,
+    key: usize,
+}
+
+impl<F> Drop for StdScopedJob<F> {
+    fn drop(&mut self) {
+        drop(self.f.take());
+        self.scopes.exit(self.key, thread::panicking());
+    }
+}
+
+impl Threadpool {
+    /// Execute a closure on the pool that may borrow anything `scope`'s threads can.
+    ///
+    /// The job is tied to the existing `std::thread::scope`: the scope does not end
+    /// until the job has finished. If the job panics, the scope panics once everything
+    /// in it has finished, as it would for a panicking scoped thread. To keep the scope
+    /// open the pool spawns one scoped thread per scope, which sleeps until the scope's
+    /// pool jobs are done.
+    ///
+    /// Fails under the same conditions as `execute`.
+    /// ```
+    /// use std::{sync::atomic::{AtomicUsize, Ordering}, thread};
+    /// use threadpool::Threadpool;
+    /// let pool = Threadpool::build(4).unwrap();
+    /// let total = AtomicUsize::new(0);
+    ///
+    /// thread::scope(|s| {
+    ///     s.spawn(|| total.fetch_add(1, Ordering::SeqCst));
+    ///     for _ in 0..3 {
+    ///         pool.execute_scoped(s, || {
+    ///             total.fetch_add(1, Ordering::SeqCst);
+    ///         })
+    ///         .unwrap();
+    ///     }
+    /// });
+    /// assert_eq!(total.load(Ordering::SeqCst), 4);
+    /// ```
+    pub fn execute_scoped<'scope, 'env, F>(
+        &self,
+        scope: &'scope thread::Scope<'scope, 'env>,
+        f: F,
+    ) -> Result<(), ExecuteError>
+    where
+        F: FnOnce() + Send + 'scope,
+    {
+        let scopes = &self.shared.std_scopes;
+        let key = scope as *const thread::Scope as usize;
+
+        if scopes.enter(key) {
+            let scopes = Arc::clone(scopes);
+            scope.spawn(move || scopes.wait(key));
+        }
+
+        let mut scoped = StdScopedJob {
+            f: Some(f),
+            scopes: Arc::clone(scopes),
+            key,
+        };
+        let job: Box<dyn FnOnce() + Send + 'scope> = Box::new(move || {
+            // A panic unwinds through `scoped`, which records it for the scope.
+            let f = scoped.f.take().unwrap();
+            f();
+        });
+
+        // SAFETY: a thread spawned in `scope` waits until every `StdScopedJob` of the
+        // scope has been dropped, and a `StdScopedJob` drops its closure before
+        // releasing the scope, so `std::thread::scope` cannot return while the job
+        // could still touch what it borrows.
+        let job: Job = unsafe { mem::transmute::<Box<dyn FnOnce() + Send + 'scope>, Job>(job) };
+
+        // On failure the job is dropped, which releases the scope.
+        self.shared.execute_job(Priority::Normal, job)
+    }
+
+    /// Create a scope in which jobs may borrow non-`'static` data.
+    ///
+    /// Every job executed through the scope has finished before `scope` returns. If any
+    /// of them panicked, `scope` panics once they have all finished, like
+    /// `std::thread::scope`.
+    ///
+    /// Calling `scope` from inside one of this pool's own jobs blocks that worker while
+    /// it waits, so the pool needs at least one other worker to make progress.
+    /// ```
+    /// use threadpool::Threadpool;
+    /// let pool = Threadpool::build(4).unwrap();
+    /// let mut numbers = vec![1, 2, 3, 4];
+    ///
+    /// pool.scope(|s| {
+    ///     for n in numbers.iter_mut() {
+    ///         s.execute(move || *n *= 10);
+    ///     }
+    /// });
+    /// assert_eq!(numbers, [10, 20, 30, 40]);
+    /// ```
+    pub fn scope<'env, F, T>(&self, f: F) -> T
+    where
+        F: for<'scope> FnOnce(&'scope Scope<'scope, 'env>) -> T,
+    {
+        let scope = Scope {
+            pool: self,
+            state: Arc::new(ScopeState {
+                pending: Mutex::new(0),
+                finished: Condvar::new(),
+                panicked: AtomicBool::new(false),
+            }),
+            scope: PhantomData,
+            env: PhantomData,
+        };
+
+        let result = panic::catch_unwind(AssertUnwindSafe(|| f(&scope)));
+        scope.state.wait();
+
+        match result {
+            Err(payload) => panic::resume_unwind(payload),
+            Ok(_) if scope.state.panicked.load(Ordering::SeqCst) => {
+                panic!("a scoped job panicked")
+            }
+            Ok(value) => value,
+        }
+    }
+}
+
+impl<'scope> Scope<'scope, '_> {
+    /// Execute a closure using a thread from the pool.
+    ///
+    /// The closure may borrow anything that outlives the scope.
+    pub fn execute<F>(&'scope self, f: F)
+    where
+        F: FnOnce() + Send + 'scope,
+    {
+        *self.state.pending.lock().unwrap() += 1;
+
+        let mut scoped = ScopedJob {
+            f: Some(f),
+            state: Arc::clone(&self.state),
+        };
+        let job: Box<dyn FnOnce() + Send + 'scope> = Box::new(move || {
+            let f = scoped.f.take().unwrap();
+
+            if panic::catch_unwind(AssertUnwindSafe(f)).is_err() {
+                scoped.state.panicked.store(true, Ordering::SeqCst);
+            }
+        });
+
+        // SAFETY: `Threadpool::scope` does not return until every `ScopedJob` has been
+        // dropped, and a `ScopedJob` drops its closure before releasing the scope. The
+        // job therefore never outlives the data it borrows, even if it is discarded by
+        // `shutdown_now` instead of being run.
+        let job: Job = unsafe { mem::transmute::<Box<dyn FnOnce() + Send + 'scope>, Job>(job) };
+
+        // Dropping a rejected job releases its slot in `pending`, so the scope still
+        // finishes before re-raising this.
+        self.pool
+            .shared
+            .execute_job(Priority::Normal, job)
+            .expect("Scope::execute called after the pool was shut down");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicUsize;
+
+    #[test]
+    fn scoped_jobs_borrow_stack_data() {
+        let pool = Threadpool::build(3).unwrap();
+        let total = AtomicUsize::new(0);
+        let values = [1, 2, 3, 4, 5];
+
+        pool.scope(|s| {
+            for value in &values {
+                let total = &total;
+                s.execute(move || {
+                    total.fetch_add(*value, Ordering::SeqCst);
+                });
+            }
+        });
+
+        assert_eq!(total.load(Ordering::SeqCst), 15);
+    }
+
+    #[test]
+    fn scope_panics_after_jobs_finish_if_one_panicked() {
+        let pool = Threadpool::build(2).unwrap();
+        let finished = AtomicUsize::new(0);
+
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            pool.scope(|s| {
+                s.execute(|| panic!("boom"));
+                s.execute(|| {
+                    finished.fetch_add(1, Ordering::SeqCst);
+                });
+            })
+        }));
+
+        assert!(result.is_err());
+        assert_eq!(finished.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn std_scope_waits_for_pool_jobs() {
+        let pool = Threadpool::build(2).unwrap();
+        let total = AtomicUsize::new(0);
+        let values = [1, 2, 3, 4, 5];
+
+        thread::scope(|s| {
+            for value in &values {
+                let total = &total;
+                pool.execute_scoped(s, move || {
+                    thread::sleep(std::time::Duration::from_millis(1));
+                    total.fetch_add(*value, Ordering::SeqCst);
+                })
+                .unwrap();
+            }
+        });
+
+        assert_eq!(total.load(Ordering::SeqCst), 15);
+        assert!(pool.shared.std_scopes.pending.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn std_scope_panics_if_a_pool_job_panicked() {
+        let pool = Threadpool::build(1).unwrap();
+
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            thread::scope(|s| {
+                pool.execute_scoped(s, || panic!("boom")).unwrap();
+            })
+        }));
+
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,120 @@
+use crate::{ExecuteError, JobId, Threadpool};
+
+/// Two pools side by side: one for blocking IO and one for CPU-bound work.
+///
+/// Jobs that spend most of their time waiting on files or sockets go to the IO pool,
+/// so however many of them are stuck, the CPU pool's threads stay free for work such
+/// as parsing requests.
+/// ```
+/// use threadpool::{SegmentedPool, Threadpool};
+/// let io = Threadpool::build(16).unwrap();
+/// let cpu = Threadpool::new_auto().unwrap();
+/// let pool = SegmentedPool::new(io, cpu);
+///
+/// pool.execute_cpu(|| println!("parsing...")).unwrap();
+/// pool.execute_io(|| println!("reading...")).unwrap();
+/// ```
+pub struct SegmentedPool {
+    io: Threadpool,
+    cpu: Threadpool,
+}
+
+impl SegmentedPool {
+    /// Combine an IO pool and a CPU pool.
+    ///
+    /// The IO pool is usually the larger of the two, since its threads spend most of
+    /// their time blocked.
+    pub fn new(io: Threadpool, cpu: Threadpool) -> SegmentedPool {
+        SegmentedPool { io, cpu }
+    }
+
+    /// Execute a blocking IO closure on the IO pool.
+    pub fn execute_io<F>(&self, f: F) -> Result<JobId, ExecuteError>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        self.io.execute(f)
+    }
+
+    /// Execute a CPU-bound closure on the CPU pool.
+    pub fn execute_cpu<F>(&self, f: F) -> Result<JobId, ExecuteError>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        self.cpu.execute(f)
+    }
+
+    /// The IO pool, for its other submission methods or a `handle` to pass into jobs.
+    pub fn io(&self) -> &Threadpool {
+        &self.io
+    }
+
+    /// The CPU pool, for its other submission methods or a `handle` to pass into jobs.
+    pub fn cpu(&self) -> &Threadpool {
+        &self.cpu
+    }
+
+    /// Block until both pools are idle.
+    ///
+    /// The CPU pool is waited for first, then the IO pool, and again until both are
+    /// idle at once, so jobs that hand work from one pool to the other are covered.
+    pub fn join(&self) {
+        loop {
+            self.cpu.join();
+            self.io.join();
+            if self.cpu.shared.is_idle() && self.io.shared.is_idle() {
+                break;
+            }
+        }
+    }
+
+    /// Shut down both pools, running everything already queued.
+    ///
+    /// The CPU pool goes first, so jobs it hands to the IO pool while draining still
+    /// run.
+    pub fn shutdown(&self) {
+        self.cpu.shutdown();
+        self.io.shutdown();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+
+    #[test]
+    fn blocked_io_pool_leaves_cpu_pool_free() {
+        let pool = SegmentedPool::new(Threadpool::build(1).unwrap(), Threadpool::build(1).unwrap());
+        let (started_tx, started_rx) = mpsc::channel();
+        let (release_tx, release_rx) = mpsc::channel::<()>();
+        let (done_tx, done_rx) = mpsc::channel();
+
+        pool.execute_io(move || {
+            started_tx.send(()).unwrap();
+            release_rx.recv().unwrap();
+        })
+        .unwrap();
+        started_rx.recv().unwrap();
+        pool.execute_cpu(move || done_tx.send(()).unwrap()).unwrap();
+
+        done_rx.recv().unwrap();
+        release_tx.send(()).unwrap();
+        pool.join();
+    }
+
+    #[test]
+    fn join_covers_jobs_handed_between_pools() {
+        let pool = SegmentedPool::new(Threadpool::build(1).unwrap(), Threadpool::build(1).unwrap());
+        let io = pool.io().handle();
+        let (sender, reciever) = mpsc::channel();
+
+        pool.execute_cpu(move || {
+            io.execute(move || sender.send(()).unwrap()).unwrap();
+        })
+        .unwrap();
+        pool.join();
+
+        assert!(reciever.try_recv().is_ok());
+    }
+}
@@ -0,0 +1,277 @@
+use std::{
+    collections::BTreeMap,
+    fmt,
+    sync::{atomic::Ordering, Arc, Mutex},
+    thread::{self, ThreadId},
+    time::{Duration, Instant},
+};
+
+use crate::{worker, ExecuteError, JobId, Priority, Threadpool};
+
+/// What each worker has been doing, for `Threadpool::snapshot`.
+#[derive(Default)]
+pub(crate) struct Activity {
+    workers: Mutex<BTreeMap<usize, WorkerActivity>>,
+}
+
+struct WorkerActivity {
+    /// The thread behind the worker id, which changes when a worker is respawned.
+    thread: ThreadId,
+    started: Instant,
+    job: Option<(Instant, Option<String>)>,
+}
+
+impl Activity {
+    /// Record that the current thread is now the worker with the given id.
+    pub(crate) fn worker_started(&self, id: usize) {
+        self.workers.lock().unwrap().insert(
+            id,
+            WorkerActivity {
+                thread: thread::current().id(),
+                started: Instant::now(),
+                job: None,
+            },
+        );
+    }
+
+    /// Record that the current thread has stopped being the worker with the given id.
+    ///
+    /// A replacement respawned under the same id registers before the thread it
+    /// replaces exits, so it is left alone.
+    pub(crate) fn worker_exited(&self, id: usize) {
+        let mut workers = self.workers.lock().unwrap();
+
+        if workers
+            .get(&id)
+            .is_some_and(|worker| worker.thread == thread::current().id())
+        {
+            workers.remove(&id);
+        }
+    }
+
+    pub(crate) fn job_started(&self, id: usize) {
+        if let Some(worker) = self.workers.lock().unwrap().get_mut(&id) {
+            worker.job = Some((Instant::now(), None));
+        }
+    }
+
+    pub(crate) fn job_finished(&self, id: usize) {
+        if let Some(worker) = self.workers.lock().unwrap().get_mut(&id) {
+            worker.job = None;
+        }
+    }
+
+    fn label_job(&self, id: usize, label: &str) {
+        if let Some((_, current)) = self
+            .workers
+            .lock()
+            .unwrap()
+            .get_mut(&id)
+            .and_then(|worker| worker.job.as_mut())
+        {
+            *current = Some(label.to_owned());
+        }
+    }
+
+    /// Return the label of the job the worker with the given id is running.
+    pub(crate) fn current_label(&self, id: usize) -> Option<String> {
+        let workers = self.workers.lock().unwrap();
+        workers.get(&id)?.job.as_ref()?.1.clone()
+    }
+
+    fn workers(&self) -> Vec<WorkerSnapshot> {
+        self.workers
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(id, worker)| WorkerSnapshot {
+                id: *id,
+                uptime: worker.started.elapsed(),
+                job: worker.job.as_ref().map(|(started, label)| RunningJob {
+                    label: label.clone(),
+                    running_for: started.elapsed(),
+                }),
+            })
+            .collect()
+    }
+}
+
+/// A point-in-time view of a pool and each of its workers, returned by
+/// `Threadpool::snapshot`.
+///
+/// Every field is plain data, so a snapshot can be logged, compared or rendered by an
+/// admin endpoint without holding on to the pool. Like `Metrics`, the fields are read
+/// one after another and may be off by a job or two while the pool is busy.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PoolSnapshot {
+    /// Number of workers the pool is sized for.
+    pub size: usize,
+    /// Jobs waiting in the queue.
+    pub queued: usize,
+    /// Jobs that have been submitted but have not finished yet.
+    pub in_flight: usize,
+    /// Jobs that ran to completion.
+    pub completed: u64,
+    /// Jobs that panicked.
+    pub panicked: u64,
+    /// Whether workers have been stopped from taking jobs with `pause`.
+    pub paused: bool,
+    /// Whether the pool has stopped accepting jobs.
+    pub closed: bool,
+    /// Every running worker, by ascending id.
+    pub workers: Vec<WorkerSnapshot>,
+}
+
+/// One worker in a `PoolSnapshot`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WorkerSnapshot {
+    /// The worker's id, as passed to `PoolObserver` and the worker hooks.
+    pub id: usize,
+    /// How long the worker's current thread has been running.
+    pub uptime: Duration,
+    /// The job the worker is running, or `None` if it is idle.
+    pub job: Option<RunningJob>,
+}
+
+impl WorkerSnapshot {
+    /// Whether the worker is running a job.
+    pub fn is_busy(&self) -> bool {
+        self.job.is_some()
+    }
+}
+
+/// The job a worker is running in a `WorkerSnapshot`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RunningJob {
+    /// The label the job was submitted with by `Threadpool::execute_labelled`.
+    pub label: Option<String>,
+    /// How long the job has been running.
+    pub running_for: Duration,
+}
+
+impl Threadpool {
+    /// Execute a closure like `execute`, showing `label` against its worker in
+    /// `snapshot` while it runs.
+    /// ```
+    /// use threadpool::Threadpool;
+    /// let pool = Threadpool::build(4).unwrap();
+    ///
+    /// pool.execute_labelled("GET /index.html", || println!("serving...")).unwrap();
+    /// ```
+    pub fn execute_labelled<F>(&self, label: impl Into<String>, f: F) -> Result<JobId, ExecuteError>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        let label = label.into();
+        let activity = Arc::clone(&self.shared.activity);
+
+        self.shared.execute_tracked(
+            Priority::Normal,
+            Box::new(move || {
+                if let Some(worker) = worker::current_id() {
+                    activity.label_job(worker, &label);
+                }
+                f();
+            }),
+        )
+    }
+
+    /// Return the state of the pool and of each worker: whether it is idle or busy,
+    /// what it is running and for how long.
+    ///
+    /// Jobs passed to `spawn_local` run outside the workers and do not appear here.
+    /// ```
+    /// use threadpool::Threadpool;
+    /// let pool = Threadpool::build(2).unwrap();
+    ///
+    /// for worker in pool.snapshot().workers {
+    ///     match worker.job {
+    ///         Some(job) => println!("worker {} busy with {:?}", worker.id, job.label),
+    ///         None => println!("worker {} idle for {:?}", worker.id, worker.uptime),
+    ///     }
+    /// }
+    /// ```
+    pub fn snapshot(&self) -> PoolSnapshot {
+        let shared = &self.shared;
+        let metrics = shared.counters.snapshot();
+
+        PoolSnapshot {
+            size: self.size(),
+            queued: metrics.queued,
+            in_flight: shared.in_flight.load(Ordering::SeqCst),
+            completed: metrics.completed,
+            panicked: metrics.panicked,
+            paused: self.is_paused(),
+            closed: shared.is_closed(),
+            workers: shared.activity.workers(),
+        }
+    }
+}
+
+impl fmt::Debug for Threadpool {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let snapshot = self.snapshot();
+
+        f.debug_struct("Threadpool")
+            .field("size", &snapshot.size)
+            .field("queued", &snapshot.queued)
+            .field("in_flight", &snapshot.in_flight)
+            .field("paused", &snapshot.paused)
+            .field("closed", &snapshot.closed)
+            .field("workers", &snapshot.workers)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+
+    #[test]
+    fn snapshot_shows_busy_workers_and_their_labels() {
+        let pool = Threadpool::build(2).unwrap();
+        let (started_tx, started_rx) = mpsc::channel();
+        let (release_tx, release_rx) = mpsc::channel::<()>();
+
+        pool.execute_labelled("slow", move || {
+            started_tx.send(()).unwrap();
+            release_rx.recv().unwrap();
+        })
+        .unwrap();
+        started_rx.recv().unwrap();
+
+        let snapshot = pool.snapshot();
+        assert_eq!(snapshot.in_flight, 1);
+        let busy: Vec<_> = snapshot
+            .workers
+            .iter()
+            .filter_map(|worker| worker.job.as_ref())
+            .collect();
+        assert_eq!(busy.len(), 1);
+        assert_eq!(busy[0].label.as_deref(), Some("slow"));
+        assert!(format!("{pool:?}").contains("\"slow\""));
+
+        release_tx.send(()).unwrap();
+        pool.join();
+        assert!(pool
+            .snapshot()
+            .workers
+            .iter()
+            .all(|worker| !worker.is_busy()));
+    }
+
+    #[test]
+    fn respawned_workers_stay_in_the_snapshot() {
+        let pool = Threadpool::build(1).unwrap();
+
+        pool.execute(|| panic!("boom")).unwrap();
+        pool.join();
+        // The replacement may still be starting up.
+        pool.submit(|| {}).unwrap().join().unwrap();
+
+        let workers = pool.snapshot().workers;
+        assert_eq!(workers.len(), 1);
+        assert_eq!(workers[0].id, 0);
+    }
+}
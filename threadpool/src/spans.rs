@@ -0,0 +1,153 @@
+use std::time::{Duration, Instant};
+
+use crate::observer::{JobInfo, PoolObserver};
+
+/// Which part of a job's life a `JobSpan` covers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stage {
+    /// From being queued until a worker took the job.
+    QueueWait,
+    /// From a worker taking the job until it returned or panicked.
+    Execute,
+}
+
+impl Stage {
+    /// The stage's name, `queue_wait` or `execute`, for span names.
+    pub fn name(self) -> &'static str {
+        match self {
+            Stage::QueueWait => "queue_wait",
+            Stage::Execute => "execute",
+        }
+    }
+}
+
+/// A finished stretch of one job's life.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct JobSpan {
+    pub stage: Stage,
+    /// The worker that took the job.
+    pub worker: usize,
+    /// The number of jobs still waiting in any queue when the job was taken.
+    pub queue_depth: usize,
+    pub started: Instant,
+    pub duration: Duration,
+    /// Whether the job panicked, which is always `false` while it waits.
+    pub panicked: bool,
+}
+
+/// An observer that gives each job's queue wait and execution as spans to `export`,
+/// so they can be recorded with the spans of whatever the jobs do.
+///
+/// The queue wait is exported as the job starts and its execution as it ends, both
+/// from the worker's thread.
+/// ```
+/// use std::sync::{Arc, Mutex};
+/// use threadpool::{JobSpan, SpanObserver, Stage, ThreadpoolBuilder};
+///
+/// let spans = Arc::new(Mutex::new(Vec::new()));
+/// let sink = Arc::clone(&spans);
+/// let pool = ThreadpoolBuilder::new(1)
+///     .observer(SpanObserver::new(move |span: &JobSpan| {
+///         sink.lock().unwrap().push(span.stage);
+///     }))
+///     .build()
+///     .unwrap();
+///
+/// pool.execute(|| {}).unwrap();
+/// pool.shutdown();
+/// assert_eq!(*spans.lock().unwrap(), [Stage::QueueWait, Stage::Execute]);
+/// ```
+pub struct SpanObserver<F> {
+    export: F,
+}
+
+impl<F> SpanObserver<F>
+where
+    F: Fn(&JobSpan) + Send + Sync,
+{
+    pub fn new(export: F) -> SpanObserver<F> {
+        SpanObserver { export }
+    }
+
+    fn export(&self, stage: Stage, job: &JobInfo, duration: Duration, panicked: bool) {
+        let now = Instant::now();
+        (self.export)(&JobSpan {
+            stage,
+            worker: job.worker,
+            queue_depth: job.queue_depth,
+            started: now.checked_sub(duration).unwrap_or(now),
+            duration,
+            panicked,
+        });
+    }
+}
+
+impl<F> PoolObserver for SpanObserver<F>
+where
+    F: Fn(&JobSpan) + Send + Sync,
+{
+    fn on_job_start(&self, job: &JobInfo) {
+        self.export(Stage::QueueWait, job, job.waited, false);
+    }
+
+    fn on_job_end(&self, job: &JobInfo, ran: Duration, panicked: bool) {
+        self.export(Stage::Execute, job, ran, panicked);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ThreadpoolBuilder;
+    use std::{
+        sync::{mpsc, Arc, Mutex},
+        thread,
+    };
+
+    #[test]
+    fn exports_queue_wait_and_execution_with_their_fields() {
+        let spans = Arc::new(Mutex::new(Vec::new()));
+        let sink = Arc::clone(&spans);
+        let pool = ThreadpoolBuilder::new(1)
+            .observer(SpanObserver::new(move |span: &JobSpan| {
+                sink.lock().unwrap().push(*span);
+            }))
+            .build()
+            .unwrap();
+        let (running_tx, running_rx) = mpsc::channel();
+        let (release_tx, release_rx) = mpsc::channel::<()>();
+
+        pool.execute(move || {
+            running_tx.send(()).unwrap();
+            release_rx.recv().unwrap();
+        })
+        .unwrap();
+        running_rx.recv().unwrap();
+        pool.execute(|| {}).unwrap();
+        pool.execute(|| panic!("fails")).unwrap();
+        thread::sleep(Duration::from_millis(50));
+        release_tx.send(()).unwrap();
+        pool.shutdown();
+
+        let spans = spans.lock().unwrap();
+        let fields: Vec<_> = spans
+            .iter()
+            .map(|span| (span.stage, span.worker, span.queue_depth, span.panicked))
+            .collect();
+        assert_eq!(
+            fields,
+            [
+                (Stage::QueueWait, 0, 0, false),
+                (Stage::Execute, 0, 0, false),
+                (Stage::QueueWait, 0, 1, false),
+                (Stage::Execute, 0, 1, false),
+                (Stage::QueueWait, 0, 0, false),
+                (Stage::Execute, 0, 0, true),
+            ]
+        );
+        // The first job held the worker, and the others waited for it.
+        assert!(spans[1].duration >= Duration::from_millis(50));
+        assert!(spans[2].duration >= Duration::from_millis(50));
+        assert!(spans[2].started + spans[2].duration <= spans[3].started);
+    }
+}
@@ -0,0 +1,379 @@
+use std::{
+    cell::UnsafeCell,
+    collections::VecDeque,
+    hint,
+    marker::PhantomData,
+    ops::{Deref, DerefMut},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, LockResult, Mutex,
+    },
+    thread::{self, Thread},
+    time::{Duration, Instant},
+};
+
+/// How many times a thread checks a held lock before it parks.
+const SPINS: u32 = 100;
+
+/// A mutex that spins for a while before it parks, in place of `std::sync::Mutex`
+/// around the pool's queues when the `spin-lock` feature is on.
+///
+/// The queues are held for a few instructions at a time, so a thread that finds one
+/// held usually gets it by waiting a moment rather than by going to sleep and being
+/// woken. Threads that do park are woken one at a time as the lock is released.
+///
+/// It has the methods of `std::sync::Mutex` the pool uses, returning `LockResult` so
+/// the two can be swapped, but is never poisoned: a panic while it is held releases
+/// it as usual.
+/// ```
+/// use threadpool::SpinMutex;
+///
+/// let jobs = SpinMutex::new(Vec::new());
+/// jobs.lock().unwrap().push(1);
+/// assert_eq!(*jobs.lock().unwrap(), [1]);
+/// ```
+pub struct SpinMutex<T> {
+    locked: AtomicBool,
+    /// Threads parked until the lock is released, only used once spinning failed.
+    parked: Mutex<VecDeque<Thread>>,
+    /// The length of `parked`, so releasing the lock only looks at it when needed.
+    waiting: AtomicUsize,
+    value: UnsafeCell<T>,
+}
+
+// SAFETY: the value is only reached through a guard, and there is only ever one guard,
+// so sharing the mutex is as safe as sending the value.
+unsafe impl<T: Send> Send for SpinMutex<T> {}
+unsafe impl<T: Send> Sync for SpinMutex<T> {}
+
+impl<T> SpinMutex<T> {
+    pub const fn new(value: T) -> SpinMutex<T> {
+        SpinMutex {
+            locked: AtomicBool::new(false),
+            parked: Mutex::new(VecDeque::new()),
+            waiting: AtomicUsize::new(0),
+            value: UnsafeCell::new(value),
+        }
+    }
+
+    /// Take the lock, spinning and then parking while another thread holds it.
+    pub fn lock(&self) -> LockResult<SpinMutexGuard<'_, T>> {
+        Ok(self.acquire())
+    }
+
+    fn acquire(&self) -> SpinMutexGuard<'_, T> {
+        if self
+            .locked
+            .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            self.lock_contended();
+        }
+        SpinMutexGuard {
+            mutex: self,
+            _thread: PhantomData,
+        }
+    }
+
+    fn try_acquire(&self) -> bool {
+        self.locked
+            .compare_exchange(false, true, Ordering::SeqCst, Ordering::Relaxed)
+            .is_ok()
+    }
+
+    fn lock_contended(&self) {
+        let current = thread::current();
+        loop {
+            for _ in 0..SPINS {
+                if !self.locked.load(Ordering::Relaxed) && self.try_acquire() {
+                    return;
+                }
+                hint::spin_loop();
+            }
+
+            {
+                let mut parked = self.parked.lock().unwrap();
+                parked.push_back(current.clone());
+                self.waiting.fetch_add(1, Ordering::SeqCst);
+            }
+            // Announced before trying again, so a release after this attempt fails
+            // either sees a waiter to wake or is seen here.
+            if self.try_acquire() {
+                self.unpark_cancelled(&current);
+                return;
+            }
+            thread::park();
+            // Woken spuriously, the thread is still on the list.
+            self.unpark_cancelled(&current);
+        }
+    }
+
+    /// Take `thread` off the list of parked threads, if releasing the lock has not.
+    fn unpark_cancelled(&self, thread: &Thread) {
+        let mut parked = self.parked.lock().unwrap();
+        if let Some(index) = parked.iter().position(|other| other.id() == thread.id()) {
+            parked.remove(index);
+            self.waiting.fetch_sub(1, Ordering::SeqCst);
+        }
+    }
+
+    fn unlock(&self) {
+        self.locked.store(false, Ordering::SeqCst);
+        if self.waiting.load(Ordering::SeqCst) == 0 {
+            return;
+        }
+        let next = {
+            let mut parked = self.parked.lock().unwrap();
+            let next = parked.pop_front();
+            if next.is_some() {
+                self.waiting.fetch_sub(1, Ordering::SeqCst);
+            }
+            next
+        };
+        if let Some(next) = next {
+            next.unpark();
+        }
+    }
+}
+
+impl<T: Default> Default for SpinMutex<T> {
+    fn default() -> SpinMutex<T> {
+        SpinMutex::new(T::default())
+    }
+}
+
+/// The lock on a `SpinMutex`, released when it is dropped.
+#[must_use = "the lock is released as soon as the guard is dropped"]
+pub struct SpinMutexGuard<'a, T> {
+    mutex: &'a SpinMutex<T>,
+    /// Released on the thread that took it, as a `std::sync::MutexGuard` is.
+    _thread: PhantomData<*const ()>,
+}
+
+// SAFETY: a shared guard only gives out shared references to the value.
+unsafe impl<T: Sync> Sync for SpinMutexGuard<'_, T> {}
+
+impl<T> Deref for SpinMutexGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        // SAFETY: the guard holds the lock, so nothing else reaches the value.
+        unsafe { &*self.mutex.value.get() }
+    }
+}
+
+impl<T> DerefMut for SpinMutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        // SAFETY: the guard holds the lock, so nothing else reaches the value.
+        unsafe { &mut *self.mutex.value.get() }
+    }
+}
+
+impl<T> Drop for SpinMutexGuard<'_, T> {
+    fn drop(&mut self) {
+        self.mutex.unlock();
+    }
+}
+
+/// Whether a wait on a `SpinCondvar` ended because its time ran out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WaitTimeoutResult(bool);
+
+impl WaitTimeoutResult {
+    pub fn timed_out(&self) -> bool {
+        self.0
+    }
+}
+
+/// A condition variable to wait on with a `SpinMutex`, with the methods of
+/// `std::sync::Condvar` the pool uses.
+///
+/// Waiting threads park until they are notified, and spurious wakeups are not passed
+/// on, though callers should still check their condition as with any condvar.
+#[derive(Default)]
+pub struct SpinCondvar {
+    waiters: Mutex<VecDeque<Arc<Waiter>>>,
+}
+
+struct Waiter {
+    thread: Thread,
+    /// Set, while `waiters` is locked, once the waiter has been taken off the list.
+    notified: AtomicBool,
+}
+
+impl SpinCondvar {
+    pub const fn new() -> SpinCondvar {
+        SpinCondvar {
+            waiters: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    /// Release `guard` and park until notified, then take the lock again.
+    pub fn wait<'a, T>(&self, guard: SpinMutexGuard<'a, T>) -> LockResult<SpinMutexGuard<'a, T>> {
+        Ok(self.wait_notified(guard))
+    }
+
+    fn wait_notified<'a, T>(&self, guard: SpinMutexGuard<'a, T>) -> SpinMutexGuard<'a, T> {
+        let (mutex, waiter) = self.enqueue(guard);
+        while !waiter.notified.load(Ordering::Acquire) {
+            thread::park();
+        }
+        mutex.acquire()
+    }
+
+    /// Like `wait`, but give up once `timeout` has elapsed.
+    pub fn wait_timeout<'a, T>(
+        &self,
+        guard: SpinMutexGuard<'a, T>,
+        timeout: Duration,
+    ) -> LockResult<(SpinMutexGuard<'a, T>, WaitTimeoutResult)> {
+        let Some(deadline) = Instant::now().checked_add(timeout) else {
+            return Ok((self.wait_notified(guard), WaitTimeoutResult(false)));
+        };
+        let (mutex, waiter) = self.enqueue(guard);
+        let timed_out = loop {
+            if waiter.notified.load(Ordering::Acquire) {
+                break false;
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                let mut waiters = self.waiters.lock().unwrap();
+                match waiters.iter().position(|other| Arc::ptr_eq(other, &waiter)) {
+                    Some(index) => {
+                        waiters.remove(index);
+                        break true;
+                    }
+                    // Notified just now, while the time ran out.
+                    None => break false,
+                }
+            }
+            thread::park_timeout(deadline - now);
+        };
+        Ok((mutex.acquire(), WaitTimeoutResult(timed_out)))
+    }
+
+    /// Wait while `condition` holds, for up to `timeout` in all.
+    pub fn wait_timeout_while<'a, T, F>(
+        &self,
+        mut guard: SpinMutexGuard<'a, T>,
+        timeout: Duration,
+        mut condition: F,
+    ) -> LockResult<(SpinMutexGuard<'a, T>, WaitTimeoutResult)>
+    where
+        F: FnMut(&mut T) -> bool,
+    {
+        let started = Instant::now();
+        loop {
+            if !condition(&mut *guard) {
+                return Ok((guard, WaitTimeoutResult(false)));
+            }
+            let Some(left) = timeout.checked_sub(started.elapsed()) else {
+                return Ok((guard, WaitTimeoutResult(true)));
+            };
+            guard = self.wait_timeout(guard, left)?.0;
+        }
+    }
+
+    /// Wake the thread that has waited longest, if any.
+    pub fn notify_one(&self) {
+        let waiter = {
+            let mut waiters = self.waiters.lock().unwrap();
+            let waiter = waiters.pop_front();
+            if let Some(waiter) = &waiter {
+                waiter.notified.store(true, Ordering::Release);
+            }
+            waiter
+        };
+        if let Some(waiter) = waiter {
+            waiter.thread.unpark();
+        }
+    }
+
+    /// Wake every waiting thread.
+    pub fn notify_all(&self) {
+        let waiters: Vec<_> = {
+            let mut waiters = self.waiters.lock().unwrap();
+            waiters
+                .drain(..)
+                .inspect(|waiter| waiter.notified.store(true, Ordering::Release))
+                .collect()
+        };
+        for waiter in waiters {
+            waiter.thread.unpark();
+        }
+    }
+
+    /// Put the current thread on the list of waiters, and only then release `guard`,
+    /// so a notification sent once the lock is free cannot be missed.
+    fn enqueue<'a, T>(&self, guard: SpinMutexGuard<'a, T>) -> (&'a SpinMutex<T>, Arc<Waiter>) {
+        let waiter = Arc::new(Waiter {
+            thread: thread::current(),
+            notified: AtomicBool::new(false),
+        });
+        self.waiters.lock().unwrap().push_back(Arc::clone(&waiter));
+        let mutex = guard.mutex;
+        drop(guard);
+        (mutex, waiter)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn excludes_other_threads() {
+        let counter = Arc::new(SpinMutex::new(0));
+        let threads: Vec<_> = (0..8)
+            .map(|_| {
+                let counter = Arc::clone(&counter);
+                thread::spawn(move || {
+                    for _ in 0..10_000 {
+                        let mut count = counter.lock().unwrap();
+                        // Read and written apart, so an overlap would lose increments.
+                        let seen = *count;
+                        hint::spin_loop();
+                        *count = seen + 1;
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        assert_eq!(*counter.lock().unwrap(), 80_000);
+    }
+
+    #[test]
+    fn wakes_waiters_and_times_out() {
+        let shared = Arc::new((SpinMutex::new(false), SpinCondvar::new()));
+        let waiter = {
+            let shared = Arc::clone(&shared);
+            thread::spawn(move || {
+                let (ready, condvar) = &*shared;
+                let mut ready = ready.lock().unwrap();
+                while !*ready {
+                    ready = condvar.wait(ready).unwrap();
+                }
+            })
+        };
+        thread::sleep(Duration::from_millis(20));
+        *shared.0.lock().unwrap() = true;
+        shared.1.notify_all();
+        waiter.join().unwrap();
+
+        let (ready, condvar) = &*shared;
+        let started = Instant::now();
+        let (_, result) = condvar
+            .wait_timeout(ready.lock().unwrap(), Duration::from_millis(20))
+            .unwrap();
+        assert!(result.timed_out());
+        assert!(started.elapsed() >= Duration::from_millis(20));
+        let (_, result) = condvar
+            .wait_timeout_while(ready.lock().unwrap(), Duration::from_secs(5), |ready| {
+                !*ready
+            })
+            .unwrap();
+        assert!(!result.timed_out());
+    }
+}
@@ -0,0 +1,163 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    thread,
+};
+
+use crate::{worker, Job, Threadpool};
+
+/// How many finished jobs keep their final status for `Threadpool::status`.
+const FINISHED_HISTORY: usize = 1024;
+
+/// Identifies a job submitted with `execute`, for use with `Threadpool::status`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct JobId(u64);
+
+impl fmt::Display for JobId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Where a job is in its lifecycle, returned by `Threadpool::status`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JobStatus {
+    /// Waiting in a queue.
+    Queued,
+    /// Being run by the worker with the given id.
+    Running { worker: usize },
+    /// Ran to completion.
+    Finished,
+    /// Panicked while running.
+    Panicked,
+}
+
+/// The status of every tracked job, shared with the jobs themselves so they can report
+/// their progress.
+#[derive(Default)]
+pub(crate) struct JobTable {
+    next_id: AtomicU64,
+    statuses: Mutex<Statuses>,
+}
+
+#[derive(Default)]
+struct Statuses {
+    jobs: HashMap<JobId, JobStatus>,
+    /// Finished jobs, oldest first, so the oldest can be forgotten.
+    finished: VecDeque<JobId>,
+}
+
+impl JobTable {
+    /// Assign `job` an id and wrap it so it records its progress in the table.
+    pub(crate) fn track(self: &Arc<Self>, job: Job) -> (JobId, Job) {
+        let id = JobId(self.next_id.fetch_add(1, Ordering::Relaxed));
+        self.set(id, JobStatus::Queued);
+
+        let mut tracked = Tracked {
+            id,
+            table: Arc::clone(self),
+            started: false,
+        };
+        let job = Box::new(move || {
+            tracked.started = true;
+            if let Some(worker) = worker::current_id() {
+                tracked.table.set(tracked.id, JobStatus::Running { worker });
+            }
+            job();
+        });
+
+        (id, job)
+    }
+
+    fn get(&self, id: JobId) -> Option<JobStatus> {
+        self.statuses.lock().unwrap().jobs.get(&id).copied()
+    }
+
+    fn set(&self, id: JobId, status: JobStatus) {
+        let mut statuses = self.statuses.lock().unwrap();
+        statuses.jobs.insert(id, status);
+
+        if matches!(status, JobStatus::Finished | JobStatus::Panicked) {
+            statuses.finished.push_back(id);
+            if statuses.finished.len() > FINISHED_HISTORY {
+                let oldest = statuses.finished.pop_front().unwrap();
+                statuses.jobs.remove(&oldest);
+            }
+        }
+    }
+
+    fn forget(&self, id: JobId) {
+        self.statuses.lock().unwrap().jobs.remove(&id);
+    }
+}
+
+/// Moves with a tracked job and records how it ended when dropped: after running,
+/// while unwinding from a panic, or without ever having run.
+struct Tracked {
+    id: JobId,
+    table: Arc<JobTable>,
+    started: bool,
+}
+
+impl Drop for Tracked {
+    fn drop(&mut self) {
+        match (self.started, thread::panicking()) {
+            (false, _) => self.table.forget(self.id),
+            (true, false) => self.table.set(self.id, JobStatus::Finished),
+            (true, true) => self.table.set(self.id, JobStatus::Panicked),
+        }
+    }
+}
+
+impl Threadpool {
+    /// Return the status of the job with the given id.
+    ///
+    /// Returns `None` for jobs that were discarded without running, for example by
+    /// `shutdown_now`, and for jobs that finished long enough ago to have been
+    /// forgotten: only the most recent 1024 finished jobs are remembered.
+    /// ```
+    /// use threadpool::{JobStatus, Threadpool};
+    /// let pool = Threadpool::build(1).unwrap();
+    ///
+    /// let id = pool.execute(|| println!("executing...")).unwrap();
+    /// pool.join();
+    /// assert_eq!(pool.status(id), Some(JobStatus::Finished));
+    /// ```
+    pub fn status(&self, id: JobId) -> Option<JobStatus> {
+        self.shared.statuses.get(id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+
+    #[test]
+    fn status_follows_a_job_through_its_lifecycle() {
+        let pool = Threadpool::build(1).unwrap();
+        let (started_tx, started_rx) = mpsc::channel();
+        let (release_tx, release_rx) = mpsc::channel::<()>();
+
+        let running = pool
+            .execute(move || {
+                started_tx.send(()).unwrap();
+                release_rx.recv().unwrap();
+            })
+            .unwrap();
+        started_rx.recv().unwrap();
+        let queued = pool.execute(|| panic!("boom")).unwrap();
+
+        assert_eq!(pool.status(running), Some(JobStatus::Running { worker: 0 }));
+        assert_eq!(pool.status(queued), Some(JobStatus::Queued));
+
+        release_tx.send(()).unwrap();
+        pool.join();
+        assert_eq!(pool.status(running), Some(JobStatus::Finished));
+        assert_eq!(pool.status(queued), Some(JobStatus::Panicked));
+    }
+}
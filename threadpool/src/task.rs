@@ -0,0 +1,292 @@
+use std::{
+    future::Future,
+    panic::{self, AssertUnwindSafe},
+    pin::Pin,
+    sync::{
+        atomic::{AtomicU8, Ordering},
+        Arc, Mutex, Weak,
+    },
+    task::{Context, Poll, Wake, Waker},
+    thread::{self, Thread},
+};
+
+use crate::{ExecuteError, JoinError, PoolError, Shared, Threadpool};
+
+const IDLE: u8 = 0;
+const SCHEDULED: u8 = 1;
+const RUNNING: u8 = 2;
+/// Woken again while running, so it must be polled once more.
+const NOTIFIED: u8 = 3;
+const DONE: u8 = 4;
+
+type BoxFuture = Pin<Box<dyn Future<Output = ()> + Send + 'static>>;
+
+/// A future spawned onto the pool. Each time it is woken a job is queued that polls it
+/// once on whichever worker picks the job up.
+struct Task {
+    future: Mutex<Option<BoxFuture>>,
+    state: AtomicU8,
+    shared: Weak<Shared>,
+}
+
+impl Task {
+    fn schedule(self: Arc<Self>) {
+        let Some(shared) = self.shared.upgrade() else {
+            return;
+        };
+
+        // Futures woken after the pool has started shutting down are dropped.
+        if !shared.is_closed() {
+            shared.push_nonblocking(Box::new(move || self.run()));
+        }
+    }
+
+    fn run(self: Arc<Self>) {
+        self.state.store(RUNNING, Ordering::SeqCst);
+
+        let waker = Waker::from(Arc::clone(&self));
+        let mut cx = Context::from_waker(&waker);
+        let mut future = self.future.lock().unwrap();
+
+        let ready = match future.as_mut() {
+            Some(future) => future.as_mut().poll(&mut cx).is_ready(),
+            None => true,
+        };
+
+        if ready {
+            *future = None;
+            self.state.store(DONE, Ordering::SeqCst);
+            return;
+        }
+        drop(future);
+
+        if self
+            .state
+            .compare_exchange(RUNNING, IDLE, Ordering::SeqCst, Ordering::SeqCst)
+            .is_err()
+        {
+            // Woken while being polled.
+            self.state.store(SCHEDULED, Ordering::SeqCst);
+            self.schedule();
+        }
+    }
+}
+
+impl Wake for Task {
+    fn wake(self: Arc<Self>) {
+        loop {
+            match self.state.load(Ordering::SeqCst) {
+                IDLE => {
+                    if self
+                        .state
+                        .compare_exchange(IDLE, SCHEDULED, Ordering::SeqCst, Ordering::SeqCst)
+                        .is_ok()
+                    {
+                        return self.schedule();
+                    }
+                }
+                RUNNING => {
+                    if self
+                        .state
+                        .compare_exchange(RUNNING, NOTIFIED, Ordering::SeqCst, Ordering::SeqCst)
+                        .is_ok()
+                    {
+                        return;
+                    }
+                }
+                _ => return,
+            }
+        }
+    }
+}
+
+/// The output of a future spawned with `Threadpool::spawn`, filled in when it completes.
+struct Output<T> {
+    value: Option<Result<T, JoinError>>,
+    waker: Option<Waker>,
+}
+
+/// A handle to a future running on the pool, returned by `Threadpool::spawn`.
+///
+/// The handle is itself a future resolving to the spawned future's output, or to a
+/// `JoinError` if polling it panicked or the pool dropped it before it completed.
+/// Outside of async code, `join` blocks for it.
+pub struct TaskHandle<T> {
+    output: Arc<Mutex<Output<T>>>,
+}
+
+/// The sending side of a task's `Output`. Reports `Dropped` if the task is dropped
+/// before completing, so its handle never waits forever.
+struct Completion<T>(Option<Arc<Mutex<Output<T>>>>);
+
+impl<T> Completion<T> {
+    fn complete(&mut self, value: Result<T, JoinError>) {
+        let Some(output) = self.0.take() else {
+            return;
+        };
+
+        let mut output = output.lock().unwrap();
+        output.value = Some(value);
+        if let Some(waker) = output.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+impl<T> Drop for Completion<T> {
+    fn drop(&mut self) {
+        self.complete(Err(JoinError::Dropped));
+    }
+}
+
+impl<T> TaskHandle<T> {
+    /// Block the current thread until the task has completed.
+    pub fn join(self) -> Result<T, JoinError> {
+        block_on(self)
+    }
+}
+
+impl<T> Future for TaskHandle<T> {
+    type Output = Result<T, JoinError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut output = self.output.lock().unwrap();
+
+        match output.value.take() {
+            Some(value) => Poll::Ready(value),
+            None => {
+                output.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+impl Threadpool {
+    /// Run a future on the pool's workers.
+    ///
+    /// The future is polled by a pool job whenever it is woken, so it never occupies a
+    /// worker while it is waiting. Blocking inside the future still blocks the worker.
+    ///
+    /// Returns a `ShuttingDown` error if the pool has been shut down. Futures woken
+    /// after that are dropped without being polled again, and their handles return
+    /// `JoinError::Dropped`.
+    /// ```
+    /// use threadpool::Threadpool;
+    /// let pool = Threadpool::build(2).unwrap();
+    ///
+    /// let handle = pool.spawn(async { 40 + 2 }).unwrap();
+    /// assert_eq!(handle.join().unwrap(), 42);
+    /// ```
+    pub fn spawn<F>(&self, future: F) -> Result<TaskHandle<F::Output>, ExecuteError>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        if self.shared.is_closed() {
+            return Err(PoolError::ShuttingDown);
+        }
+
+        let output = Arc::new(Mutex::new(Output {
+            value: None,
+            waker: None,
+        }));
+
+        let mut completion = Completion(Some(Arc::clone(&output)));
+        let mut future = Box::pin(future);
+        let wrapped = std::future::poll_fn(move |cx| {
+            let value = match panic::catch_unwind(AssertUnwindSafe(|| future.as_mut().poll(cx))) {
+                Ok(Poll::Pending) => return Poll::Pending,
+                Ok(Poll::Ready(value)) => Ok(value),
+                Err(payload) => Err(JoinError::JobPanicked(payload)),
+            };
+
+            completion.complete(value);
+            Poll::Ready(())
+        });
+
+        let task = Arc::new(Task {
+            future: Mutex::new(Some(Box::pin(wrapped))),
+            state: AtomicU8::new(SCHEDULED),
+            shared: Arc::downgrade(&self.shared),
+        });
+        task.schedule();
+
+        Ok(TaskHandle { output })
+    }
+}
+
+struct ThreadWaker(Thread);
+
+impl Wake for ThreadWaker {
+    fn wake(self: Arc<Self>) {
+        self.0.unpark();
+    }
+}
+
+/// Run a future to completion on the current thread, parking it while the future is
+/// waiting.
+/// ```
+/// let answer = threadpool::block_on(async { 6 * 7 });
+/// assert_eq!(answer, 42);
+/// ```
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    let mut future = std::pin::pin!(future);
+    let waker = Waker::from(Arc::new(ThreadWaker(thread::current())));
+    let mut cx = Context::from_waker(&waker);
+
+    loop {
+        match future.as_mut().poll(&mut cx) {
+            Poll::Ready(output) => return output,
+            Poll::Pending => thread::park(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    /// Resolves once it has been woken from another thread, to exercise rescheduling.
+    struct WakeLater {
+        woken: bool,
+    }
+
+    impl Future for WakeLater {
+        type Output = &'static str;
+
+        fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+            if self.woken {
+                return Poll::Ready("woken");
+            }
+
+            self.woken = true;
+            let waker = cx.waker().clone();
+            thread::spawn(move || {
+                thread::sleep(Duration::from_millis(10));
+                waker.wake();
+            });
+            Poll::Pending
+        }
+    }
+
+    #[test]
+    fn spawned_future_is_polled_again_after_wake() {
+        let pool = Threadpool::build(2).unwrap();
+
+        let handle = pool.spawn(WakeLater { woken: false }).unwrap();
+
+        assert_eq!(handle.join().unwrap(), "woken");
+    }
+
+    #[test]
+    fn spawned_future_panic_is_reported() {
+        let pool = Threadpool::build(1).unwrap();
+
+        let handle = pool.spawn(async { panic!("boom") }).unwrap();
+
+        let error = block_on(handle).unwrap_err();
+        assert_eq!(error.panic_message(), Some("boom"));
+    }
+}
@@ -0,0 +1,155 @@
+use std::sync::{Arc, Condvar, Mutex};
+
+use crate::{ExecuteError, JobId, PoolError, PoolHandle, Threadpool, TryExecuteError};
+
+/// A handle that lets at most a fixed number of its jobs be queued or running at once,
+/// returned by `Threadpool::throttle`.
+///
+/// The limit only covers jobs submitted through this handle and its clones, so it
+/// bounds the concurrency of one call site without affecting the rest of the pool.
+/// ```
+/// use threadpool::Threadpool;
+/// let pool = Threadpool::build(8).unwrap();
+/// let uploads = pool.throttle(2);
+///
+/// for _ in 0..10 {
+///     // Blocks while two uploads are already in flight.
+///     uploads.execute(|| println!("uploading...")).unwrap();
+/// }
+/// ```
+#[derive(Clone)]
+pub struct ThrottledHandle {
+    handle: PoolHandle,
+    slots: Arc<Slots>,
+}
+
+struct Slots {
+    limit: usize,
+    in_flight: Mutex<usize>,
+    released: Condvar,
+}
+
+/// Moves with a throttled job and frees its slot when dropped, whether the job ran,
+/// panicked or was discarded.
+struct Permit {
+    slots: Arc<Slots>,
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        *self.slots.in_flight.lock().unwrap() -= 1;
+        self.slots.released.notify_one();
+    }
+}
+
+impl ThrottledHandle {
+    /// Execute a closure using a thread from the pool, first waiting until fewer than
+    /// the limit of this handle's jobs are in flight.
+    pub fn execute<F>(&self, f: F) -> Result<JobId, ExecuteError>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        let mut in_flight = self.slots.in_flight.lock().unwrap();
+        while *in_flight >= self.slots.limit {
+            in_flight = self.slots.released.wait(in_flight).unwrap();
+        }
+        *in_flight += 1;
+        drop(in_flight);
+
+        self.submit_permitted(f)
+    }
+
+    /// Execute a closure like `execute`, but return a `Throttled` error with the
+    /// closure instead of waiting when the limit is reached.
+    ///
+    /// Once a slot is free the job is submitted like `execute`, so it can still wait
+    /// for space in a bounded pool queue.
+    pub fn try_execute<F>(&self, f: F) -> Result<JobId, TryExecuteError<F>>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        let mut in_flight = self.slots.in_flight.lock().unwrap();
+        if *in_flight >= self.slots.limit {
+            return Err(TryExecuteError::new(PoolError::Throttled, f));
+        }
+        *in_flight += 1;
+        drop(in_flight);
+
+        // Kept outside the job so it can be handed back if the pool rejects the job.
+        let job = Arc::new(Mutex::new(Some(f)));
+        let submitted = Arc::clone(&job);
+        self.submit_permitted(move || {
+            if let Some(f) = submitted.lock().unwrap().take() {
+                f();
+            }
+        })
+        .map_err(|error| {
+            let f = job.lock().unwrap().take().unwrap();
+            TryExecuteError::new(error, f)
+        })
+    }
+
+    /// Return the number of this handle's jobs that are queued or running.
+    pub fn in_flight(&self) -> usize {
+        *self.slots.in_flight.lock().unwrap()
+    }
+
+    /// Submit a job for which a slot has already been taken.
+    fn submit_permitted<F>(&self, f: F) -> Result<JobId, ExecuteError>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        let permit = Permit {
+            slots: Arc::clone(&self.slots),
+        };
+
+        // On failure the job is dropped, and its permit with it.
+        self.handle.execute(move || {
+            let _permit = permit;
+            f();
+        })
+    }
+}
+
+impl Threadpool {
+    /// Return a handle whose submissions are limited to `max_in_flight` queued or
+    /// running jobs at a time. A limit below one is raised to one.
+    pub fn throttle(&self, max_in_flight: usize) -> ThrottledHandle {
+        ThrottledHandle {
+            handle: self.handle(),
+            slots: Arc::new(Slots {
+                limit: max_in_flight.max(1),
+                in_flight: Mutex::new(0),
+                released: Condvar::new(),
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+
+    #[test]
+    fn throttle_rejects_beyond_the_limit() {
+        let pool = Threadpool::build(4).unwrap();
+        let throttled = pool.throttle(1);
+        let (release_tx, release_rx) = mpsc::channel::<()>();
+
+        throttled
+            .execute(move || release_rx.recv().unwrap())
+            .unwrap();
+        let error = throttled.try_execute(|| {}).unwrap_err();
+        assert!(matches!(error.error(), PoolError::Throttled));
+        assert_eq!(throttled.in_flight(), 1);
+
+        // Other submissions to the pool are not limited.
+        pool.submit(|| {}).unwrap().join().unwrap();
+
+        release_tx.send(()).unwrap();
+        throttled.execute(|| {}).unwrap();
+        pool.join();
+        assert_eq!(throttled.in_flight(), 0);
+    }
+}
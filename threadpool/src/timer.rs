@@ -0,0 +1,142 @@
+use std::{
+    cmp::Ordering,
+    collections::BinaryHeap,
+    sync::{Arc, Condvar, Mutex},
+    thread,
+    time::Instant,
+};
+
+use crate::{Job, Shared};
+
+/// Holds jobs scheduled with `Threadpool::execute_after` until they are due.
+///
+/// A single timer thread, spawned the first time a job is delayed, sleeps until the
+/// earliest deadline and then moves the job onto the pool's queue, so no worker is
+/// tied up waiting.
+#[derive(Default)]
+pub(crate) struct Timer {
+    state: Mutex<TimerState>,
+    changed: Condvar,
+    thread: Mutex<Option<thread::JoinHandle<()>>>,
+}
+
+#[derive(Default)]
+struct TimerState {
+    entries: BinaryHeap<Entry>,
+    next_seq: u64,
+    closed: bool,
+}
+
+struct Entry {
+    due: Instant,
+    seq: u64,
+    job: Job,
+}
+
+// `BinaryHeap` is a max-heap, so entries compare in reverse to pop the earliest
+// deadline first, oldest first among equal deadlines.
+impl Ord for Entry {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (other.due, other.seq).cmp(&(self.due, self.seq))
+    }
+}
+
+impl PartialOrd for Entry {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Entry {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Entry {}
+
+impl Timer {
+    /// Queue `job` to be moved onto `shared`'s queue once `due` has passed.
+    ///
+    /// Returns `false` if the timer has already been closed.
+    pub(crate) fn schedule(shared: &Arc<Shared>, due: Instant, job: Job) -> bool {
+        let timer = &shared.timer;
+        let mut state = timer.state.lock().unwrap();
+
+        if state.closed {
+            return false;
+        }
+
+        let seq = state.next_seq;
+        state.next_seq += 1;
+        state.entries.push(Entry { due, seq, job });
+        timer.changed.notify_one();
+        drop(state);
+
+        let mut thread = timer.thread.lock().unwrap();
+        if thread.is_none() {
+            let mut builder = thread::Builder::new();
+            if let Some(prefix) = &shared.thread_name {
+                builder = builder.name(format!("{prefix}-timer"));
+            }
+
+            let shared = Arc::clone(shared);
+            *thread = Some(
+                builder
+                    .spawn(move || Timer::run(&shared))
+                    .expect("failed to spawn the Threadpool timer thread"),
+            );
+        }
+
+        true
+    }
+
+    fn run(shared: &Shared) {
+        let timer = &shared.timer;
+
+        loop {
+            let job = {
+                let mut state = timer.state.lock().unwrap();
+
+                loop {
+                    if state.closed {
+                        return;
+                    }
+
+                    let now = Instant::now();
+                    match state.entries.peek() {
+                        Some(entry) if entry.due <= now => {
+                            break state.entries.pop().unwrap().job;
+                        }
+                        Some(entry) => {
+                            let timeout = entry.due - now;
+                            state = timer.changed.wait_timeout(state, timeout).unwrap().0;
+                        }
+                        None => state = timer.changed.wait(state).unwrap(),
+                    }
+                }
+            };
+
+            // Due jobs bypass the queue capacity so a full queue cannot stall the
+            // timer and delay every later job with it.
+            shared.push_nonblocking(job);
+        }
+    }
+
+    /// Stop the timer thread and return the jobs that were not yet due.
+    pub(crate) fn close(&self) -> Vec<Job> {
+        let jobs = {
+            let mut state = self.state.lock().unwrap();
+
+            state.closed = true;
+            self.changed.notify_all();
+            state.entries.drain().map(|entry| entry.job).collect()
+        };
+
+        if let Some(thread) = self.thread.lock().unwrap().take() {
+            thread.join().unwrap();
+        }
+
+        jobs
+    }
+}
@@ -0,0 +1,192 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Condvar, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{Shared, Worker};
+
+/// Called with the worker's id and how long its current job has been running.
+pub(crate) type StuckHook = Arc<dyn Fn(usize, Duration) + Send + Sync>;
+
+/// Watches for jobs that run for longer than a threshold, see
+/// `ThreadpoolBuilder::watchdog`.
+///
+/// Workers record when they start and finish each job, and a monitor thread wakes up a
+/// few times per threshold to look for jobs that have overrun it.
+pub(crate) struct Watchdog {
+    threshold: Duration,
+    on_stuck: Option<StuckHook>,
+    replace: bool,
+    state: Mutex<WatchdogState>,
+    changed: Condvar,
+    thread: Mutex<Option<thread::JoinHandle<()>>>,
+}
+
+#[derive(Default)]
+struct WatchdogState {
+    /// The job each busy worker is running, by worker id.
+    running: HashMap<usize, Running>,
+    closed: bool,
+}
+
+struct Running {
+    started: Instant,
+    reported: bool,
+    /// A replacement worker has been spawned, so this one exits after the job.
+    replaced: bool,
+}
+
+impl Watchdog {
+    pub(crate) fn new(threshold: Duration, on_stuck: Option<StuckHook>, replace: bool) -> Watchdog {
+        Watchdog {
+            threshold,
+            on_stuck,
+            replace,
+            state: Mutex::default(),
+            changed: Condvar::new(),
+            thread: Mutex::new(None),
+        }
+    }
+
+    /// Start the monitor thread for `shared`'s watchdog.
+    pub(crate) fn start(shared: &Arc<Shared>) {
+        let Some(watchdog) = &shared.watchdog else {
+            return;
+        };
+
+        let mut builder = thread::Builder::new();
+        if let Some(prefix) = &shared.thread_name {
+            builder = builder.name(format!("{prefix}-watchdog"));
+        }
+
+        let shared = Arc::clone(shared);
+        let thread = builder
+            .spawn(move || Watchdog::run(&shared))
+            .expect("failed to spawn the Threadpool watchdog thread");
+        *watchdog.thread.lock().unwrap() = Some(thread);
+    }
+
+    pub(crate) fn job_started(&self, worker: usize) {
+        self.state.lock().unwrap().running.insert(
+            worker,
+            Running {
+                started: Instant::now(),
+                reported: false,
+                replaced: false,
+            },
+        );
+    }
+
+    /// Record that `worker` finished its job, returning whether it has been replaced
+    /// and should exit.
+    pub(crate) fn job_finished(&self, worker: usize) -> bool {
+        let mut state = self.state.lock().unwrap();
+
+        state
+            .running
+            .remove(&worker)
+            .is_some_and(|running| running.replaced)
+    }
+
+    /// Stop the monitor thread and wait for it to exit.
+    pub(crate) fn close(&self) {
+        self.state.lock().unwrap().closed = true;
+        self.changed.notify_all();
+
+        let thread = self.thread.lock().unwrap().take();
+        if let Some(thread) = thread {
+            let _ = thread.join();
+        }
+    }
+
+    fn run(shared: &Arc<Shared>) {
+        let watchdog = shared.watchdog.as_ref().unwrap();
+        let interval = (watchdog.threshold / 4).max(Duration::from_millis(1));
+        let mut state = watchdog.state.lock().unwrap();
+
+        while !state.closed {
+            let now = Instant::now();
+            let mut stuck = Vec::new();
+
+            for (&worker, running) in state.running.iter_mut() {
+                let elapsed = now.duration_since(running.started);
+
+                if elapsed >= watchdog.threshold && !running.reported {
+                    running.reported = true;
+                    running.replaced = watchdog.replace;
+                    stuck.push((worker, elapsed));
+                }
+            }
+            drop(state);
+
+            for (worker, elapsed) in stuck {
+                match &watchdog.on_stuck {
+                    Some(hook) => hook(worker, elapsed),
+                    None => eprintln!("Worker {worker} has been running a job for {elapsed:?}"),
+                }
+
+                if watchdog.replace {
+                    Watchdog::replace(worker, shared);
+                }
+            }
+
+            state = watchdog.state.lock().unwrap();
+            if !state.closed {
+                state = watchdog.changed.wait_timeout(state, interval).unwrap().0;
+            }
+        }
+    }
+
+    /// Spawn an extra worker to stand in for `worker` until its job finishes.
+    fn replace(worker: usize, shared: &Arc<Shared>) {
+        let id = {
+            let mut state = shared.state.lock().unwrap();
+            state.next_id += 1;
+            state.next_id - 1
+        };
+
+        match Worker::new(id, Arc::clone(shared)) {
+            Ok(replacement) => shared.workers.lock().unwrap().push(replacement),
+            Err(error) => {
+                eprintln!("Worker {worker} could not be replaced: {error}");
+
+                // Keep the stuck worker, since nothing has taken its place.
+                let watchdog = shared.watchdog.as_ref().unwrap();
+                if let Some(running) = watchdog.state.lock().unwrap().running.get_mut(&worker) {
+                    running.replaced = false;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ThreadpoolBuilder;
+    use std::{sync::mpsc, time::Duration};
+
+    #[test]
+    fn stuck_job_is_reported_and_its_worker_replaced() {
+        let (stuck_tx, stuck_rx) = mpsc::channel();
+        let pool = ThreadpoolBuilder::new(1)
+            .watchdog(Duration::from_millis(20))
+            .on_stuck_job(move |worker, _| {
+                let _ = stuck_tx.send(worker);
+            })
+            .replace_stuck_workers(true)
+            .build()
+            .unwrap();
+        let (release_tx, release_rx) = mpsc::channel::<()>();
+
+        pool.execute(move || release_rx.recv().unwrap()).unwrap();
+        assert_eq!(stuck_rx.recv_timeout(Duration::from_secs(5)), Ok(0));
+
+        // Runs on the replacement while the first job is still blocked.
+        let handle = pool.submit(|| "unblocked").unwrap();
+        assert_eq!(handle.join().unwrap(), "unblocked");
+
+        release_tx.send(()).unwrap();
+    }
+}
@@ -0,0 +1,293 @@
+use std::{
+    any::Any,
+    cell::{Cell, RefCell},
+    collections::VecDeque,
+    io,
+    panic::{self, AssertUnwindSafe},
+    sync::{atomic::Ordering, Arc},
+    thread,
+    time::Instant,
+};
+
+use crate::{observer::JobInfo, Job, Message, Priority, QueueLock, Shared, Stamped, WorkerExit};
+
+/// A callback run on a worker thread with the worker's id, see
+/// `ThreadpoolBuilder::on_worker_start`.
+pub(crate) type WorkerHook = Arc<dyn Fn(usize) + Send + Sync>;
+
+/// A callback run on a worker thread after one of its jobs panicked, see
+/// `ThreadpoolBuilder::on_panic`.
+pub(crate) type PanicHook = Arc<dyn Fn(usize, &(dyn Any + Send), Option<&str>) + Send + Sync>;
+
+thread_local! {
+    /// The pool and local deque of the worker running on this thread, if any.
+    static CURRENT: RefCell<Option<(*const Shared, Arc<LocalQueue>)>> = const { RefCell::new(None) };
+
+    /// The id of the worker running on this thread, if any.
+    static WORKER_ID: Cell<Option<usize>> = const { Cell::new(None) };
+}
+
+/// Return the id of the worker running on the current thread, if it is a pool worker.
+pub(crate) fn current_id() -> Option<usize> {
+    WORKER_ID.get()
+}
+
+/// A worker's own deque of jobs submitted from inside its jobs.
+///
+/// The owning worker pushes and pops at the back, so the most recently spawned work
+/// runs first while its data is still in cache. Other workers steal from the front.
+pub(crate) struct LocalQueue {
+    jobs: QueueLock<VecDeque<Stamped>>,
+    /// The NUMA node of the owning worker, always 0 unless the pool is NUMA-aware.
+    node: usize,
+}
+
+impl LocalQueue {
+    fn new(node: usize) -> LocalQueue {
+        LocalQueue {
+            jobs: QueueLock::default(),
+            node,
+        }
+    }
+
+    pub(crate) fn pop(&self) -> Option<Stamped> {
+        self.jobs.lock().unwrap().pop_back()
+    }
+
+    /// Add jobs taken from the shared queue, so they are popped in the order given.
+    pub(crate) fn push_batch(&self, batch: Vec<Stamped>) {
+        self.jobs.lock().unwrap().extend(batch.into_iter().rev());
+    }
+
+    fn steal(&self) -> Option<Stamped> {
+        self.jobs.lock().unwrap().pop_front()
+    }
+
+    pub(crate) fn take_all(&self) -> Vec<Job> {
+        self.jobs
+            .lock()
+            .unwrap()
+            .drain(..)
+            .map(|(job, _)| job)
+            .collect()
+    }
+}
+
+/// Push `job` onto the local deque of the current worker, if this thread is one of
+/// `shared`'s workers. Otherwise the job is handed back.
+pub(crate) fn push_local(shared: &Shared, job: Job) -> Result<(), Job> {
+    CURRENT.with_borrow(|current| match current {
+        Some((pool, local)) if std::ptr::eq(*pool, shared) => {
+            local.jobs.lock().unwrap().push_back((job, Instant::now()));
+            shared.queued_unlocked();
+            Ok(())
+        }
+        _ => Err(job),
+    })
+}
+
+/// Steal the oldest job from another worker's deque, trying workers on the same NUMA
+/// node before the rest.
+pub(crate) fn steal(shared: &Shared, own: &Arc<LocalQueue>) -> Option<Stamped> {
+    let locals = shared.locals.read().unwrap();
+    let others = || locals.iter().filter(|local| !Arc::ptr_eq(local, own));
+
+    others()
+        .filter(|local| local.node == own.node)
+        .chain(others().filter(|local| local.node != own.node))
+        .find_map(|local| local.steal())
+}
+
+pub(crate) struct Worker {
+    pub(crate) id: usize,
+    pub(crate) thread: Option<thread::JoinHandle<()>>,
+}
+
+impl Worker {
+    pub(crate) fn new(id: usize, shared: Arc<Shared>) -> io::Result<Worker> {
+        let mut builder = thread::Builder::new();
+        // Counted before spawning so the pool never observes a spawned but uncounted
+        // worker.
+        shared.state.lock().unwrap().live += 1;
+
+        if let Some(prefix) = &shared.thread_name {
+            builder = builder.name(format!("{prefix}-{id}"));
+        }
+        if let Some(size) = shared.stack_size {
+            builder = builder.stack_size(size);
+        }
+
+        let spawned = builder.spawn({
+            let shared = Arc::clone(&shared);
+            move || {
+                #[cfg(feature = "affinity")]
+                let node = crate::affinity::place_worker(&shared, id);
+                #[cfg(not(feature = "affinity"))]
+                let node = 0;
+                let local = Arc::new(LocalQueue::new(node));
+
+                shared.locals.write().unwrap().push(Arc::clone(&local));
+                CURRENT.set(Some((Arc::as_ptr(&shared), Arc::clone(&local))));
+                WORKER_ID.set(Some(id));
+                shared.activity.worker_started(id);
+
+                if let Some(hook) = &shared.on_worker_start {
+                    hook(id);
+                }
+                Worker::run(id, &shared, &local);
+                if let Some(hook) = &shared.on_worker_stop {
+                    hook(id);
+                }
+
+                CURRENT.set(None);
+                shared.activity.worker_exited(id);
+                Worker::release_local(&shared, &local);
+                shared.worker_exited();
+            }
+        });
+
+        match spawned {
+            Ok(thread) => Ok(Worker {
+                id,
+                thread: Some(thread),
+            }),
+            Err(error) => {
+                shared.worker_exited();
+                Err(error)
+            }
+        }
+    }
+
+    fn run(id: usize, shared: &Arc<Shared>, local: &Arc<LocalQueue>) {
+        loop {
+            let message = shared.next_job(local);
+
+            match message {
+                Message::Job {
+                    job,
+                    waited,
+                    queue_depth,
+                } => {
+                    let info = JobInfo {
+                        worker: id,
+                        waited,
+                        queue_depth,
+                    };
+                    shared.observer.on_job_start(&info);
+                    shared.activity.job_started(id);
+                    if let Some(watchdog) = &shared.watchdog {
+                        watchdog.job_started(id);
+                    }
+
+                    let started = Instant::now();
+                    let result =
+                        panic::catch_unwind(AssertUnwindSafe(|| shared.middleware.run(job)));
+                    let replaced = shared
+                        .watchdog
+                        .as_ref()
+                        .is_some_and(|watchdog| watchdog.job_finished(id));
+                    if let (Err(payload), Some(hook)) = (&result, &shared.on_panic) {
+                        let label = shared.activity.current_label(id);
+                        hook(id, &**payload, label.as_deref());
+                    }
+                    shared.activity.job_finished(id);
+                    let ran = started.elapsed();
+                    shared.job_finished(result.is_err(), ran);
+                    shared.observer.on_job_end(&info, ran, result.is_err());
+
+                    // The replacement already stands in for this worker, so it neither
+                    // carries on nor respawns.
+                    if replaced {
+                        shared.observer.on_worker_exit(id, WorkerExit::Replaced);
+                        Worker::forget(id, shared);
+                        break;
+                    }
+
+                    if result.is_err() {
+                        shared.observer.on_worker_exit(id, WorkerExit::Panicked);
+
+                        Worker::respawn(id, shared);
+                        break;
+                    }
+                }
+                Message::Recycle => {
+                    shared.observer.on_worker_exit(id, WorkerExit::Recycled);
+                    Worker::respawn(id, shared);
+                    shared.worker_recycled();
+                    break;
+                }
+                Message::Retire => {
+                    shared.observer.on_worker_exit(id, WorkerExit::Retired);
+                    Worker::forget(id, shared);
+                    break;
+                }
+                Message::IdleExpired => {
+                    shared.observer.on_worker_exit(id, WorkerExit::Idle);
+                    Worker::forget(id, shared);
+                    break;
+                }
+                Message::Shutdown => {
+                    shared.observer.on_worker_exit(id, WorkerExit::Shutdown);
+                    break;
+                }
+            }
+        }
+    }
+
+    /// Drop the pool's handle to a worker that is exiting while the pool keeps running.
+    fn forget(id: usize, shared: &Shared) {
+        shared
+            .workers
+            .lock()
+            .unwrap()
+            .retain(|worker| worker.id != id);
+    }
+
+    /// Unregister an exiting worker's deque and move anything left in it to the shared
+    /// queue, so no job is lost with the thread.
+    fn release_local(shared: &Shared, local: &Arc<LocalQueue>) {
+        shared
+            .locals
+            .write()
+            .unwrap()
+            .retain(|other| !Arc::ptr_eq(other, local));
+
+        let leftover = local.take_all();
+        if leftover.is_empty() {
+            return;
+        }
+
+        let mut state = shared.state.lock().unwrap();
+        shared
+            .unlocked_jobs
+            .fetch_sub(leftover.len(), Ordering::SeqCst);
+        for job in leftover {
+            state.jobs.push(Priority::Normal, job);
+        }
+        shared.job_available.notify_all();
+    }
+
+    /// Replace the worker with the given id by a freshly spawned thread.
+    ///
+    /// Called from the worker's own thread after a job panicked, so the pool keeps its
+    /// size without reusing a thread whose state may have been left inconsistent, and
+    /// when the worker is recycled.
+    fn respawn(id: usize, shared: &Arc<Shared>) {
+        let replacement = match Worker::new(id, Arc::clone(shared)) {
+            Ok(replacement) => replacement,
+            Err(error) => {
+                eprintln!("Worker {id} could not be respawned: {error}");
+
+                shared.state.lock().unwrap().size -= 1;
+                Worker::forget(id, shared);
+                return;
+            }
+        };
+        let mut workers = shared.workers.lock().unwrap();
+
+        match workers.iter_mut().find(|worker| worker.id == id) {
+            Some(slot) => *slot = replacement,
+            None => workers.push(replacement),
+        }
+    }
+}